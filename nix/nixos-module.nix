@@ -49,7 +49,14 @@ in
             cfg.microphoneName
             "--fifo-path"
             cfg.fifoPath
+            "--max-latency-ms"
+            (toString cfg.maxLatencyMs)
           ]
+          ++ lib.optionals (cfg.nodeLatency != null) [
+            "--node-latency"
+            cfg.nodeLatency
+          ]
+          ++ lib.optional cfg.realtime "--realtime"
           ++ lib.optional cfg.verbose "--verbose"
         );
         Restart = "no";
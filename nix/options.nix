@@ -36,6 +36,25 @@ in
     description = "Path to the FIFO pipe for audio data.";
   };
 
+  nodeLatency = mkOption {
+    type = types.nullOr types.str;
+    default = null;
+    example = "256/48000";
+    description = "PipeWire node latency for the virtual source, as quantum/rate.";
+  };
+
+  realtime = mkOption {
+    type = types.bool;
+    default = false;
+    description = "Request real-time scheduling for the FIFO writer threads.";
+  };
+
+  maxLatencyMs = mkOption {
+    type = types.ints.unsigned;
+    default = 1000;
+    description = "Maximum queued audio in milliseconds before the receiver skips ahead to live audio (0 disables).";
+  };
+
   verbose = mkOption {
     type = types.bool;
     default = false;
@@ -0,0 +1,207 @@
+//! Ownership and permissions of a receiver's FIFO
+//!
+//! Several receivers on one machine, one per remote microphone, can each
+//! hand their FIFO to a different local user or group with `--fifo-owner`
+//! and `--fifo-mode`, so only the application it is meant for can read that
+//! channel's audio. The receiver applies them whenever it creates the FIFO
+//! (or the `--backend null` output file), including after recovering a lost
+//! one.
+//!
+//! Giving a FIFO to another user needs root, while a group the receiver's
+//! user is a member of works without. The receiver and the sound server
+//! reading the pipe keep their already open handles either way.
+
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::fs::{PermissionsExt, chown};
+use std::str::FromStr;
+
+/// Owner and group to give the FIFO, parsed from `USER[:GROUP]` or
+/// `:GROUP`, by name or numeric id
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::access::FileOwner;
+///
+/// let owner: FileOwner = "0:0".parse().unwrap();
+/// assert_eq!((owner.uid, owner.gid), (Some(0), Some(0)));
+/// let group: FileOwner = ":100".parse().unwrap();
+/// assert_eq!((group.uid, group.gid), (None, Some(100)));
+/// assert!("".parse::<FileOwner>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOwner {
+    /// User id, or `None` to keep the owner
+    pub uid: Option<u32>,
+    /// Group id, or `None` to keep the group
+    pub gid: Option<u32>,
+}
+
+impl FromStr for FileOwner {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let uid = match user {
+            "" => None,
+            user => Some(lookup_user(user)?),
+        };
+        let gid = match group {
+            None | Some("") => None,
+            Some(group) => Some(lookup_group(group)?),
+        };
+        if uid.is_none() && gid.is_none() {
+            return Err(anyhow::anyhow!(
+                "Expected USER, USER:GROUP or :GROUP, got '{s}'"
+            ));
+        }
+        Ok(Self { uid, gid })
+    }
+}
+
+/// Permission bits to give the FIFO, parsed from octal (e.g. `0660`)
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::access::FileMode;
+///
+/// let mode: FileMode = "0640".parse().unwrap();
+/// assert_eq!(mode.0, 0o640);
+/// assert_eq!(mode.to_string(), "0640");
+/// assert!("0999".parse::<FileMode>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Self(mode)),
+            _ => Err(anyhow::anyhow!(
+                "Expected octal permissions such as 0660, got '{s}'"
+            )),
+        }
+    }
+}
+
+/// Give the file at `path` the requested owner and permissions
+pub fn apply_access(
+    path: &str,
+    owner: Option<FileOwner>,
+    mode: Option<FileMode>,
+) -> anyhow::Result<()> {
+    if let Some(owner) = owner {
+        chown(path, owner.uid, owner.gid)
+            .map_err(|e| anyhow::anyhow!("Failed to change the owner of {path}: {e}"))?;
+    }
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0))
+            .map_err(|e| anyhow::anyhow!("Failed to change the permissions of {path}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Size of the buffer for the strings of a passwd or group entry
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+pub(crate) fn lookup_user(user: &str) -> anyhow::Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user)?;
+    let mut buffer = vec![0u8; ENTRY_BUFFER_SIZE];
+    // SAFETY: an all-zero passwd is a valid value for getpwnam_r to fill in
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and the
+    // buffer length matches the buffer
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 || found.is_null() {
+        return Err(anyhow::anyhow!("Unknown user '{user}'"));
+    }
+    Ok(entry.pw_uid)
+}
+
+pub(crate) fn lookup_group(group: &str) -> anyhow::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group)?;
+    let mut buffer = vec![0u8; ENTRY_BUFFER_SIZE];
+    // SAFETY: an all-zero group is a valid value for getgrnam_r to fill in
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and the
+    // buffer length matches the buffer
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 || found.is_null() {
+        return Err(anyhow::anyhow!("Unknown group '{group}'"));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_owner_names_are_resolved() {
+        let root: FileOwner = "root:root".parse().unwrap();
+        assert_eq!((root.uid, root.gid), (Some(0), Some(0)));
+        let user: FileOwner = "root:".parse().unwrap();
+        assert_eq!((user.uid, user.gid), (Some(0), None));
+        assert!("no_such_user_rsonance".parse::<FileOwner>().is_err());
+        assert!(":no_such_group_rsonance".parse::<FileOwner>().is_err());
+    }
+
+    #[test]
+    fn test_apply_access() {
+        let path = std::env::temp_dir().join(format!("rsonance_access_{}", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let path_str = path.to_string_lossy();
+
+        // Our own group can always be set
+        let gid = std::fs::metadata(&path).unwrap().gid();
+        let owner = FileOwner {
+            uid: None,
+            gid: Some(gid),
+        };
+        apply_access(&path_str, Some(owner), Some(FileMode(0o620))).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o620);
+        assert_eq!(metadata.gid(), gid);
+
+        let _ = std::fs::remove_file(&path);
+        assert!(apply_access(&path_str, None, Some(FileMode(0o600))).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Detection of broken capture: sustained clipping and dead silence
+//!
+//! The transmitter runs every captured block through an [`AnomalyDetector`]
+//! and logs a warning as soon as the input has been clipping at full scale
+//! or has been pure digital silence (a muted or disconnected microphone) for
+//! a while, so the problem is noticed within seconds.
+
+use std::fmt;
+
+/// Length of the analysis window in milliseconds
+const WINDOW_MS: u32 = 100;
+
+/// Fraction of samples at full scale that marks a window as clipped
+const CLIPPED_FRACTION: f32 = 0.01;
+
+/// Consecutive clipped windows before clipping is reported (1 s)
+const CLIPPING_WINDOWS: u32 = 10;
+
+/// Consecutive silent windows before silence is reported (5 s)
+const SILENCE_WINDOWS: u32 = 50;
+
+/// Largest sample magnitude still counted as digital silence
+const SILENCE_THRESHOLD: u16 = 1;
+
+/// Kind of capture problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// Input at full scale, the gain is too high
+    Clipping,
+    /// Input is pure digital silence, the microphone is muted or dead
+    Silence,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Anomaly::Clipping => "clipping",
+            Anomaly::Silence => "silence",
+        })
+    }
+}
+
+/// Change in the state of an [`Anomaly`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyEvent {
+    /// The anomaly has persisted long enough to be reported
+    Started(Anomaly),
+    /// The input is back to normal
+    Cleared(Anomaly),
+}
+
+/// Tracks captured S16LE audio for sustained clipping and silence
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::anomaly::{Anomaly, AnomalyDetector, AnomalyEvent};
+///
+/// let mut detector = AnomalyDetector::new(48000, 1);
+/// // Five seconds of zeros
+/// let events = detector.push(&vec![0u8; 48000 * 2 * 5]);
+/// assert_eq!(events, vec![AnomalyEvent::Started(Anomaly::Silence)]);
+/// ```
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    window_samples: usize,
+    /// Samples seen in the current window
+    samples: usize,
+    /// Full-scale samples in the current window
+    clipped: usize,
+    /// Whether every sample in the current window was silent
+    silent: bool,
+    clipped_windows: u32,
+    silent_windows: u32,
+    clean_windows: u32,
+    clipping: bool,
+    silence: bool,
+}
+
+impl AnomalyDetector {
+    /// Create a detector for audio at `sample_rate` Hz with `channels` channels
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let frames = (sample_rate * WINDOW_MS / 1000).max(1) as usize;
+        Self {
+            window_samples: frames * usize::from(channels.max(1)),
+            samples: 0,
+            clipped: 0,
+            silent: true,
+            clipped_windows: 0,
+            silent_windows: 0,
+            clean_windows: 0,
+            clipping: false,
+            silence: false,
+        }
+    }
+
+    /// Analyse a block of S16LE audio, returning any state changes it caused
+    pub fn push(&mut self, s16le: &[u8]) -> Vec<AnomalyEvent> {
+        let mut events = Vec::new();
+        for bytes in s16le.chunks_exact(2) {
+            let magnitude = i16::from_le_bytes([bytes[0], bytes[1]]).unsigned_abs();
+            if magnitude >= i16::MAX as u16 {
+                self.clipped += 1;
+            }
+            if magnitude > SILENCE_THRESHOLD {
+                self.silent = false;
+            }
+            self.samples += 1;
+            if self.samples == self.window_samples {
+                self.finish_window(&mut events);
+            }
+        }
+        events
+    }
+
+    fn finish_window(&mut self, events: &mut Vec<AnomalyEvent>) {
+        let clipped = self.clipped as f32 >= self.samples as f32 * CLIPPED_FRACTION;
+        if clipped {
+            self.clipped_windows += 1;
+            self.clean_windows = 0;
+        } else {
+            self.clipped_windows = 0;
+            self.clean_windows += 1;
+        }
+        if !self.clipping && self.clipped_windows >= CLIPPING_WINDOWS {
+            self.clipping = true;
+            events.push(AnomalyEvent::Started(Anomaly::Clipping));
+        } else if self.clipping && self.clean_windows >= CLIPPING_WINDOWS {
+            self.clipping = false;
+            events.push(AnomalyEvent::Cleared(Anomaly::Clipping));
+        }
+
+        if self.silent {
+            self.silent_windows += 1;
+            if !self.silence && self.silent_windows >= SILENCE_WINDOWS {
+                self.silence = true;
+                events.push(AnomalyEvent::Started(Anomaly::Silence));
+            }
+        } else {
+            self.silent_windows = 0;
+            if self.silence {
+                self.silence = false;
+                events.push(AnomalyEvent::Cleared(Anomaly::Silence));
+            }
+        }
+
+        self.samples = 0;
+        self.clipped = 0;
+        self.silent = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s16le(samples: impl IntoIterator<Item = i16>) -> Vec<u8> {
+        samples.into_iter().flat_map(i16::to_le_bytes).collect()
+    }
+
+    /// One 100 ms window at 1 kHz mono
+    fn window(sample: i16) -> Vec<u8> {
+        s16le(std::iter::repeat_n(sample, 100))
+    }
+
+    #[test]
+    fn test_clipping_starts_and_clears() {
+        let mut detector = AnomalyDetector::new(1000, 1);
+        for _ in 0..CLIPPING_WINDOWS - 1 {
+            assert!(detector.push(&window(i16::MIN)).is_empty());
+        }
+        assert_eq!(
+            detector.push(&window(i16::MAX)),
+            vec![AnomalyEvent::Started(Anomaly::Clipping)]
+        );
+
+        for _ in 0..CLIPPING_WINDOWS - 1 {
+            assert!(detector.push(&window(1000)).is_empty());
+        }
+        assert_eq!(
+            detector.push(&window(1000)),
+            vec![AnomalyEvent::Cleared(Anomaly::Clipping)]
+        );
+    }
+
+    #[test]
+    fn test_occasional_peaks_are_not_clipping() {
+        let mut detector = AnomalyDetector::new(1000, 1);
+        // Less than 1% of samples at full scale
+        let samples: Vec<i16> = (0..1000)
+            .map(|i| if i % 200 == 0 { i16::MAX } else { 1000 })
+            .collect();
+        for _ in 0..20 {
+            assert!(detector.push(&s16le(samples.iter().copied())).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_silence_starts_and_clears_on_signal() {
+        let mut detector = AnomalyDetector::new(1000, 2);
+        let stereo_window = s16le(std::iter::repeat_n(1, 200));
+        for _ in 0..SILENCE_WINDOWS - 1 {
+            assert!(detector.push(&stereo_window).is_empty());
+        }
+        assert_eq!(
+            detector.push(&stereo_window),
+            vec![AnomalyEvent::Started(Anomaly::Silence)]
+        );
+        assert_eq!(
+            detector.push(&s16le([0, 0, 500, 0])),
+            Vec::<AnomalyEvent>::new()
+        );
+        assert_eq!(
+            detector.push(&s16le(std::iter::repeat_n(0, 196))),
+            vec![AnomalyEvent::Cleared(Anomaly::Silence)]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Connection audit log of the receiver
+//!
+//! With `--audit-log <file>` the receiver appends one JSON object per line
+//! for every connection attempt: when a connection is accepted or refused,
+//! and when an accepted one ends, with how long it lasted. The file is only
+//! ever appended to, and each line is written with a single write, so
+//! several receivers can share one log and a crash leaves no partial lines.
+//!
+//! # Format
+//!
+//! ```text
+//! {"time":"2026-01-01T12:00:00.000Z","event":"accepted","connection":1,"listener":"0.0.0.0:8080","peer":"192.0.2.1:50000","identity":null}
+//! {"time":"2026-01-01T12:05:00.000Z","event":"closed","connection":1,"listener":"0.0.0.0:8080","peer":"192.0.2.1:50000","identity":null,"duration_secs":300.000,"bytes_received":52920000,"error":null}
+//! {"time":"2026-01-01T12:05:01.000Z","event":"refused","connection":null,"listener":"0.0.0.0:8080","peer":"192.0.2.9:40000","identity":null,"reason":"banned"}
+//! ```
+//!
+//! `event` is `accepted`, `refused` (with `reason` `rate_limited`, `banned`,
+//! `error`, or `busy` once the stream header shows another transmitter is
+//! streaming) or, once an accepted connection ends, `closed`, `malformed`
+//! (an invalid stream) or `failed` (with `error`). `peer` is null for Unix
+//! socket connections, whose `identity` gives the connecting process as
+//! `{"uid":1000,"gid":1000,"pid":4242}`. TCP connections carry no identity.
+
+use crate::listen::PeerCredentials;
+use log::error;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What happened to a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    /// The connection was accepted
+    Accepted,
+    /// The connection was closed at once, for the reason given
+    Refused(&'static str),
+    /// An accepted connection ended normally
+    Closed(ConnectionSummary),
+    /// An accepted connection ended with an invalid stream
+    Malformed(ConnectionSummary, String),
+    /// An accepted connection ended with another error
+    Failed(ConnectionSummary, String),
+}
+
+/// Length and traffic of a connection that has ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionSummary {
+    pub duration: Duration,
+    pub bytes_received: u64,
+}
+
+/// A connection attempt as recorded in the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Connection id, as in the receiver's log lines (none if refused)
+    pub connection: Option<u64>,
+    /// Listener the connection arrived on
+    pub listener: String,
+    /// Remote address of a TCP connection
+    pub peer: Option<SocketAddr>,
+    /// Process on the other end of a Unix socket connection
+    pub identity: Option<PeerCredentials>,
+    pub event: AuditEvent,
+}
+
+impl AuditRecord {
+    /// The record as a line of JSON, stamped with `time`
+    pub fn to_json(&self, time: SystemTime) -> String {
+        let mut line = format!("{{\"time\":\"{}\"", utc_timestamp(time));
+        let event = match &self.event {
+            AuditEvent::Accepted => "accepted",
+            AuditEvent::Refused(_) => "refused",
+            AuditEvent::Closed(_) => "closed",
+            AuditEvent::Malformed(..) => "malformed",
+            AuditEvent::Failed(..) => "failed",
+        };
+        let _ = write!(line, ",\"event\":\"{event}\",\"connection\":");
+        match self.connection {
+            Some(id) => {
+                let _ = write!(line, "{id}");
+            }
+            None => line.push_str("null"),
+        }
+        let _ = write!(
+            line,
+            ",\"listener\":{},\"peer\":",
+            json_string(&self.listener)
+        );
+        match self.peer {
+            Some(peer) => line.push_str(&json_string(&peer.to_string())),
+            None => line.push_str("null"),
+        }
+        line.push_str(",\"identity\":");
+        match &self.identity {
+            Some(PeerCredentials { uid, gid, pid }) => {
+                let _ = write!(line, "{{\"uid\":{uid},\"gid\":{gid},\"pid\":{pid}}}");
+            }
+            None => line.push_str("null"),
+        }
+        match &self.event {
+            AuditEvent::Accepted => {}
+            AuditEvent::Refused(reason) => {
+                let _ = write!(line, ",\"reason\":{}", json_string(reason));
+            }
+            AuditEvent::Closed(summary) => push_summary(&mut line, summary, None),
+            AuditEvent::Malformed(summary, error) | AuditEvent::Failed(summary, error) => {
+                push_summary(&mut line, summary, Some(error))
+            }
+        }
+        line.push('}');
+        line
+    }
+}
+
+fn push_summary(line: &mut String, summary: &ConnectionSummary, error: Option<&str>) {
+    let _ = write!(
+        line,
+        ",\"duration_secs\":{:.3},\"bytes_received\":{},\"error\":{}",
+        summary.duration.as_secs_f64(),
+        summary.bytes_received,
+        error.map_or_else(|| "null".to_string(), json_string)
+    );
+}
+
+/// Appends [`AuditRecord`]s to the audit log file
+#[derive(Debug)]
+pub struct AuditLog {
+    path: String,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open the log at `path` for appending, creating it readable by its
+    /// owner only
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open audit log {path}: {e}"))?;
+        Ok(Self {
+            path: path.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append `record`, stamped with the current time
+    ///
+    /// A write error is logged rather than returned: the connection it
+    /// describes has already been accepted or refused.
+    pub fn record(&self, record: &AuditRecord) {
+        let mut line = record.to_json(SystemTime::now());
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            error!("Failed to write to audit log {}: {e}", self.path);
+        }
+    }
+}
+
+/// `time` in UTC as `YYYY-MM-DDTHH:MM:SS.mmmZ`
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// `s` as a quoted JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |secs: u64, millis: u64| UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis);
+        assert_eq!(utc_timestamp(at(0, 0)), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            utc_timestamp(at(951_827_696, 7)),
+            "2000-02-29T12:34:56.007Z"
+        );
+        assert_eq!(
+            utc_timestamp(at(1_798_761_599, 999)),
+            "2026-12-31T23:59:59.999Z"
+        );
+    }
+
+    #[test]
+    fn test_record_json() {
+        let time = UNIX_EPOCH + Duration::from_secs(86400);
+        let mut record = AuditRecord {
+            connection: Some(3),
+            listener: "unix:/run/a\"b.sock".to_string(),
+            peer: None,
+            identity: Some(PeerCredentials {
+                uid: 1000,
+                gid: 100,
+                pid: 42,
+            }),
+            event: AuditEvent::Accepted,
+        };
+        assert_eq!(
+            record.to_json(time),
+            r#"{"time":"1970-01-02T00:00:00.000Z","event":"accepted","connection":3,"listener":"unix:/run/a\"b.sock","peer":null,"identity":{"uid":1000,"gid":100,"pid":42}}"#
+        );
+
+        record.identity = None;
+        record.peer = Some("192.0.2.1:5000".parse().unwrap());
+        record.event = AuditEvent::Malformed(
+            ConnectionSummary {
+                duration: Duration::from_millis(1500),
+                bytes_received: 14,
+            },
+            "bad\nheader".to_string(),
+        );
+        assert!(record.to_json(time).ends_with(
+            r#""peer":"192.0.2.1:5000","identity":null,"duration_secs":1.500,"bytes_received":14,"error":"bad\nheader"}"#
+        ));
+    }
+}
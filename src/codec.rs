@@ -0,0 +1,515 @@
+//! Wire encodings for the audio stream
+//!
+//! The default encoding is raw S16LE at the capture rate and channel count.
+//! S24LE keeps 24 bits per sample (packed, 3 bytes) from capture to the
+//! virtual microphone, for feeding pro-audio software, and F32LE sends the
+//! 32-bit float samples of devices that capture in float unchanged. G.711 (µ-law and A-law) streams 8 kHz mono at one byte per sample, 64 kbps,
+//! for bridging into telephony equipment. The transmitter announces its codec
+//! in the stream header (see [`crate::protocol`]); the receiver's `--codec`
+//! only applies to raw streams, which carry no header.
+
+use crate::{AudioConfig, AudioFormat};
+use std::fmt;
+use std::str::FromStr;
+
+/// Sample rate of G.711 streams in Hz
+pub const G711_SAMPLE_RATE: u32 = 8000;
+
+/// Encoding of audio on the wire
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::Codec;
+///
+/// let codec: Codec = "g711u".parse().unwrap();
+/// assert_eq!(codec, Codec::G711U);
+/// assert_eq!(codec.to_string(), "g711u");
+/// assert_eq!(codec.audio_config().sample_rate, 8000);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// Signed 16-bit little-endian PCM at the capture rate and channel count
+    #[default]
+    S16LE,
+    /// Signed 24-bit little-endian PCM, packed in 3 bytes per sample, at the
+    /// capture rate and channel count
+    S24LE,
+    /// 32-bit float little-endian PCM at the capture rate and channel count
+    F32LE,
+    /// G.711 µ-law, 8 kHz mono
+    G711U,
+    /// G.711 A-law, 8 kHz mono
+    G711A,
+}
+
+impl Codec {
+    /// Short name as accepted by `--codec`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::S16LE => "s16le",
+            Codec::S24LE => "s24le",
+            Codec::F32LE => "f32le",
+            Codec::G711U => "g711u",
+            Codec::G711A => "g711a",
+        }
+    }
+
+    /// Size of one encoded sample in bytes
+    pub fn sample_size(&self) -> usize {
+        match self {
+            Codec::S16LE => 2,
+            Codec::S24LE => 3,
+            Codec::F32LE => 4,
+            Codec::G711U | Codec::G711A => 1,
+        }
+    }
+
+    /// Size in bytes of one captured sample passed to the [`Encoder`]
+    ///
+    /// Audio is captured as S24LE for the S24LE codec, as F32LE for the
+    /// F32LE codec and as S16LE otherwise.
+    pub fn capture_sample_size(&self) -> usize {
+        match self {
+            Codec::S24LE => 3,
+            Codec::F32LE => 4,
+            Codec::S16LE | Codec::G711U | Codec::G711A => 2,
+        }
+    }
+
+    /// Frame rate in Hz and frame size in bytes of the encoded stream
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_rate` - Sample rate of the PCM audio being encoded
+    /// * `channels` - Channel count of the PCM audio being encoded
+    pub fn wire_format(&self, sample_rate: u32, channels: u16) -> (u32, usize) {
+        match self {
+            Codec::S16LE | Codec::S24LE | Codec::F32LE => (
+                sample_rate,
+                usize::from(channels.max(1)) * self.sample_size(),
+            ),
+            Codec::G711U | Codec::G711A => (G711_SAMPLE_RATE, 1),
+        }
+    }
+
+    /// PCM format the receiver feeds to the virtual microphone after decoding
+    /// a raw stream, which does not announce its rate and channel count
+    pub fn audio_config(&self) -> AudioConfig {
+        let default = AudioConfig::default();
+        self.decoded_config(default.sample_rate, default.channels)
+    }
+
+    /// PCM format of the audio after decoding, for audio captured at
+    /// `sample_rate` Hz with `channels` channels
+    pub fn decoded_config(&self, sample_rate: u32, channels: u16) -> AudioConfig {
+        match self {
+            Codec::S16LE => AudioConfig {
+                sample_rate,
+                channels,
+                format: AudioFormat::S16LE,
+            },
+            Codec::S24LE => AudioConfig {
+                sample_rate,
+                channels,
+                format: AudioFormat::S24LE,
+            },
+            Codec::F32LE => AudioConfig {
+                sample_rate,
+                channels,
+                format: AudioFormat::F32LE,
+            },
+            Codec::G711U | Codec::G711A => AudioConfig {
+                sample_rate: G711_SAMPLE_RATE,
+                channels: 1,
+                format: AudioFormat::S16LE,
+            },
+        }
+    }
+
+    /// Decode received bytes to the format of [`Codec::audio_config`],
+    /// appending to `out`
+    ///
+    /// PCM input is copied unchanged; G.711 is expanded to S16LE.
+    pub fn decode(&self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Codec::S16LE | Codec::S24LE | Codec::F32LE => out.extend_from_slice(data),
+            Codec::G711U => {
+                for &byte in data {
+                    out.extend_from_slice(&ulaw_to_linear(byte).to_le_bytes());
+                }
+            }
+            Codec::G711A => {
+                for &byte in data {
+                    out.extend_from_slice(&alaw_to_linear(byte).to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s16le" => Ok(Codec::S16LE),
+            "s24le" => Ok(Codec::S24LE),
+            "f32le" => Ok(Codec::F32LE),
+            "g711u" => Ok(Codec::G711U),
+            "g711a" => Ok(Codec::G711A),
+            _ => Err(anyhow::anyhow!(
+                "Unknown codec '{s}' (expected s16le, s24le, f32le, g711u, or g711a)"
+            )),
+        }
+    }
+}
+
+/// Stateful encoder turning captured audio into the wire encoding
+///
+/// Captured audio is S24LE for the S24LE codec, F32LE for the F32LE codec
+/// and S16LE otherwise, see [`Codec::capture_sample_size`]. PCM codecs pass
+/// it through unchanged.
+///
+/// For G.711, audio is mixed down to mono and decimated to 8 kHz by averaging
+/// the input frames that fall into each output sample period. The averaging
+/// doubles as a (simple) anti-aliasing filter; state carries over between
+/// calls so packet boundaries do not matter.
+#[derive(Debug)]
+pub struct Encoder {
+    codec: Codec,
+    channels: usize,
+    input_rate: u32,
+    /// Progress toward the next output sample, in units of 1 / input_rate seconds
+    phase: u32,
+    sum: i64,
+    count: i64,
+}
+
+impl Encoder {
+    /// Create an encoder for audio captured at `input_rate` Hz with `channels` channels
+    pub fn new(codec: Codec, input_rate: u32, channels: u16) -> Self {
+        Self {
+            codec,
+            channels: usize::from(channels.max(1)),
+            input_rate: input_rate.max(1),
+            phase: 0,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    /// Encode a block of interleaved captured audio
+    ///
+    /// PCM input is returned unchanged. For G.711 a trailing partial frame
+    /// is ignored.
+    pub fn encode(&mut self, s16le: Vec<u8>) -> Vec<u8> {
+        let compress = match self.codec {
+            Codec::S16LE | Codec::S24LE | Codec::F32LE => return s16le,
+            Codec::G711U => linear_to_ulaw,
+            Codec::G711A => linear_to_alaw,
+        };
+
+        let frame_size = self.channels * 2;
+        let mut out = Vec::with_capacity(
+            s16le.len() / frame_size * G711_SAMPLE_RATE as usize / self.input_rate as usize + 1,
+        );
+        for frame in s16le.chunks_exact(frame_size) {
+            let mono: i64 = frame
+                .chunks_exact(2)
+                .map(|bytes| i64::from(i16::from_le_bytes([bytes[0], bytes[1]])))
+                .sum();
+            self.sum += mono / self.channels as i64;
+            self.count += 1;
+
+            self.phase += G711_SAMPLE_RATE;
+            if self.phase >= self.input_rate {
+                self.phase -= self.input_rate;
+                out.push(compress((self.sum / self.count) as i16));
+                self.sum = 0;
+                self.count = 0;
+            }
+        }
+        out
+    }
+}
+
+/// Convert packed S24LE samples to S16LE, keeping the 16 most significant bits
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::s24le_to_s16le;
+///
+/// // 0x123456 and -1
+/// assert_eq!(s24le_to_s16le(&[0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF]), vec![0x34, 0x12, 0xFF, 0xFF]);
+/// ```
+pub fn s24le_to_s16le(s24le: &[u8]) -> Vec<u8> {
+    s24le
+        .chunks_exact(3)
+        .flat_map(|sample| [sample[1], sample[2]])
+        .collect()
+}
+
+/// Convert S16LE samples to packed S24LE, leaving the low 8 bits zero
+pub fn s16le_to_s24le(s16le: &[u8]) -> Vec<u8> {
+    s16le
+        .chunks_exact(2)
+        .flat_map(|sample| [0, sample[0], sample[1]])
+        .collect()
+}
+
+/// Convert PCM between S16LE, packed S24LE and F32LE, given as their
+/// sample sizes of 2, 3 and 4 bytes
+///
+/// Integer samples are scaled to -1.0 to 1.0 as floats; floats are clamped
+/// to that range before scaling back. Converting 24 to 16 bits keeps the
+/// most significant bits.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::convert_pcm;
+///
+/// let f32le = convert_pcm(&(-16384i16).to_le_bytes(), 2, 4);
+/// assert_eq!(f32le, (-0.5f32).to_le_bytes());
+/// assert_eq!(convert_pcm(&f32le, 4, 2), (-16384i16).to_le_bytes());
+/// ```
+pub fn convert_pcm(pcm: &[u8], from: usize, to: usize) -> Vec<u8> {
+    match (from, to) {
+        (3, 2) => s24le_to_s16le(pcm),
+        (2, 3) => s16le_to_s24le(pcm),
+        (2, 4) => pcm
+            .chunks_exact(2)
+            .flat_map(|s| (f32::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0).to_le_bytes())
+            .collect(),
+        (3, 4) => pcm
+            .chunks_exact(3)
+            .flat_map(|s| {
+                ((i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f32 / 8_388_608.0)
+                    .to_le_bytes()
+            })
+            .collect(),
+        (4, 2 | 3) => pcm
+            .chunks_exact(4)
+            .flat_map(|s| {
+                let value = f32::from_le_bytes([s[0], s[1], s[2], s[3]]).clamp(-1.0, 1.0);
+                let sample = (value * 8_388_608.0).clamp(-8_388_608.0, 8_388_607.0) as i32;
+                sample.to_le_bytes()[3 - to..3].to_vec()
+            })
+            .collect(),
+        _ => pcm.to_vec(),
+    }
+}
+
+/// Find the G.711 segment of `value`: the index of the first end point it does not exceed
+fn segment(value: i32, ends: &[i32; 8]) -> usize {
+    ends.iter().position(|&end| value <= end).unwrap_or(8)
+}
+
+/// Segment end points for µ-law, on 14-bit magnitudes
+const ULAW_SEGMENT_ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+
+/// Segment end points for A-law, on 13-bit magnitudes
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+/// µ-law bias added before segment search (0x84, scaled to 14 bits)
+const ULAW_BIAS: i32 = 0x84;
+
+/// Largest 14-bit magnitude encodable in µ-law
+const ULAW_CLIP: i32 = 8159;
+
+/// Compress a 16-bit sample to µ-law (ITU-T G.711)
+pub fn linear_to_ulaw(sample: i16) -> u8 {
+    let mut value = i32::from(sample) >> 2;
+    let mask: i32 = if value < 0 {
+        value = -value;
+        0x7F
+    } else {
+        0xFF
+    };
+    value = value.min(ULAW_CLIP) + (ULAW_BIAS >> 2);
+
+    let seg = segment(value, &ULAW_SEGMENT_ENDS);
+    if seg >= 8 {
+        return (0x7F ^ mask) as u8;
+    }
+    let ulaw = ((seg as i32) << 4) | ((value >> (seg + 1)) & 0x0F);
+    (ulaw ^ mask) as u8
+}
+
+/// Expand a µ-law byte to a 16-bit sample
+pub fn ulaw_to_linear(ulaw: u8) -> i16 {
+    let ulaw = !ulaw;
+    let magnitude = ((i32::from(ulaw & 0x0F) << 3) + ULAW_BIAS) << ((ulaw & 0x70) >> 4);
+    if ulaw & 0x80 != 0 {
+        (ULAW_BIAS - magnitude) as i16
+    } else {
+        (magnitude - ULAW_BIAS) as i16
+    }
+}
+
+/// Compress a 16-bit sample to A-law (ITU-T G.711)
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let mut value = i32::from(sample) >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+
+    let seg = segment(value, &ALAW_SEGMENT_ENDS);
+    if seg >= 8 {
+        return (0x7F ^ mask) as u8;
+    }
+    let shift = if seg < 2 { 1 } else { seg };
+    let alaw = ((seg as i32) << 4) | ((value >> shift) & 0x0F);
+    (alaw ^ mask) as u8
+}
+
+/// Expand an A-law byte to a 16-bit sample
+pub fn alaw_to_linear(alaw: u8) -> i16 {
+    let alaw = alaw ^ 0x55;
+    let mut magnitude = i32::from(alaw & 0x0F) << 4;
+    match (alaw & 0x70) >> 4 {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        seg => magnitude = (magnitude + 0x108) << (seg - 1),
+    }
+    if alaw & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_parse_and_display() {
+        for codec in [
+            Codec::S16LE,
+            Codec::S24LE,
+            Codec::F32LE,
+            Codec::G711U,
+            Codec::G711A,
+        ] {
+            assert_eq!(codec.to_string().parse::<Codec>().unwrap(), codec);
+        }
+        assert!("opus".parse::<Codec>().is_err());
+        assert_eq!(Codec::default(), Codec::S16LE);
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!(Codec::S16LE.wire_format(48000, 2), (48000, 4));
+        assert_eq!(Codec::S24LE.wire_format(48000, 2), (48000, 6));
+        assert_eq!(Codec::S24LE.audio_config().frame_size(), 6);
+        assert_eq!(Codec::G711A.wire_format(48000, 2), (8000, 1));
+    }
+
+    #[test]
+    fn test_ulaw_reference_values() {
+        assert_eq!(linear_to_ulaw(0), 0xFF);
+        assert_eq!(ulaw_to_linear(0xFF), 0);
+        assert_eq!(ulaw_to_linear(0x80), 32124);
+        assert_eq!(ulaw_to_linear(0x00), -32124);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn test_alaw_reference_values() {
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(alaw_to_linear(0xD5), 8);
+        assert_eq!(alaw_to_linear(0x55), -8);
+        assert_eq!(alaw_to_linear(0xAA), 32256);
+        assert_eq!(alaw_to_linear(0x2A), -32256);
+    }
+
+    #[test]
+    fn test_g711_round_trip_error_is_bounded() {
+        // Quantisation error grows with magnitude but stays within ~1/16 of the value
+        for sample in (i16::MIN..=i16::MAX).step_by(7) {
+            let tolerance = (i32::from(sample).abs() / 16).max(16);
+            let ulaw = i32::from(ulaw_to_linear(linear_to_ulaw(sample)));
+            let alaw = i32::from(alaw_to_linear(linear_to_alaw(sample)));
+            assert!(
+                (ulaw - i32::from(sample)).abs() <= tolerance,
+                "µ-law {sample}"
+            );
+            assert!(
+                (alaw - i32::from(sample)).abs() <= tolerance,
+                "A-law {sample}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encoder_passes_s16le_through() {
+        let mut encoder = Encoder::new(Codec::S16LE, 48000, 2);
+        assert_eq!(encoder.encode(vec![1, 2, 3, 4]), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_encoder_downmixes_and_decimates() {
+        // 48 kHz stereo: every 6 input frames produce one 8 kHz sample
+        let mut encoder = Encoder::new(Codec::G711U, 48000, 2);
+        let frame: Vec<u8> = [1000i16, 3000]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        let first = encoder.encode(frame.repeat(9));
+        assert_eq!(first.len(), 1);
+        // The 3 leftover frames carry over into the next call
+        let second = encoder.encode(frame.repeat(3));
+        assert_eq!(second.len(), 1);
+
+        let decoded = i32::from(ulaw_to_linear(first[0]));
+        assert!((decoded - 2000).abs() <= 2000 / 16);
+    }
+
+    #[test]
+    fn test_s24le_conversions() {
+        let s16le = [0x34, 0x12, 0x00, 0x80];
+        let s24le = s16le_to_s24le(&s16le);
+        assert_eq!(s24le, vec![0, 0x34, 0x12, 0, 0x00, 0x80]);
+        assert_eq!(s24le_to_s16le(&s24le), s16le);
+        assert_eq!(convert_pcm(&s24le, 3, 2), s16le);
+
+        let f32le = convert_pcm(&s24le, 3, 4);
+        let floats: Vec<f32> = f32le
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+            .collect();
+        assert_eq!(floats, [f32::from(0x1234i16) / 32768.0, -1.0]);
+        assert_eq!(convert_pcm(&f32le, 4, 3), s24le);
+        assert_eq!(convert_pcm(&f32le, 4, 2), s16le);
+        // Out of range floats are clamped
+        assert_eq!(
+            convert_pcm(&2.0f32.to_le_bytes(), 4, 2),
+            i16::MAX.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_decode_g711() {
+        let mut out = Vec::new();
+        Codec::G711A.decode(&[0xD5, 0x55], &mut out);
+        assert_eq!(out, [8i16.to_le_bytes(), (-8i16).to_le_bytes()].concat());
+
+        out.clear();
+        Codec::S16LE.decode(&[1, 2], &mut out);
+        assert_eq!(out, vec![1, 2]);
+    }
+}
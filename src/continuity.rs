@@ -0,0 +1,308 @@
+//! Seamless reconnects: audio replayed by the transmitter is written once
+//!
+//! After a reconnect the transmitter replays its resend buffer, the last
+//! `--resend-ms` of audio it sent. Part of that usually reached the receiver
+//! over the old connection already and would be heard twice. The stream
+//! carries no sequence numbers, but the replay repeats the sent bytes
+//! exactly, so the receiver keeps the tail of the stream in a
+//! [`StreamHistory`] and matches the start of each new connection against
+//! it with an [`OverlapTrimmer`]. The part of the replay that was already
+//! received is dropped; the rest fills the gap the old connection left.
+//!
+//! Only a replay by the same transmitter is looked for: a stream with
+//! another client id (see [`crate::protocol::client_id`]) starts afresh.
+//! Silence, or any audio that repeats one frame over and over, matches
+//! itself wherever it is cut, so an overlap has to hold at least two
+//! different frames to count.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+
+/// Shortest overlap in milliseconds that is trusted to be a replay rather
+/// than a chance match
+pub const MIN_OVERLAP_MS: u64 = 5;
+
+/// The most recent bytes of the received stream, shared by all connections
+#[derive(Debug, Default)]
+pub struct StreamHistory {
+    inner: Mutex<History>,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    data: VecDeque<u8>,
+    capacity: usize,
+    /// Bytes pushed since the receiver started
+    total: u64,
+    /// Client id of the stream the bytes came from, `None` for raw streams
+    source: Option<u64>,
+}
+
+impl StreamHistory {
+    /// Keep up to `capacity` bytes (0 keeps nothing)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(History {
+                data: VecDeque::with_capacity(capacity),
+                capacity,
+                total: 0,
+                source: None,
+            }),
+        }
+    }
+
+    /// Append bytes that were received, in stream order
+    pub fn push(&self, bytes: &[u8]) {
+        let mut history = self.lock();
+        history.total += bytes.len() as u64;
+        let capacity = history.capacity;
+        let keep = bytes.len().min(capacity);
+        let excess = (history.data.len() + keep).saturating_sub(capacity);
+        history.data.drain(..excess);
+        history.data.extend(&bytes[bytes.len() - keep..]);
+    }
+
+    /// Start trimming a new connection from the transmitter with client id
+    /// `source` against the history, or `None` if there is too little
+    /// history to match
+    ///
+    /// The history of another transmitter is discarded instead. A partial
+    /// frame the previous connection ended with is dropped, as the replay
+    /// starts on a frame boundary.
+    pub fn trimmer(
+        &self,
+        source: Option<u64>,
+        frame_size: usize,
+        min_overlap: usize,
+    ) -> Option<OverlapTrimmer> {
+        let mut history = self.lock();
+        if history.source != source {
+            history.data.clear();
+            history.source = source;
+            return None;
+        }
+        let partial = (history.total % frame_size.max(1) as u64) as usize;
+        let partial = partial.min(history.data.len());
+        let len = history.data.len() - partial;
+        history.data.truncate(len);
+        history.total -= partial as u64;
+        let start = history.total - history.data.len() as u64;
+        OverlapTrimmer::new(
+            history.data.iter().copied().collect(),
+            start,
+            frame_size,
+            min_overlap,
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, History> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Finds where a new connection's stream continues the previous one
+///
+/// Received bytes are held back while they might still be a repeat of the
+/// history. The longest frame-aligned tail of the history, of at least the
+/// minimum length and not a single frame repeated, that the new stream
+/// starts with is treated as replayed and dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::continuity::StreamHistory;
+///
+/// let history = StreamHistory::new(8);
+/// history.push(&[1, 2, 3, 4, 5, 6]);
+///
+/// // The new connection replays 3 to 6 before continuing with 7, 8
+/// let mut trimmer = history.trimmer(None, 2, 2).unwrap();
+/// assert_eq!(trimmer.push(&[3, 4, 5, 6, 7, 8]), Some((vec![7, 8], 4)));
+///
+/// // A new stream is passed through unchanged
+/// let mut trimmer = history.trimmer(None, 2, 2).unwrap();
+/// assert_eq!(trimmer.push(&[9, 9]), Some((vec![9, 9], 0)));
+/// ```
+#[derive(Debug)]
+pub struct OverlapTrimmer {
+    history: Vec<u8>,
+    /// Offsets into `history` where the new stream may start, ascending
+    candidates: Vec<usize>,
+    held: Vec<u8>,
+}
+
+impl OverlapTrimmer {
+    /// `start` is the stream position of the first history byte, so
+    /// candidates stay on frame boundaries
+    fn new(history: Vec<u8>, start: u64, frame_size: usize, min_overlap: usize) -> Option<Self> {
+        let frame_size = frame_size.max(1);
+        let last = history.len().checked_sub(min_overlap.max(1))?;
+        let aligned = |offset: &usize| (start + *offset as u64).is_multiple_of(frame_size as u64);
+        // Tails starting after the last frame that differs from the final
+        // one repeat a single frame
+        let final_frame = &history[history.len().saturating_sub(frame_size)..];
+        let varied_until = (0..history.len().saturating_sub(frame_size))
+            .rev()
+            .filter(aligned)
+            .find(|&offset| history[offset..offset + frame_size] != *final_frame);
+        let candidates = match varied_until {
+            Some(until) => (0..=last.min(until)).filter(aligned).collect(),
+            None => Vec::new(),
+        };
+        Some(Self {
+            history,
+            candidates,
+            held: Vec::new(),
+        })
+    }
+
+    /// Take in bytes from the new connection
+    ///
+    /// # Returns
+    ///
+    /// `None` while the bytes may still be a replay, then the held bytes
+    /// without the replayed part, together with the number of bytes dropped.
+    /// Later bytes need no trimming.
+    pub fn push(&mut self, data: &[u8]) -> Option<(Vec<u8>, usize)> {
+        let checked = self.held.len();
+        self.held.extend_from_slice(data);
+        let (history, held) = (&self.history, &self.held);
+        self.candidates.retain(|&offset| {
+            let tail = &history[offset..];
+            let end = held.len().min(tail.len());
+            checked >= end || held[checked..end] == tail[checked..end]
+        });
+
+        // The longest possible overlap decides once it is confirmed
+        match self.candidates.first() {
+            None => Some((std::mem::take(&mut self.held), 0)),
+            Some(&offset) if self.held.len() >= self.history.len() - offset => {
+                Some(self.take(self.history.len() - offset))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// The held bytes when the connection ends before a decision, without
+    /// the longest overlap confirmed so far
+    pub fn finish(mut self) -> (Vec<u8>, usize) {
+        let held = self.held.len();
+        let overlap = self
+            .candidates
+            .iter()
+            .map(|&offset| self.history.len() - offset)
+            .find(|&overlap| overlap <= held)
+            .unwrap_or(0);
+        self.take(overlap)
+    }
+
+    fn take(&mut self, overlap: usize) -> (Vec<u8>, usize) {
+        (self.held.split_off(overlap), overlap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(range: std::ops::Range<u32>) -> Vec<u8> {
+        range.map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_history_keeps_the_tail() {
+        let history = StreamHistory::new(4);
+        history.push(&[1, 2, 3]);
+        history.push(&[4, 5, 6]);
+        let trimmer = history.trimmer(None, 1, 1).unwrap();
+        assert_eq!(trimmer.history, vec![3, 4, 5, 6]);
+        // A single frame proves no replay
+        assert_eq!(trimmer.candidates, vec![0, 1, 2]);
+
+        assert!(StreamHistory::new(0).trimmer(None, 1, 1).is_none());
+        assert!(StreamHistory::new(4).trimmer(None, 1, 1).is_none());
+    }
+
+    #[test]
+    fn test_replay_overlap_is_dropped_across_reads() {
+        let history = StreamHistory::new(1000);
+        history.push(&stream(0..600));
+
+        // The replay starts 200 bytes before the end of the history and
+        // goes on with bytes the old connection lost
+        let mut trimmer = history.trimmer(None, 4, 20).unwrap();
+        let replay = stream(400..700);
+        assert_eq!(trimmer.push(&replay[..150]), None);
+        let (rest, overlap) = trimmer.push(&replay[150..]).unwrap();
+        assert_eq!(overlap, 200);
+        assert_eq!(rest, stream(600..700));
+    }
+
+    #[test]
+    fn test_unrelated_stream_passes_through() {
+        let history = StreamHistory::new(100);
+        history.push(&stream(0..100));
+        let mut trimmer = history.trimmer(None, 4, 8).unwrap();
+        assert_eq!(
+            trimmer.push(&[9, 9, 9, 9, 9]),
+            Some((vec![9, 9, 9, 9, 9], 0))
+        );
+    }
+
+    #[test]
+    fn test_overlap_stays_frame_aligned() {
+        let history = StreamHistory::new(100);
+        // The old connection broke off one byte into a 2-byte frame, and the
+        // history starts in the middle of a frame
+        history.push(&stream(0..101));
+        let mut trimmer = history.trimmer(None, 2, 2).unwrap();
+        assert_eq!(trimmer.history, stream(1..100));
+        // A match starting mid-frame is not taken
+        assert_eq!(trimmer.push(&stream(97..100)), Some((stream(97..100), 0)));
+        let mut trimmer = history.trimmer(None, 2, 2).unwrap();
+        assert_eq!(trimmer.push(&stream(96..101)), Some((stream(100..101), 4)));
+    }
+
+    #[test]
+    fn test_finish_keeps_unconfirmed_bytes() {
+        let history = StreamHistory::new(100);
+        history.push(&stream(0..100));
+        let mut trimmer = history.trimmer(None, 1, 4).unwrap();
+        // Could still be a replay from offset 50 when the connection ends
+        assert_eq!(trimmer.push(&stream(50..60)), None);
+        assert_eq!(trimmer.finish(), (stream(50..60), 0));
+
+        // A repeating history: the last 4 bytes are confirmed to be
+        // replayed, all 8 are not
+        let history = StreamHistory::new(8);
+        history.push(&[1, 2, 3, 4, 1, 2, 3, 4]);
+        let mut trimmer = history.trimmer(None, 1, 4).unwrap();
+        assert_eq!(trimmer.push(&[1, 2, 3, 4]), None);
+        assert_eq!(trimmer.finish(), (vec![], 4));
+    }
+
+    #[test]
+    fn test_silence_and_other_transmitters_are_not_matched() {
+        // A stream that ended in silence, and a new one starting silent
+        let history = StreamHistory::new(100);
+        let mut ended = stream(0..50);
+        ended.extend([0; 50]);
+        history.push(&ended);
+        let mut trimmer = history.trimmer(None, 2, 4).unwrap();
+        assert_eq!(trimmer.push(&[0; 60]), Some((vec![0; 60], 0)));
+
+        // Audio before the silence is still matched
+        let mut trimmer = history.trimmer(None, 2, 4).unwrap();
+        let mut replay = ended[40..].to_vec();
+        replay.extend([1, 2]);
+        assert_eq!(trimmer.push(&replay), Some((vec![1, 2], 60)));
+
+        // The history of another transmitter is dropped
+        let history = StreamHistory::new(100);
+        history.push(&stream(0..100));
+        assert!(history.trimmer(Some(7), 2, 4).is_none());
+        history.push(&stream(0..10));
+        let mut trimmer = history.trimmer(Some(7), 2, 4).unwrap();
+        assert_eq!(trimmer.push(&stream(0..10)), Some((vec![], 10)));
+    }
+}
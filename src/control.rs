@@ -0,0 +1,578 @@
+//! Local control socket for inspecting and managing a running receiver
+//!
+//! The receiver listens on a Unix domain socket and answers one text command
+//! per connection, after which it closes the connection:
+//!
+//! - `clients` - one line per connected transmitter, with the name and tags
+//!   it sent (see [`ClientInfo`])
+//! - `session <id>` - the stream parameters of a transmitter (see
+//!   [`SessionSummary`])
+//! - `kick <id>` - disconnect the transmitter with the given connection id
+//! - `spectrum <id>` - per-band levels of a transmitter's stream, with
+//!   `--spectrum` (see [`Spectrum`])
+//! - `health` - the receiver's health checks (see [`crate::health`])
+//! - `mark <label>` - add a marker to the file being written by `--record`
+//!   (see [`Recorder::mark`]) and to the manifest of the `--debug-dump`
+//!   recording (see [`crate::manifest`]), and answer its offset in seconds
+//!   into the `--record` file, or into the dump without one
+//!
+//! Failures are answered with a single `ERR <message>` line.
+
+use crate::health::{HealthCheck, HealthProbe};
+use crate::manifest::RecordingManifest;
+use crate::protocol::{Metadata, parse_tag};
+use crate::receiver::ClientRegistry;
+use crate::record::Recorder;
+use crate::session::SessionSummary;
+use crate::spectrum::Spectrum;
+use log::{debug, error, info};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Default path of the receiver control socket
+pub const DEFAULT_CONTROL_SOCKET: &str = "/tmp/rsonance_control.sock";
+
+/// Longest a command waits for the receiver's answer, and the receiver for
+/// the command
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest command read, far above any valid one
+const MAX_COMMAND_LEN: u64 = 4096;
+
+/// A transmitter connected to the receiver, as reported by the control socket
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::control::ClientInfo;
+/// use rsonance::protocol::Metadata;
+/// use std::time::Duration;
+///
+/// let info = ClientInfo {
+///     id: 3,
+///     peer: Some("192.168.1.20:51234".parse().unwrap()),
+///     codec: "s16le".to_string(),
+///     connected_for: Duration::from_secs(90),
+///     bytes_received: 15_876_000,
+///     socket_queue_ms: 4,
+///     output_queue_ms: 120,
+///     metadata: Metadata {
+///         name: Some("Stage left".to_string()),
+///         tags: vec![("room".to_string(), "hall".to_string())],
+///     },
+/// };
+/// assert_eq!(ClientInfo::from_line(&info.to_line()).unwrap(), info);
+/// assert_eq!(info.average_bitrate(), 1_411_200.0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+    /// Connection id, as shown in receiver log lines
+    pub id: u64,
+    /// Remote address of the transmitter, if known
+    pub peer: Option<SocketAddr>,
+    /// Audio encoding of the stream (e.g. "s16le")
+    pub codec: String,
+    /// Time since the transmitter connected
+    pub connected_for: Duration,
+    /// Bytes received from the transmitter since it connected
+    pub bytes_received: u64,
+    /// Audio received but not read from the socket yet, in milliseconds
+    pub socket_queue_ms: u64,
+    /// Audio written to the output but not played yet, in milliseconds
+    pub output_queue_ms: u64,
+    /// Name and tags the transmitter sent, see [`Metadata`]
+    pub metadata: Metadata,
+}
+
+impl ClientInfo {
+    /// Average bitrate over the lifetime of the connection, in bits per second
+    pub fn average_bitrate(&self) -> f64 {
+        let secs = self.connected_for.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.bytes_received as f64 * 8.0 / secs
+    }
+
+    /// Encode as a tab-separated control protocol line (without newline)
+    ///
+    /// The name and tags follow as one `key=value` field each, the name
+    /// under the key `name`; neither can contain a tab.
+    pub fn to_line(&self) -> String {
+        let peer = self
+            .peer
+            .map_or_else(|| "-".to_string(), |peer| peer.to_string());
+        let mut line = format!(
+            "{}\t{peer}\t{}\t{}\t{}\t{}\t{}",
+            self.id,
+            self.codec,
+            self.connected_for.as_millis(),
+            self.bytes_received,
+            self.socket_queue_ms,
+            self.output_queue_ms
+        );
+        let name = self
+            .metadata
+            .name
+            .iter()
+            .map(|name| ("name", name.as_str()));
+        let tags = (self.metadata.tags.iter()).map(|(k, v)| (k.as_str(), v.as_str()));
+        for (key, value) in name.chain(tags) {
+            line.push_str(&format!("\t{key}={value}"));
+        }
+        line
+    }
+
+    /// Decode a line produced by [`ClientInfo::to_line`]
+    ///
+    /// # Returns
+    ///
+    /// The decoded client, or an error if the line is malformed
+    pub fn from_line(line: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [
+            id,
+            peer,
+            codec,
+            connected_for,
+            bytes_received,
+            socket_queue_ms,
+            output_queue_ms,
+            ref metadata @ ..,
+        ] = fields[..]
+        else {
+            return Err(anyhow::anyhow!("Malformed client line: {line}"));
+        };
+        let mut parsed = Metadata::default();
+        for field in metadata {
+            match parse_tag(field)? {
+                (key, value) if key == "name" => parsed.name = Some(value),
+                tag => parsed.tags.push(tag),
+            }
+        }
+
+        Ok(Self {
+            id: id.parse()?,
+            peer: match peer {
+                "-" => None,
+                peer => Some(peer.parse()?),
+            },
+            codec: codec.to_string(),
+            connected_for: Duration::from_millis(connected_for.parse()?),
+            bytes_received: bytes_received.parse()?,
+            socket_queue_ms: socket_queue_ms.parse()?,
+            output_queue_ms: output_queue_ms.parse()?,
+            metadata: parsed,
+        })
+    }
+}
+
+/// Start serving control commands on a Unix socket at `path`
+///
+/// A stale socket file left behind by a previous run is replaced. The socket
+/// is restricted to the current user (mode 0600). Commands are handled on a
+/// background thread until [`ControlServer::close`] is called; dropping the
+/// server instead leaves it running for the lifetime of the process.
+///
+/// # Arguments
+///
+/// * `path` - Filesystem path of the control socket
+/// * `registry` - Connected clients of the receiver
+/// * `health` - What the `health` command checks
+/// * `manifest` - Manifest of the `--debug-dump` recording `mark` adds
+///   markers to, if the receiver records one
+/// * `recorder` - The `--record` archive `mark` adds markers to, if any
+///
+/// # Returns
+///
+/// Returns the running server once the socket is listening, or an error if
+/// it cannot be bound
+pub fn serve(
+    path: &str,
+    registry: Arc<ClientRegistry>,
+    health: Arc<HealthProbe>,
+    manifest: Option<Arc<RecordingManifest>>,
+    recorder: Option<Arc<Recorder>>,
+) -> anyhow::Result<ControlServer> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Control socket listening on {path}");
+
+    let handle = listener.try_clone()?;
+    let closed = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+        let closed = Arc::clone(&closed);
+        move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = handle_command(
+                            stream,
+                            &registry,
+                            &health,
+                            manifest.as_deref(),
+                            recorder.as_deref(),
+                        ) {
+                            error!("Control command failed: {e}");
+                        }
+                    }
+                    Err(_) if closed.load(Ordering::SeqCst) => break,
+                    Err(e) => error!("Control socket accept error: {e}"),
+                }
+            }
+        }
+    });
+
+    Ok(ControlServer {
+        path: path.to_string(),
+        listener: handle,
+        closed,
+        thread,
+    })
+}
+
+/// A control socket being served, see [`serve`]
+#[derive(Debug)]
+pub struct ControlServer {
+    path: String,
+    /// Handle of the listening socket, to wake the serving thread
+    listener: UnixListener,
+    closed: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl ControlServer {
+    /// Stop serving commands and remove the socket file
+    ///
+    /// Returns once the command being handled, if any, has been answered.
+    pub fn close(self) {
+        self.closed.store(true, Ordering::SeqCst);
+        crate::listen::shutdown_listener(self.listener.as_raw_fd());
+        let _ = self.thread.join();
+        let _ = remove_stale_socket(&self.path);
+    }
+}
+
+/// Remove the socket left at `path` by an earlier receiver
+///
+/// Returns an error, leaving it alone, if something other than a socket is
+/// at `path`.
+fn remove_stale_socket(path: &str) -> anyhow::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(std::fs::remove_file(path)?),
+        Ok(_) => Err(anyhow::anyhow!(
+            "{path} exists and is not a socket, refusing to replace it"
+        )),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Read one command from `stream` and write the response
+fn handle_command(
+    stream: UnixStream,
+    registry: &ClientRegistry,
+    health: &HealthProbe,
+    manifest: Option<&RecordingManifest>,
+    recorder: Option<&Recorder>,
+) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_COMMAND_LEN));
+    let mut command = String::new();
+    reader.read_line(&mut command)?;
+    let command = command.trim();
+    debug!("Control command: {command}");
+
+    let response = match command.split_whitespace().collect::<Vec<_>>()[..] {
+        ["clients"] => registry
+            .snapshot()
+            .iter()
+            .map(|client| format!("{}\n", client.to_line()))
+            .collect(),
+        ["session", id] => match id.parse() {
+            Ok(id) => match registry.session(id) {
+                Some(session) => format!("{}\n", session.to_line()),
+                None => format!("ERR no session with id {id}\n"),
+            },
+            Err(_) => format!("ERR invalid client id: {id}\n"),
+        },
+        ["spectrum", id] => match id.parse() {
+            Ok(id) => match registry.spectrum(id) {
+                Some(spectrum) => format!("{}\n", spectrum.to_line()),
+                None => format!(
+                    "ERR no spectrum for id {id} (needs a connected transmitter and --spectrum)\n"
+                ),
+            },
+            Err(_) => format!("ERR invalid client id: {id}\n"),
+        },
+        ["kick", id] => match id.parse() {
+            Ok(id) if registry.kick(id) => "OK\n".to_string(),
+            Ok(id) => format!("ERR no client with id {id}\n"),
+            Err(_) => format!("ERR invalid client id: {id}\n"),
+        },
+        ["health"] => health
+            .check()
+            .iter()
+            .map(|check| format!("{}\n", check.to_line()))
+            .collect(),
+        ["mark", ..] => {
+            let label = command["mark".len()..].trim();
+            if label.is_empty() {
+                "ERR missing marker label\n".to_string()
+            } else {
+                let recorded = recorder.and_then(|recorder| recorder.mark(label));
+                if let Some((file, offset)) = &recorded {
+                    info!("Marker \"{label}\" at {offset:.3} s into {file}");
+                }
+                let dumped = manifest.map(|manifest| manifest.mark(label));
+                if let Some(offset) = dumped {
+                    info!("Marker \"{label}\" at {offset:.3} s into the debug dump");
+                }
+                match (recorded, dumped) {
+                    (Some((_, offset)), _) | (None, Some(offset)) => format!("{offset:.3}\n"),
+                    (None, None) if recorder.is_some() => {
+                        "ERR no --record file open yet, or recording paused for lack of space\n"
+                            .to_string()
+                    }
+                    (None, None) => {
+                        "ERR nothing to mark, start the receiver with --record or --debug-dump\n"
+                            .to_string()
+                    }
+                }
+            }
+        }
+        _ => format!("ERR unknown command: {command}\n"),
+    };
+
+    (&stream).write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Send `command` to the control socket at `path` and return the response
+fn request(path: &str, command: &str) -> anyhow::Result<String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| anyhow::anyhow!("Cannot connect to control socket {path}: {e}"))?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    if let Some(message) = response.strip_prefix("ERR ") {
+        return Err(anyhow::anyhow!("{}", message.trim_end()));
+    }
+    Ok(response)
+}
+
+/// List the transmitters connected to the receiver at `path`
+///
+/// # Examples
+///
+/// ```no_run
+/// use rsonance::control::{list_clients, DEFAULT_CONTROL_SOCKET};
+///
+/// for client in list_clients(DEFAULT_CONTROL_SOCKET)? {
+///     println!("{} {:?}", client.id, client.peer);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn list_clients(path: &str) -> anyhow::Result<Vec<ClientInfo>> {
+    request(path, "clients")?
+        .lines()
+        .map(ClientInfo::from_line)
+        .collect()
+}
+
+/// Stream parameters of the transmitter with connection id `id` at the
+/// receiver at `path`
+///
+/// # Returns
+///
+/// The summary, or an error if no such client has started its stream or
+/// the receiver could not be reached
+pub fn session_summary(path: &str, id: u64) -> anyhow::Result<SessionSummary> {
+    SessionSummary::from_line(request(path, &format!("session {id}"))?.trim_end())
+}
+
+/// Per-band levels of the stream of the transmitter with connection id `id`
+/// at the receiver at `path`
+///
+/// # Returns
+///
+/// The spectrum, or an error if the receiver runs without `--spectrum`, no
+/// such client has sent enough audio, or the receiver could not be reached
+pub fn spectrum(path: &str, id: u64) -> anyhow::Result<Spectrum> {
+    Spectrum::from_line(request(path, &format!("spectrum {id}"))?.trim_end())
+}
+
+/// Run the health checks of the receiver at `path`
+///
+/// Whether they passed is up to the caller, see
+/// [`crate::health::is_healthy`]; an unreachable receiver is an error.
+pub fn check_health(path: &str) -> anyhow::Result<Vec<HealthCheck>> {
+    request(path, "health")?
+        .lines()
+        .map(HealthCheck::from_line)
+        .collect()
+}
+
+/// Add a marker labelled `label` to the recording of the receiver at `path`
+///
+/// # Returns
+///
+/// The offset of the marker into the recording in seconds, or an error if
+/// the receiver is not recording or could not be reached
+pub fn add_marker(path: &str, label: &str) -> anyhow::Result<f64> {
+    let label = label.replace(['\r', '\n'], " ");
+    let offset = request(path, &format!("mark {label}"))?;
+    offset
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Malformed marker offset: {offset}"))
+}
+
+/// Disconnect the transmitter with connection id `id` from the receiver at `path`
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the client was disconnected, or an error if no such
+/// client exists or the receiver could not be reached
+pub fn kick_client(path: &str, id: u64) -> anyhow::Result<()> {
+    request(path, &format!("kick {id}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::receiver::ConnectionContext;
+    use crate::udp::Transport;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_client_info_line_round_trip() {
+        let info = ClientInfo {
+            id: 12,
+            peer: Some("[::1]:4000".parse().unwrap()),
+            codec: "s16le".to_string(),
+            connected_for: Duration::from_secs(3600),
+            bytes_received: 28_800_000,
+            socket_queue_ms: 2,
+            output_queue_ms: 85,
+            metadata: Metadata::default(),
+        };
+        assert_eq!(
+            info.to_line(),
+            "12\t[::1]:4000\ts16le\t3600000\t28800000\t2\t85"
+        );
+        assert_eq!(info.average_bitrate(), 64_000.0);
+        assert_eq!(ClientInfo::from_line(&info.to_line()).unwrap(), info);
+
+        let info = ClientInfo { peer: None, ..info };
+        assert_eq!(ClientInfo::from_line(&info.to_line()).unwrap(), info);
+
+        let info = ClientInfo {
+            metadata: Metadata {
+                name: Some("Desk = left".to_string()),
+                tags: vec![("mic".to_string(), "sm58".to_string())],
+            },
+            ..info
+        };
+        assert!(info.to_line().ends_with("\t85\tname=Desk = left\tmic=sm58"));
+        assert_eq!(ClientInfo::from_line(&info.to_line()).unwrap(), info);
+    }
+
+    #[test]
+    fn test_client_info_malformed_line() {
+        assert!(ClientInfo::from_line("").is_err());
+        assert!(ClientInfo::from_line("1\t-\ts16le").is_err());
+        assert!(ClientInfo::from_line("1\t-\ts16le\t5\t0\t0").is_err());
+        assert!(ClientInfo::from_line("x\t-\ts16le\t5\t0\t0\t0").is_err());
+    }
+
+    #[test]
+    fn test_serve_clients_and_kick() {
+        let path = format!("/tmp/rsonance_control_test_{}.sock", std::process::id());
+        let registry = Arc::new(ClientRegistry::default());
+        let health = Arc::new(HealthProbe::default());
+        health.add_listener("127.0.0.1:8080");
+        serve(&path, Arc::clone(&registry), health, None, None).unwrap();
+
+        assert!(list_clients(&path).unwrap().is_empty());
+        assert_eq!(
+            add_marker(&path, "intro").unwrap_err().to_string(),
+            "nothing to mark, start the receiver with --record or --debug-dump"
+        );
+        let checks = check_health(&path).unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].ok);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, peer) = listener.accept().unwrap();
+        let connection = ConnectionContext {
+            id: 5,
+            peer: Some(peer),
+        };
+        let stats = registry.register(&connection, &server.into()).unwrap();
+        stats.add_received(4000);
+        stats.add_received(410);
+        stats.set_queues(3.4, 120.0);
+
+        let clients = list_clients(&path).unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].id, 5);
+        assert_eq!(clients[0].peer, Some(peer));
+        assert_eq!(clients[0].bytes_received, 4410);
+        assert_eq!(
+            (clients[0].socket_queue_ms, clients[0].output_queue_ms),
+            (3, 120)
+        );
+
+        // No session until the stream has started
+        assert!(session_summary(&path, 5).is_err());
+        let session = SessionSummary::new(
+            Codec::S16LE,
+            &Codec::S16LE.audio_config(),
+            Transport::Tcp,
+            false,
+        );
+        stats.set_session(session.clone());
+        assert_eq!(session_summary(&path, 5).unwrap(), session);
+        assert!(session_summary(&path, 6).is_err());
+
+        assert!(kick_client(&path, 6).is_err());
+        kick_client(&path, 5).unwrap();
+        assert!(list_clients(&path).unwrap().is_empty());
+
+        // The kicked transmitter sees end-of-stream
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_serve_leaves_other_files_alone() {
+        let path = format!("/tmp/rsonance_control_file_{}.sock", std::process::id());
+        std::fs::write(&path, b"keep").unwrap();
+        let registry = Arc::new(ClientRegistry::default());
+        let health = Arc::new(HealthProbe::default());
+        assert!(serve(&path, registry, health, None, None).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"keep");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_request_without_receiver() {
+        assert!(list_clients("/tmp/rsonance_no_such_control.sock").is_err());
+    }
+}
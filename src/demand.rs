@@ -0,0 +1,284 @@
+//! On-demand streaming: only send audio while something records it
+//!
+//! A receiver with `--on-demand` tells its transmitters whether any
+//! application is recording from its virtual microphone. A transmitter with
+//! `--on-demand` pauses capture and sends nothing while nobody is, saving
+//! bandwidth and battery, and resumes as soon as a recorder attaches.
+//!
+//! # Protocol
+//!
+//! The receiver writes lines back over the audio connection, the only data
+//! ever sent in that direction: `recording` while at least one application
+//! records from the virtual microphone, `idle` otherwise. The current state
+//! is sent right after a transmitter connects and again on every change.
+//! Receivers without a virtual microphone (`--pipe-to`, `--backend null`)
+//! always report `recording`.
+//!
+//! Both sides have to opt in: a client that never reads the connection,
+//! such as a transmitter without `--on-demand` or `rsonance replay`, would
+//! reset it on close because of the unread lines, and the receiver could
+//! lose the end of its stream.
+
+use crate::queue::CaptureReceiver;
+use crate::receiver::ClientRegistry;
+use crate::{list_sources, spawn_sound_server_watch, subscribe_sound_server};
+use log::{debug, info};
+use std::io::{self, ErrorKind};
+use std::process::Command;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Interval between checks for recorders while the sound server's events
+/// are unavailable
+const RECORDER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest state line kept while waiting for its end
+const MAX_LINE: usize = 64;
+
+/// Whether the receiver's audio is being recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecorderState {
+    /// At least one application records from the virtual microphone
+    Recording,
+    /// Nothing records from the virtual microphone
+    #[default]
+    Idle,
+}
+
+impl RecorderState {
+    /// Line sent to the transmitter for this state, including the newline
+    pub fn line(self) -> &'static [u8] {
+        match self {
+            RecorderState::Recording => b"recording\n",
+            RecorderState::Idle => b"idle\n",
+        }
+    }
+
+    /// Parse a line sent by the receiver, without the newline
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rsonance::demand::RecorderState;
+    ///
+    /// assert_eq!(RecorderState::parse(b"idle"), Some(RecorderState::Idle));
+    /// assert_eq!(RecorderState::parse(b"paused"), None);
+    /// ```
+    pub fn parse(line: &[u8]) -> Option<Self> {
+        match line {
+            b"recording" => Some(RecorderState::Recording),
+            b"idle" => Some(RecorderState::Idle),
+            _ => None,
+        }
+    }
+}
+
+/// Keep the transmitters in `registry` informed whether anything records
+/// from the source named `source_name`
+///
+/// The sound server is checked whenever a source or a recording stream is
+/// added or removed, until the receiver shuts down.
+pub(crate) fn spawn_recorder_watch(source_name: String, registry: Weak<ClientRegistry>) {
+    spawn_sound_server_watch(
+        RECORDER_CHECK_INTERVAL,
+        |event| {
+            matches!(event.facility.as_str(), "source" | "source-output") && event.kind != "change"
+        },
+        subscribe_sound_server,
+        move || {
+            let Some(registry) = registry.upgrade() else {
+                return false;
+            };
+            match recorder_attached(&source_name) {
+                Ok(true) => registry.set_recorder_state(RecorderState::Recording),
+                Ok(false) => registry.set_recorder_state(RecorderState::Idle),
+                Err(e) => debug!("Failed to look for recorders of '{source_name}': {e}"),
+            }
+            true
+        },
+    );
+}
+
+/// Whether an application records from the source named `source_name`
+fn recorder_attached(source_name: &str) -> anyhow::Result<bool> {
+    let output = Command::new("pactl")
+        .args(["list", "source-outputs", "short"])
+        .output()?;
+    let source_outputs = String::from_utf8(output.stdout)?;
+    Ok(has_recorder(&list_sources()?, &source_outputs, source_name))
+}
+
+/// Whether `pactl list source-outputs short` output has a stream recording
+/// from `source_name`, looked up in `pactl list sources short` output
+fn has_recorder(sources: &str, source_outputs: &str, source_name: &str) -> bool {
+    let index = sources.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let index = fields.next()?;
+        (fields.next()? == source_name).then_some(index)
+    });
+    let Some(index) = index else {
+        return false;
+    };
+    source_outputs
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(index))
+}
+
+/// The receiver's recorder state as seen by an `--on-demand` transmitter
+///
+/// Starts out idle until the receiver reports otherwise, so no audio is sent
+/// before the receiver has said it is wanted.
+#[derive(Debug, Default)]
+pub(crate) struct DemandReader {
+    state: RecorderState,
+    /// Start of a line whose end has not arrived yet
+    pending: Vec<u8>,
+}
+
+impl DemandReader {
+    /// Whether the receiver's audio is being recorded
+    pub(crate) fn recording(&self) -> bool {
+        self.state == RecorderState::Recording
+    }
+
+    /// Take in the state lines the receiver has sent so far, without waiting
+    ///
+    /// Returns an error if the receiver closed the connection.
+    pub(crate) fn read_available(&mut self, stream: &TcpStream) -> io::Result<()> {
+        let mut buf = [0u8; 256];
+        loop {
+            match stream.try_read(&mut buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "receiver closed the connection",
+                    ));
+                }
+                Ok(n) => self.push(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Wait until the receiver reports a recorder, discarding captured audio
+    /// meanwhile
+    ///
+    /// Also returns once capture has stopped. Returns an error if the
+    /// connection fails while waiting.
+    pub(crate) async fn wait_for_recorder(
+        &mut self,
+        stream: &TcpStream,
+        rx: &mut CaptureReceiver,
+    ) -> io::Result<()> {
+        while !self.recording() {
+            tokio::select! {
+                ready = stream.readable() => {
+                    ready?;
+                    self.read_available(stream)?;
+                }
+                packet = rx.recv() => {
+                    if packet.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            match RecorderState::parse(line[..end].trim_ascii()) {
+                Some(state) if state != self.state => {
+                    match state {
+                        RecorderState::Recording => {
+                            info!("Receiver audio is being recorded, streaming")
+                        }
+                        RecorderState::Idle => {
+                            info!("Nothing records the receiver's audio, pausing the stream")
+                        }
+                    }
+                    self.state = state;
+                }
+                Some(_) => {}
+                None => debug!(
+                    "Ignoring unknown message from the receiver: {:?}",
+                    String::from_utf8_lossy(&line)
+                ),
+            }
+        }
+        if self.pending.len() > MAX_LINE {
+            debug!("Ignoring overlong message from the receiver");
+            self.pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_has_recorder() {
+        let sources = "0\talsa_input.pci\tmodule-alsa-card.c\ts16le 2ch 44100Hz\tSUSPENDED\n\
+                       7\tmic\tmodule-pipe-source.c\ts16le 2ch 44100Hz\tRUNNING\n";
+        let outputs = "3\t0\t12\tprotocol-native.c\tfloat32le 1ch 48000Hz\n";
+        assert!(!has_recorder(sources, outputs, "mic"));
+        assert!(has_recorder(sources, outputs, "alsa_input.pci"));
+
+        let outputs = "3\t0\t12\tprotocol-native.c\tfloat32le 1ch 48000Hz\n\
+                       4\t7\t13\tprotocol-native.c\ts16le 2ch 44100Hz\n";
+        assert!(has_recorder(sources, outputs, "mic"));
+        assert!(!has_recorder(sources, outputs, "other"));
+    }
+
+    #[test]
+    fn test_demand_reader_parses_split_lines() {
+        let mut reader = DemandReader::default();
+        assert!(!reader.recording());
+        reader.push(b"recor");
+        assert!(!reader.recording());
+        reader.push(b"ding\nbogus\n");
+        assert!(reader.recording());
+        reader.push(b"idle\n");
+        assert!(!reader.recording());
+
+        // An endless line is dropped rather than buffered
+        reader.push(&[b'x'; 100]);
+        reader.push(b"\nrecording\n");
+        assert!(reader.recording());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_recorder() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut receiver, _) = listener.accept().await.unwrap();
+        let (tx, mut rx) = crate::queue::capture_queue(0, 1000);
+
+        receiver.write_all(b"idle\n").await.unwrap();
+        tx.send(vec![0; 4]).unwrap();
+        let mut reader = DemandReader::default();
+        let waiting = reader.wait_for_recorder(&stream, &mut rx);
+        let notify = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            receiver.write_all(b"recording\n").await.unwrap();
+        };
+        let (result, ()) = tokio::join!(waiting, notify);
+        result.unwrap();
+        assert!(reader.recording());
+        // Audio captured while waiting was discarded
+        assert!(rx.is_empty());
+
+        reader.push(b"idle\n");
+        drop(receiver);
+        assert!(reader.wait_for_recorder(&stream, &mut rx).await.is_err());
+    }
+}
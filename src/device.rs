@@ -0,0 +1,310 @@
+//! Transmitter `--device` and receiver `--output-device` device selection
+//!
+//! The transmitter captures from the host's default input device unless
+//! `--device` names another one, by its index in `rsonance list-devices` or
+//! by its name. A name matches a device called exactly that, or else the
+//! one device whose name contains it, ignoring case, so `--device usb`
+//! picks the only USB microphone. Indices follow the order the audio host
+//! lists the devices in, which can change when devices are plugged in or
+//! removed; names stay put. The receiver's `--output-device` picks among
+//! the output devices (`rsonance list-devices --output`) the same way.
+//! Without it the receiver looks for a virtual audio cable, see
+//! [`LOOPBACK_DEVICES`], since playing a remote microphone on the speakers
+//! is rarely what is wanted.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use log::info;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parts of the names of virtual audio cables and loopback devices,
+/// lowercase: BlackHole and Loopback on macOS, VB-Cable on Windows and
+/// `snd-aloop` on Linux, whose card is called Loopback
+pub const LOOPBACK_DEVICES: &[&str] = &["blackhole", "cable input", "loopback", "soundflower"];
+
+/// Device chosen with `--device` or `--output-device`
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::device::DeviceSelector;
+///
+/// assert_eq!("2".parse::<DeviceSelector>().unwrap(), DeviceSelector::Index(2));
+/// let name: DeviceSelector = "USB Audio".parse().unwrap();
+/// assert_eq!(name.to_string(), "\"USB Audio\"");
+/// assert!("".parse::<DeviceSelector>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in the list of input devices, from 0
+    Index(usize),
+    /// Exact name, or a unique part of it ignoring case
+    Name(String),
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{index}"),
+            Self::Name(name) => write!(f, "\"{name}\""),
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(anyhow::anyhow!("Device name or index cannot be empty"));
+        }
+        Ok(match s.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(s.to_string()),
+        })
+    }
+}
+
+/// Input or output device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::Output => "output",
+        })
+    }
+}
+
+/// Device as listed by `rsonance list-devices`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioDevice {
+    /// Position in the list, as taken by `--device` and `--output-device`
+    pub index: usize,
+    pub name: String,
+    /// Whether this is the host's default device of its direction
+    pub default: bool,
+    /// Default format, e.g. "48000 Hz, 2 channel(s), f32", if the device
+    /// reports one
+    pub config: Option<String>,
+}
+
+/// The devices of the default audio host in `direction`, in order
+pub fn list_devices(direction: Direction) -> anyhow::Result<Vec<AudioDevice>> {
+    let host = cpal::default_host();
+    let default_name = match direction {
+        Direction::Input => host.default_input_device(),
+        Direction::Output => host.default_output_device(),
+    }
+    .and_then(|d| d.name().ok());
+    Ok(devices(&host, direction)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, device)| {
+            let name = device.name().unwrap_or_else(|_| "(unnamed)".to_string());
+            let config = match direction {
+                Direction::Input => device.default_input_config(),
+                Direction::Output => device.default_output_config(),
+            };
+            let config = config.ok().map(|config| {
+                format!(
+                    "{} Hz, {} channel(s), {:?}",
+                    config.sample_rate().0,
+                    config.channels(),
+                    config.sample_format()
+                )
+            });
+            AudioDevice {
+                index,
+                default: default_name.as_deref() == Some(name.as_str()),
+                name,
+                config,
+            }
+        })
+        .collect())
+}
+
+fn devices(host: &cpal::Host, direction: Direction) -> anyhow::Result<Vec<cpal::Device>> {
+    Ok(match direction {
+        Direction::Input => host.input_devices()?.collect(),
+        Direction::Output => host.output_devices()?.collect(),
+    })
+}
+
+/// Find the input device to capture from: the one `selector` picks, or the
+/// host's default without one
+///
+/// # Returns
+///
+/// The device, or an error naming the available devices if none matches
+pub fn find_input_device(selector: Option<&DeviceSelector>) -> anyhow::Result<cpal::Device> {
+    find_device(Direction::Input, selector)
+}
+
+/// Find the output device to play to: the one `selector` picks, or without
+/// one the first virtual audio cable, or else the host's default
+pub fn find_output_device(selector: Option<&DeviceSelector>) -> anyhow::Result<cpal::Device> {
+    if selector.is_none() {
+        let mut devices = devices(&cpal::default_host(), Direction::Output)?;
+        let names: Vec<String> = devices
+            .iter()
+            .map(|device| device.name().unwrap_or_default())
+            .collect();
+        if let Some(index) = loopback_index(&names) {
+            info!(
+                "Found the virtual audio cable \"{}\", give --output-device to play elsewhere",
+                names[index]
+            );
+            return Ok(devices.swap_remove(index));
+        }
+    }
+    find_device(Direction::Output, selector)
+}
+
+/// Index of the first device in `names` that is a virtual audio cable
+fn loopback_index(names: &[String]) -> Option<usize> {
+    names.iter().position(|name| {
+        let name = name.to_lowercase();
+        LOOPBACK_DEVICES.iter().any(|part| name.contains(part))
+    })
+}
+
+fn find_device(
+    direction: Direction,
+    selector: Option<&DeviceSelector>,
+) -> anyhow::Result<cpal::Device> {
+    let host = cpal::default_host();
+    let Some(selector) = selector else {
+        return match direction {
+            Direction::Input => host.default_input_device(),
+            Direction::Output => host.default_output_device(),
+        }
+        .ok_or_else(|| anyhow::anyhow!("No {direction} device available"));
+    };
+
+    let mut devices = devices(&host, direction)?;
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    let index = select(&names, selector, direction).map_err(|reason| {
+        let command = match direction {
+            Direction::Input => "rsonance list-devices",
+            Direction::Output => "rsonance list-devices --output",
+        };
+        anyhow::anyhow!(
+            "{reason}; {direction} devices: {} (see {command})",
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| format!("{index}: {name}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        )
+    })?;
+    Ok(devices.swap_remove(index))
+}
+
+/// Index of the device in `names` that `selector` picks, or why none does
+fn select(
+    names: &[String],
+    selector: &DeviceSelector,
+    direction: Direction,
+) -> Result<usize, String> {
+    match selector {
+        DeviceSelector::Index(index) if *index < names.len() => Ok(*index),
+        DeviceSelector::Index(index) => Err(format!("No {direction} device #{index}")),
+        DeviceSelector::Name(wanted) => {
+            if let Some(index) = names.iter().position(|name| name == wanted) {
+                return Ok(index);
+            }
+            let lower = wanted.to_lowercase();
+            let matches: Vec<usize> = names
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| name.to_lowercase().contains(&lower))
+                .map(|(index, _)| index)
+                .collect();
+            match matches[..] {
+                [index] => Ok(index),
+                [] => Err(format!("No {direction} device matches \"{wanted}\"")),
+                _ => Err(format!(
+                    "{} {direction} devices match \"{wanted}\", give the full name or the index",
+                    matches.len()
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_index() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            loopback_index(&names(&["MacBook Pro Speakers", "BlackHole 2ch"])),
+            Some(1)
+        );
+        assert_eq!(
+            loopback_index(&names(&[
+                "Speakers (Realtek)",
+                "CABLE Input (VB-Audio Virtual Cable)"
+            ])),
+            Some(1)
+        );
+        assert_eq!(
+            loopback_index(&names(&[
+                "default",
+                "hw:CARD=Loopback,DEV=0",
+                "hw:CARD=Loopback,DEV=1"
+            ])),
+            Some(1)
+        );
+        assert_eq!(loopback_index(&names(&["default", "HDMI"])), None);
+    }
+
+    #[test]
+    fn test_select_by_index_exact_name_and_unique_part() {
+        let names: Vec<String> = ["default", "USB Audio", "USB Audio Headset", "HDMI"]
+            .map(String::from)
+            .to_vec();
+        let select_str = |s: &str| select(&names, &s.parse().unwrap(), Direction::Input);
+
+        assert_eq!(select_str("3"), Ok(3));
+        assert_eq!(select_str("4"), Err("No input device #4".to_string()));
+        // Exact before partial, even where the part is ambiguous
+        assert_eq!(select_str("USB Audio"), Ok(1));
+        assert_eq!(select_str("headset"), Ok(2));
+        assert_eq!(select_str("hdmi"), Ok(3));
+        assert_eq!(
+            select_str("usb"),
+            Err("2 input devices match \"usb\", give the full name or the index".to_string())
+        );
+        assert_eq!(
+            select_str("bluetooth"),
+            Err("No input device matches \"bluetooth\"".to_string())
+        );
+        assert_eq!(
+            select(&names, &DeviceSelector::Index(9), Direction::Output),
+            Err("No output device #9".to_string())
+        );
+    }
+}
@@ -0,0 +1,731 @@
+//! Receiver discovery on the local network over mDNS / DNS-SD
+//!
+//! A receiver started with `--advertise` announces itself as a DNS-SD
+//! service of type [`SERVICE_TYPE`] and answers mDNS queries for it, so
+//! `rsonance transmitter --discover` can find it without anyone typing an
+//! IP address: the transmitter asks the local network for receivers and
+//! connects to the first one that answers, or to the one with the name
+//! given. Other DNS-SD browsers see the receivers too, e.g.
+//! `avahi-browse -r _rsonance._tcp`.
+//!
+//! The service type is `_tcp` whatever the receiver's `--transport`; the
+//! TXT record says which one it expects (`transport=udp`), and the
+//! transmitter follows it.
+//!
+//! Only the parts of mDNS (RFC 6762) and DNS-SD (RFC 6763) needed for this
+//! are implemented: IPv4, answering PTR, SRV, TXT and A queries for the
+//! service, announcing on start and saying goodbye on shutdown. Queries
+//! are sent from an ephemeral port, so answers come back by unicast and
+//! the transmitter does not have to share port 5353 with a local
+//! responder such as Avahi; the receiver does share it, through
+//! `SO_REUSEPORT`.
+
+use crate::udp::Transport;
+use log::{debug, error, info, warn};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::fd::FromRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// DNS-SD service type of rsonance receivers
+pub const SERVICE_TYPE: &str = "_rsonance._tcp.local";
+
+/// Time `--discover` waits for receivers to answer
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// mDNS port and IPv4 group
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// Time other hosts may cache the records
+const RECORD_TTL: u32 = 120;
+
+/// DNS record types
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+/// Class IN, and with the cache-flush bit set for records only this host
+/// owns
+const CLASS_IN: u16 = 1;
+const CLASS_IN_FLUSH: u16 = 0x8001;
+
+/// Header flags of an authoritative response
+const FLAGS_RESPONSE: u16 = 0x8400;
+
+/// A receiver as announced on the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advertisement {
+    /// Instance name, shown by browsers and matched by `--discover NAME`
+    pub name: String,
+    /// Port the receiver listens on
+    pub port: u16,
+    pub transport: Transport,
+}
+
+/// A receiver found by [`browse`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredReceiver {
+    /// Instance name of the receiver
+    pub name: String,
+    /// Address to connect to
+    pub addr: SocketAddr,
+    /// Transport the receiver expects
+    pub transport: Transport,
+}
+
+/// Name of this host without its domain, the default instance name
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its length, and gethostname leaves
+    // it NUL-terminated when the name fits
+    let rc = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len() - 1) };
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(0);
+    match std::str::from_utf8(&buffer[..len]) {
+        Ok(name) if rc == 0 && !name.is_empty() => {
+            name.split('.').next().unwrap_or(name).to_string()
+        }
+        _ => "rsonance".to_string(),
+    }
+}
+
+/// Answers mDNS queries for a receiver until closed
+#[derive(Debug)]
+pub struct Advertiser {
+    socket: UdpSocket,
+    advertisement: Advertisement,
+    closed: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Advertiser {
+    /// Announce `advertisement` and answer queries for it on a background
+    /// thread
+    pub fn start(advertisement: Advertisement) -> anyhow::Result<Self> {
+        let socket = bind_shared(MDNS_PORT)
+            .map_err(|e| anyhow::anyhow!("Cannot bind the mDNS port {MDNS_PORT}: {e}"))?;
+        socket
+            .join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)
+            .map_err(|e| anyhow::anyhow!("Cannot join the mDNS group {MDNS_GROUP}: {e}"))?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+        let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+        announce(&socket, &advertisement, group, RECORD_TTL);
+        info!(
+            "Advertising \"{}\" as {SERVICE_TYPE} on port {}",
+            advertisement.name, advertisement.port
+        );
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let socket = socket.try_clone()?;
+            let advertisement = advertisement.clone();
+            let closed = Arc::clone(&closed);
+            move || respond(&socket, &advertisement, &closed)
+        });
+        Ok(Self {
+            socket,
+            advertisement,
+            closed,
+            thread,
+        })
+    }
+
+    /// Stop answering and tell the network the receiver is gone
+    pub fn close(self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+        announce(
+            &self.socket,
+            &self.advertisement,
+            SocketAddr::from((MDNS_GROUP, MDNS_PORT)),
+            0,
+        );
+    }
+}
+
+/// A UDP socket bound to `port` on all addresses, shared with other
+/// sockets bound the same way
+fn bind_shared(port: u16) -> std::io::Result<UdpSocket> {
+    // SAFETY: plain socket calls on a descriptor owned by this function
+    // until it is handed to the UdpSocket
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let socket = UdpSocket::from_raw_fd(fd);
+        let on: libc::c_int = 1;
+        for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+            if libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                (&on as *const libc::c_int).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: port.to_be(),
+            sin_addr: libc::in_addr { s_addr: 0 },
+            sin_zero: [0; 8],
+        };
+        if libc::bind(
+            fd,
+            (&addr as *const libc::sockaddr_in).cast(),
+            size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        ) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+/// Send the records of `advertisement` unsolicited to `to`, with `ttl` 0
+/// saying goodbye
+fn announce(socket: &UdpSocket, advertisement: &Advertisement, to: SocketAddr, ttl: u32) {
+    let response = encode_response(advertisement, 0, None, local_ip_toward(to), ttl);
+    if let Err(e) = socket.send_to(&response, to) {
+        warn!("Failed to send mDNS announcement: {e}");
+    }
+}
+
+/// Answer queries for `advertisement` until `closed` is set
+fn respond(socket: &UdpSocket, advertisement: &Advertisement, closed: &AtomicBool) {
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let mut buffer = [0u8; 9000];
+    // RFC 6762 asks for a second announcement after a second
+    let mut reannounce = Some(Instant::now() + Duration::from_secs(1));
+    while !closed.load(Ordering::SeqCst) {
+        if let Some(at) = reannounce
+            && Instant::now() >= at
+        {
+            announce(socket, advertisement, group, RECORD_TTL);
+            reannounce = None;
+        }
+        let (len, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => {
+                error!("mDNS receive error: {e}");
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+        let Some(message) = Message::parse(&buffer[..len]) else {
+            continue;
+        };
+        let Some(question) = message.question_for(advertisement) else {
+            continue;
+        };
+        // A query from another port than 5353 is a one-shot query wanting a
+        // unicast answer that repeats its id and question
+        let (to, id, question) = if from.port() == MDNS_PORT {
+            (group, 0, None)
+        } else {
+            (from, message.id, Some(question))
+        };
+        debug!("Answering mDNS query from {from}");
+        let response = encode_response(
+            advertisement,
+            id,
+            question.as_ref(),
+            local_ip_toward(to),
+            RECORD_TTL,
+        );
+        if let Err(e) = socket.send_to(&response, to) {
+            warn!("Failed to answer mDNS query from {from}: {e}");
+        }
+    }
+}
+
+/// Address of this host on the interface that reaches `to`
+fn local_ip_toward(to: SocketAddr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect(to).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+/// Ask the local network for receivers and collect the answers for
+/// `timeout`
+pub fn browse(timeout: Duration) -> anyhow::Result<Vec<DiscoveredReceiver>> {
+    browse_until(timeout, |_| false)
+}
+
+/// Find a receiver: the first to answer, or the one named `name` (ignoring
+/// case)
+///
+/// # Returns
+///
+/// The receiver, or an error naming the receivers found if none matched
+/// within `timeout`
+pub fn discover(name: Option<&str>, timeout: Duration) -> anyhow::Result<DiscoveredReceiver> {
+    let matches = |receiver: &DiscoveredReceiver| {
+        name.is_none_or(|name| receiver.name.eq_ignore_ascii_case(name))
+    };
+    let found = browse_until(timeout, |found| found.iter().any(matches))?;
+    if let Some(receiver) = found.iter().find(|receiver| matches(receiver)) {
+        info!(
+            "Discovered receiver \"{}\" at {} ({})",
+            receiver.name, receiver.addr, receiver.transport
+        );
+        return Ok(receiver.clone());
+    }
+    let names: Vec<&str> = found
+        .iter()
+        .map(|receiver| receiver.name.as_str())
+        .collect();
+    Err(match (name, names.is_empty()) {
+        (_, true) => anyhow::anyhow!(
+            "No receiver answered within {:.1} s; is one running with --advertise on this network?",
+            timeout.as_secs_f64()
+        ),
+        (Some(name), false) => anyhow::anyhow!(
+            "No receiver named \"{name}\" found, only: {}",
+            names.join(", ")
+        ),
+        (None, false) => unreachable!("any receiver matches without a name"),
+    })
+}
+
+/// Browse until `timeout` has passed or `done` is satisfied with the
+/// receivers found so far
+fn browse_until(
+    timeout: Duration,
+    done: impl Fn(&[DiscoveredReceiver]) -> bool,
+) -> anyhow::Result<Vec<DiscoveredReceiver>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_multicast_ttl_v4(255)?;
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let id = std::process::id() as u16;
+    let query = encode_query(id);
+
+    let deadline = Instant::now() + timeout;
+    // Asked again once a second, in case the first query was lost
+    let mut next_query = Instant::now();
+    let mut found: Vec<DiscoveredReceiver> = Vec::new();
+    let mut buffer = [0u8; 9000];
+    while Instant::now() < deadline && !done(&found) {
+        if Instant::now() >= next_query {
+            socket
+                .send_to(&query, group)
+                .map_err(|e| anyhow::anyhow!("Cannot send the mDNS query: {e}"))?;
+            next_query = Instant::now() + Duration::from_secs(1);
+        }
+        let wait = deadline
+            .min(next_query)
+            .saturating_duration_since(Instant::now());
+        socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+        let (len, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(message) = Message::parse(&buffer[..len]) {
+            for receiver in message.receivers(from.ip()) {
+                if !found.contains(&receiver) {
+                    debug!("Found receiver \"{}\" at {}", receiver.name, receiver.addr);
+                    found.push(receiver);
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// A domain name as its labels
+type Name = Vec<String>;
+
+fn name(s: &str) -> Name {
+    s.split('.').map(str::to_string).collect()
+}
+
+fn same_name(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Name of the instance `advertisement` announces
+fn instance_name(advertisement: &Advertisement) -> Name {
+    let mut instance = vec![advertisement.name.clone()];
+    instance.extend(name(SERVICE_TYPE));
+    instance
+}
+
+fn push_name(out: &mut Vec<u8>, name: &[String]) {
+    for label in name {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+fn push_record(out: &mut Vec<u8>, name: &[String], kind: u16, class: u16, ttl: u32, data: &[u8]) {
+    push_name(out, name);
+    out.extend_from_slice(&kind.to_be_bytes());
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&ttl.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn push_header(out: &mut Vec<u8>, id: u16, flags: u16, questions: u16, answers: u16) {
+    for field in [id, flags, questions, answers, 0, 0] {
+        out.extend_from_slice(&field.to_be_bytes());
+    }
+}
+
+/// A query for the receivers on the network
+fn encode_query(id: u16) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_header(&mut out, id, 0, 1, 0);
+    push_name(&mut out, &name(SERVICE_TYPE));
+    out.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out
+}
+
+/// The records describing `advertisement`, answering `question` if given
+///
+/// Without the host's address (`ip`) the A record is left out and a
+/// browser falls back to the address the answer came from.
+fn encode_response(
+    advertisement: &Advertisement,
+    id: u16,
+    question: Option<&(Name, u16)>,
+    ip: Option<Ipv4Addr>,
+    ttl: u32,
+) -> Vec<u8> {
+    let instance = instance_name(advertisement);
+    let host = vec![host_name(), "local".to_string()];
+
+    let mut out = Vec::new();
+    push_header(
+        &mut out,
+        id,
+        FLAGS_RESPONSE,
+        u16::from(question.is_some()),
+        3 + u16::from(ip.is_some()),
+    );
+    if let Some((name, kind)) = question {
+        push_name(&mut out, name);
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+
+    let mut target = Vec::new();
+    push_name(&mut target, &instance);
+    push_record(
+        &mut out,
+        &name(SERVICE_TYPE),
+        TYPE_PTR,
+        CLASS_IN,
+        ttl,
+        &target,
+    );
+
+    let mut srv = Vec::new();
+    srv.extend_from_slice(&[0, 0, 0, 0]);
+    srv.extend_from_slice(&advertisement.port.to_be_bytes());
+    push_name(&mut srv, &host);
+    push_record(&mut out, &instance, TYPE_SRV, CLASS_IN_FLUSH, ttl, &srv);
+
+    let mut txt = Vec::new();
+    for entry in [
+        format!("transport={}", advertisement.transport),
+        format!("version={}", crate::protocol::VERSION),
+    ] {
+        txt.push(entry.len() as u8);
+        txt.extend_from_slice(entry.as_bytes());
+    }
+    push_record(&mut out, &instance, TYPE_TXT, CLASS_IN_FLUSH, ttl, &txt);
+
+    if let Some(ip) = ip {
+        push_record(&mut out, &host, TYPE_A, CLASS_IN_FLUSH, ttl, &ip.octets());
+    }
+    out
+}
+
+/// A resource record of interest
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    Ptr { name: Name, target: Name },
+    Srv { name: Name, port: u16, target: Name },
+    Txt { name: Name, entries: Vec<String> },
+    A { name: Name, ip: Ipv4Addr },
+}
+
+/// The parts of a DNS message used here
+#[derive(Debug, Default)]
+struct Message {
+    id: u16,
+    response: bool,
+    questions: Vec<(Name, u16)>,
+    records: Vec<Record>,
+}
+
+impl Message {
+    /// Parse a DNS message, or None if it is malformed
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let u16_at = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+        let mut message = Self {
+            id: u16_at(0)?,
+            response: u16_at(2)? & 0x8000 != 0,
+            ..Self::default()
+        };
+        let questions = u16_at(4)?;
+        let records = u32::from(u16_at(6)?) + u32::from(u16_at(8)?) + u32::from(u16_at(10)?);
+
+        let mut at = 12;
+        for _ in 0..questions {
+            let (name, next) = read_name(bytes, at)?;
+            message.questions.push((name, u16_at(next)?));
+            at = next + 4;
+        }
+        for _ in 0..records {
+            let (name, next) = read_name(bytes, at)?;
+            let kind = u16_at(next)?;
+            let len = usize::from(u16_at(next + 8)?);
+            let data_at = next + 10;
+            let data = bytes.get(data_at..data_at + len)?;
+            let record = match kind {
+                TYPE_PTR => Some(Record::Ptr {
+                    name,
+                    target: read_name(bytes, data_at)?.0,
+                }),
+                TYPE_SRV if len >= 6 => Some(Record::Srv {
+                    name,
+                    port: u16::from_be_bytes([data[4], data[5]]),
+                    target: read_name(bytes, data_at + 6)?.0,
+                }),
+                TYPE_TXT => {
+                    let mut entries = Vec::new();
+                    let mut rest = data;
+                    while let Some((&len, tail)) = rest.split_first() {
+                        let entry = tail.get(..usize::from(len))?;
+                        entries.push(String::from_utf8_lossy(entry).into_owned());
+                        rest = &tail[usize::from(len)..];
+                    }
+                    Some(Record::Txt { name, entries })
+                }
+                TYPE_A if len == 4 => Some(Record::A {
+                    name,
+                    ip: Ipv4Addr::new(data[0], data[1], data[2], data[3]),
+                }),
+                _ => None,
+            };
+            message.records.extend(record);
+            at = data_at + len;
+        }
+        Some(message)
+    }
+
+    /// The question of this query that `advertisement` answers, if any
+    fn question_for(&self, advertisement: &Advertisement) -> Option<(Name, u16)> {
+        if self.response {
+            return None;
+        }
+        let service = name(SERVICE_TYPE);
+        let instance = instance_name(advertisement);
+        self.questions
+            .iter()
+            .find(|(name, kind)| {
+                (same_name(name, &service) && matches!(*kind, TYPE_PTR | TYPE_ANY))
+                    || (same_name(name, &instance)
+                        && matches!(*kind, TYPE_SRV | TYPE_TXT | TYPE_ANY))
+            })
+            .cloned()
+    }
+
+    /// The receivers this response describes, reached at `from` unless it
+    /// gives their address
+    fn receivers(&self, from: IpAddr) -> Vec<DiscoveredReceiver> {
+        if !self.response {
+            return Vec::new();
+        }
+        let service = name(SERVICE_TYPE);
+        self.records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Ptr { name, target } if same_name(name, &service) => Some(target),
+                _ => None,
+            })
+            .filter_map(|instance| {
+                let (port, host) = self.records.iter().find_map(|record| match record {
+                    Record::Srv { name, port, target } if same_name(name, instance) => {
+                        Some((*port, target))
+                    }
+                    _ => None,
+                })?;
+                let ip = self
+                    .records
+                    .iter()
+                    .find_map(|record| match record {
+                        Record::A { name, ip } if same_name(name, host) => Some(IpAddr::V4(*ip)),
+                        _ => None,
+                    })
+                    .unwrap_or(from);
+                let transport = self
+                    .records
+                    .iter()
+                    .find_map(|record| match record {
+                        Record::Txt { name, entries } if same_name(name, instance) => entries
+                            .iter()
+                            .find_map(|entry| entry.strip_prefix("transport="))
+                            .and_then(|transport| transport.parse().ok()),
+                        _ => None,
+                    })
+                    .unwrap_or(Transport::Tcp);
+                Some(DiscoveredReceiver {
+                    name: instance.first()?.clone(),
+                    addr: SocketAddr::V4(SocketAddrV4::new(
+                        match ip {
+                            IpAddr::V4(ip) => ip,
+                            IpAddr::V6(_) => return None,
+                        },
+                        port,
+                    )),
+                    transport,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Read the possibly compressed name at `at`, returning it and the
+/// position after it
+fn read_name(bytes: &[u8], mut at: usize) -> Option<(Name, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds the pointers followed, so a pointer loop cannot hang
+    for _ in 0..128 {
+        let len = *bytes.get(at)?;
+        match len {
+            0 => return Some((labels, end.unwrap_or(at + 1))),
+            len if len & 0xc0 == 0xc0 => {
+                let pointer = usize::from(u16::from_be_bytes([len & 0x3f, *bytes.get(at + 1)?]));
+                end.get_or_insert(at + 2);
+                at = pointer;
+            }
+            len if len < 64 => {
+                let label = bytes.get(at + 1..at + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                at += 1 + usize::from(len);
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertisement() -> Advertisement {
+        Advertisement {
+            name: "Studio Mac.2".to_string(),
+            port: 8080,
+            transport: Transport::Udp,
+        }
+    }
+
+    #[test]
+    fn test_query_is_answered_and_the_answer_found() {
+        let query = Message::parse(&encode_query(7)).unwrap();
+        assert_eq!(query.id, 7);
+        let question = query.question_for(&advertisement()).unwrap();
+
+        let response = encode_response(
+            &advertisement(),
+            7,
+            Some(&question),
+            Some(Ipv4Addr::new(192, 0, 2, 5)),
+            RECORD_TTL,
+        );
+        let response = Message::parse(&response).unwrap();
+        assert_eq!(response.questions, [question]);
+        // A response is not a query to answer
+        assert!(response.question_for(&advertisement()).is_none());
+        assert_eq!(
+            response.receivers("198.51.100.1".parse().unwrap()),
+            [DiscoveredReceiver {
+                name: "Studio Mac.2".to_string(),
+                addr: "192.0.2.5:8080".parse().unwrap(),
+                transport: Transport::Udp,
+            }]
+        );
+
+        // Without an A record, the answer's source address is used
+        let response =
+            Message::parse(&encode_response(&advertisement(), 0, None, None, 0)).unwrap();
+        assert_eq!(
+            response.receivers("198.51.100.1".parse().unwrap())[0].addr,
+            "198.51.100.1:8080".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_other_services_and_malformed_messages_are_ignored() {
+        let mut other = Vec::new();
+        push_header(&mut other, 0, 0, 1, 0);
+        push_name(&mut other, &name("_http._tcp.local"));
+        other.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        other.extend_from_slice(&CLASS_IN.to_be_bytes());
+        assert!(
+            Message::parse(&other)
+                .unwrap()
+                .question_for(&advertisement())
+                .is_none()
+        );
+
+        let query = encode_query(1);
+        assert!(Message::parse(&query[..query.len() - 3]).is_none());
+        // A pointer to itself
+        assert!(read_name(&[0xc0, 0x00], 0).is_none());
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        // "local" at 0, then "_rsonance._tcp" pointing back to it
+        let mut bytes = vec![5];
+        bytes.extend_from_slice(b"local");
+        bytes.push(0);
+        bytes.push(9);
+        bytes.extend_from_slice(b"_rsonance");
+        bytes.push(4);
+        bytes.extend_from_slice(b"_tcp");
+        bytes.extend_from_slice(&[0xc0, 0x00]);
+        let (name, end) = read_name(&bytes, 7).unwrap();
+        assert!(same_name(&name, &super::name("_RSONANCE._tcp.local")));
+        assert_eq!(end, bytes.len());
+    }
+}
@@ -0,0 +1,200 @@
+//! Debug dumps of the raw network stream received by the receiver
+//!
+//! With `--debug-dump <file>` the receiver records every block of bytes read
+//! from each transmitter, exactly as received and before decoding, together
+//! with its arrival time. `rsonance replay` sends a dump back to a receiver
+//! with the original timing, so user-reported audio problems can be
+//! reproduced offline.
+//!
+//! # Format
+//!
+//! The file starts with the magic `RSDUMP01`, one byte giving the length of
+//! the codec name and the name itself (e.g. `s16le`). Each record follows as
+//! a connection id (`u64`), the arrival time in microseconds since the dump
+//! was created (`u64`), the data length (`u32`) and the data, all integers
+//! little-endian. A record with no data marks the end of a connection.
+
+use crate::codec::Codec;
+use log::error;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Magic bytes at the start of a dump file
+const DUMP_MAGIC: &[u8; 8] = b"RSDUMP01";
+
+/// Size of a record header in bytes
+const RECORD_HEADER_SIZE: usize = 8 + 8 + 4;
+
+/// Records the bytes received from every transmitter to a dump file
+///
+/// Each record is written with a single unbuffered write, so the file is
+/// complete up to the last record even if the receiver is killed.
+#[derive(Debug)]
+pub struct DumpWriter {
+    file: Mutex<File>,
+    started: Instant,
+}
+
+impl DumpWriter {
+    /// Create (or truncate) the dump file at `path` for a stream using `codec`
+    pub fn create(path: &str, codec: Codec) -> anyhow::Result<Self> {
+        let mut file = File::create(path)
+            .map_err(|e| anyhow::anyhow!("Failed to create debug dump {path}: {e}"))?;
+        let name = codec.name();
+        let mut header = Vec::with_capacity(DUMP_MAGIC.len() + 1 + name.len());
+        header.extend_from_slice(DUMP_MAGIC);
+        header.push(name.len() as u8);
+        header.extend_from_slice(name.as_bytes());
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            started: Instant::now(),
+        })
+    }
+
+    /// When the dump was created, which record times are measured from
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Record `data` received on connection `connection`
+    ///
+    /// An empty `data` records the end of the connection. Write errors are
+    /// logged rather than returned, so a full disk does not stop the stream.
+    pub fn record(&self, connection: u64, data: &[u8]) {
+        let micros = self.started.elapsed().as_micros() as u64;
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+        record.extend_from_slice(&connection.to_le_bytes());
+        record.extend_from_slice(&micros.to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = file.write_all(&record) {
+            error!("Failed to write debug dump: {e}");
+        }
+    }
+}
+
+/// One block of bytes from a dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpRecord {
+    /// Receiver connection id the bytes arrived on
+    pub connection: u64,
+    /// Arrival time relative to the start of the dump
+    pub offset: Duration,
+    /// Bytes as received; empty when the connection ended
+    pub data: Vec<u8>,
+}
+
+/// Reads the records of a dump file
+#[derive(Debug)]
+pub struct DumpReader<R> {
+    reader: R,
+    codec: Codec,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Read the dump header from `reader`
+    ///
+    /// # Returns
+    ///
+    /// The reader positioned at the first record, or an error if the data is
+    /// not a dump or uses an unknown codec
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; DUMP_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != DUMP_MAGIC {
+            return Err(anyhow::anyhow!("Not an rsonance debug dump"));
+        }
+        let mut len = [0u8; 1];
+        reader.read_exact(&mut len)?;
+        let mut name = vec![0u8; usize::from(len[0])];
+        reader.read_exact(&mut name)?;
+        let codec = String::from_utf8_lossy(&name).parse()?;
+
+        Ok(Self { reader, codec })
+    }
+
+    /// Codec of the recorded stream
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Read the next record, or `None` at the end of the dump
+    ///
+    /// A record cut short by the end of the file, e.g. because the receiver
+    /// was killed while writing it, is treated as the end of the dump.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<DumpRecord>> {
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        if !self.read_full(&mut header)? {
+            return Ok(None);
+        }
+        let connection = u64::from_le_bytes(header[0..8].try_into()?);
+        let micros = u64::from_le_bytes(header[8..16].try_into()?);
+        let len = u32::from_le_bytes(header[16..20].try_into()?) as usize;
+
+        let mut data = vec![0u8; len];
+        if !self.read_full(&mut data)? {
+            return Ok(None);
+        }
+        Ok(Some(DumpRecord {
+            connection,
+            offset: Duration::from_micros(micros),
+            data,
+        }))
+    }
+
+    /// Fill `buf`, returning `false` if the input ends first
+    fn read_full(&mut self, buf: &mut [u8]) -> anyhow::Result<bool> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = anyhow::Result<DumpRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_round_trip() {
+        let path = format!("/tmp/rsonance_dump_test_{}", std::process::id());
+        let writer = DumpWriter::create(&path, Codec::G711U).unwrap();
+        writer.record(1, &[1, 2, 3]);
+        writer.record(2, &[4]);
+        writer.record(1, &[]);
+        drop(writer);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut reader = DumpReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(reader.codec(), Codec::G711U);
+
+        let first = reader.next_record().unwrap().unwrap();
+        assert_eq!((first.connection, first.data), (1, vec![1, 2, 3]));
+        let second = reader.next_record().unwrap().unwrap();
+        assert_eq!((second.connection, second.data), (2, vec![4]));
+        assert!(second.offset >= first.offset);
+        // The last record was truncated
+        assert_eq!(reader.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn test_dump_reader_rejects_other_files() {
+        assert!(DumpReader::new(&b"RIFF\0\0\0\0WAVE"[..]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Events of a running receiver, for applications embedding it
+//!
+//! [`Receiver::events`](crate::receiver::Receiver::events) subscribes to the
+//! transmitters connecting, being refused, kicked through the control
+//! socket and disconnecting, so a GUI or supervisor can react to them
+//! without polling [`Receiver::clients`](crate::receiver::Receiver::clients).
+//!
+//! Every subscription gets every event from the moment it was made, in the
+//! order they happened for each connection. A subscription whose receiving
+//! end is dropped is forgotten at the next event.
+
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Something that happened to a transmitter connection
+///
+/// Each accepted connection produces a [`Connected`](Self::Connected) event
+/// and, once it ends for whatever reason, a
+/// [`Disconnected`](Self::Disconnected) event with the same id. Connection
+/// ids match the receiver's log lines and the control socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReceiverEvent {
+    /// A transmitter connected
+    Connected {
+        id: u64,
+        /// Remote address, `None` for a Unix socket connection
+        peer: Option<SocketAddr>,
+    },
+    /// A connection was closed at once, before getting an id: `reason` is
+    /// `rate_limited`, `banned` or `error`, as in the audit log
+    Refused {
+        peer: Option<SocketAddr>,
+        reason: &'static str,
+    },
+    /// A transmitter was disconnected by the control socket's `kick`
+    /// command; its `Disconnected` event follows
+    Kicked { id: u64 },
+    /// A connection ended
+    Disconnected {
+        id: u64,
+        /// How long it was connected
+        duration: Duration,
+        /// Bytes received over it
+        bytes_received: u64,
+        /// Why it failed, `None` if the transmitter closed it or it was
+        /// kicked
+        error: Option<String>,
+    },
+}
+
+/// The subscriptions to a receiver's events
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::events::{EventBus, ReceiverEvent};
+///
+/// let bus = EventBus::default();
+/// let events = bus.subscribe();
+/// bus.emit(ReceiverEvent::Kicked { id: 3 });
+/// assert_eq!(events.try_recv().unwrap(), ReceiverEvent::Kicked { id: 3 });
+/// ```
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<ReceiverEvent>>>,
+}
+
+impl EventBus {
+    /// A new subscription to every event emitted from now on
+    pub fn subscribe(&self) -> Receiver<ReceiverEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().push(sender);
+        receiver
+    }
+
+    /// Send `event` to every subscription still being received from
+    pub fn emit(&self, event: ReceiverEvent) {
+        self.lock()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Sender<ReceiverEvent>>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_subscription_gets_every_event() {
+        let bus = EventBus::default();
+        bus.emit(ReceiverEvent::Kicked { id: 1 });
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.emit(ReceiverEvent::Kicked { id: 2 });
+        for events in [&first, &second] {
+            assert_eq!(events.try_recv().unwrap(), ReceiverEvent::Kicked { id: 2 });
+            assert!(events.try_recv().is_err());
+        }
+
+        // A dropped subscription is forgotten
+        drop(first);
+        bus.emit(ReceiverEvent::Kicked { id: 3 });
+        assert_eq!(bus.lock().len(), 1);
+        assert_eq!(second.try_recv().unwrap(), ReceiverEvent::Kicked { id: 3 });
+    }
+}
@@ -0,0 +1,233 @@
+//! Receiver connection rate limit and temporary bans
+//!
+//! A transmitter connection is only refused as busy once its header is
+//! read, and one sending the active transmitter's client id replaces it, so
+//! a peer that keeps connecting can tie up the receiver or, with a copied
+//! id, hold the real transmitter off the air. The receiver therefore limits
+//! how often one address may connect (`--max-connection-rate`) and closes
+//! connections over the limit before they are read. Connections over the limit and streams that turn out
+//! malformed are strikes; a peer with `--ban-after` strikes within
+//! [`STRIKE_WINDOW`] is banned for `--ban-secs`, during which its
+//! connections are closed at once.
+//!
+//! IPv6 peers are tracked by their /64 prefix, which a single host can
+//! usually pick addresses from at will.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Period `--max-connection-rate` counts connections over
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Period strikes are counted over for `--ban-after`
+pub const STRIKE_WINDOW: Duration = Duration::from_secs(600);
+
+/// Peer count above which peers with nothing recent are forgotten
+const MAX_TRACKED_PEERS: usize = 4096;
+
+/// Whether a new connection may proceed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// The connection may proceed
+    Allowed,
+    /// Over the rate limit; `banned` if this strike started a ban
+    RateLimited { banned: bool },
+    /// The peer is banned for the time given
+    Banned(Duration),
+}
+
+/// Connection and strike history of each peer
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::guard::{Admission, PeerGuard};
+/// use std::time::Duration;
+///
+/// let guard = PeerGuard::new(2, 1, Duration::from_secs(60));
+/// let peer = "192.0.2.1".parse().unwrap();
+/// assert_eq!(guard.admit(peer), Admission::Allowed);
+/// assert_eq!(guard.admit(peer), Admission::Allowed);
+/// assert_eq!(guard.admit(peer), Admission::RateLimited { banned: true });
+/// assert!(matches!(guard.admit(peer), Admission::Banned(_)));
+/// ```
+#[derive(Debug)]
+pub struct PeerGuard {
+    /// Connections allowed per [`RATE_WINDOW`] (0 = unlimited)
+    max_rate: u32,
+    /// Strikes per [`STRIKE_WINDOW`] that ban a peer (0 = never)
+    ban_after: u32,
+    ban_duration: Duration,
+    peers: Mutex<HashMap<IpAddr, PeerRecord>>,
+}
+
+#[derive(Debug, Default)]
+struct PeerRecord {
+    connections: VecDeque<Instant>,
+    strikes: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl PeerRecord {
+    /// Drop connections and strikes that no longer count
+    fn expire(&mut self, now: Instant) {
+        let expired = |window| move |time: &Instant| now.duration_since(*time) >= window;
+        while self.connections.front().is_some_and(expired(RATE_WINDOW)) {
+            self.connections.pop_front();
+        }
+        while self.strikes.front().is_some_and(expired(STRIKE_WINDOW)) {
+            self.strikes.pop_front();
+        }
+        if self.banned_until.is_some_and(|until| until <= now) {
+            self.banned_until = None;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.connections.is_empty() && self.strikes.is_empty() && self.banned_until.is_none()
+    }
+}
+
+impl PeerGuard {
+    /// Guard allowing `max_rate` connections per minute from one peer and
+    /// banning it for `ban_duration` after `ban_after` strikes
+    pub fn new(max_rate: u32, ban_after: u32, ban_duration: Duration) -> Self {
+        Self {
+            max_rate,
+            ban_after,
+            ban_duration,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a connection from `peer` and decide whether it may proceed
+    pub fn admit(&self, peer: IpAddr) -> Admission {
+        self.admit_at(peer, Instant::now())
+    }
+
+    /// Record a malformed stream from `peer`
+    ///
+    /// Returns whether this strike banned the peer.
+    pub fn strike(&self, peer: IpAddr) -> bool {
+        self.strike_at(peer, Instant::now())
+    }
+
+    fn admit_at(&self, peer: IpAddr, now: Instant) -> Admission {
+        let mut peers = self.lock();
+        if peers.len() > MAX_TRACKED_PEERS {
+            peers.retain(|_, record| {
+                record.expire(now);
+                !record.is_idle()
+            });
+        }
+        let record = peers.entry(peer_key(peer)).or_default();
+        record.expire(now);
+        if let Some(until) = record.banned_until {
+            return Admission::Banned(until - now);
+        }
+
+        record.connections.push_back(now);
+        if self.max_rate > 0 && record.connections.len() > self.max_rate as usize {
+            let banned = self.add_strike(record, now);
+            return Admission::RateLimited { banned };
+        }
+        Admission::Allowed
+    }
+
+    fn strike_at(&self, peer: IpAddr, now: Instant) -> bool {
+        let mut peers = self.lock();
+        let record = peers.entry(peer_key(peer)).or_default();
+        record.expire(now);
+        record.banned_until.is_none() && self.add_strike(record, now)
+    }
+
+    /// Count a strike against `record`, banning it once it has enough
+    fn add_strike(&self, record: &mut PeerRecord, now: Instant) -> bool {
+        record.strikes.push_back(now);
+        if self.ban_after == 0 || record.strikes.len() < self.ban_after as usize {
+            return false;
+        }
+        record.strikes.clear();
+        record.connections.clear();
+        record.banned_until = Some(now + self.ban_duration);
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<IpAddr, PeerRecord>> {
+        self.peers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Address a peer is tracked under: IPv4 as is (also when mapped into
+/// IPv6), IPv6 by its /64 prefix
+fn peer_key(peer: IpAddr) -> IpAddr {
+    match peer {
+        IpAddr::V4(_) => peer,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6((u128::from(v6) & !((1u128 << 64) - 1)).into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_resets_after_the_window() {
+        let guard = PeerGuard::new(1, 0, Duration::from_secs(60));
+        let peer = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+        assert_eq!(guard.admit_at(peer, start), Admission::Allowed);
+        assert_eq!(
+            guard.admit_at(peer, start + Duration::from_secs(1)),
+            Admission::RateLimited { banned: false }
+        );
+        // Other peers are not affected
+        assert_eq!(
+            guard.admit_at("192.0.2.2".parse().unwrap(), start),
+            Admission::Allowed
+        );
+        assert_eq!(
+            guard.admit_at(peer, start + RATE_WINDOW + Duration::from_secs(1)),
+            Admission::Allowed
+        );
+    }
+
+    #[test]
+    fn test_strikes_ban_until_it_expires() {
+        let guard = PeerGuard::new(0, 2, Duration::from_secs(300));
+        let peer = "2001:db8::1".parse().unwrap();
+        let start = Instant::now();
+        assert!(!guard.strike_at(peer, start));
+        // Strikes outside the window are forgotten
+        assert!(!guard.strike_at(peer, start + STRIKE_WINDOW));
+        // The same /64 counts as one peer
+        assert!(guard.strike_at("2001:db8::2".parse().unwrap(), start + STRIKE_WINDOW));
+
+        let banned = start + STRIKE_WINDOW + Duration::from_secs(100);
+        assert_eq!(
+            guard.admit_at(peer, banned),
+            Admission::Banned(Duration::from_secs(200))
+        );
+        assert_eq!(
+            guard.admit_at("2001:db8:0:1::1".parse().unwrap(), banned),
+            Admission::Allowed
+        );
+        assert_eq!(
+            guard.admit_at(peer, start + STRIKE_WINDOW + Duration::from_secs(300)),
+            Admission::Allowed
+        );
+    }
+
+    #[test]
+    fn test_peer_key() {
+        let key = |ip: &str| peer_key(ip.parse().unwrap()).to_string();
+        assert_eq!(key("192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("2001:db8::1:2:3:4"), "2001:db8::");
+    }
+}
@@ -0,0 +1,257 @@
+//! Receiver health check for service managers and load balancers
+//!
+//! The control socket's `health` command, run by `rsonance health`, checks
+//! that the receiver can still take a stream end to end: every listener is
+//! accepting connections (or receiving datagrams), the virtual microphone's
+//! `module-pipe-source` is loaded, and its FIFO opens for writing. With the
+//! null backend the output file is checked instead of the last two.
+//! `rsonance health` exits with an error unless every check passes, so a
+//! systemd `ExecStartPost`/watchdog script or an orchestrator's exec probe
+//! can restart a wedged receiver.
+//!
+//! # Format
+//!
+//! The answer is one tab-separated line per check: its name, `ok` or
+//! `fail`, and a detail for people.
+
+use crate::get_module_id_for_source;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Outcome of one health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// What was checked, e.g. "listener 0.0.0.0:8080"
+    pub name: String,
+    pub ok: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+}
+
+impl HealthCheck {
+    fn new(name: impl Into<String>, result: Result<String, String>) -> Self {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self {
+            name: name.into(),
+            ok,
+            detail,
+        }
+    }
+
+    /// Encode as a tab-separated control protocol line (without newline)
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.name,
+            if self.ok { "ok" } else { "fail" },
+            self.detail.replace(['\t', '\n'], " ")
+        )
+    }
+
+    /// Decode a line produced by [`HealthCheck::to_line`]
+    ///
+    /// # Returns
+    ///
+    /// The decoded check, or an error if the line is malformed
+    pub fn from_line(line: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, status, detail] = fields[..] else {
+            return Err(anyhow::anyhow!("Malformed health line: {line}"));
+        };
+        let ok = match status {
+            "ok" => true,
+            "fail" => false,
+            _ => return Err(anyhow::anyhow!("Malformed health line: {line}")),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            ok,
+            detail: detail.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.ok { "ok" } else { "FAIL" };
+        write!(f, "{status:<4} {}: {}", self.name, self.detail)
+    }
+}
+
+/// Whether every check passed
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::health::{HealthCheck, is_healthy};
+///
+/// let check = HealthCheck::from_line("listener 0.0.0.0:8080\tok\taccepting").unwrap();
+/// assert!(is_healthy(&[check.clone()]));
+/// assert!(!is_healthy(&[]));
+/// assert_eq!(check.to_string(), "ok   listener 0.0.0.0:8080: accepting");
+/// ```
+pub fn is_healthy(checks: &[HealthCheck]) -> bool {
+    !checks.is_empty() && checks.iter().all(|check| check.ok)
+}
+
+/// What the receiver's health is checked against
+///
+/// Listeners report whether they are accepting through the flag
+/// [`HealthProbe::add_listener`] returns; the output is checked when asked.
+#[derive(Debug, Default)]
+pub struct HealthProbe {
+    listeners: Mutex<Vec<(String, Arc<AtomicBool>)>>,
+    /// Source whose pipe-source module has to be loaded
+    microphone: Option<String>,
+    /// FIFO feeding the virtual microphone
+    fifo: Option<String>,
+    /// Output file of the null backend
+    output_file: Option<String>,
+}
+
+impl HealthProbe {
+    /// Probe for a receiver feeding the virtual microphone `microphone`
+    /// through the FIFO at `fifo`
+    pub fn for_microphone(microphone: &str, fifo: &str) -> Self {
+        Self {
+            microphone: Some(microphone.to_string()),
+            fifo: Some(fifo.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Probe for a receiver writing to the file at `path`
+    pub fn for_file(path: &str) -> Self {
+        Self {
+            output_file: Some(path.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Track a listener named `name`, which sets the returned flag while it
+    /// accepts connections
+    pub fn add_listener(&self, name: &str) -> Arc<AtomicBool> {
+        let accepting = Arc::new(AtomicBool::new(true));
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((name.to_string(), Arc::clone(&accepting)));
+        accepting
+    }
+
+    /// Run the checks
+    pub fn check(&self) -> Vec<HealthCheck> {
+        let mut checks: Vec<HealthCheck> = self
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, accepting)| {
+                HealthCheck::new(
+                    format!("listener {name}"),
+                    if accepting.load(Ordering::SeqCst) {
+                        Ok("accepting".to_string())
+                    } else {
+                        Err("failed, rebinding".to_string())
+                    },
+                )
+            })
+            .collect();
+        if let Some(microphone) = &self.microphone {
+            checks.push(HealthCheck::new(
+                "virtual microphone",
+                match get_module_id_for_source(microphone) {
+                    Ok(Some(id)) => Ok(format!("{microphone} loaded as module {id}")),
+                    Ok(None) => Err(format!("no pipe-source module for {microphone}")),
+                    Err(e) => Err(format!("cannot list modules: {e}")),
+                },
+            ));
+        }
+        if let Some(fifo) = &self.fifo {
+            checks.push(HealthCheck::new("fifo", check_fifo(fifo)));
+        }
+        if let Some(path) = &self.output_file {
+            checks.push(HealthCheck::new(
+                "output file",
+                OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .map(|_| format!("{path} is writable"))
+                    .map_err(|e| format!("{path}: {e}")),
+            ));
+        }
+        checks
+    }
+}
+
+/// Whether the FIFO at `path` exists and opens for writing without blocking,
+/// which needs a reader on the other end
+fn check_fifo(path: &str) -> Result<String, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| format!("{path}: {e}"))?;
+    if !metadata.file_type().is_fifo() {
+        return Err(format!("{path} is not a FIFO"));
+    }
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map(|_| format!("{path} is writable"))
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::ENXIO) => format!("{path} has no reader"),
+            _ => format!("{path}: {e}"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_check_fifo() {
+        let path = format!("/tmp/rsonance_health_test_{}", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        assert!(check_fifo(&path).is_err());
+
+        File::create(&path).unwrap();
+        assert_eq!(check_fifo(&path), Err(format!("{path} is not a FIFO")));
+        std::fs::remove_file(&path).unwrap();
+
+        crate::create_fifo(&path).unwrap();
+        assert_eq!(check_fifo(&path), Err(format!("{path} has no reader")));
+        let _reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        assert!(check_fifo(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_probe_reports_failed_listeners() {
+        let probe = HealthProbe::default();
+        let tcp = probe.add_listener("0.0.0.0:8080");
+        probe.add_listener("unix:/run/rsonance.sock");
+        assert!(is_healthy(&probe.check()));
+
+        tcp.store(false, Ordering::SeqCst);
+        let checks = probe.check();
+        assert!(!is_healthy(&checks));
+        assert_eq!(
+            checks[0].to_line(),
+            "listener 0.0.0.0:8080\tfail\tfailed, rebinding"
+        );
+        assert_eq!(
+            HealthCheck::from_line(&checks[0].to_line()).unwrap(),
+            checks[0]
+        );
+        assert!(HealthCheck::from_line("fifo\tmaybe\tx").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+//! Transmitter `--mute-hotkey` global mute toggle
+//!
+//! Muting in the desktop mixer does not stop rsonance from streaming
+//! whatever the capture device still picks up. With `--mute-hotkey
+//! ctrl+alt+m` the transmitter watches the keyboards itself, and while muted
+//! it sends silence in place of the captured audio, so the receiver keeps
+//! playing without a gap. Every toggle is logged.
+//!
+//! Keys are read from the Linux input devices (`/dev/input/event*`), which
+//! works under X11, Wayland and on the console alike but needs read access
+//! to them, usually through membership in the `input` group. Keyboards
+//! plugged in after startup are found by rescanning every few seconds.
+
+use libc::c_ulong;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// Directory of the input device nodes
+const INPUT_DIR: &str = "/dev/input";
+
+/// How often to look for keyboards plugged in after startup
+const RESCAN_INTERVAL: Duration = Duration::from_secs(3);
+
+/// `EV_KEY` event type of a key press or release
+const EV_KEY: u16 = 1;
+
+/// Modifier bits of a [`Hotkey`]
+const CTRL: u8 = 1;
+const ALT: u8 = 2;
+const SHIFT: u8 = 4;
+const SUPER: u8 = 8;
+
+/// Modifier names and the key codes of their left and right keys
+const MODIFIERS: &[(&str, u8, [u16; 2])] = &[
+    ("ctrl", CTRL, [29, 97]),
+    ("alt", ALT, [56, 100]),
+    ("shift", SHIFT, [42, 54]),
+    ("super", SUPER, [125, 126]),
+];
+
+/// Named keys besides letters, digits and function keys
+const NAMED_KEYS: &[(&str, u16)] = &[
+    ("esc", 1),
+    ("space", 57),
+    ("scrolllock", 70),
+    ("insert", 110),
+    ("pause", 119),
+    ("micmute", 248),
+];
+
+/// Key combination toggling the mute, such as `ctrl+alt+m`
+///
+/// Modifiers are `ctrl`, `alt`, `shift` and `super`; the key is a letter, a
+/// digit, `f1` to `f12`, `esc`, `space`, `scrolllock`, `insert`, `pause` or
+/// `micmute`. The combination fires only with exactly its modifiers held.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::hotkey::Hotkey;
+///
+/// let hotkey: Hotkey = "Ctrl+Alt+M".parse().unwrap();
+/// assert_eq!(hotkey.to_string(), "ctrl+alt+m");
+/// assert!("ctrl+".parse::<Hotkey>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkey {
+    modifiers: u8,
+    key: u16,
+    /// The key as written, for display
+    key_name: String,
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, bit, _) in MODIFIERS {
+            if self.modifiers & bit != 0 {
+                write!(f, "{name}+")?;
+            }
+        }
+        f.write_str(&self.key_name)
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+        let mut modifiers = 0;
+        for part in parts {
+            let (_, bit, _) = MODIFIERS
+                .iter()
+                .find(|(name, _, _)| *name == part)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Unknown modifier '{part}' in hotkey '{s}' (expected ctrl, alt, shift or super)"
+                    )
+                })?;
+            modifiers |= bit;
+        }
+        let key = key_code(key_name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown key '{key_name}' in hotkey '{s}'"))?;
+        Ok(Self {
+            modifiers,
+            key,
+            key_name: key_name.to_string(),
+        })
+    }
+}
+
+/// Linux key code of a key name
+fn key_code(name: &str) -> Option<u16> {
+    if let Some((_, code)) = NAMED_KEYS.iter().find(|(key, _)| *key == name) {
+        return Some(*code);
+    }
+    if let Some(number) = name.strip_prefix('f')
+        && let Ok(number) = number.parse::<u16>()
+    {
+        return match number {
+            1..=10 => Some(58 + number),
+            11 | 12 => Some(76 + number),
+            _ => None,
+        };
+    }
+    let mut chars = name.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    match c {
+        '1'..='9' => Some(c as u16 - '1' as u16 + 2),
+        '0' => Some(11),
+        _ => ["qwertyuiop", "asdfghjkl", "zxcvbnm"]
+            .iter()
+            .zip([16, 30, 44])
+            .find_map(|(row, first)| row.find(c).map(|i| first + i as u16)),
+    }
+}
+
+/// Modifiers held on one keyboard, deciding when the hotkey fires
+#[derive(Debug, Default)]
+struct KeyboardState {
+    held: u8,
+}
+
+impl KeyboardState {
+    /// Track a key event (`value` 1 = press, 0 = release, 2 = repeat)
+    ///
+    /// Returns whether it is a press of `hotkey`.
+    fn key_event(&mut self, hotkey: &Hotkey, code: u16, value: i32) -> bool {
+        if let Some((_, bit, _)) = MODIFIERS.iter().find(|(_, _, keys)| keys.contains(&code)) {
+            match value {
+                0 => self.held &= !bit,
+                _ => self.held |= bit,
+            }
+            return false;
+        }
+        value == 1 && code == hotkey.key && self.held == hotkey.modifiers
+    }
+}
+
+/// Whether the key capability bitmap of an input device, as in
+/// `/sys/class/input/eventN/device/capabilities/key`, includes `code`
+///
+/// The bitmap is written as hexadecimal words of the kernel's `long`, most
+/// significant first.
+fn has_key(capabilities: &str, code: u16) -> bool {
+    let bits = c_ulong::BITS as usize;
+    let code = usize::from(code);
+    capabilities
+        .split_whitespace()
+        .rev()
+        .nth(code / bits)
+        .and_then(|word| c_ulong::from_str_radix(word, 16).ok())
+        .is_some_and(|word| word >> (code % bits) & 1 == 1)
+}
+
+/// Watch every keyboard with `hotkey`'s key and flip the returned flag each
+/// time it is pressed
+///
+/// Keyboards plugged in later are picked up by a rescan every
+/// [`RESCAN_INTERVAL`]. Returns an error if `/dev/input` cannot be listed, or
+/// if there are keyboards but none may be read.
+pub fn spawn_mute_hotkey(hotkey: &Hotkey) -> anyhow::Result<Arc<AtomicBool>> {
+    let muted = Arc::new(AtomicBool::new(false));
+    let watched = Arc::new(Mutex::new(HashSet::new()));
+    let (found, denied) = scan_keyboards(hotkey, &muted, &watched)
+        .map_err(|e| anyhow::anyhow!("Cannot list {INPUT_DIR} for --mute-hotkey: {e}"))?;
+    match (found, denied) {
+        (0, 0) => warn!(
+            "No keyboard with the {hotkey} key found in {INPUT_DIR} yet; the mute hotkey works once one is plugged in"
+        ),
+        (0, _) => {
+            return Err(anyhow::anyhow!(
+                "No permission to read the keyboards in {INPUT_DIR}; add the user to the input group \
+                 (sudo usermod -aG input $USER, then log in again) for --mute-hotkey"
+            ));
+        }
+        _ => {}
+    }
+    info!("Press {hotkey} to mute or unmute the microphone");
+
+    thread::Builder::new()
+        .name("rsonance-hotkey-scan".to_string())
+        .spawn({
+            let (hotkey, muted) = (hotkey.clone(), Arc::clone(&muted));
+            move || {
+                loop {
+                    thread::sleep(RESCAN_INTERVAL);
+                    if let Err(e) = scan_keyboards(&hotkey, &muted, &watched) {
+                        debug!("Cannot list {INPUT_DIR} for the mute hotkey: {e}");
+                    }
+                }
+            }
+        })?;
+    Ok(muted)
+}
+
+/// Start watching the keyboards with `hotkey`'s key that are not in
+/// `watched` yet
+///
+/// Returns how many keyboards are watched now and how many could not be
+/// opened for lack of permission.
+fn scan_keyboards(
+    hotkey: &Hotkey,
+    muted: &Arc<AtomicBool>,
+    watched: &Arc<Mutex<HashSet<String>>>,
+) -> std::io::Result<(usize, usize)> {
+    let mut denied = 0;
+    for entry in std::fs::read_dir(INPUT_DIR)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("event")
+            || watched
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains(&name)
+        {
+            continue;
+        }
+        let capabilities =
+            std::fs::read_to_string(format!("/sys/class/input/{name}/device/capabilities/key"))
+                .unwrap_or_default();
+        if !has_key(&capabilities, hotkey.key) {
+            continue;
+        }
+        let device = match File::open(entry.path()) {
+            Ok(device) => device,
+            Err(e) => {
+                if e.kind() == ErrorKind::PermissionDenied {
+                    denied += 1;
+                }
+                debug!(
+                    "Cannot watch {} for the mute hotkey: {e}",
+                    entry.path().display()
+                );
+                continue;
+            }
+        };
+        debug!("Watching {} for the mute hotkey", entry.path().display());
+        watched
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.clone());
+        let spawned = thread::Builder::new()
+            .name(format!("rsonance-hotkey-{name}"))
+            .spawn({
+                let (hotkey, muted) = (hotkey.clone(), Arc::clone(muted));
+                let (watched, name) = (Arc::clone(watched), name.clone());
+                move || {
+                    watch_keyboard(device, &hotkey, &muted);
+                    watched
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .remove(&name);
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Cannot watch {name} for the mute hotkey: {e}");
+            watched
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&name);
+        }
+    }
+    let found = watched.lock().unwrap_or_else(PoisonError::into_inner).len();
+    Ok((found, denied))
+}
+
+/// Read key events from one keyboard until it goes away
+fn watch_keyboard(mut device: File, hotkey: &Hotkey, muted: &AtomicBool) {
+    // struct input_event ends in u16 type, u16 code and i32 value, after a
+    // timestamp whose size depends on the platform
+    let mut event = [0u8; size_of::<libc::input_event>()];
+    let tail = event.len() - 8;
+    let mut state = KeyboardState::default();
+    loop {
+        if let Err(e) = device.read_exact(&mut event) {
+            warn!("Stopped watching a keyboard for the mute hotkey: {e}");
+            return;
+        }
+        let kind = u16::from_ne_bytes([event[tail], event[tail + 1]]);
+        let code = u16::from_ne_bytes([event[tail + 2], event[tail + 3]]);
+        let value = i32::from_ne_bytes([
+            event[tail + 4],
+            event[tail + 5],
+            event[tail + 6],
+            event[tail + 7],
+        ]);
+        if kind == EV_KEY && state.key_event(hotkey, code, value) {
+            if muted.fetch_not(Ordering::SeqCst) {
+                info!("Microphone unmuted");
+            } else {
+                info!("Microphone muted, sending silence until {hotkey} is pressed again");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkey() {
+        let key = |s: &str| {
+            s.parse::<Hotkey>()
+                .map(|hotkey| (hotkey.modifiers, hotkey.key))
+        };
+        assert_eq!(key("m").unwrap(), (0, 50));
+        assert_eq!(key("super+shift+a").unwrap(), (SUPER | SHIFT, 30));
+        assert_eq!(key("ctrl + F12").unwrap(), (CTRL, 88));
+        assert_eq!(key("alt+f1").unwrap(), (ALT, 59));
+        assert_eq!(key("0").unwrap(), (0, 11));
+        assert_eq!(key("micmute").unwrap(), (0, 248));
+        assert!(key("hyper+m").is_err());
+        assert!(key("f13").is_err());
+        assert!(key("ctrl+mm").is_err());
+    }
+
+    #[test]
+    fn test_hotkey_needs_exactly_its_modifiers() {
+        let hotkey: Hotkey = "ctrl+m".parse().unwrap();
+        let mut state = KeyboardState::default();
+        assert!(!state.key_event(&hotkey, 50, 1));
+        // Right ctrl down, m pressed, repeated and released
+        assert!(!state.key_event(&hotkey, 97, 1));
+        assert!(state.key_event(&hotkey, 50, 1));
+        assert!(!state.key_event(&hotkey, 50, 2));
+        assert!(!state.key_event(&hotkey, 50, 0));
+        // With shift held too it is a different combination
+        assert!(!state.key_event(&hotkey, 42, 1));
+        assert!(!state.key_event(&hotkey, 50, 1));
+        assert!(!state.key_event(&hotkey, 42, 0));
+        assert!(state.key_event(&hotkey, 50, 1));
+        assert!(!state.key_event(&hotkey, 97, 0));
+        assert!(!state.key_event(&hotkey, 50, 1));
+    }
+
+    /// A capabilities bitmap with `codes` set, as the kernel writes it in
+    /// words of its `long`
+    fn bitmap(codes: &[u16]) -> String {
+        let bits = c_ulong::BITS as usize;
+        let mut words: Vec<c_ulong> = vec![0; 1 + usize::from(*codes.iter().max().unwrap()) / bits];
+        for &code in codes {
+            words[usize::from(code) / bits] |= 1 << (usize::from(code) % bits);
+        }
+        let words: Vec<String> = words.iter().rev().map(|word| format!("{word:x}")).collect();
+        words.join(" ")
+    }
+
+    #[test]
+    fn test_has_key() {
+        // A keyboard: esc (1) and the micmute key (248)
+        let keyboard = &bitmap(&[1, 248]);
+        assert!(has_key(keyboard, 1));
+        assert!(has_key(keyboard, 248));
+        assert!(!has_key(keyboard, 50));
+        assert!(!has_key("0", 1));
+        assert!(!has_key("", 1));
+    }
+}
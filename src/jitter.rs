@@ -0,0 +1,288 @@
+//! Receiver `--jitter-buffer-ms` adaptive jitter buffer
+//!
+//! Without it, the audio is written to the output as it arrives from the
+//! network, so a stall longer than the FIFO holds is heard as a stutter and
+//! the burst that follows it piles up as latency. With a jitter buffer the
+//! decoded audio is collected in memory and a separate thread plays it out
+//! at the stream's sample rate, smoothing the bursts over.
+//!
+//! Playback starts once the buffer holds its target latency. When it runs
+//! dry, the missing audio is replaced with silence, the target is raised by
+//! [`TARGET_STEP_MS`] (up to the maximum) and playback resumes once the
+//! buffer is filled to the new target, again with silence meanwhile. After
+//! [`SETTLE_MS`] of audio played without running dry, the target is lowered
+//! by a step (down to the minimum) and the audio above it is dropped. Audio
+//! that would take the buffer past its maximum is dropped from the oldest
+//! end, back down to the target.
+
+use std::collections::VecDeque;
+
+/// Change of the target latency after running dry or settling
+pub const TARGET_STEP_MS: u64 = 20;
+
+/// Audio played without running dry before the target is lowered
+pub const SETTLE_MS: u64 = 30_000;
+
+/// Playback state of a [`JitterBuffer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Filling up before the first playback; nothing is played
+    Filling,
+    /// Playing the queued audio
+    Playing,
+    /// Filling up again after running dry; silence is played
+    Refilling,
+}
+
+/// Change of the target latency reported by [`JitterBuffer::pull`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjustment {
+    /// The buffer ran dry and the target was raised to this many ms (or
+    /// stayed at the maximum)
+    Raised(u64),
+    /// The buffer settled and the target was lowered to this many ms
+    Lowered(u64),
+}
+
+/// Decoded audio waiting to be played out, in whole frames
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::jitter::JitterBuffer;
+///
+/// // 1 kHz mono S16LE: 2 bytes per ms
+/// let mut buffer = JitterBuffer::new(10, 10, 100, 1000, 2);
+/// let mut out = Vec::new();
+/// buffer.push(&[1; 16]);
+/// buffer.pull(4, &mut out);
+/// assert!(out.is_empty(), "still filling up to the 10 ms target");
+///
+/// buffer.push(&[1; 4]);
+/// buffer.pull(4, &mut out);
+/// assert_eq!(out, [1; 4]);
+/// assert_eq!(buffer.queued_ms(), 8);
+/// ```
+#[derive(Debug)]
+pub struct JitterBuffer {
+    queue: VecDeque<u8>,
+    state: State,
+    sample_rate: u32,
+    frame_size: usize,
+    target_ms: u64,
+    min_ms: u64,
+    max_ms: u64,
+    /// Bytes played since the target last changed or the buffer ran dry
+    settled: usize,
+    underruns: u64,
+    silence: usize,
+    dropped: usize,
+}
+
+impl JitterBuffer {
+    /// Buffer aiming for `target_ms` of audio, adapting between `min_ms` and
+    /// `max_ms`, for a stream of `sample_rate` frames of `frame_size` bytes
+    /// per second
+    pub fn new(
+        target_ms: u64,
+        min_ms: u64,
+        max_ms: u64,
+        sample_rate: u32,
+        frame_size: usize,
+    ) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            state: State::Filling,
+            sample_rate,
+            frame_size: frame_size.max(1),
+            target_ms: target_ms.clamp(min_ms, max_ms.max(min_ms)),
+            min_ms,
+            max_ms: max_ms.max(min_ms),
+            settled: 0,
+            underruns: 0,
+            silence: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Bytes of `ms` milliseconds of audio, in whole frames
+    fn ms_to_bytes(&self, ms: u64) -> usize {
+        (u64::from(self.sample_rate) * ms / 1000) as usize * self.frame_size
+    }
+
+    fn bytes_to_ms(&self, bytes: usize) -> u64 {
+        (bytes / self.frame_size) as u64 * 1000 / u64::from(self.sample_rate.max(1))
+    }
+
+    /// Queue received audio, dropping the oldest audio down to the target
+    /// if the buffer would exceed its maximum
+    pub fn push(&mut self, audio: &[u8]) {
+        self.queue.extend(audio);
+        if self.queue.len() > self.ms_to_bytes(self.max_ms) {
+            self.drop_to(self.ms_to_bytes(self.target_ms));
+        }
+        let target = self.ms_to_bytes(self.target_ms);
+        if self.state != State::Playing && self.queue.len() >= target {
+            self.state = State::Playing;
+        }
+    }
+
+    /// Append `len` bytes (whole frames) of audio to play to `out`
+    ///
+    /// Nothing is appended before the first playback. Audio missing after
+    /// that is made up with silence. Returns the change of the target, if
+    /// any.
+    pub fn pull(&mut self, len: usize, out: &mut Vec<u8>) -> Option<Adjustment> {
+        let len = len - len % self.frame_size;
+        match self.state {
+            State::Filling => None,
+            State::Refilling => {
+                self.fill_silence(len, out);
+                None
+            }
+            State::Playing if self.queue.len() < len => {
+                let available = self.queue.len() - self.queue.len() % self.frame_size;
+                out.extend(self.queue.drain(..available));
+                self.fill_silence(len - available, out);
+                self.state = State::Refilling;
+                self.underruns += 1;
+                self.settled = 0;
+                self.target_ms = (self.target_ms + TARGET_STEP_MS).min(self.max_ms);
+                Some(Adjustment::Raised(self.target_ms))
+            }
+            State::Playing => {
+                out.extend(self.queue.drain(..len));
+                self.settled += len;
+                if self.settled < self.ms_to_bytes(SETTLE_MS) || self.target_ms <= self.min_ms {
+                    return None;
+                }
+                self.settled = 0;
+                self.target_ms = self
+                    .target_ms
+                    .saturating_sub(TARGET_STEP_MS)
+                    .max(self.min_ms);
+                if self.queue.len() > self.ms_to_bytes(self.target_ms) {
+                    self.drop_to(self.ms_to_bytes(self.target_ms));
+                }
+                Some(Adjustment::Lowered(self.target_ms))
+            }
+        }
+    }
+
+    /// Take all the queued audio, for playing what is left once the stream
+    /// has ended
+    pub fn drain(&mut self) -> Vec<u8> {
+        let len = self.queue.len() - self.queue.len() % self.frame_size;
+        self.queue.drain(..len).collect()
+    }
+
+    fn fill_silence(&mut self, len: usize, out: &mut Vec<u8>) {
+        out.resize(out.len() + len, 0);
+        self.silence += len;
+    }
+
+    fn drop_to(&mut self, len: usize) {
+        let excess = self.queue.len().saturating_sub(len);
+        let excess = excess - excess % self.frame_size;
+        self.queue.drain(..excess);
+        self.dropped += excess;
+    }
+
+    /// Audio queued, in bytes
+    pub fn queued_bytes(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Audio queued, in milliseconds
+    pub fn queued_ms(&self) -> u64 {
+        self.bytes_to_ms(self.queue.len())
+    }
+
+    /// Current target latency in milliseconds
+    pub fn target_ms(&self) -> u64 {
+        self.target_ms
+    }
+
+    /// Times the buffer ran dry during playback
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Silence played in place of missing audio, in milliseconds
+    pub fn silence_ms(&self) -> u64 {
+        self.bytes_to_ms(self.silence)
+    }
+
+    /// Audio dropped to stay within the limits, in milliseconds
+    pub fn dropped_ms(&self) -> u64 {
+        self.bytes_to_ms(self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underrun_plays_silence_and_raises_the_target() {
+        // 1 kHz stereo S16LE: 4 bytes per ms
+        let mut buffer = JitterBuffer::new(10, 10, 40, 1000, 4);
+        let mut out = Vec::new();
+        buffer.push(&[1; 40]);
+        assert_eq!(buffer.pull(32, &mut out), None);
+        assert_eq!(out, [1; 32]);
+
+        // 2 ms left for 4 ms of playback
+        out.clear();
+        assert_eq!(buffer.pull(16, &mut out), Some(Adjustment::Raised(30)));
+        assert_eq!(out[..8], [1; 8]);
+        assert_eq!(out[8..], [0; 8]);
+
+        // Silence until the raised target of 30 ms is reached
+        buffer.push(&[2; 100]);
+        out.clear();
+        buffer.pull(8, &mut out);
+        assert_eq!(out, [0; 8]);
+        buffer.push(&[2; 20]);
+        out.clear();
+        buffer.pull(8, &mut out);
+        assert_eq!(out, [2; 8]);
+
+        assert_eq!(buffer.underruns(), 1);
+        assert_eq!(buffer.silence_ms(), 4);
+        assert_eq!(buffer.target_ms(), 30);
+        // Raised no further than the maximum
+        assert_eq!(buffer.pull(1000, &mut out), Some(Adjustment::Raised(40)));
+        assert_eq!(buffer.pull(1000, &mut out), None);
+        buffer.push(&[3; 160]);
+        assert_eq!(buffer.pull(1000, &mut out), Some(Adjustment::Raised(40)));
+    }
+
+    #[test]
+    fn test_overflow_and_settling_drop_the_oldest_audio() {
+        let mut buffer = JitterBuffer::new(40, 20, 100, 1000, 2);
+        let mut out = Vec::new();
+        buffer.push(&[1; 150]);
+        buffer.push(&[2; 60]);
+        // Past 100 ms, back down to the 40 ms target
+        assert_eq!(buffer.queued_ms(), 40);
+        assert_eq!(buffer.dropped_ms(), 65);
+        buffer.pull(20, &mut out);
+        assert_eq!(out, [1; 20]);
+
+        // After SETTLE_MS of steady playback the target drops a step
+        let step = 2 * 10;
+        let mut lowered = None;
+        for _ in 0..SETTLE_MS / 10 {
+            buffer.push(&[3; 20]);
+            out.clear();
+            if let Some(adjustment) = buffer.pull(step, &mut out) {
+                lowered = Some(adjustment);
+                break;
+            }
+        }
+        assert_eq!(lowered, Some(Adjustment::Lowered(20)));
+        assert_eq!(buffer.queued_ms(), 20);
+        assert_eq!(buffer.drain().len(), 40);
+    }
+}
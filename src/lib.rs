@@ -22,7 +22,7 @@
 //! // Set up virtual microphone
 //! match setup_virtual_microphone() {
 //!     Ok(VirtualMicResult::Success) => info!("Virtual microphone created"),
-//!     Ok(VirtualMicResult::Failed) => log::warn!("Failed to create virtual microphone"),
+//!     Ok(VirtualMicResult::Failed(reason)) => log::warn!("Failed to create virtual microphone: {reason}"),
 //!     Err(e) => log::error!("Error: {}", e),
 //! }
 //!
@@ -30,12 +30,61 @@
 //! cleanup_virtual_microphone().unwrap();
 //! ```
 
+pub mod access;
+pub mod anomaly;
+pub mod audit;
+pub mod codec;
+pub mod continuity;
+pub mod control;
+pub mod dehum;
+pub mod demand;
+pub mod device;
+pub mod discovery;
+pub mod dump;
+pub mod events;
+pub mod guard;
+pub mod health;
+pub mod hotkey;
+pub mod jitter;
+pub mod listen;
+pub mod manifest;
+pub mod meter;
+pub mod metrics;
+pub mod mock;
+pub mod pipeline;
+pub mod playback;
+pub mod power;
+pub mod privileges;
+pub mod protocol;
+pub mod queue;
+pub mod realtime;
 pub mod receiver;
+pub mod record;
+pub mod replay;
+pub mod resample;
+pub mod rtp;
+pub mod sandbox;
+pub mod secondary;
+pub mod session;
+pub mod sidetone;
+pub mod sink;
+pub mod spectrum;
+pub mod state;
+pub mod stats;
+pub mod transcribe;
 pub mod transmitter;
+pub mod udp;
+pub mod voice;
 
 use anyhow::Result;
-use log::{debug, error, info};
-use std::process::Command;
+use log::{debug, error, info, warn};
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Lines};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::str::FromStr;
 
 /// Configuration for audio streaming
 ///
@@ -52,7 +101,7 @@ use std::process::Command;
 /// assert_eq!(config.sample_rate, 44100);
 /// assert_eq!(config.channels, 2);
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AudioConfig {
     /// Sample rate in Hz (e.g., 44100, 48000)
     pub sample_rate: u32,
@@ -65,12 +114,14 @@ pub struct AudioConfig {
 /// Supported audio sample formats
 ///
 /// This enum represents the different audio sample formats that can be
-/// used for audio streaming. Currently supports signed 16-bit little-endian
-/// and 32-bit floating point little-endian formats.
-#[derive(Debug, Clone)]
+/// used for audio streaming. Currently supports signed 16-bit and 24-bit
+/// little-endian and 32-bit floating point little-endian formats.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AudioFormat {
     /// Signed 16-bit little-endian format (most common)
     S16LE,
+    /// Signed 24-bit little-endian format, packed in 3 bytes
+    S24LE,
     /// 32-bit floating point little-endian format
     F32LE,
 }
@@ -80,9 +131,134 @@ impl AudioFormat {
     pub fn as_pa_format(&self) -> &str {
         match self {
             AudioFormat::S16LE => "s16le",
+            AudioFormat::S24LE => "s24le",
             AudioFormat::F32LE => "f32le",
         }
     }
+
+    /// Size of one sample in bytes
+    pub fn sample_size(&self) -> usize {
+        match self {
+            AudioFormat::S16LE => 2,
+            AudioFormat::S24LE => 3,
+            AudioFormat::F32LE => 4,
+        }
+    }
+}
+
+impl AudioConfig {
+    /// Size of one frame (one sample for every channel) in bytes
+    pub fn frame_size(&self) -> usize {
+        usize::from(self.channels) * self.format.sample_size()
+    }
+
+    /// Number of bytes holding `ms` milliseconds of audio, rounded down to whole frames
+    pub fn bytes_for_ms(&self, ms: u64) -> usize {
+        (self.sample_rate as u64 * ms / 1000) as usize * self.frame_size()
+    }
+
+    /// Sample specification as `pactl` lists it, e.g. `s16le 2ch 44100Hz`
+    pub fn sample_spec(&self) -> String {
+        format!(
+            "{} {}ch {}Hz",
+            self.format.as_pa_format(),
+            self.channels,
+            self.sample_rate
+        )
+    }
+}
+
+/// Size of a network buffer, in bytes or as a duration of audio
+///
+/// Parsed from a plain byte count (`4096`) or milliseconds with an `ms`
+/// suffix (`20ms`). Durations are converted to bytes once the stream format
+/// is known, see [`BufferSize::to_bytes`].
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::BufferSize;
+///
+/// let size: BufferSize = "20ms".parse().unwrap();
+/// // 20 ms of 48 kHz stereo S16LE
+/// assert_eq!(size.to_bytes(48000, 4), 3840);
+///
+/// let size: BufferSize = "4096".parse().unwrap();
+/// assert_eq!(size.to_bytes(48000, 4), 4096);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferSize {
+    /// Fixed number of bytes
+    Bytes(usize),
+    /// Milliseconds of audio in the stream format
+    Millis(u64),
+}
+
+impl BufferSize {
+    /// Size in bytes for a stream of `frame_rate` frames per second of
+    /// `frame_size` bytes each
+    ///
+    /// Durations are rounded down to whole frames, but never below one frame.
+    pub fn to_bytes(&self, frame_rate: u32, frame_size: usize) -> usize {
+        match *self {
+            BufferSize::Bytes(bytes) => bytes,
+            BufferSize::Millis(ms) => {
+                let frames = (u64::from(frame_rate) * ms / 1000).max(1) as usize;
+                frames * frame_size
+            }
+        }
+    }
+}
+
+impl Default for BufferSize {
+    fn default() -> Self {
+        BufferSize::Bytes(4096)
+    }
+}
+
+impl From<usize> for BufferSize {
+    fn from(bytes: usize) -> Self {
+        BufferSize::Bytes(bytes)
+    }
+}
+
+impl fmt::Display for BufferSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferSize::Bytes(bytes) => write!(f, "{bytes}"),
+            BufferSize::Millis(ms) => write!(f, "{ms}ms"),
+        }
+    }
+}
+
+impl FromStr for BufferSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(ms) = s.strip_suffix("ms") {
+            return ms
+                .trim()
+                .parse()
+                .map(BufferSize::Millis)
+                .map_err(|_| anyhow::anyhow!("Invalid buffer duration '{s}' (e.g. 20ms)"));
+        }
+        s.strip_suffix('b')
+            .unwrap_or(s)
+            .trim()
+            .parse()
+            .map(BufferSize::Bytes)
+            .map_err(|_| anyhow::anyhow!("Invalid buffer size '{s}' (bytes, or e.g. 20ms)"))
+    }
+}
+
+/// Duration in milliseconds of `bytes` of audio at `frame_rate` frames per
+/// second of `frame_size` bytes, for reporting buffer latency
+pub fn bytes_to_ms(bytes: usize, frame_rate: u32, frame_size: usize) -> f64 {
+    if frame_rate == 0 || frame_size == 0 {
+        return 0.0;
+    }
+    bytes as f64 / frame_size as f64 * 1000.0 / f64::from(frame_rate)
 }
 
 impl Default for AudioConfig {
@@ -118,7 +294,7 @@ impl Default for AudioConfig {
 ///
 /// match setup_virtual_microphone() {
 ///     Ok(VirtualMicResult::Success) => info!("Virtual microphone created!"),
-///     Ok(VirtualMicResult::Failed) => log::warn!("Failed to create virtual microphone"),
+///     Ok(VirtualMicResult::Failed(reason)) => log::warn!("Failed to create virtual microphone: {reason}"),
 ///     Err(e) => log::error!("Error: {}", e),
 /// }
 /// ```
@@ -126,8 +302,101 @@ impl Default for AudioConfig {
 pub enum VirtualMicResult {
     /// Virtual microphone was created successfully
     Success,
-    /// Virtual microphone creation failed
-    Failed,
+    /// The sound server refused to create the virtual microphone
+    Failed(MicSetupFailure),
+}
+
+/// Why the sound server refused to create a virtual microphone
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::MicSetupFailure;
+///
+/// let failure = MicSetupFailure::classify("Connection failure: Connection refused", false);
+/// assert_eq!(failure, MicSetupFailure::ServerNotRunning);
+/// assert!(failure.is_transient());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MicSetupFailure {
+    /// No PulseAudio or PipeWire server could be reached
+    ServerNotRunning,
+    /// The server does not allow loading `module-pipe-source`
+    ModuleBlocked,
+    /// A source with the requested name already exists
+    NameConflict,
+    /// Any other failure, with the error output of `pactl`
+    Other(String),
+}
+
+impl MicSetupFailure {
+    /// Classify the error output of a failed `pactl load-module`
+    ///
+    /// # Arguments
+    ///
+    /// * `stderr` - Error output of `pactl`
+    /// * `name_taken` - Whether a source with the requested name exists; the
+    ///   server only reports that loading the module failed
+    pub fn classify(stderr: &str, name_taken: bool) -> Self {
+        let lower = stderr.to_lowercase();
+        if [
+            "connection refused",
+            "connection failure",
+            "pa_context_connect",
+        ]
+        .iter()
+        .any(|pattern| lower.contains(pattern))
+        {
+            MicSetupFailure::ServerNotRunning
+        } else if ["access denied", "not permitted", "not supported"]
+            .iter()
+            .any(|pattern| lower.contains(pattern))
+        {
+            MicSetupFailure::ModuleBlocked
+        } else if name_taken {
+            MicSetupFailure::NameConflict
+        } else {
+            MicSetupFailure::Other(stderr.trim().to_string())
+        }
+    }
+
+    /// Whether trying again later may succeed, e.g. while the sound server
+    /// is still starting
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            MicSetupFailure::ServerNotRunning | MicSetupFailure::Other(_)
+        )
+    }
+
+    /// What the user can do about the failure
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            MicSetupFailure::ServerNotRunning => {
+                "start PulseAudio or PipeWire (pipewire-pulse) for this user and check `pactl info`"
+            }
+            MicSetupFailure::ModuleBlocked => {
+                "the sound server does not allow loading module-pipe-source; allow module loading or use --backend null or --pipe-to"
+            }
+            MicSetupFailure::NameConflict => {
+                "another receiver or program already provides this source; stop it or pick a different --microphone-name"
+            }
+            MicSetupFailure::Other(_) => {
+                "check the sound server log, e.g. `journalctl --user -u pipewire-pulse`"
+            }
+        }
+    }
+}
+
+impl fmt::Display for MicSetupFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MicSetupFailure::ServerNotRunning => write!(f, "sound server is not running"),
+            MicSetupFailure::ModuleBlocked => write!(f, "loading the module is not allowed"),
+            MicSetupFailure::NameConflict => write!(f, "source name is already in use"),
+            MicSetupFailure::Other(stderr) => write!(f, "{stderr}"),
+        }
+    }
 }
 
 /// Creates a virtual microphone using default configuration
@@ -149,7 +418,7 @@ pub enum VirtualMicResult {
 ///
 /// match setup_virtual_microphone()? {
 ///     VirtualMicResult::Success => log::info!("Virtual microphone ready!"),
-///     VirtualMicResult::Failed => log::warn!("Setup failed"),
+///     VirtualMicResult::Failed(reason) => log::warn!("Setup failed: {reason}"),
 /// }
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
@@ -164,6 +433,7 @@ pub fn setup_virtual_microphone() -> Result<VirtualMicResult> {
         "rsonance_virtual_microphone",
         "/tmp/rsonance_audio_pipe",
         &AudioConfig::default(),
+        None,
     )
 }
 
@@ -179,12 +449,15 @@ pub fn setup_virtual_microphone() -> Result<VirtualMicResult> {
 /// * `source_name` - Name for the virtual microphone source (e.g., "my_virtual_mic")
 /// * `fifo_path` - Path where the FIFO pipe will be created (e.g., "/tmp/my_audio_pipe")
 /// * `config` - Audio configuration specifying format, sample rate, and channel count
+/// * `node_latency` - Optional PipeWire `node.latency` property (e.g. "256/48000"),
+///   see [`validate_node_latency`]
 ///
 /// # Returns
 ///
 /// Returns `Ok(VirtualMicResult::Success)` if the virtual microphone was
-/// created successfully, `Ok(VirtualMicResult::Failed)` if the PulseAudio
-/// operation failed, or `Err` if there was a system error (e.g., FIFO creation failed).
+/// created successfully, `Ok(VirtualMicResult::Failed)` with the classified
+/// reason if the PulseAudio operation failed, or `Err` if there was a system
+/// error (e.g., FIFO creation failed).
 ///
 /// # Examples
 ///
@@ -195,11 +468,12 @@ pub fn setup_virtual_microphone() -> Result<VirtualMicResult> {
 ///     "my_custom_mic",
 ///     "/tmp/my_custom_pipe",
 ///     &AudioConfig::default(),
+///     None,
 /// )?;
 ///
 /// match result {
 ///     VirtualMicResult::Success => log::info!("Custom virtual microphone created!"),
-///     VirtualMicResult::Failed => log::warn!("PulseAudio operation failed"),
+///     VirtualMicResult::Failed(reason) => log::warn!("PulseAudio operation failed: {reason}"),
 /// }
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
@@ -207,7 +481,7 @@ pub fn setup_virtual_microphone() -> Result<VirtualMicResult> {
 /// # Requirements
 ///
 /// - PulseAudio must be running
-/// - `pactl` and `mkfifo` commands must be available in PATH
+/// - `pactl` command must be available in PATH
 /// - User must have permissions to create files at `fifo_path`
 /// - User must have permissions to load PulseAudio modules
 ///
@@ -216,19 +490,22 @@ pub fn setup_virtual_microphone() -> Result<VirtualMicResult> {
 /// - If a FIFO already exists at `fifo_path`, it will be removed and recreated
 /// - The audio format, sample rate, and channels are determined by `config`
 /// - The source description will be the source name with underscores replaced by spaces
+/// - `node_latency` is only honoured by PipeWire; plain PulseAudio ignores the property
 pub fn setup_virtual_microphone_with_config(
     source_name: &str,
     fifo_path: &str,
     config: &AudioConfig,
+    node_latency: Option<&str>,
 ) -> Result<VirtualMicResult> {
     // First, ensure the FIFO exists
     if std::path::Path::new(fifo_path).exists() {
         std::fs::remove_file(fifo_path)?;
     }
+    create_fifo(fifo_path)?;
 
-    let mkfifo_status = Command::new("mkfifo").arg(fifo_path).status()?;
-    if !mkfifo_status.success() {
-        return Err(anyhow::anyhow!("Failed to create FIFO pipe at {fifo_path}"));
+    let mut source_properties = format!("device.description='{}'", source_name.replace('_', " "));
+    if let Some(latency) = node_latency {
+        source_properties.push_str(&format!(" node.latency={latency}"));
     }
 
     let output = Command::new("pactl")
@@ -240,10 +517,7 @@ pub fn setup_virtual_microphone_with_config(
             &format!("format={}", config.format.as_pa_format()),
             &format!("rate={}", config.sample_rate),
             &format!("channels={}", config.channels),
-            &format!(
-                "source_properties=device.description={}",
-                source_name.replace('_', " ")
-            ),
+            &format!("source_properties=\"{source_properties}\""),
         ])
         .output()?;
 
@@ -253,11 +527,233 @@ pub fn setup_virtual_microphone_with_config(
         Ok(VirtualMicResult::Success)
     } else {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        error!("Failed to create virtual microphone: {stderr}");
-        Ok(VirtualMicResult::Failed)
+        debug!("pactl load-module module-pipe-source failed: {stderr}");
+        Ok(VirtualMicResult::Failed(MicSetupFailure::classify(
+            &stderr,
+            source_exists(source_name),
+        )))
     }
 }
 
+/// Create a FIFO at `path`, readable and writable by everyone the umask
+/// allows, like `mkfifo` does
+fn create_fifo(path: &str) -> Result<()> {
+    let c_path = std::ffi::CString::new(path)
+        .map_err(|_| anyhow::anyhow!("FIFO path {path:?} contains a NUL byte"))?;
+    // SAFETY: `c_path` is a valid NUL-terminated string for the whole call.
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o666) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to create FIFO pipe at {path}: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the sound server has a source named `source_name`
+fn source_exists(source_name: &str) -> bool {
+    list_sources().is_ok_and(|sources| source_names(&sources).contains(&source_name))
+}
+
+/// Find a name for a new source based on `source_name` that is not taken yet
+///
+/// Returns `source_name` itself if no source has that name, otherwise the
+/// first free name of `<source_name>_2`, `<source_name>_3` and so on.
+///
+/// # Requirements
+///
+/// - PulseAudio must be running
+/// - `pactl` command must be available in PATH
+pub fn free_source_name(source_name: &str) -> Result<String> {
+    Ok(pick_free_name(source_name, &source_names(&list_sources()?)))
+}
+
+/// Sample specification of the source named `source_name` (see
+/// [`AudioConfig::sample_spec`]), or `None` if there is no such source
+///
+/// # Requirements
+///
+/// - PulseAudio must be running
+/// - `pactl` command must be available in PATH
+pub fn source_sample_spec(source_name: &str) -> Result<Option<String>> {
+    Ok(find_sample_spec(&list_sources()?, source_name).map(str::to_string))
+}
+
+/// Sample specification of `source_name` in `pactl list sources short` output
+fn find_sample_spec<'a>(sources: &'a str, source_name: &str) -> Option<&'a str> {
+    sources.lines().find_map(|line| {
+        let mut fields = line.split('\t');
+        (fields.nth(1)? == source_name).then(|| fields.nth(1))?
+    })
+}
+
+/// First of `name`, `name_2`, `name_3`, ... that is not in `taken`
+fn pick_free_name(name: &str, taken: &[&str]) -> String {
+    if !taken.contains(&name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{name}_{n}"))
+        .find(|candidate| !taken.contains(&candidate.as_str()))
+        .expect("only finitely many names are taken")
+}
+
+/// Output of `pactl list sources short`
+fn list_sources() -> Result<String> {
+    let output = Command::new("pactl")
+        .args(["list", "sources", "short"])
+        .output()?;
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Source names in `pactl list sources short` output
+fn source_names(sources: &str) -> Vec<&str> {
+    sources
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .collect()
+}
+
+/// A change reported by the sound server, see [`SoundServerEvents`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundServerEvent {
+    /// `new`, `change` or `remove`
+    pub kind: String,
+    /// Kind of object that changed, e.g. `source`, `module` or `server`
+    pub facility: String,
+    /// Index of the object; `None` for the server itself
+    pub index: Option<u32>,
+}
+
+impl SoundServerEvent {
+    /// Parse a line of `pactl subscribe` output
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rsonance::SoundServerEvent;
+    ///
+    /// let event = SoundServerEvent::parse("Event 'remove' on source #12").unwrap();
+    /// assert_eq!(event.kind, "remove");
+    /// assert_eq!(event.facility, "source");
+    /// assert_eq!(event.index, Some(12));
+    /// ```
+    pub fn parse(line: &str) -> Option<Self> {
+        let (kind, object) = line.strip_prefix("Event '")?.split_once("' on ")?;
+        let (facility, index) = match object.split_once(" #") {
+            Some((facility, index)) => (facility, Some(index.trim().parse().ok()?)),
+            None => (object.trim(), None),
+        };
+        Some(Self {
+            kind: kind.to_string(),
+            facility: facility.to_string(),
+            index,
+        })
+    }
+}
+
+/// Changes of the sound server as they happen, from `pactl subscribe`
+///
+/// Iterating blocks until the next event and ends when the sound server goes
+/// away. The `pactl` process is stopped when this is dropped.
+///
+/// # Requirements
+///
+/// - PulseAudio (or `pipewire-pulse`) must be running
+/// - `pactl` command must be available in PATH
+#[derive(Debug)]
+pub struct SoundServerEvents {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl SoundServerEvents {
+    /// Start listening for events
+    pub fn subscribe() -> Result<Self> {
+        let mut child = Command::new("pactl")
+            .arg("subscribe")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("pactl subscribe has no output"))?;
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+}
+
+impl Iterator for SoundServerEvents {
+    type Item = SoundServerEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?.ok()?;
+            if let Some(event) = SoundServerEvent::parse(&line) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+impl Drop for SoundServerEvents {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Events of the sound server as consumed by [`spawn_sound_server_watch`]
+pub(crate) type SoundServerEventStream = Box<dyn Iterator<Item = SoundServerEvent> + Send>;
+
+/// Subscribe to the sound server's events with `pactl`
+pub(crate) fn subscribe_sound_server() -> Result<SoundServerEventStream> {
+    Ok(Box::new(SoundServerEvents::subscribe()?))
+}
+
+/// Run `check` on a background thread whenever the sound server may have
+/// changed in a way it cares about
+///
+/// `check` runs after every subscription to the events from `subscribe`,
+/// since earlier changes are not reported, and then for every event
+/// `relevant` accepts. While no subscription can be set up, e.g. during a
+/// sound server restart, it runs every `interval` instead. The thread stops
+/// once `check` returns `false`.
+pub(crate) fn spawn_sound_server_watch(
+    interval: std::time::Duration,
+    relevant: impl Fn(&SoundServerEvent) -> bool + Send + 'static,
+    subscribe: impl Fn() -> Result<SoundServerEventStream> + Send + 'static,
+    mut check: impl FnMut() -> bool + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        loop {
+            match subscribe() {
+                Ok(events) => {
+                    if !check() {
+                        return;
+                    }
+                    for event in events {
+                        if relevant(&event) && !check() {
+                            return;
+                        }
+                    }
+                    debug!("Sound server events ended, checking every {interval:?}");
+                }
+                Err(e) => {
+                    debug!("Sound server events unavailable ({e}), checking every {interval:?}")
+                }
+            }
+            std::thread::sleep(interval);
+            if !check() {
+                return;
+            }
+        }
+    });
+}
+
 /// Get the module ID of the virtual microphone for cleanup
 ///
 /// This function queries PulseAudio for loaded modules and searches for
@@ -286,22 +782,211 @@ pub fn setup_virtual_microphone_with_config(
 /// - PulseAudio must be running
 /// - `pactl` command must be available in PATH
 pub fn get_virtual_microphone_module_id() -> Result<Option<String>> {
+    get_module_id_for_source("rsonance_virtual_microphone")
+}
+
+/// Get the module ID of the pipe-source module providing `source_name`
+///
+/// Like [`get_virtual_microphone_module_id`], but for a virtual microphone
+/// created with a custom name via [`setup_virtual_microphone_with_config`].
+/// The source name must match exactly, so `my_mic` does not match `my_mic_2`.
+///
+/// # Arguments
+///
+/// * `source_name` - Name of the virtual microphone source
+///
+/// # Returns
+///
+/// Returns `Ok(Some(module_id))` if a matching module is found,
+/// `Ok(None)` if no matching module is loaded, or `Err` if the query failed.
+///
+/// # Requirements
+///
+/// - PulseAudio must be running
+/// - `pactl` command must be available in PATH
+pub fn get_module_id_for_source(source_name: &str) -> Result<Option<String>> {
+    Ok(find_pipe_source_module(&list_modules()?, source_name))
+}
+
+/// Check whether module `id` is loaded with an argument set to `value`
+///
+/// Module indices start over when the sound server restarts, so a recorded
+/// index is only trusted if the module still refers to the recorded source
+/// or sink name.
+///
+/// # Requirements
+///
+/// - PulseAudio must be running
+/// - `pactl` command must be available in PATH
+pub fn module_loaded_with(id: &str, value: &str) -> Result<bool> {
+    Ok(module_has_argument_value(&list_modules()?, id, value))
+}
+
+/// Output of `pactl list modules short`
+fn list_modules() -> Result<String> {
     let output = Command::new("pactl")
         .args(["list", "modules", "short"])
         .output()?;
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Find the module ID for `source_name` in `pactl list modules short` output
+fn find_pipe_source_module(modules: &str, source_name: &str) -> Option<String> {
+    find_module(
+        modules,
+        "module-pipe-source",
+        &[format!("source_name={source_name}")],
+    )
+}
 
-    let output_str = String::from_utf8(output.stdout)?;
+/// Find the ID of a `module` loaded with all of `args` in `pactl list modules short` output
+fn find_module(modules: &str, module: &str, args: &[String]) -> Option<String> {
+    modules
+        .lines()
+        .filter(|line| line.split_whitespace().nth(1) == Some(module))
+        .find(|line| {
+            args.iter()
+                .all(|arg| line.split_whitespace().any(|field| field == arg))
+        })
+        .and_then(|line| line.split_whitespace().next())
+        .map(str::to_string)
+}
 
-    for line in output_str.lines() {
-        if line.contains("module-pipe-source")
-            && line.contains("source_name=rsonance_virtual_microphone")
-            && let Some(module_id) = line.split_whitespace().next()
-        {
-            return Ok(Some(module_id.to_string()));
+/// Whether module `id` in `pactl list modules short` output has an argument set to `value`
+fn module_has_argument_value(modules: &str, id: &str, value: &str) -> bool {
+    modules
+        .lines()
+        .filter(|line| line.split_whitespace().next() == Some(id))
+        .flat_map(|line| line.split_whitespace().skip(2))
+        .any(|arg| arg.split_once('=').is_some_and(|(_, v)| v == value))
+}
+
+/// Name of the sink created by [`setup_monitor_sink`] for `source_name`
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::monitor_sink_name;
+///
+/// assert_eq!(monitor_sink_name("rsonance_virtual_microphone"), "rsonance_virtual_microphone_monitor");
+/// ```
+pub fn monitor_sink_name(source_name: &str) -> String {
+    format!("{source_name}_monitor")
+}
+
+/// Create a null sink playing the virtual microphone `source_name`
+///
+/// A `module-null-sink` named [`monitor_sink_name`] is loaded with the same
+/// audio format as the microphone, and a `module-loopback` plays the
+/// microphone into it. Applications that only capture desktop audio, such
+/// as OBS audio output capture, can then record the remote microphone from
+/// the sink's monitor. A monitor sink left over from an earlier run is
+/// removed first.
+///
+/// # Arguments
+///
+/// * `source_name` - Name of the virtual microphone source
+/// * `config` - Audio format of the virtual microphone
+///
+/// # Returns
+///
+/// Returns the ids of the null-sink and loopback modules, or `Err` if either
+/// could not be loaded. Nothing stays loaded on error.
+///
+/// # Requirements
+///
+/// - PulseAudio must be running and provide `source_name`
+/// - `pactl` command must be available in PATH
+pub fn setup_monitor_sink(source_name: &str, config: &AudioConfig) -> Result<Vec<String>> {
+    cleanup_monitor_sink(source_name)?;
+    let sink_name = monitor_sink_name(source_name);
+
+    let null_sink = load_module(&[
+        "module-null-sink",
+        &format!("sink_name={sink_name}"),
+        &format!("format={}", config.format.as_pa_format()),
+        &format!("rate={}", config.sample_rate),
+        &format!("channels={}", config.channels),
+        &format!(
+            "sink_properties=\"device.description='{} monitor'\"",
+            source_name.replace('_', " ")
+        ),
+    ])?;
+    let loopback = load_module(&[
+        "module-loopback",
+        &format!("source={source_name}"),
+        &format!("sink={sink_name}"),
+        "source_dont_move=true",
+        "sink_dont_move=true",
+    ]);
+    match loopback {
+        Ok(loopback) => {
+            info!("Monitor sink '{sink_name}' created");
+            Ok(vec![null_sink, loopback])
+        }
+        Err(e) => {
+            unload_module(&null_sink);
+            Err(e)
         }
     }
+}
+
+/// Remove the sink created by [`setup_monitor_sink`] for `source_name`
+///
+/// # Returns
+///
+/// Returns `Ok(true)` if any of its modules were unloaded, `Ok(false)` if
+/// none were loaded, or `Err` if the modules could not be listed.
+pub fn cleanup_monitor_sink(source_name: &str) -> Result<bool> {
+    let modules = list_modules()?;
+    let sink_name = monitor_sink_name(source_name);
+    let loopback = find_module(
+        &modules,
+        "module-loopback",
+        &[format!("source={source_name}"), format!("sink={sink_name}")],
+    );
+    let null_sink = find_module(
+        &modules,
+        "module-null-sink",
+        &[format!("sink_name={sink_name}")],
+    );
 
-    Ok(None)
+    let mut unloaded = false;
+    for id in loopback.into_iter().chain(null_sink) {
+        unloaded |= unload_module(&id);
+    }
+    Ok(unloaded)
+}
+
+/// Load a PulseAudio module, returning its id
+fn load_module(args: &[&str]) -> Result<String> {
+    let output = Command::new("pactl")
+        .arg("load-module")
+        .args(args)
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(anyhow::anyhow!(
+            "Failed to load {}: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Unload PulseAudio module `id`, returning whether it succeeded
+fn unload_module(id: &str) -> bool {
+    let unloaded = Command::new("pactl")
+        .args(["unload-module", id])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if unloaded {
+        debug!("Module {id} unloaded");
+    } else {
+        warn!("Failed to unload module {id}");
+    }
+    unloaded
 }
 
 /// Remove the virtual microphone module from PulseAudio
@@ -337,9 +1022,33 @@ pub fn get_virtual_microphone_module_id() -> Result<Option<String>> {
 ///
 /// This function specifically looks for the "rsonance_virtual_microphone" source.
 /// If you used a different source name with `setup_virtual_microphone_with_config`,
-/// you may need to manually unload the module using `pactl unload-module <id>`.
+/// use [`cleanup_virtual_microphone_with_name`] instead.
 pub fn cleanup_virtual_microphone() -> Result<bool> {
-    if let Some(module_id) = get_virtual_microphone_module_id()? {
+    cleanup_virtual_microphone_with_name("rsonance_virtual_microphone")
+}
+
+/// Remove the virtual microphone module providing `source_name`
+///
+/// Like [`cleanup_virtual_microphone`], but for a virtual microphone created
+/// with a custom name via [`setup_virtual_microphone_with_config`].
+///
+/// # Arguments
+///
+/// * `source_name` - Name of the virtual microphone source
+///
+/// # Returns
+///
+/// Returns `Ok(true)` if a module was found and successfully unloaded,
+/// `Ok(false)` if no module was found or unloading failed, or `Err` if
+/// there was a system error during the operation.
+///
+/// # Requirements
+///
+/// - PulseAudio must be running
+/// - `pactl` command must be available in PATH
+/// - User must have permissions to unload PulseAudio modules
+pub fn cleanup_virtual_microphone_with_name(source_name: &str) -> Result<bool> {
+    if let Some(module_id) = get_module_id_for_source(source_name)? {
         let output = Command::new("pactl")
             .args(["unload-module", &module_id])
             .output()?;
@@ -405,6 +1114,247 @@ pub fn validate_buffer_size(size: usize) -> Result<usize> {
     }
 }
 
+/// Longest buffer, in milliseconds of audio, accepted without an explicit opt-in
+pub const MAX_BUFFER_MS: u64 = 250;
+
+/// Largest buffer accepted without an explicit opt-in for a stream format
+///
+/// This is [`MAX_BUFFER_MS`] of audio, but never less than the 64KB
+/// [`validate_buffer_size`] allows for any format.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::max_buffer_size;
+///
+/// // 44.1 kHz stereo S16LE: 250 ms is below 64KB
+/// assert_eq!(max_buffer_size(44100, 4), 65536);
+/// // 192 kHz 8-channel S16LE
+/// assert_eq!(max_buffer_size(192000, 16), 768000);
+/// ```
+pub fn max_buffer_size(frame_rate: u32, frame_size: usize) -> usize {
+    let format_limit = (u64::from(frame_rate) * MAX_BUFFER_MS / 1000) as usize * frame_size;
+    format_limit.max(65536)
+}
+
+/// Validate a buffer size against the limit for a stream format
+///
+/// Like [`validate_buffer_size`], but the upper limit is
+/// [`max_buffer_size`] for the format, so high-rate multichannel streams can
+/// use proportionally larger buffers. With `allow_large` any non-zero size is
+/// accepted.
+///
+/// # Arguments
+///
+/// * `size` - Buffer size in bytes to validate
+/// * `frame_rate` - Frames per second of the stream
+/// * `frame_size` - Size of one frame in bytes
+/// * `allow_large` - Accept sizes above the limit (`--allow-large-buffers`)
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::validate_buffer_size_for_format;
+///
+/// assert!(validate_buffer_size_for_format(100000, 44100, 4, false).is_err());
+/// assert!(validate_buffer_size_for_format(100000, 96000, 16, false).is_ok());
+/// assert!(validate_buffer_size_for_format(100000, 44100, 4, true).is_ok());
+/// ```
+pub fn validate_buffer_size_for_format(
+    size: usize,
+    frame_rate: u32,
+    frame_size: usize,
+    allow_large: bool,
+) -> Result<usize> {
+    let limit = max_buffer_size(frame_rate, frame_size);
+    match size {
+        0 => Err(anyhow::anyhow!("Buffer size cannot be zero")),
+        s if s > limit && !allow_large => Err(anyhow::anyhow!(
+            "Buffer size too large: {s} bytes ({:.0} ms of audio, limit {limit} bytes); \
+             pass --allow-large-buffers to use it anyway",
+            bytes_to_ms(s, frame_rate, frame_size)
+        )),
+        s => Ok(s),
+    }
+}
+
+/// Validate a PipeWire node latency specification
+///
+/// The latency is given as a `quantum/rate` fraction, e.g. `256/48000` for
+/// 256 frames at 48 kHz (~5.3 ms). PipeWire uses it to size the processing
+/// quantum of the virtual source: smaller values reduce latency at the sound
+/// server level, larger values make the graph more tolerant of scheduling jitter.
+///
+/// # Arguments
+///
+/// * `latency` - Latency specification to validate
+///
+/// # Returns
+///
+/// Returns `Ok(latency)` if the specification is valid, or `Err` with a
+/// descriptive error message otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::validate_node_latency;
+///
+/// assert_eq!(validate_node_latency("256/48000").unwrap(), "256/48000");
+///
+/// assert!(validate_node_latency("256").is_err());
+/// assert!(validate_node_latency("0/48000").is_err());
+/// ```
+pub fn validate_node_latency(latency: &str) -> Result<&str> {
+    let (quantum, rate) = latency
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Node latency must be <quantum>/<rate>: {latency}"))?;
+
+    match (quantum.parse::<u32>(), rate.parse::<u32>()) {
+        (Ok(q), Ok(r)) if q > 0 && r > 0 => Ok(latency),
+        _ => Err(anyhow::anyhow!("Invalid node latency: {latency}")),
+    }
+}
+
+/// Longest source name PulseAudio accepts
+const PA_NAME_MAX: usize = 128;
+
+/// Validate a virtual microphone name against PulseAudio's naming rules
+///
+/// Source names may only contain ASCII letters, digits, `_`, `-` and `.`,
+/// and are limited to 128 characters. The name is also used unquoted in the
+/// `pactl load-module` arguments, so anything else would break module loading.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::validate_microphone_name;
+///
+/// assert!(validate_microphone_name("rsonance_virtual_microphone").is_ok());
+///
+/// assert!(validate_microphone_name("my mic").is_err());
+/// assert!(validate_microphone_name("").is_err());
+/// ```
+pub fn validate_microphone_name(name: &str) -> Result<&str> {
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("Microphone name cannot be empty"));
+    }
+    if name.len() > PA_NAME_MAX {
+        return Err(anyhow::anyhow!(
+            "Microphone name is longer than {PA_NAME_MAX} characters"
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid character {c:?} in microphone name '{name}' \
+             (use letters, digits, '_', '-' or '.'; underscores are shown as spaces)"
+        ));
+    }
+    Ok(name)
+}
+
+/// Check that a FIFO can be created at `path`
+///
+/// The parent directory must exist and be writable. An existing file at the
+/// path is replaced during setup, so anything other than a FIFO there is
+/// rejected rather than deleted.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::validate_fifo_path;
+///
+/// assert!(validate_fifo_path("/tmp/rsonance_audio_pipe").is_ok());
+///
+/// assert!(validate_fifo_path("/nonexistent/dir/pipe").is_err());
+/// ```
+pub fn validate_fifo_path(path: &str) -> Result<&str> {
+    let fifo = Path::new(path);
+    let parent = match fifo.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if fifo.file_name().is_none() {
+        return Err(anyhow::anyhow!("FIFO path '{path}' does not name a file"));
+    }
+    if !parent.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Directory {} for the FIFO does not exist",
+            parent.display()
+        ));
+    }
+    if !is_writable_dir(parent) {
+        return Err(anyhow::anyhow!(
+            "No permission to create the FIFO in {} (choose another --fifo-path)",
+            parent.display()
+        ));
+    }
+    if let Ok(metadata) = std::fs::symlink_metadata(fifo)
+        && !metadata.file_type().is_fifo()
+    {
+        return Err(anyhow::anyhow!(
+            "{path} exists and is not a FIFO; refusing to replace it"
+        ));
+    }
+    Ok(path)
+}
+
+/// Whether the current user may create files in `dir`
+fn is_writable_dir(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(dir) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `dir` is a valid NUL-terminated path for the duration of the call.
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+/// Bind the receiver's listening socket, explaining common failures
+///
+/// # Arguments
+///
+/// * `host` - Host name or address to listen on
+/// * `port` - Port to listen on
+///
+/// # Returns
+///
+/// The listener, or an error saying why the address cannot be used and what
+/// to do about it: unresolvable host, address not on this machine, port
+/// already in use, or a privileged port.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::bind_listener;
+///
+/// let listener = bind_listener("127.0.0.1", 0).unwrap();
+/// let port = listener.local_addr().unwrap().port();
+/// assert!(bind_listener("127.0.0.1", port).is_err());
+/// ```
+pub fn bind_listener(host: &str, port: u16) -> Result<TcpListener> {
+    let addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| anyhow::anyhow!("Cannot resolve listen address '{host}': {e}"))?
+        .collect();
+
+    TcpListener::bind(addrs.as_slice()).map_err(|e| match e.kind() {
+        ErrorKind::AddrInUse => anyhow::anyhow!(
+            "Port {port} on {host} is already in use; stop the other program \
+             (see `ss -ltnp 'sport = :{port}'`) or choose another --port"
+        ),
+        ErrorKind::AddrNotAvailable => anyhow::anyhow!(
+            "{host} is not an address of this machine; use 0.0.0.0 to listen on all interfaces"
+        ),
+        ErrorKind::PermissionDenied if port < 1024 => {
+            anyhow::anyhow!("Port {port} is privileged; choose a --port of 1024 or above")
+        }
+        _ => anyhow::anyhow!("Cannot listen on {host}:{port}: {e}"),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -417,6 +1367,94 @@ mod tests {
         assert!(matches!(config.format, AudioFormat::S16LE));
     }
 
+    #[test]
+    fn test_validate_microphone_name() {
+        assert!(validate_microphone_name("mic-1.left_channel").is_ok());
+        assert!(validate_microphone_name("mic;rm").is_err());
+        assert!(validate_microphone_name("micro\u{fb}").is_err());
+        assert!(validate_microphone_name(&"m".repeat(PA_NAME_MAX + 1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_fifo_path() {
+        let path = format!("/tmp/rsonance_fifo_path_test_{}", std::process::id());
+        assert!(validate_fifo_path(&path).is_ok());
+
+        // A regular file in the way is not deleted
+        std::fs::write(&path, b"keep").unwrap();
+        assert!(validate_fifo_path(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(validate_fifo_path("/tmp/").is_err());
+    }
+
+    #[test]
+    fn test_create_fifo() {
+        let path = format!("/tmp/rsonance_create_fifo_test_{}", std::process::id());
+        create_fifo(&path).unwrap();
+        let file_type = std::fs::symlink_metadata(&path).unwrap().file_type();
+        // Nothing is replaced
+        let error = create_fifo(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(file_type.is_fifo());
+        assert!(error.contains("exists"), "{error}");
+
+        assert!(create_fifo("/nonexistent/dir/fifo").is_err());
+    }
+
+    #[test]
+    fn test_bind_listener_errors() {
+        let listener = bind_listener("127.0.0.1", 0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let in_use = bind_listener("127.0.0.1", port).unwrap_err().to_string();
+        assert!(in_use.contains("already in use"), "{in_use}");
+
+        assert!(bind_listener("host.invalid", 8080).is_err());
+    }
+
+    #[test]
+    fn test_buffer_size_parse() {
+        assert_eq!(
+            "4096".parse::<BufferSize>().unwrap(),
+            BufferSize::Bytes(4096)
+        );
+        assert_eq!(
+            "512b".parse::<BufferSize>().unwrap(),
+            BufferSize::Bytes(512)
+        );
+        assert_eq!(
+            "20ms".parse::<BufferSize>().unwrap(),
+            BufferSize::Millis(20)
+        );
+        assert_eq!("5 ms".parse::<BufferSize>().unwrap(), BufferSize::Millis(5));
+        assert!("20s".parse::<BufferSize>().is_err());
+        assert!("-1".parse::<BufferSize>().is_err());
+        assert_eq!(BufferSize::Millis(20).to_string(), "20ms");
+    }
+
+    #[test]
+    fn test_buffer_size_to_bytes() {
+        // 8 kHz G.711: one byte per frame
+        assert_eq!(BufferSize::Millis(20).to_bytes(8000, 1), 160);
+        // 44.1 kHz stereo, rounded down to whole frames
+        assert_eq!(BufferSize::Millis(10).to_bytes(44100, 4), 1764);
+        assert_eq!(BufferSize::Millis(0).to_bytes(44100, 4), 4);
+        assert!((bytes_to_ms(1764, 44100, 4) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_validate_buffer_size_for_format() {
+        assert_eq!(
+            validate_buffer_size_for_format(65536, 8000, 1, false).unwrap(),
+            65536
+        );
+        assert!(validate_buffer_size_for_format(65537, 8000, 1, false).is_err());
+        assert!(validate_buffer_size_for_format(768000, 192000, 16, false).is_ok());
+        assert!(validate_buffer_size_for_format(768001, 192000, 16, false).is_err());
+        assert!(validate_buffer_size_for_format(0, 44100, 4, true).is_err());
+        assert!(validate_buffer_size_for_format(1 << 24, 44100, 4, true).is_ok());
+    }
+
     #[test]
     fn test_validate_buffer_size_valid() {
         assert_eq!(validate_buffer_size(4096).unwrap(), 4096);
@@ -439,7 +1477,7 @@ mod tests {
         let result = setup_virtual_microphone();
         // Function should return either Success, Failed, or an error
         match result {
-            Ok(VirtualMicResult::Success) | Ok(VirtualMicResult::Failed) => {
+            Ok(VirtualMicResult::Success) | Ok(VirtualMicResult::Failed(_)) => {
                 // Both success and failure are acceptable in test environment
             }
             Err(_) => {
@@ -454,6 +1492,7 @@ mod tests {
             "test_virtual_mic",
             "/tmp/test_fifo_pipe",
             &AudioConfig::default(),
+            None,
         );
         match result {
             Ok(_) | Err(_) => {}
@@ -477,6 +1516,32 @@ mod tests {
         assert_eq!(validate_buffer_size(16384).unwrap(), 16384);
     }
 
+    #[test]
+    fn test_validate_node_latency() {
+        assert_eq!(validate_node_latency("256/48000").unwrap(), "256/48000");
+        assert_eq!(validate_node_latency("1024/44100").unwrap(), "1024/44100");
+        assert!(validate_node_latency("256").is_err());
+        assert!(validate_node_latency("0/48000").is_err());
+        assert!(validate_node_latency("256/0").is_err());
+        assert!(validate_node_latency("abc/48000").is_err());
+    }
+
+    #[test]
+    fn test_audio_config_sizes() {
+        let config = AudioConfig::default();
+        assert_eq!(config.frame_size(), 4);
+        assert_eq!(config.bytes_for_ms(1000), 44100 * 4);
+        assert_eq!(config.bytes_for_ms(0), 0);
+
+        let config = AudioConfig {
+            sample_rate: 48000,
+            channels: 1,
+            format: AudioFormat::F32LE,
+        };
+        assert_eq!(config.frame_size(), 4);
+        assert_eq!(config.bytes_for_ms(10), 480 * 4);
+    }
+
     #[test]
     fn test_audio_format_as_pa_format() {
         assert_eq!(AudioFormat::S16LE.as_pa_format(), "s16le");
@@ -515,7 +1580,37 @@ mod tests {
     #[test]
     fn test_virtual_mic_result_debug() {
         assert_eq!(format!("{:?}", VirtualMicResult::Success), "Success");
-        assert_eq!(format!("{:?}", VirtualMicResult::Failed), "Failed");
+        assert_eq!(
+            format!(
+                "{:?}",
+                VirtualMicResult::Failed(MicSetupFailure::NameConflict)
+            ),
+            "Failed(NameConflict)"
+        );
+    }
+
+    #[test]
+    fn test_classify_mic_setup_failure() {
+        assert_eq!(
+            MicSetupFailure::classify("pa_context_connect() failed: Connection refused", false),
+            MicSetupFailure::ServerNotRunning
+        );
+        assert_eq!(
+            MicSetupFailure::classify("Failure: Access denied", true),
+            MicSetupFailure::ModuleBlocked
+        );
+        assert_eq!(
+            MicSetupFailure::classify("Failure: Module initialization failed", true),
+            MicSetupFailure::NameConflict
+        );
+        let other = MicSetupFailure::classify("Failure: Module initialization failed\n", false);
+        assert_eq!(
+            other,
+            MicSetupFailure::Other("Failure: Module initialization failed".to_string())
+        );
+        assert!(other.is_transient());
+        assert!(!MicSetupFailure::NameConflict.is_transient());
+        assert!(!MicSetupFailure::ModuleBlocked.is_transient());
     }
 
     #[test]
@@ -536,6 +1631,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_find_pipe_source_module() {
+        let modules = "\
+12\tmodule-null-sink\tsink_name=rsonance_virtual_microphone
+27\tmodule-pipe-source\tsource_name=rsonance_virtual_microphone_2 file=/tmp/b
+28\tmodule-pipe-source\tsource_name=rsonance_virtual_microphone file=/tmp/a
+";
+        assert_eq!(
+            find_pipe_source_module(modules, "rsonance_virtual_microphone"),
+            Some("28".to_string())
+        );
+        assert_eq!(
+            find_pipe_source_module(modules, "rsonance_virtual_microphone_2"),
+            Some("27".to_string())
+        );
+        assert_eq!(find_pipe_source_module(modules, "other"), None);
+    }
+
+    #[test]
+    fn test_parse_sound_server_event() {
+        assert_eq!(
+            SoundServerEvent::parse("Event 'new' on source-output #3"),
+            Some(SoundServerEvent {
+                kind: "new".to_string(),
+                facility: "source-output".to_string(),
+                index: Some(3),
+            })
+        );
+        let server = SoundServerEvent::parse("Event 'change' on server").unwrap();
+        assert_eq!((server.facility.as_str(), server.index), ("server", None));
+        assert_eq!(SoundServerEvent::parse("Connection failure"), None);
+        assert_eq!(SoundServerEvent::parse("Event 'remove' on module #x"), None);
+    }
+
+    #[test]
+    fn test_pick_free_source_name() {
+        let sources = "\
+1\talsa_input.usb-mic\tmodule-alsa-card.c\ts16le 2ch 48000Hz\tRUNNING
+5\tmic\tmodule-pipe-source.c\ts16le 2ch 44100Hz\tIDLE
+6\tmic_2\tmodule-pipe-source.c\ts16le 2ch 44100Hz\tIDLE
+";
+        let taken = source_names(sources);
+        assert_eq!(taken, vec!["alsa_input.usb-mic", "mic", "mic_2"]);
+        assert_eq!(pick_free_name("mic", &taken), "mic_3");
+        assert_eq!(pick_free_name("mic_2", &taken), "mic_2_2");
+        assert_eq!(pick_free_name("other", &taken), "other");
+
+        assert_eq!(
+            find_sample_spec(sources, "alsa_input.usb-mic"),
+            Some("s16le 2ch 48000Hz")
+        );
+        assert_eq!(
+            find_sample_spec(sources, "mic_2"),
+            Some(AudioConfig::default().sample_spec().as_str())
+        );
+        assert_eq!(find_sample_spec(sources, "other"), None);
+    }
+
+    #[test]
+    fn test_find_monitor_sink_modules() {
+        let modules = "\
+12\tmodule-null-sink\tsink_name=mic_monitor rate=44100
+13\tmodule-loopback\tsource=other sink=mic_monitor
+14\tmodule-loopback\tsource=mic sink=mic_monitor source_dont_move=true
+";
+        let loopback = ["source=mic".to_string(), "sink=mic_monitor".to_string()];
+        assert_eq!(
+            find_module(modules, "module-loopback", &loopback),
+            Some("14".to_string())
+        );
+        assert_eq!(find_module(modules, "module-null-sink", &loopback), None);
+
+        assert!(module_has_argument_value(modules, "12", "mic_monitor"));
+        assert!(module_has_argument_value(modules, "14", "mic"));
+        assert!(!module_has_argument_value(modules, "13", "mic"));
+        assert!(!module_has_argument_value(modules, "99", "mic_monitor"));
+    }
+
     #[test]
     fn test_cleanup_virtual_microphone() {
         // This test verifies the function compiles and handles cleanup
@@ -0,0 +1,324 @@
+//! Listening addresses of the receiver: TCP and Unix domain sockets
+//!
+//! `--listen` can be given several times, e.g. for a LAN address, a
+//! WireGuard address and a local Unix socket at once. Every listener hands
+//! its connections to the same virtual microphone.
+
+use crate::bind_listener;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+
+/// Address the receiver accepts transmitters on
+///
+/// Written as `HOST:PORT` (`[ADDR]:PORT` for IPv6) or `unix:PATH`.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::listen::ListenAddr;
+///
+/// let tcp: ListenAddr = "[fd00::1]:8080".parse().unwrap();
+/// assert_eq!(tcp, ListenAddr::Tcp { host: "fd00::1".to_string(), port: 8080 });
+/// assert_eq!(tcp.to_string(), "[fd00::1]:8080");
+///
+/// let unix: ListenAddr = "unix:/run/rsonance.sock".parse().unwrap();
+/// assert_eq!(unix, ListenAddr::Unix("/run/rsonance.sock".to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// TCP on a host name or address and port
+    Tcp { host: String, port: u16 },
+    /// Unix domain stream socket at a path
+    Unix(String),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp { host, port } if host.contains(':') => write!(f, "[{host}]:{port}"),
+            ListenAddr::Tcp { host, port } => write!(f, "{host}:{port}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{path}"),
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(anyhow::anyhow!("Missing socket path in '{s}'"));
+            }
+            return Ok(ListenAddr::Unix(path.to_string()));
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or_else(|| {
+            anyhow::anyhow!("Invalid listen address '{s}' (expected HOST:PORT or unix:PATH)")
+        })?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Missing host in listen address '{s}'"));
+        }
+        let port = port
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid port in listen address '{s}'"))?;
+        Ok(ListenAddr::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// A bound listening socket
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind `addr`, explaining common failures
+    ///
+    /// A socket file left at a Unix path by a previous receiver is replaced;
+    /// any other kind of file there is an error.
+    pub(crate) fn bind(addr: &ListenAddr) -> anyhow::Result<Self> {
+        match addr {
+            ListenAddr::Tcp { host, port } => Ok(Listener::Tcp(bind_listener(host, *port)?)),
+            ListenAddr::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(anyhow::anyhow!(
+                            "Cannot listen on {path}: a file that is not a socket is in the way"
+                        ));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path)
+                    .map(Listener::Unix)
+                    .map_err(|e| anyhow::anyhow!("Cannot listen on {path}: {e}"))
+            }
+        }
+    }
+
+    /// Accept a connection, with the peer address for TCP
+    pub(crate) fn accept(&self) -> io::Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, peer)| (Stream::Tcp(stream), Some(peer))),
+            Listener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| (Stream::Unix(stream), None)),
+        }
+    }
+
+    /// Another handle to the same socket
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            Listener::Unix(listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+
+    /// Stop listening, waking up a thread blocked in [`Listener::accept`]
+    /// with an error
+    pub(crate) fn shutdown(&self) {
+        shutdown_listener(self.as_raw_fd());
+    }
+}
+
+/// Process on the other end of a Unix socket connection, as the kernel
+/// reported it when connecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32,
+}
+
+/// A transmitter connection accepted by a [`Listener`]
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// Credentials of the connecting process, for Unix socket connections
+    pub(crate) fn peer_credentials(&self) -> Option<PeerCredentials> {
+        let Stream::Unix(stream) = self else {
+            return None;
+        };
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: SO_PEERCRED writes at most `len` bytes to the valid ucred
+        // passed.
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        (rc == 0).then_some(PeerCredentials {
+            uid: cred.uid,
+            gid: cred.gid,
+            pid: cred.pid,
+        })
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Stop the listening socket `fd`, so a thread blocked accepting on it (or
+/// on another handle of the same socket) returns with an error
+///
+/// The standard library has no way to interrupt `accept`; on Linux shutting
+/// a listening socket down does.
+pub(crate) fn shutdown_listener(fd: RawFd) {
+    // SAFETY: shutdown only acts on the socket and is harmless on a socket
+    // that is already shut down
+    unsafe {
+        libc::shutdown(fd, libc::SHUT_RDWR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            "0.0.0.0:8080".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp {
+                host: "0.0.0.0".to_string(),
+                port: 8080
+            }
+        );
+        assert_eq!(
+            "10.8.0.1:9000".parse::<ListenAddr>().unwrap().to_string(),
+            "10.8.0.1:9000"
+        );
+        assert!("8080".parse::<ListenAddr>().is_err());
+        assert!(":8080".parse::<ListenAddr>().is_err());
+        assert!("host:port".parse::<ListenAddr>().is_err());
+        assert!("unix:".parse::<ListenAddr>().is_err());
+    }
+
+    #[test]
+    fn test_shutdown_wakes_accept() {
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let handle = listener.try_clone().unwrap();
+        let accepting = std::thread::spawn(move || listener.accept().map(|_| ()));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.shutdown();
+        assert!(accepting.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_unix_listener_replaces_stale_socket() {
+        let path = format!("/tmp/rsonance_listen_test_{}.sock", std::process::id());
+        let addr = ListenAddr::Unix(path.clone());
+        drop(Listener::bind(&addr).unwrap());
+
+        // The socket file of the dropped listener is still there
+        let listener = Listener::bind(&addr).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let (mut server, peer) = listener.accept().unwrap();
+        assert_eq!(peer, None);
+        let credentials = server.peer_credentials().unwrap();
+        assert_eq!(credentials.pid, std::process::id() as i32);
+        // SAFETY: getuid has no preconditions and always succeeds.
+        assert_eq!(credentials.uid, unsafe { libc::getuid() });
+        client.write_all(b"audio").unwrap();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"audio");
+
+        let _ = std::fs::remove_file(&path);
+        std::fs::write(&path, b"").unwrap();
+        assert!(Listener::bind(&addr).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
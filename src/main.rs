@@ -14,6 +14,8 @@ struct Cli {
     command: Commands,
 }
 
+// Parsed once at startup, so the size of the receiver's options does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Create a virtual microphone and receive audio streams
@@ -26,9 +28,21 @@ enum Commands {
         #[arg(short, long, default_value_t = 8080)]
         port: u16,
 
-        /// Audio buffer size in bytes (affects latency)
-        #[arg(short, long, default_value_t = 4096)]
-        buffer_size: usize,
+        /// Listen on HOST:PORT or unix:PATH instead of --host/--port (repeatable)
+        #[arg(long, value_name = "ADDR", conflicts_with_all = ["host", "port"])]
+        listen: Vec<rsonance::listen::ListenAddr>,
+
+        /// Receive over tcp, or as sequenced udp datagrams that skip lost audio instead of stalling
+        #[arg(long, default_value_t = rsonance::udp::Transport::Tcp)]
+        transport: rsonance::udp::Transport,
+
+        /// Audio buffer size in bytes, or in milliseconds with an `ms` suffix (affects latency)
+        #[arg(short, long, alias = "buffer", default_value_t = rsonance::BufferSize::default())]
+        buffer_size: rsonance::BufferSize,
+
+        /// Allow buffers larger than 250 ms of audio (or 64KB, whichever is larger)
+        #[arg(long)]
+        allow_large_buffers: bool,
 
         /// Virtual microphone name
         #[arg(short, long, default_value = "rsonance_virtual_microphone")]
@@ -38,6 +52,166 @@ enum Commands {
         #[arg(short, long, default_value = "/tmp/rsonance_audio_pipe")]
         fifo_path: String,
 
+        /// Owner of the FIFO as USER[:GROUP] or :GROUP, for a local user's recording application
+        #[arg(long, value_name = "USER[:GROUP]")]
+        fifo_owner: Option<rsonance::access::FileOwner>,
+
+        /// Octal permissions of the FIFO (e.g. 0660)
+        #[arg(long, value_name = "MODE")]
+        fifo_mode: Option<rsonance::access::FileMode>,
+
+        /// PipeWire node latency for the virtual source as quantum/rate (e.g. 256/48000)
+        #[arg(long)]
+        node_latency: Option<String>,
+
+        /// Also create a null sink playing the virtual microphone, for OBS and other desktop-audio capture
+        #[arg(long)]
+        with_monitor_sink: bool,
+
+        /// Request real-time scheduling for the FIFO writer threads
+        #[arg(long)]
+        realtime: bool,
+
+        /// Maximum queued audio in milliseconds before skipping ahead to live (0 disables)
+        #[arg(long, default_value_t = 1000)]
+        max_latency_ms: u64,
+
+        /// Audio in milliseconds to collect from a new connection before playback starts (0 disables)
+        #[arg(long, default_value_t = 0)]
+        prebuffer_ms: u64,
+
+        /// Target latency in milliseconds of the adaptive jitter buffer before the output (0 disables it)
+        #[arg(long, default_value_t = 0)]
+        jitter_buffer_ms: u64,
+
+        /// Lowest latency in milliseconds the jitter buffer adapts down to
+        #[arg(long, default_value_t = 20)]
+        jitter_min_ms: u64,
+
+        /// Most audio in milliseconds the jitter buffer holds before dropping the oldest
+        #[arg(long, default_value_t = 500)]
+        jitter_max_ms: u64,
+
+        /// Recent audio in milliseconds kept to drop what a reconnecting transmitter replays twice (0 disables)
+        #[arg(long, default_value_t = 1000)]
+        continuity_ms: u64,
+
+        /// Only play audio while the primary receiver's virtual microphone with this name is missing
+        #[arg(long, value_name = "SOURCE")]
+        secondary_for: Option<String>,
+
+        /// Tell transmitters using --on-demand whether an application records from the virtual microphone
+        #[arg(long)]
+        on_demand: bool,
+
+        /// Wire encoding of raw streams without a header: s16le, s24le, f32le, g711u, or g711a
+        #[arg(long, default_value = "s16le")]
+        codec: rsonance::codec::Codec,
+
+        /// Interval in seconds between CPU/memory and queued audio reports (0 disables them)
+        #[arg(long, default_value_t = 0)]
+        stats_interval: u64,
+
+        /// Measure per-band levels and 50/60 Hz hum of each stream, shown by `spectrum`
+        #[arg(long)]
+        spectrum: bool,
+
+        /// Pipe received audio as 16 kHz mono S16LE into this shell command (e.g. a whisper.cpp stream)
+        #[arg(long, value_name = "CMD")]
+        transcribe_cmd: Option<String>,
+
+        /// Pipe received audio into this shell command instead of the virtual microphone (e.g. ffmpeg)
+        #[arg(long, value_name = "CMD")]
+        pipe_to: Option<String>,
+
+        /// Audio output: `pulse` (virtual microphone), `null` (write to the FIFO path as a plain file) or `device` (play to an output device, such as a virtual audio cable)
+        #[arg(long, default_value_t = rsonance::receiver::Backend::default())]
+        backend: rsonance::receiver::Backend,
+
+        /// Output device for `--backend device`, by index or name as shown by `list-devices --output` (default: the system default)
+        #[arg(long, value_name = "NAME|INDEX")]
+        output_device: Option<rsonance::device::DeviceSelector>,
+
+        /// Record the raw bytes received from every transmitter to this file, for `rsonance replay`, with a FILE.json manifest
+        #[arg(long, value_name = "FILE")]
+        debug_dump: Option<String>,
+
+        /// Archive the received audio to this file, FLAC if it ends in .flac and WAV otherwise
+        #[arg(long, value_name = "PATH")]
+        record: Option<String>,
+
+        /// Start a new recording file (PATH-2, PATH-3, ...) once the current one reaches this size in MiB (0 = no limit)
+        #[arg(long, default_value_t = 0, requires = "record")]
+        record_max_mb: u64,
+
+        /// Start a new recording file once the current one holds this many seconds of audio (0 = no limit)
+        #[arg(long, default_value_t = 0, requires = "record")]
+        record_max_secs: u64,
+
+        /// Delete the oldest recording files to keep them all under this many MiB (0 = no limit)
+        #[arg(long, default_value_t = 0, requires = "record")]
+        record_max_total_mb: u64,
+
+        /// Pause recording while the disk has less than this many MiB free (0 = no limit)
+        #[arg(long, default_value_t = 100, requires = "record")]
+        record_min_free_mb: u64,
+
+        /// What --record keeps: always, or voice for only the audio around speech
+        #[arg(long, default_value = "always", requires = "record")]
+        record_when: rsonance::voice::RecordWhen,
+
+        /// Audio kept before speech with --record-when voice, in milliseconds
+        #[arg(long, default_value_t = 500, requires = "record")]
+        record_preroll_ms: u64,
+
+        /// Audio kept after speech with --record-when voice, in milliseconds
+        #[arg(long, default_value_t = 1500, requires = "record")]
+        record_postroll_ms: u64,
+
+        /// Append every connection attempt to this file as JSON lines (time, peer, identity, outcome, duration)
+        #[arg(long, value_name = "FILE")]
+        audit_log: Option<String>,
+
+        /// Control socket path for the `clients` and `kick` commands
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+
+        /// Serve Prometheus metrics at http://ADDR/metrics and health checks at /healthz, e.g. 127.0.0.1:9464
+        #[arg(long, value_name = "ADDR")]
+        metrics_addr: Option<String>,
+
+        /// Advertise the receiver on the local network over mDNS for `transmitter --discover`, under NAME (default: the host name)
+        #[arg(long, value_name = "NAME", num_args = 0..=1)]
+        advertise: Option<Option<String>>,
+
+        /// File recording created modules, FIFOs and sockets for `rsonance cleanup`
+        #[arg(long, default_value_t = rsonance::state::default_state_path())]
+        state_file: String,
+
+        /// When started as root, switch to this user once listening and set up
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Group to switch to with --user (default: the user's primary group)
+        #[arg(long, requires = "user")]
+        group: Option<String>,
+
+        /// Stop the threads reading transmitters' streams from starting programs, opening files or connecting out
+        #[arg(long)]
+        sandbox: bool,
+
+        /// Connections accepted from one address per minute (0 disables the limit)
+        #[arg(long, default_value_t = 30)]
+        max_connection_rate: u32,
+
+        /// Ban an address after this many connections over the rate limit or malformed streams within 10 minutes (0 disables bans)
+        #[arg(long, default_value_t = 5)]
+        ban_after: u32,
+
+        /// Length of a ban in seconds
+        #[arg(long, default_value_t = 600)]
+        ban_secs: u64,
+
         /// Enable verbose output
         #[arg(short, long)]
         verbose: bool,
@@ -52,18 +226,252 @@ enum Commands {
         #[arg(short, long, default_value_t = 8080)]
         port: u16,
 
-        /// Audio buffer size in bytes (affects latency)
-        #[arg(short, long, default_value_t = 4096)]
-        buffer_size: usize,
+        /// Send over tcp, or as sequenced udp datagrams that skip lost audio instead of stalling
+        #[arg(long, default_value_t = rsonance::udp::Transport::Tcp)]
+        transport: rsonance::udp::Transport,
+
+        /// Find the receiver on the local network over mDNS instead of --host/--port: the first to answer, or the one named NAME
+        #[arg(long, value_name = "NAME", num_args = 0..=1, conflicts_with_all = ["host", "port", "transport", "aes67"])]
+        discover: Option<Option<String>>,
+
+        /// Audio buffer size in bytes, or in milliseconds with an `ms` suffix (affects latency)
+        #[arg(short, long, alias = "buffer", default_value_t = rsonance::BufferSize::default())]
+        buffer_size: rsonance::BufferSize,
+
+        /// Allow buffers larger than 250 ms of audio (or 64KB, whichever is larger)
+        #[arg(long)]
+        allow_large_buffers: bool,
 
         /// Reconnection attempts on connection failure
         #[arg(short, long, default_value_t = 5)]
         reconnect_attempts: u32,
 
+        /// Start capturing right away and keep trying to connect until the receiver is reachable
+        #[arg(long, conflicts_with = "aes67")]
+        wait_for_server: bool,
+
+        /// Standby receiver (HOST:PORT) to stream to while the primary one is unreachable
+        #[arg(long, value_name = "HOST:PORT", conflicts_with = "aes67")]
+        standby: Option<String>,
+
+        /// Second receiver (HOST:PORT) to send a copy of the stream to, e.g. one run with --secondary-for
+        #[arg(long, value_name = "HOST:PORT", conflicts_with = "aes67")]
+        duplicate_to: Option<String>,
+
+        /// Encode the --duplicate-to copy with this codec instead, to compare the two recordings
+        #[arg(
+            long,
+            value_name = "CODEC",
+            requires = "duplicate_to",
+            conflicts_with = "raw_output_compat"
+        )]
+        compare_codec: Option<rsonance::codec::Codec>,
+
+        /// Only capture and stream while an application records from the receiver's virtual microphone
+        #[arg(long, conflicts_with_all = ["aes67", "raw_output_compat"])]
+        on_demand: bool,
+
+        /// Pause capture and streaming while on battery at or below --power-save-threshold: auto or off
+        #[arg(long, default_value = "off", conflicts_with = "aes67")]
+        power_save: rsonance::power::PowerSave,
+
+        /// Battery charge in percent at or below which --power-save auto pauses
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(0..=100))]
+        power_save_threshold: u8,
+
+        /// Maximum delay in milliseconds for coalescing audio into buffer-size writes
+        #[arg(long, default_value_t = 5)]
+        max_batch_delay_ms: u64,
+
+        /// Send the audio in frames of this many milliseconds (0 = one frame per batch)
+        #[arg(long, default_value_t = 0)]
+        frame_ms: u64,
+
+        /// Request real-time scheduling for the audio capture thread
+        #[arg(long)]
+        realtime: bool,
+
+        /// Interval in seconds between CPU/memory usage reports (0 disables them)
+        #[arg(long, default_value_t = 0)]
+        stats_interval: u64,
+
+        /// Wire encoding of the audio stream: s16le, s24le, f32le, g711u, or g711a
+        #[arg(long, default_value = "s16le")]
+        codec: rsonance::codec::Codec,
+
+        /// Send the input device's own sample format without converting it, picking the codec to match
+        #[arg(long, conflicts_with_all = ["codec", "sample_rate", "dehum", "raw_output_compat", "aes67"])]
+        passthrough: bool,
+
+        /// Name the receiver shows for this transmitter in its logs and `clients` list
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+
+        /// Tag the stream with a KEY=VALUE pair shown next to the name (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = rsonance::protocol::parse_tag)]
+        tags: Vec<(String, String)>,
+
+        /// Resample the captured audio to this rate in Hz (default: the capture device's rate)
+        #[arg(long, value_name = "HZ")]
+        sample_rate: Option<u32>,
+
+        /// Send plain 48 kHz S16LE without a stream header for GStreamer/ffmpeg instead of a rsonance receiver
+        #[arg(long)]
+        raw_output_compat: bool,
+
+        /// Input device to capture from, by index or name as shown by `list-devices` (default: the system default)
+        #[arg(long, value_name = "NAME|INDEX", conflicts_with = "mock_input")]
+        device: Option<rsonance::device::DeviceSelector>,
+
+        /// Stream a generated test signal instead of capturing from a microphone
+        #[arg(long)]
+        mock_input: bool,
+
+        /// Signal for --mock-input: sine (440 Hz), pink (noise), or sweep (20 Hz to 20 kHz)
+        #[arg(long, default_value = "sine", requires = "mock_input")]
+        mock_signal: rsonance::mock::MockSignal,
+
+        /// Restart the mock signal every this many milliseconds, with silence between bursts (0 = continuous)
+        #[arg(long, default_value_t = 0, requires = "mock_input")]
+        mock_repeat_ms: u64,
+
+        /// Print GStreamer/ffmpeg commands that can receive the --raw-output-compat stream, then exit
+        #[arg(long)]
+        print_pipeline: bool,
+
+        /// Send AES67 RTP multicast to this group:port instead of a receiver (e.g. 239.69.1.10:5004)
+        #[arg(long, value_name = "ADDR:PORT")]
+        aes67: Option<std::net::SocketAddr>,
+
+        /// RTP payload encoding for --aes67: l16 or l24
+        #[arg(long, default_value = "l24", requires = "aes67")]
+        aes67_encoding: rsonance::rtp::RtpEncoding,
+
+        /// Spread each buffer's bytes over its real-time duration instead of sending bursts
+        #[arg(long)]
+        pacing: bool,
+
+        /// Handling of audio queued behind a slow connection: buffer, drop, or disconnect
+        #[arg(long, default_value = "buffer")]
+        on_backpressure: rsonance::transmitter::BackpressurePolicy,
+
+        /// Most captured audio in milliseconds waiting to be sent; older audio is dropped (0 = no limit)
+        #[arg(long, default_value_t = 2000)]
+        max_queue_ms: u64,
+
+        /// Milliseconds of recent audio to replay after reconnecting (0 disables)
+        #[arg(long, default_value_t = 250)]
+        resend_ms: u64,
+
+        /// Remove mains hum at this frequency (50 or 60 Hz) and its harmonics with notch filters
+        #[arg(long, value_name = "50|60")]
+        dehum: Option<rsonance::dehum::Mains>,
+
+        /// Play the microphone back on local headphones at this level in dB (e.g. -20)
+        #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+        sidetone: Option<f32>,
+
+        /// Show a live input level bar in the terminal
+        #[arg(long)]
+        level_meter: bool,
+
+        /// Key combination that mutes and unmutes the microphone, e.g. ctrl+alt+m (Linux, needs read access to /dev/input)
+        #[arg(long, value_name = "KEYS")]
+        mute_hotkey: Option<rsonance::hotkey::Hotkey>,
+
         /// Enable verbose output
         #[arg(short, long)]
         verbose: bool,
     },
+    /// List the input devices the transmitter can capture from
+    ListDevices {
+        /// List the output devices the receiver's `--backend device` can play to instead
+        #[arg(long)]
+        output: bool,
+    },
+    /// List transmitters connected to a running receiver
+    Clients {
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
+    /// Send a `--debug-dump` recording or a WAV file to a receiver
+    Replay {
+        /// Dump file written by `receiver --debug-dump`, or a 16-bit PCM WAV file
+        file: String,
+
+        /// Receiver address to connect to
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Receiver port to connect to
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+
+        /// Wire encoding for WAV files; must match the receiver (dumps carry their own)
+        #[arg(long, default_value_t = rsonance::codec::Codec::S16LE)]
+        codec: rsonance::codec::Codec,
+
+        /// Playback speed relative to real time (0 sends as fast as possible)
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Remove virtual microphones, FIFOs and sockets left behind by a killed receiver
+    Cleanup {
+        /// State file of the receivers, as given to `receiver --state-file`
+        #[arg(long, default_value_t = rsonance::state::default_state_path())]
+        state_file: String,
+
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Show the stream parameters of a transmitter connected to a running receiver
+    Session {
+        /// Connection id of the transmitter, as shown by `clients`
+        id: u64,
+
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
+    /// Show per-band levels of a transmitter's stream at a receiver running with --spectrum
+    Spectrum {
+        /// Connection id of the transmitter, as shown by `clients`
+        id: u64,
+
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
+    /// Check that a running receiver can take a stream; exits with an error if not
+    Health {
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
+    /// Mark the current moment in a running receiver's --record file and --debug-dump recording
+    Mark {
+        /// Label of the marker, e.g. "Q&A starts"
+        label: String,
+
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
+    /// Disconnect a transmitter from a running receiver
+    Kick {
+        /// Connection id of the transmitter, as shown by `clients`
+        id: u64,
+
+        /// Control socket path of the receiver
+        #[arg(short = 's', long, default_value = rsonance::control::DEFAULT_CONTROL_SOCKET)]
+        control_socket: String,
+    },
 }
 
 #[tokio::main]
@@ -72,7 +480,17 @@ async fn main() -> anyhow::Result<()> {
 
     // Extract verbose flag from whichever subcommand was used
     let verbose = match &cli.command {
-        Commands::Receiver { verbose, .. } | Commands::Transmitter { verbose, .. } => *verbose,
+        Commands::Receiver { verbose, .. }
+        | Commands::Transmitter { verbose, .. }
+        | Commands::Replay { verbose, .. }
+        | Commands::Cleanup { verbose, .. } => *verbose,
+        Commands::ListDevices { .. }
+        | Commands::Clients { .. }
+        | Commands::Session { .. }
+        | Commands::Spectrum { .. }
+        | Commands::Health { .. }
+        | Commands::Mark { .. }
+        | Commands::Kick { .. } => false,
     };
 
     // Initialize logger: -v sets default to info, RUST_LOG overrides
@@ -87,33 +505,347 @@ async fn main() -> anyhow::Result<()> {
         Commands::Receiver {
             host,
             port,
+            listen,
+            transport,
             buffer_size,
+            allow_large_buffers,
             microphone_name,
             fifo_path,
+            fifo_owner,
+            fifo_mode,
+            node_latency,
+            with_monitor_sink,
+            realtime,
+            max_latency_ms,
+            prebuffer_ms,
+            jitter_buffer_ms,
+            jitter_min_ms,
+            jitter_max_ms,
+            continuity_ms,
+            secondary_for,
+            on_demand,
+            codec,
+            transcribe_cmd,
+            pipe_to,
+            backend,
+            output_device,
+            debug_dump,
+            record,
+            record_max_mb,
+            record_max_secs,
+            record_max_total_mb,
+            record_min_free_mb,
+            record_when,
+            record_preroll_ms,
+            record_postroll_ms,
+            audit_log,
+            stats_interval,
+            spectrum,
+            control_socket,
+            metrics_addr,
+            advertise,
+            state_file,
+            user,
+            group,
+            sandbox,
+            max_connection_rate,
+            ban_after,
+            ban_secs,
             verbose,
-        } => rsonance::receiver::run_receiver(
+        } => rsonance::receiver::run_receiver(rsonance::receiver::ReceiverConfig {
             host,
             port,
+            listen,
+            transport,
             buffer_size,
+            allow_large_buffers,
             microphone_name,
             fifo_path,
+            fifo_owner,
+            fifo_mode,
+            node_latency,
+            with_monitor_sink,
+            realtime,
+            max_latency_ms,
+            prebuffer_ms,
+            jitter_buffer_ms,
+            jitter_min_ms,
+            jitter_max_ms,
+            continuity_ms,
+            secondary_for,
+            on_demand,
+            codec,
+            transcribe_cmd,
+            pipe_to,
+            backend,
+            output_device,
+            debug_dump,
+            record,
+            record_max_mb,
+            record_max_secs,
+            record_max_total_mb,
+            record_min_free_mb,
+            record_when,
+            record_preroll_ms,
+            record_postroll_ms,
+            audit_log,
+            stats_interval,
+            spectrum,
+            control_socket: Some(control_socket),
+            metrics_addr,
+            advertise: advertise.map(|name| name.unwrap_or_else(rsonance::discovery::host_name)),
+            state_file: Some(state_file),
+            user,
+            group,
+            sandbox,
+            max_connection_rate,
+            ban_after,
+            ban_secs,
             verbose,
-        ),
+        }),
         Commands::Transmitter {
-            host,
-            port,
+            mut host,
+            mut port,
+            mut transport,
+            discover,
             buffer_size,
+            allow_large_buffers,
             reconnect_attempts,
+            wait_for_server,
+            standby,
+            duplicate_to,
+            compare_codec,
+            on_demand,
+            power_save,
+            power_save_threshold,
+            max_batch_delay_ms,
+            frame_ms,
+            realtime,
+            stats_interval,
+            codec,
+            passthrough,
+            name,
+            tags,
+            sample_rate,
+            aes67,
+            aes67_encoding,
+            raw_output_compat,
+            device,
+            mock_input,
+            mock_signal,
+            mock_repeat_ms,
+            print_pipeline,
+            pacing,
+            on_backpressure,
+            max_queue_ms,
+            resend_ms,
+            dehum,
+            sidetone,
+            level_meter,
+            mute_hotkey,
             verbose,
         } => {
-            rsonance::transmitter::run_transmitter(
+            if let Some(name) = discover {
+                let receiver = rsonance::discovery::discover(
+                    name.as_deref(),
+                    rsonance::discovery::DISCOVERY_TIMEOUT,
+                )?;
+                host = receiver.addr.ip().to_string();
+                port = receiver.addr.port();
+                transport = receiver.transport;
+            }
+            let config = rsonance::transmitter::TransmitterConfig {
                 host,
                 port,
+                transport,
                 buffer_size,
+                allow_large_buffers,
                 reconnect_attempts,
+                wait_for_server,
+                standby,
+                duplicate_to,
+                compare_codec,
+                on_demand,
+                power_save,
+                power_save_threshold,
+                max_batch_delay_ms,
+                frame_ms,
+                realtime,
+                stats_interval,
+                pacing,
+                on_backpressure,
+                max_queue_ms,
+                resend_ms,
+                dehum,
+                sidetone_db: sidetone,
+                level_meter,
+                mute_hotkey,
+                codec,
+                passthrough,
+                metadata: rsonance::protocol::Metadata { name, tags },
+                sample_rate,
+                aes67: aes67.map(|destination| rsonance::rtp::Aes67Config {
+                    destination,
+                    encoding: aes67_encoding,
+                }),
+                raw_output_compat,
+                device,
+                mock_input,
+                mock_signal,
+                mock_repeat_ms,
                 verbose,
-            )
-            .await
+            };
+            if print_pipeline {
+                return rsonance::transmitter::print_pipeline(&config);
+            }
+            rsonance::transmitter::run_transmitter(config).await
+        }
+        Commands::ListDevices { output } => {
+            let direction = if output {
+                rsonance::device::Direction::Output
+            } else {
+                rsonance::device::Direction::Input
+            };
+            let devices = rsonance::device::list_devices(direction)?;
+            if devices.is_empty() {
+                println!("No {direction} devices found");
+                return Ok(());
+            }
+            for device in devices {
+                println!(
+                    "{:>3}{} {}{}",
+                    device.index,
+                    if device.default { "*" } else { " " },
+                    device.name,
+                    device
+                        .config
+                        .map_or_else(String::new, |config| format!(" ({config})"))
+                );
+            }
+            Ok(())
+        }
+        Commands::Clients { control_socket } => {
+            let clients = rsonance::control::list_clients(&control_socket)?;
+            if clients.is_empty() {
+                println!("No transmitters connected");
+                return Ok(());
+            }
+
+            println!(
+                "{:<6} {:<40} {:<8} {:<10} {:<11} {:<13} {:<8} {:<8} NAME",
+                "ID", "ADDRESS", "CODEC", "CONNECTED", "RECEIVED", "AVG RATE", "SOCKET", "OUTPUT"
+            );
+            for client in clients {
+                let address = client
+                    .peer
+                    .map_or_else(|| "-".to_string(), |peer| peer.to_string());
+                let secs = client.connected_for.as_secs();
+                let connected =
+                    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+                let name = if client.metadata.is_empty() {
+                    "-".to_string()
+                } else {
+                    client.metadata.to_string()
+                };
+                println!(
+                    "{:<6} {address:<40} {:<8} {connected:<10} {:<11} {:<13} {:<8} {:<8} {name}",
+                    client.id,
+                    client.codec,
+                    format!(
+                        "{:.1} MiB",
+                        client.bytes_received as f64 / (1024.0 * 1024.0)
+                    ),
+                    format!("{:.1} kbit/s", client.average_bitrate() / 1000.0),
+                    format!("{} ms", client.socket_queue_ms),
+                    format!("{} ms", client.output_queue_ms)
+                );
+            }
+            Ok(())
+        }
+        Commands::Replay {
+            file,
+            host,
+            port,
+            codec,
+            speed,
+            verbose: _,
+        } => rsonance::replay::run_replay(&rsonance::replay::ReplayConfig {
+            path: file,
+            host,
+            port,
+            codec,
+            speed,
+        }),
+        Commands::Cleanup {
+            state_file,
+            verbose: _,
+        } => {
+            let report =
+                rsonance::state::cleanup_stale(&rsonance::state::StateFile::new(state_file))?;
+            println!("Removed {} leftover resources", report.removed);
+            if report.in_use > 0 {
+                println!(
+                    "Kept {} resources of receivers that are still running",
+                    report.in_use
+                );
+            }
+            Ok(())
+        }
+        Commands::Session { id, control_socket } => {
+            let session = rsonance::control::session_summary(&control_socket, id)?;
+            println!("Codec:     {}", session.codec);
+            println!(
+                "Audio:     {} Hz, {} channel(s), {}",
+                session.sample_rate, session.channels, session.format
+            );
+            println!("Bitrate:   {:.1} kbit/s", session.bitrate as f64 / 1000.0);
+            println!("Transport: {}", session.transport);
+            println!(
+                "Format:    {}",
+                if session.announced {
+                    "announced in the stream header"
+                } else {
+                    "raw stream, assumed to be the receiver's --codec"
+                }
+            );
+            println!("Encrypted: no");
+            println!("Latency:   {} ms", session.latency_ms());
+            for stage in &session.latency {
+                println!("  {:<14} {} ms", stage.stage, stage.ms);
+            }
+            Ok(())
+        }
+        Commands::Spectrum { id, control_socket } => {
+            let spectrum = rsonance::control::spectrum(&control_socket, id)?;
+            for band in &spectrum.bands {
+                println!("{:>7} Hz  {:>6.1} dBFS", band.center_hz, band.dbfs);
+            }
+            println!("Hum 50 Hz   {:>6.1} dBFS", spectrum.hum_50hz_dbfs);
+            println!("Hum 60 Hz   {:>6.1} dBFS", spectrum.hum_60hz_dbfs);
+            Ok(())
+        }
+        Commands::Health { control_socket } => {
+            let checks = rsonance::control::check_health(&control_socket)?;
+            for check in &checks {
+                println!("{check}");
+            }
+            if !rsonance::health::is_healthy(&checks) {
+                return Err(anyhow::anyhow!("The receiver is unhealthy"));
+            }
+            Ok(())
+        }
+        Commands::Mark {
+            label,
+            control_socket,
+        } => {
+            let offset = rsonance::control::add_marker(&control_socket, &label)?;
+            println!("Marked \"{label}\" at {offset:.3} s into the recording");
+            Ok(())
+        }
+        Commands::Kick { id, control_socket } => {
+            rsonance::control::kick_client(&control_socket, id)?;
+            println!("Disconnected transmitter {id}");
+            Ok(())
         }
     }
 }
@@ -0,0 +1,461 @@
+//! Sidecar manifest of a `--debug-dump` recording
+//!
+//! Next to a dump `<file>`, the receiver keeps `<file>.json` describing the
+//! recording, so post-production tools can find their way around a long
+//! capture without decoding it: when it started, who took part, where each
+//! transmitter connected, reconnected and disconnected, and how loud each
+//! connection was. The manifest is rewritten after every change (through a
+//! temporary file, so it is never seen half written).
+//!
+//! # Format
+//!
+//! ```text
+//! {
+//!   "recording": "session.dump",
+//!   "codec": "s16le",
+//!   "started": "2026-01-01T12:00:00.000Z",
+//!   "updated": "2026-01-01T12:05:00.000Z",
+//!   "participants": [
+//!     {"participant":"192.0.2.1","identity":null,"connections":[1,2]}
+//!   ],
+//!   "connections": [
+//!     {"connection":1,"participant":"192.0.2.1","peer":"192.0.2.1:50000","start_secs":0.012,"end_secs":61.250,"error":null,"peak_dbfs":-3.1,"rms_dbfs":-24.6,"clipped_samples":0}
+//!   ],
+//!   "markers": [
+//!     {"offset_secs":0.012,"event":"connected","connection":1,"participant":"192.0.2.1"},
+//!     {"offset_secs":61.250,"event":"disconnected","connection":1,"participant":"192.0.2.1"},
+//!     {"offset_secs":63.004,"event":"reconnected","connection":2,"participant":"192.0.2.1"},
+//!     {"offset_secs":95.871,"event":"mark","label":"Q&A starts"}
+//!   ]
+//! }
+//! ```
+//!
+//! Offsets are seconds since the dump was created, on the same clock as the
+//! arrival times of its records. A participant is a transmitter's IP
+//! address, or for Unix socket connections its user (`uid:1000`); a
+//! participant connecting again is marked `reconnected`. `end_secs` and the
+//! levels are null while a connection is open, and the levels stay null for
+//! a connection that sent only silence. `mark` markers are set by an
+//! operator with `rsonance mark <label>` through the control socket, to flag
+//! a moment while it happens.
+
+use crate::audit::{json_string, utc_timestamp};
+use crate::codec::Codec;
+use crate::listen::PeerCredentials;
+use log::error;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Instant, SystemTime};
+
+/// Peak, loudness and clipping of the audio of one connection
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::manifest::LevelStats;
+///
+/// let mut levels = LevelStats::default();
+/// assert_eq!(levels.peak_dbfs(), None);
+/// levels.push(&[0x00, 0x80, 0xff, 0x3f]); // -32768 and 16383
+/// assert_eq!(levels.clipped_samples, 1);
+/// assert_eq!(levels.peak_dbfs(), Some(0.0));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LevelStats {
+    /// Largest absolute sample value
+    pub peak: u32,
+    sum_squares: f64,
+    samples: u64,
+    /// Samples at full scale
+    pub clipped_samples: u64,
+}
+
+impl LevelStats {
+    /// Account for a block of S16LE audio
+    pub fn push(&mut self, s16le: &[u8]) {
+        for sample in s16le.chunks_exact(2) {
+            let sample = i16::from_le_bytes([sample[0], sample[1]]);
+            let magnitude = sample.unsigned_abs();
+            self.peak = self.peak.max(u32::from(magnitude));
+            self.sum_squares += f64::from(sample) * f64::from(sample);
+            self.samples += 1;
+            if magnitude >= i16::MAX as u16 {
+                self.clipped_samples += 1;
+            }
+        }
+    }
+
+    /// Peak level in dBFS, or None for silence
+    pub fn peak_dbfs(&self) -> Option<f64> {
+        (self.peak > 0).then(|| to_dbfs(f64::from(self.peak)).min(0.0))
+    }
+
+    /// Average (RMS) level in dBFS, or None for silence
+    pub fn rms_dbfs(&self) -> Option<f64> {
+        (self.sum_squares > 0.0)
+            .then(|| to_dbfs((self.sum_squares / self.samples as f64).sqrt()).min(0.0))
+    }
+}
+
+fn to_dbfs(level: f64) -> f64 {
+    20.0 * (level / 32768.0).log10()
+}
+
+/// What happened at a marker
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MarkerEvent {
+    Connected,
+    Reconnected,
+    Disconnected,
+    /// Set by an operator, with a label
+    Mark(String),
+}
+
+#[derive(Debug, Clone)]
+struct Marker {
+    offset_secs: f64,
+    event: MarkerEvent,
+    /// Connection and participant of a connection marker
+    connection: Option<(u64, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Participant {
+    name: String,
+    identity: Option<PeerCredentials>,
+    connections: Vec<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Connection {
+    id: u64,
+    participant: String,
+    peer: Option<SocketAddr>,
+    start_secs: f64,
+    end_secs: Option<f64>,
+    error: Option<String>,
+    levels: Option<LevelStats>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    participants: Vec<Participant>,
+    connections: Vec<Connection>,
+    markers: Vec<Marker>,
+}
+
+/// Keeps the manifest of a recording up to date
+#[derive(Debug)]
+pub struct RecordingManifest {
+    recording: String,
+    path: String,
+    codec: Codec,
+    /// When the recording started, as a time of day and on the clock the
+    /// offsets are measured with
+    started_at: SystemTime,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+impl RecordingManifest {
+    /// Create the manifest of the recording at `recording`, in `codec`,
+    /// which started at `started`
+    pub fn create(recording: &str, codec: Codec, started: Instant) -> anyhow::Result<Self> {
+        let manifest = Self {
+            recording: recording.to_string(),
+            path: manifest_path(recording),
+            codec,
+            started_at: SystemTime::now() - started.elapsed(),
+            started,
+            state: Mutex::default(),
+        };
+        manifest
+            .write(&manifest.lock())
+            .map_err(|e| anyhow::anyhow!("Failed to create manifest {}: {e}", manifest.path))?;
+        Ok(manifest)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn offset_secs(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// Mark connection `connection` from `peer` (TCP) or `identity` (Unix
+    /// socket) as started
+    pub fn connected(
+        &self,
+        connection: u64,
+        peer: Option<SocketAddr>,
+        identity: Option<PeerCredentials>,
+    ) {
+        let name = match (peer, identity) {
+            (Some(peer), _) => peer.ip().to_string(),
+            (None, Some(identity)) => format!("uid:{}", identity.uid),
+            (None, None) => "unknown".to_string(),
+        };
+        let offset_secs = self.offset_secs();
+        let mut state = self.lock();
+        let event = match state.participants.iter_mut().find(|p| p.name == name) {
+            Some(participant) => {
+                participant.connections.push(connection);
+                MarkerEvent::Reconnected
+            }
+            None => {
+                state.participants.push(Participant {
+                    name: name.clone(),
+                    identity,
+                    connections: vec![connection],
+                });
+                MarkerEvent::Connected
+            }
+        };
+        state.connections.push(Connection {
+            id: connection,
+            participant: name.clone(),
+            peer,
+            start_secs: offset_secs,
+            end_secs: None,
+            error: None,
+            levels: None,
+        });
+        state.markers.push(Marker {
+            offset_secs,
+            event,
+            connection: Some((connection, name)),
+        });
+        self.update(&state);
+    }
+
+    /// Mark connection `connection` as ended, with the levels of its audio
+    /// and the error that ended it, if any
+    pub fn disconnected(&self, connection: u64, levels: LevelStats, error: Option<String>) {
+        let offset_secs = self.offset_secs();
+        let mut state = self.lock();
+        let Some(entry) = state.connections.iter_mut().find(|c| c.id == connection) else {
+            return;
+        };
+        entry.end_secs = Some(offset_secs);
+        entry.error = error;
+        entry.levels = Some(levels);
+        let participant = entry.participant.clone();
+        state.markers.push(Marker {
+            offset_secs,
+            event: MarkerEvent::Disconnected,
+            connection: Some((connection, participant)),
+        });
+        self.update(&state);
+    }
+
+    /// Add a marker labelled `label` at the current point of the recording
+    ///
+    /// # Returns
+    ///
+    /// The offset of the marker into the recording in seconds
+    pub fn mark(&self, label: &str) -> f64 {
+        let offset_secs = self.offset_secs();
+        let mut state = self.lock();
+        state.markers.push(Marker {
+            offset_secs,
+            event: MarkerEvent::Mark(label.to_string()),
+            connection: None,
+        });
+        self.update(&state);
+        offset_secs
+    }
+
+    /// Rewrite the manifest, logging rather than returning a failure, so a
+    /// full disk does not stop the stream
+    fn update(&self, state: &State) {
+        if let Err(e) = self.write(state) {
+            error!("Failed to update manifest {}: {e}", self.path);
+        }
+    }
+
+    fn write(&self, state: &State) -> std::io::Result<()> {
+        let temporary = format!("{}.tmp", self.path);
+        std::fs::write(&temporary, self.to_json(state, SystemTime::now()))?;
+        std::fs::rename(&temporary, &self.path)
+    }
+
+    fn to_json(&self, state: &State, now: SystemTime) -> String {
+        let mut json = String::new();
+        let _ = writeln!(json, "{{");
+        let _ = writeln!(json, "  \"recording\": {},", json_string(&self.recording));
+        let _ = writeln!(json, "  \"codec\": \"{}\",", self.codec);
+        let _ = writeln!(
+            json,
+            "  \"started\": \"{}\",",
+            utc_timestamp(self.started_at)
+        );
+        let _ = writeln!(json, "  \"updated\": \"{}\",", utc_timestamp(now));
+
+        let participants: Vec<String> = state
+            .participants
+            .iter()
+            .map(|p| {
+                let identity = match p.identity {
+                    Some(PeerCredentials { uid, gid, pid }) => {
+                        format!("{{\"uid\":{uid},\"gid\":{gid},\"pid\":{pid}}}")
+                    }
+                    None => "null".to_string(),
+                };
+                let connections: Vec<String> = p.connections.iter().map(u64::to_string).collect();
+                format!(
+                    "{{\"participant\":{},\"identity\":{identity},\"connections\":[{}]}}",
+                    json_string(&p.name),
+                    connections.join(",")
+                )
+            })
+            .collect();
+        push_array(&mut json, "participants", &participants, false);
+
+        let connections: Vec<String> = state
+            .connections
+            .iter()
+            .map(|c| {
+                let levels = c.levels.unwrap_or_default();
+                format!(
+                    "{{\"connection\":{},\"participant\":{},\"peer\":{},\"start_secs\":{:.3},\"end_secs\":{},\"error\":{},\"peak_dbfs\":{},\"rms_dbfs\":{},\"clipped_samples\":{}}}",
+                    c.id,
+                    json_string(&c.participant),
+                    c.peer
+                        .map_or_else(|| "null".to_string(), |p| json_string(&p.to_string())),
+                    c.start_secs,
+                    c.end_secs
+                        .map_or_else(|| "null".to_string(), |s| format!("{s:.3}")),
+                    c.error.as_deref().map_or_else(|| "null".to_string(), json_string),
+                    json_number(levels.peak_dbfs()),
+                    json_number(levels.rms_dbfs()),
+                    c.levels
+                        .map_or_else(|| "null".to_string(), |l| l.clipped_samples.to_string())
+                )
+            })
+            .collect();
+        push_array(&mut json, "connections", &connections, false);
+
+        let markers: Vec<String> = state
+            .markers
+            .iter()
+            .map(|m| {
+                let event = match &m.event {
+                    MarkerEvent::Connected => "connected",
+                    MarkerEvent::Reconnected => "reconnected",
+                    MarkerEvent::Disconnected => "disconnected",
+                    MarkerEvent::Mark(label) => {
+                        return format!(
+                            "{{\"offset_secs\":{:.3},\"event\":\"mark\",\"label\":{}}}",
+                            m.offset_secs,
+                            json_string(label)
+                        );
+                    }
+                };
+                let (connection, participant) = m.connection.clone().unwrap_or_default();
+                format!(
+                    "{{\"offset_secs\":{:.3},\"event\":\"{event}\",\"connection\":{connection},\"participant\":{}}}",
+                    m.offset_secs,
+                    json_string(&participant)
+                )
+            })
+            .collect();
+        push_array(&mut json, "markers", &markers, true);
+        json.push_str("}\n");
+        json
+    }
+}
+
+/// Path of the manifest of the recording at `recording`
+pub fn manifest_path(recording: &str) -> String {
+    format!("{recording}.json")
+}
+
+/// A level in dB with one decimal, or null
+fn json_number(value: Option<f64>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| format!("{v:.1}"))
+}
+
+/// Append `"name": [...]` with one item per line
+fn push_array(json: &mut String, name: &str, items: &[String], last: bool) {
+    let _ = write!(json, "  \"{name}\": [");
+    for (i, item) in items.iter().enumerate() {
+        let _ = write!(
+            json,
+            "\n    {item}{}",
+            if i + 1 < items.len() { "," } else { "" }
+        );
+    }
+    if !items.is_empty() {
+        json.push_str("\n  ");
+    }
+    json.push(']');
+    if !last {
+        json.push(',');
+    }
+    json.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_marks_reconnects_and_levels() {
+        let recording = format!("/tmp/rsonance_manifest_test_{}.dump", std::process::id());
+        let manifest = RecordingManifest::create(&recording, Codec::S16LE, Instant::now()).unwrap();
+        let peer: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        manifest.connected(1, Some(peer), None);
+
+        let mut levels = LevelStats::default();
+        levels.push(&16384i16.to_le_bytes());
+        manifest.disconnected(1, levels, Some("reset \"by peer\"".to_string()));
+        manifest.connected(
+            2,
+            None,
+            Some(PeerCredentials {
+                uid: 1000,
+                gid: 100,
+                pid: 42,
+            }),
+        );
+        manifest.connected(3, Some("192.0.2.1:50001".parse().unwrap()), None);
+        assert!(manifest.mark("Q&A \"starts\"") >= 0.0);
+
+        let json = std::fs::read_to_string(manifest_path(&recording)).unwrap();
+        let _ = std::fs::remove_file(manifest_path(&recording));
+        assert!(json.contains(&format!("\"recording\": \"{recording}\",")));
+        assert!(
+            json.contains(
+                "{\"participant\":\"192.0.2.1\",\"identity\":null,\"connections\":[1,3]}"
+            )
+        );
+        assert!(json.contains(
+            "{\"participant\":\"uid:1000\",\"identity\":{\"uid\":1000,\"gid\":100,\"pid\":42},\"connections\":[2]}"
+        ));
+        assert!(json.contains(
+            "\"error\":\"reset \\\"by peer\\\"\",\"peak_dbfs\":-6.0,\"rms_dbfs\":-6.0,\"clipped_samples\":0}"
+        ));
+        assert!(json.contains(
+            "\"end_secs\":null,\"error\":null,\"peak_dbfs\":null,\"rms_dbfs\":null,\"clipped_samples\":null}"
+        ));
+        let events: Vec<&str> = json
+            .lines()
+            .filter_map(|line| line.split("\"event\":\"").nth(1))
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "connected",
+                "disconnected",
+                "connected",
+                "reconnected",
+                "mark"
+            ]
+        );
+        assert!(json.contains("\"event\":\"mark\",\"label\":\"Q&A \\\"starts\\\"\"}"));
+        assert!(json.trim_end().ends_with("]\n}"));
+    }
+}
@@ -0,0 +1,136 @@
+//! Terminal level meter for the transmitter
+//!
+//! With `--level-meter` the transmitter draws a live input level bar on
+//! stderr, so it is obvious whether the capture device picks up sound at all
+//! before the network is suspected. The capture callback only records the
+//! peak sample; a separate thread redraws the bar ten times per second.
+
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Interval between redraws of the bar
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Level shown at the left edge of the bar, in dBFS
+const FLOOR_DB: f32 = -60.0;
+
+/// Width of the bar in characters
+const BAR_WIDTH: usize = 40;
+
+/// Handle used by the capture callback to feed the level meter
+#[derive(Debug, Clone, Default)]
+pub struct LevelMeter {
+    /// Highest absolute sample value since the last redraw
+    peak: Arc<AtomicU32>,
+}
+
+impl LevelMeter {
+    /// Record the peak of a block of captured S16LE audio
+    pub fn push(&self, s16le: &[u8]) {
+        let peak = s16le
+            .chunks_exact(2)
+            .map(|bytes| u32::from(i16::from_le_bytes([bytes[0], bytes[1]]).unsigned_abs()))
+            .max()
+            .unwrap_or(0);
+        self.peak.fetch_max(peak, Ordering::Relaxed);
+    }
+
+    /// Take the peak recorded since the last call, in dBFS
+    fn take_peak_db(&self) -> f32 {
+        peak_to_db(self.peak.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// Start redrawing the level bar on stderr
+///
+/// # Returns
+///
+/// The meter to feed from the capture callback, or `None` if stderr is not a
+/// terminal
+pub fn start_level_meter() -> Option<LevelMeter> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+
+    let meter = LevelMeter::default();
+    let reader = meter.clone();
+    thread::Builder::new()
+        .name("level-meter".to_string())
+        .spawn(move || {
+            loop {
+                thread::sleep(REDRAW_INTERVAL);
+                let line = render_bar(reader.take_peak_db());
+                let mut stderr = std::io::stderr().lock();
+                let _ = write!(stderr, "\r\x1b[2K{line}");
+                let _ = stderr.flush();
+            }
+        })
+        .ok()?;
+    Some(meter)
+}
+
+/// Convert a peak sample magnitude to dBFS
+fn peak_to_db(peak: u32) -> f32 {
+    if peak == 0 {
+        f32::NEG_INFINITY
+    } else {
+        20.0 * (peak as f32 / 32768.0).log10()
+    }
+}
+
+/// Render a coloured bar for `db`, green below -18 dBFS, yellow below -6, red above
+fn render_bar(db: f32) -> String {
+    let fraction = ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0);
+    let filled = (fraction * BAR_WIDTH as f32).round() as usize;
+    let colour = if db >= -6.0 {
+        "31"
+    } else if db >= -18.0 {
+        "33"
+    } else {
+        "32"
+    };
+    let label = if db > FLOOR_DB {
+        format!("{db:>5.1} dBFS")
+    } else {
+        "  -inf dBFS".to_string()
+    };
+    format!(
+        "Level [\x1b[{colour}m{}\x1b[0m{}] {label}",
+        "#".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter_tracks_and_resets_peak() {
+        let meter = LevelMeter::default();
+        let bytes: Vec<u8> = [100i16, -16384, 200]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        meter.push(&bytes);
+        assert!((meter.take_peak_db() + 6.02).abs() < 0.01);
+        assert_eq!(meter.take_peak_db(), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_render_bar() {
+        let silent = render_bar(f32::NEG_INFINITY);
+        assert!(silent.contains(&format!("\x1b[0m{}]", " ".repeat(BAR_WIDTH))));
+        assert!(silent.ends_with("-inf dBFS"));
+
+        let full = render_bar(0.0);
+        assert!(full.contains(&format!("\x1b[31m{}\x1b[0m]", "#".repeat(BAR_WIDTH))));
+
+        let half = render_bar(-30.0);
+        assert!(half.contains(&format!("\x1b[32m{}\x1b[0m", "#".repeat(BAR_WIDTH / 2))));
+        assert!(half.ends_with("-30.0 dBFS"));
+    }
+}
@@ -0,0 +1,500 @@
+//! Receiver `--metrics-addr`: Prometheus metrics over HTTP
+//!
+//! With `--metrics-addr 127.0.0.1:9464` the receiver answers `GET /metrics`
+//! on that address in the Prometheus text format, so a long-running receiver
+//! can be scraped by Prometheus and graphed or alerted on in Grafana:
+//!
+//! - `rsonance_connections_total` - transmitter connections accepted
+//! - `rsonance_reconnects_total` - connections from an address (or, for
+//!   Unix sockets, the local host) that had connected before
+//! - `rsonance_received_bytes_total` - bytes received from transmitters
+//! - `rsonance_dropped_audio_seconds_total` - audio dropped by the
+//!   `--max-latency-ms` guard or an overfull jitter buffer
+//! - `rsonance_udp_datagrams_lost_total` - UDP datagrams lost, or discarded
+//!   for arriving after a later one
+//! - `rsonance_connected_clients` - transmitters connected now
+//! - `rsonance_socket_queue_seconds` and `rsonance_output_queue_seconds` -
+//!   per connected transmitter, audio received but not read yet, and audio
+//!   in the jitter buffer or output but not played yet
+//! - `rsonance_band_level_dbfs` and `rsonance_hum_level_dbfs` - with
+//!   `--spectrum`, per connected transmitter, the octave band levels (`band`
+//!   label, its center in Hz) and the mains hum at 50 and 60 Hz (`mains`
+//!   label), see [`crate::spectrum`]
+//!
+//! `GET /healthz` runs the receiver's health checks (see [`crate::health`])
+//! and answers `200 OK` if all of them pass, `503 Service Unavailable`
+//! otherwise, with one line per check, for HTTP probes of orchestrators and
+//! load balancers.
+//!
+//! Counters start at zero when the receiver starts. The server handles one
+//! request at a time on its own thread; anything else is answered with an
+//! error status. A request must arrive within
+//! [`REQUEST_TIMEOUT`] and [`MAX_REQUEST_LEN`] bytes, so a slow or endless
+//! one cannot hold up the next scrape.
+
+use crate::control::ClientInfo;
+use crate::health::{HealthProbe, is_healthy};
+use crate::receiver::ClientRegistry;
+use crate::spectrum::Spectrum;
+use log::{debug, error, info};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest a scrape may take to send its whole request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most bytes of a request read, far above any scrape's
+pub const MAX_REQUEST_LEN: u64 = 8192;
+
+/// Most request header lines read before giving up on a request
+const MAX_HEADER_LINES: usize = 100;
+
+/// Counters over the lifetime of a receiver, see
+/// [`ClientRegistry::totals`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    /// Transmitter connections accepted
+    pub connections: u64,
+    /// Connections from an address that had connected before
+    pub reconnects: u64,
+    /// Bytes received from transmitters, including audio dropped to catch up
+    pub bytes_received: u64,
+    /// Audio dropped to keep the latency down, in milliseconds
+    pub dropped_ms: u64,
+    /// UDP datagrams lost or discarded for arriving late
+    pub datagrams_lost: u64,
+}
+
+/// Render `totals`, the `clients` connected now and the `spectra` of their
+/// streams, by connection id, in the Prometheus text exposition format
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::metrics::{Totals, render};
+///
+/// let text = render(
+///     &Totals {
+///         connections: 3,
+///         dropped_ms: 1500,
+///         ..Totals::default()
+///     },
+///     &[],
+///     &[],
+/// );
+/// assert!(text.contains("\nrsonance_connections_total 3\n"));
+/// assert!(text.contains("\nrsonance_dropped_audio_seconds_total 1.5\n"));
+/// assert!(text.contains("\nrsonance_connected_clients 0\n"));
+/// ```
+pub fn render(totals: &Totals, clients: &[ClientInfo], spectra: &[(u64, Spectrum)]) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(text, "{name}{labels} {value}");
+        }
+    };
+    let total = |value: u64| [(String::new(), value as f64)];
+
+    metric(
+        "rsonance_connections_total",
+        "counter",
+        "Transmitter connections accepted.",
+        &total(totals.connections),
+    );
+    metric(
+        "rsonance_reconnects_total",
+        "counter",
+        "Connections from an address that had connected before.",
+        &total(totals.reconnects),
+    );
+    metric(
+        "rsonance_received_bytes_total",
+        "counter",
+        "Bytes received from transmitters.",
+        &total(totals.bytes_received),
+    );
+    metric(
+        "rsonance_dropped_audio_seconds_total",
+        "counter",
+        "Audio dropped to keep the latency down.",
+        &[(String::new(), totals.dropped_ms as f64 / 1000.0)],
+    );
+    metric(
+        "rsonance_udp_datagrams_lost_total",
+        "counter",
+        "UDP datagrams lost or discarded for arriving late.",
+        &total(totals.datagrams_lost),
+    );
+    metric(
+        "rsonance_connected_clients",
+        "gauge",
+        "Transmitters connected.",
+        &total(clients.len() as u64),
+    );
+    let per_client = |ms: fn(&ClientInfo) -> u64| -> Vec<(String, f64)> {
+        clients
+            .iter()
+            .map(|client| {
+                (
+                    format!("{{{}}}", client_labels(client)),
+                    ms(client) as f64 / 1000.0,
+                )
+            })
+            .collect()
+    };
+    metric(
+        "rsonance_socket_queue_seconds",
+        "gauge",
+        "Audio received from a transmitter but not read yet.",
+        &per_client(|client| client.socket_queue_ms),
+    );
+    metric(
+        "rsonance_output_queue_seconds",
+        "gauge",
+        "Audio in the jitter buffer or output but not played yet.",
+        &per_client(|client| client.output_queue_ms),
+    );
+
+    // Spectra of the clients still connected, labeled like their queues
+    let analyzed: Vec<(String, &Spectrum)> = spectra
+        .iter()
+        .filter_map(|(id, spectrum)| {
+            let client = clients.iter().find(|client| client.id == *id)?;
+            Some((client_labels(client), spectrum))
+        })
+        .collect();
+    let bands: Vec<(String, f64)> = analyzed
+        .iter()
+        .flat_map(|(labels, spectrum)| {
+            spectrum.bands.iter().map(move |band| {
+                (
+                    format!("{{{labels},band=\"{}\"}}", band.center_hz),
+                    band.dbfs,
+                )
+            })
+        })
+        .collect();
+    metric(
+        "rsonance_band_level_dbfs",
+        "gauge",
+        "Smoothed level of an octave band of a stream, by center frequency in Hz.",
+        &bands,
+    );
+    let hum: Vec<(String, f64)> = analyzed
+        .iter()
+        .flat_map(|(labels, spectrum)| {
+            [
+                ("50", spectrum.hum_50hz_dbfs),
+                ("60", spectrum.hum_60hz_dbfs),
+            ]
+            .map(|(mains, dbfs)| (format!("{{{labels},mains=\"{mains}\"}}"), dbfs))
+        })
+        .collect();
+    metric(
+        "rsonance_hum_level_dbfs",
+        "gauge",
+        "Level of the strongest line near the mains frequency in a stream.",
+        &hum,
+    );
+    text
+}
+
+/// Labels identifying a connected transmitter, without the braces
+fn client_labels(client: &ClientInfo) -> String {
+    let peer = client
+        .peer
+        .map_or_else(|| "local".to_string(), |peer| peer.to_string());
+    format!(
+        "id=\"{}\",peer=\"{}\",codec=\"{}\"",
+        client.id,
+        escape_label(&peer),
+        escape_label(&client.codec)
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve the metrics of `registry` and the checks of `health` over HTTP on
+/// `addr` until the returned server is closed
+///
+/// Returns an error if `addr` cannot be bound.
+pub fn serve(
+    addr: &str,
+    registry: Arc<ClientRegistry>,
+    health: Arc<HealthProbe>,
+) -> anyhow::Result<MetricsServer> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| anyhow::anyhow!("Cannot serve metrics on {addr}: {e}"))?;
+    let local_addr = listener.local_addr()?;
+    info!("Metrics available at http://{local_addr}/metrics");
+
+    let handle = listener.try_clone()?;
+    let closed = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+        let closed = Arc::clone(&closed);
+        move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = handle_request(stream, &registry, &health) {
+                            debug!("Metrics request failed: {e}");
+                        }
+                    }
+                    Err(_) if closed.load(Ordering::SeqCst) => break,
+                    Err(e) => error!("Metrics accept error: {e}"),
+                }
+            }
+        }
+    });
+
+    Ok(MetricsServer {
+        local_addr,
+        listener: handle,
+        closed,
+        thread,
+    })
+}
+
+/// A metrics endpoint being served, see [`serve`]
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    /// Handle of the listening socket, to wake the serving thread
+    listener: TcpListener,
+    closed: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Address the endpoint is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop serving metrics
+    ///
+    /// Returns once the request being handled, if any, has been answered.
+    pub fn close(self) {
+        self.closed.store(true, Ordering::SeqCst);
+        crate::listen::shutdown_listener(self.listener.as_raw_fd());
+        let _ = self.thread.join();
+    }
+}
+
+/// A connection read from until a deadline, however slowly its bytes
+/// trickle in
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "request took too long"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Read one HTTP request from `stream` and answer it
+fn handle_request(
+    stream: TcpStream,
+    registry: &ClientRegistry,
+    health: &HealthProbe,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(
+        DeadlineReader {
+            stream: stream.try_clone()?,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        }
+        .take(MAX_REQUEST_LEN),
+    );
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers are not needed, but are read so the client is not reset
+    let mut line = String::new();
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    debug!("Metrics request: {}", request.trim());
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => {
+            let clients = registry.snapshot();
+            let spectra: Vec<(u64, Spectrum)> = clients
+                .iter()
+                .filter_map(|client| Some((client.id, registry.spectrum(client.id)?)))
+                .collect();
+            ("200 OK", render(&registry.totals(), &clients, &spectra))
+        }
+        ("GET" | "HEAD", "/healthz") => {
+            let checks = health.check();
+            let status = if is_healthy(&checks) {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            let body = checks.iter().map(|check| format!("{check}\n")).collect();
+            (status, body)
+        }
+        ("GET" | "HEAD", _) => (
+            "404 Not Found",
+            "Metrics are at /metrics, health checks at /healthz\n".to_string(),
+        ),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+
+    // The Prometheus text format is versioned, other answers are plain text
+    let version = if path == "/metrics" {
+        "version=0.0.4; "
+    } else {
+        ""
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; {version}charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(body.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::spectrum::BandLevel;
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_render_labels_every_connected_client() {
+        let client = ClientInfo {
+            id: 7,
+            peer: Some("10.0.0.5:40000".parse().unwrap()),
+            codec: "s16le".to_string(),
+            connected_for: Duration::from_secs(5),
+            bytes_received: 882_000,
+            socket_queue_ms: 4,
+            output_queue_ms: 120,
+            metadata: Default::default(),
+        };
+        let local = ClientInfo {
+            id: 8,
+            peer: None,
+            ..client.clone()
+        };
+        let spectrum = Spectrum {
+            bands: vec![BandLevel {
+                center_hz: 31.5,
+                dbfs: -60.5,
+            }],
+            hum_50hz_dbfs: -42.0,
+            hum_60hz_dbfs: -120.0,
+        };
+        // The spectrum of a client gone since is left out
+        let spectra = [(7, spectrum.clone()), (9, spectrum)];
+        let text = render(&Totals::default(), &[client, local], &spectra);
+        assert!(text.contains(
+            "\nrsonance_band_level_dbfs{id=\"7\",peer=\"10.0.0.5:40000\",codec=\"s16le\",band=\"31.5\"} -60.5\n"
+        ));
+        assert!(text.contains(
+            "\nrsonance_hum_level_dbfs{id=\"7\",peer=\"10.0.0.5:40000\",codec=\"s16le\",mains=\"50\"} -42\n"
+        ));
+        assert_eq!(text.matches("rsonance_hum_level_dbfs{").count(), 2);
+        assert!(text.contains(
+            "\nrsonance_output_queue_seconds{id=\"7\",peer=\"10.0.0.5:40000\",codec=\"s16le\"} 0.12\n"
+        ));
+        assert!(text.contains(
+            "\nrsonance_socket_queue_seconds{id=\"8\",peer=\"local\",codec=\"s16le\"} 0.004\n"
+        ));
+        assert!(text.contains("\nrsonance_connected_clients 2\n"));
+        // Every sample follows its metric's TYPE line
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(text.contains(&format!("# TYPE {name} ")), "{line}");
+        }
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+
+    #[test]
+    fn test_serves_metrics_and_rejects_other_requests() {
+        let registry = Arc::new(ClientRegistry::with_codec(Codec::S16LE));
+        let health = Arc::new(HealthProbe::default());
+        let accepting = health.add_listener("0.0.0.0:8080");
+        let server = serve("127.0.0.1:0", registry, health).unwrap();
+        let addr = server.local_addr();
+
+        let response = get(addr, "GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body.contains("rsonance_connections_total 0\n"));
+
+        let response = get(addr, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+        let response = get(addr, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 "), "{response}");
+
+        let response = get(addr, "GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("ok   listener 0.0.0.0:8080: accepting\n"));
+        accepting.store(false, Ordering::SeqCst);
+        let response = get(addr, "GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 "), "{response}");
+
+        // A request that has not arrived by the deadline is given up on,
+        // whether or not bytes still trickle in
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /metrics").unwrap();
+        let mut slow = DeadlineReader {
+            stream: listener.accept().unwrap().0,
+            deadline: Instant::now(),
+        };
+        let error = slow.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        server.close();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
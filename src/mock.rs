@@ -0,0 +1,350 @@
+//! Mock capture source for running without a microphone
+//!
+//! With `--mock-input` the transmitter streams a deterministic test signal
+//! instead of opening an audio device, so a transmitter and a receiver with
+//! `--backend null` can be tested end to end in containers without a sound
+//! server. `--mock-signal` picks the signal:
+//!
+//! - `sine`: a 440 Hz tone, the default
+//! - `pink`: pink noise, equal energy per octave, for judging frequency
+//!   response by ear or with a spectrum analyzer
+//! - `sweep`: a logarithmic sweep from 20 Hz to 20 kHz (or just below the
+//!   Nyquist frequency) over 5 seconds
+//!
+//! `--mock-repeat-ms` restarts the signal at a fixed interval with silence in
+//! between: the tone and the noise sound for the first 500 ms of each
+//! interval, the sweep once. Missing or late bursts then show dropouts and
+//! latency at the receiving end.
+
+use anyhow::anyhow;
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frequency of the generated tone in Hz
+pub const MOCK_FREQUENCY: f64 = 440.0;
+
+/// Peak amplitude of the generated signals (-6 dBFS)
+const MOCK_AMPLITUDE: f64 = 16384.0;
+
+/// Duration of each generated block in milliseconds
+const MOCK_BLOCK_MS: u64 = 10;
+
+/// Duration of one sweep in milliseconds
+const SWEEP_MS: u64 = 5000;
+
+/// Start and end frequency of the sweep in Hz
+const SWEEP_RANGE: (f64, f64) = (20.0, 20000.0);
+
+/// Duration of the tone or noise burst in each `--mock-repeat-ms` interval
+const BURST_MS: u64 = 500;
+
+/// Scales the pink noise filter output to about -6 dBFS peaks
+const PINK_GAIN: f64 = 0.12;
+
+/// Test signal generated by `--mock-input`
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::mock::MockSignal;
+///
+/// let signal: MockSignal = "sweep".parse().unwrap();
+/// assert_eq!(signal, MockSignal::Sweep);
+/// assert_eq!(signal.to_string(), "sweep");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MockSignal {
+    /// 440 Hz sine tone
+    #[default]
+    Sine,
+    /// Pink noise
+    Pink,
+    /// Logarithmic sweep from 20 Hz to 20 kHz
+    Sweep,
+}
+
+impl fmt::Display for MockSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MockSignal::Sine => "sine",
+            MockSignal::Pink => "pink",
+            MockSignal::Sweep => "sweep",
+        })
+    }
+}
+
+impl FromStr for MockSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sine" => Ok(MockSignal::Sine),
+            "pink" => Ok(MockSignal::Pink),
+            "sweep" => Ok(MockSignal::Sweep),
+            _ => Err(anyhow!(
+                "Unknown mock signal '{s}' (expected sine, pink, or sweep)"
+            )),
+        }
+    }
+}
+
+/// Generates a test signal as interleaved S16LE, identical on every channel
+///
+/// Samples depend only on their position in the stream, so two sources with
+/// the same settings produce bit-identical audio.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::mock::{MockSignal, SignalSource};
+///
+/// let mut source = SignalSource::new(48000, 2);
+/// let block = source.next_block(480);
+/// assert_eq!(block.len(), 480 * 2 * 2);
+/// assert_eq!(block, SignalSource::new(48000, 2).next_block(480));
+///
+/// let noise = SignalSource::new(48000, 2).signal(MockSignal::Pink).next_block(480);
+/// assert_ne!(noise, block);
+/// ```
+#[derive(Debug)]
+pub struct SignalSource {
+    signal: MockSignal,
+    sample_rate: u32,
+    channels: u16,
+    /// Frames between restarts of the signal (0 = continuous)
+    repeat: u64,
+    /// Index of the next frame
+    position: u64,
+    pink: PinkNoise,
+}
+
+impl SignalSource {
+    /// Create a sine source for `sample_rate` Hz audio with `channels`
+    /// channels
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            signal: MockSignal::Sine,
+            sample_rate: sample_rate.max(1),
+            channels: channels.max(1),
+            repeat: 0,
+            position: 0,
+            pink: PinkNoise::default(),
+        }
+    }
+
+    /// Generate `signal` instead of the sine tone
+    pub fn signal(mut self, signal: MockSignal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Restart the signal every `repeat_ms` milliseconds, with silence after
+    /// each burst or sweep (0 = continuous)
+    pub fn repeat_ms(mut self, repeat_ms: u64) -> Self {
+        self.repeat = self.frames(repeat_ms);
+        self
+    }
+
+    /// Generate the next `frames` frames
+    pub fn next_block(&mut self, frames: usize) -> Vec<u8> {
+        let mut block = Vec::with_capacity(frames * usize::from(self.channels) * 2);
+        for _ in 0..frames {
+            let sample = (self.next_sample() * MOCK_AMPLITUDE).round() as i16;
+            for _ in 0..self.channels {
+                block.extend_from_slice(&sample.to_le_bytes());
+            }
+            self.position += 1;
+        }
+        block
+    }
+
+    /// Value of the frame at `position`, -1.0 to 1.0
+    fn next_sample(&mut self) -> f64 {
+        let sweep = self.frames(SWEEP_MS);
+        let (offset, length) = match (self.repeat, self.signal) {
+            (0, MockSignal::Sweep) => (self.position % sweep, sweep),
+            (0, _) => (self.position, u64::MAX),
+            (repeat, MockSignal::Sweep) => (self.position % repeat, sweep),
+            (repeat, _) => (self.position % repeat, self.frames(BURST_MS)),
+        };
+        if offset == 0 {
+            self.pink = PinkNoise::default();
+        }
+        if offset >= length {
+            return 0.0;
+        }
+
+        let rate = f64::from(self.sample_rate);
+        match self.signal {
+            MockSignal::Sine => (TAU * MOCK_FREQUENCY * offset as f64 / rate).sin(),
+            MockSignal::Pink => self.pink.next(),
+            MockSignal::Sweep => {
+                let (start, end) = SWEEP_RANGE;
+                let end = end.min(rate * 0.45);
+                let duration = SWEEP_MS as f64 / 1000.0;
+                let growth = (end / start).ln();
+                let t = offset as f64 / rate;
+                let phase = TAU * start * duration / growth * ((t * growth / duration).exp() - 1.0);
+                phase.sin()
+            }
+        }
+    }
+
+    fn frames(&self, ms: u64) -> u64 {
+        u64::from(self.sample_rate) * ms / 1000
+    }
+}
+
+/// Deterministic pink noise: white noise from a xorshift generator through
+/// Paul Kellet's three-pole filter
+#[derive(Debug, Clone)]
+struct PinkNoise {
+    state: u32,
+    poles: [f64; 3],
+}
+
+impl Default for PinkNoise {
+    fn default() -> Self {
+        Self {
+            state: 0x9E37_79B9,
+            poles: [0.0; 3],
+        }
+    }
+}
+
+impl PinkNoise {
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        let white = f64::from(self.state) / f64::from(u32::MAX) * 2.0 - 1.0;
+
+        let [b0, b1, b2] = &mut self.poles;
+        *b0 = 0.99765 * *b0 + white * 0.0990460;
+        *b1 = 0.96300 * *b1 + white * 0.2965164;
+        *b2 = 0.57000 * *b2 + white * 1.0526913;
+        ((*b0 + *b1 + *b2 + white * 0.1848) * PINK_GAIN).clamp(-1.0, 1.0)
+    }
+}
+
+/// Generate `source` in real time on a separate thread
+///
+/// Blocks of 10 ms are passed to `deliver` on schedule; timing follows the
+/// wall clock, so late wake-ups do not make the stream drift. The thread
+/// stops when `deliver` returns `false`.
+pub fn spawn_mock_capture(
+    mut source: SignalSource,
+    mut deliver: impl FnMut(Vec<u8>) -> bool + Send + 'static,
+) {
+    let sample_rate = source.sample_rate;
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut sent_frames = 0u64;
+        for block in 1u32.. {
+            let due = started + Duration::from_millis(MOCK_BLOCK_MS) * block;
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            let elapsed = started.elapsed();
+            let total_frames = (elapsed.as_micros() * u128::from(sample_rate) / 1_000_000) as u64;
+            let frames = (total_frames - sent_frames) as usize;
+            if frames == 0 {
+                continue;
+            }
+            sent_frames = total_frames;
+            if !deliver(source.next_block(frames)) {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn samples(block: &[u8]) -> Vec<i16> {
+        block
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_sine_source_is_continuous_across_blocks() {
+        let whole = SignalSource::new(8000, 1).next_block(100);
+        let mut source = SignalSource::new(8000, 1);
+        let mut split = source.next_block(30);
+        split.extend(source.next_block(70));
+        assert_eq!(whole, split);
+
+        let samples = samples(&whole);
+        assert_eq!(samples[0], 0);
+        let peak = *samples.iter().max().unwrap();
+        assert!(peak > 16000 && peak <= MOCK_AMPLITUDE as i16);
+    }
+
+    #[test]
+    fn test_sine_source_duplicates_channels() {
+        let samples = samples(&SignalSource::new(48000, 2).next_block(10));
+        assert!(samples.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn test_pink_noise_favors_low_frequencies() {
+        let mut source = SignalSource::new(48000, 1).signal(MockSignal::Pink);
+        let noise: Vec<f64> = samples(&source.next_block(48000))
+            .into_iter()
+            .map(f64::from)
+            .collect();
+        let energy: f64 = noise.iter().map(|x| x * x).sum();
+        let peak = noise.iter().fold(0.0f64, |peak, x| peak.max(x.abs()));
+        assert!(peak > 8000.0 && peak < MOCK_AMPLITUDE, "{peak}");
+
+        // White noise has twice its energy in the differences, pink far less
+        let changes: f64 = noise.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+        assert!(changes < energy * 0.5, "{changes} vs {energy}");
+    }
+
+    #[test]
+    fn test_sweep_rises_in_frequency() {
+        let mut source = SignalSource::new(8000, 1).signal(MockSignal::Sweep);
+        let sweep = samples(&source.next_block(8000 * 5));
+        let crossings = |part: &[i16]| part.windows(2).filter(|w| w[0] < 0 && w[1] >= 0).count();
+        // 20 Hz at the start, close to 3.6 kHz at the end
+        assert!(crossings(&sweep[..8000]) < 60);
+        assert!(crossings(&sweep[8000 * 4..]) > 1500);
+        // Then it starts over
+        assert_eq!(samples(&source.next_block(100)), sweep[..100]);
+    }
+
+    #[test]
+    fn test_repeat_plays_bursts_with_silence_between() {
+        let mut source = SignalSource::new(1000, 1)
+            .signal(MockSignal::Pink)
+            .repeat_ms(2000);
+        let first = samples(&source.next_block(2000));
+        let second = samples(&source.next_block(2000));
+        assert_eq!(first, second);
+        assert!(first[..500].iter().any(|&sample| sample != 0));
+        assert!(first[500..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn test_mock_capture_delivers_in_real_time() {
+        let (tx, rx) = mpsc::channel();
+        spawn_mock_capture(SignalSource::new(1000, 1), move |block| {
+            tx.send(block).is_ok()
+        });
+
+        let started = Instant::now();
+        let mut frames = 0;
+        while frames < 50 {
+            frames += rx.recv().unwrap().len() / 2;
+        }
+        drop(rx);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,75 @@
+//! Commands for receiving the raw stream with external tools
+//!
+//! With `--raw-output-compat` the TCP stream is headerless interleaved S16LE
+//! at 48 kHz, so GStreamer and ffmpeg can take the receiver's place once they
+//! are told the rate and channel count.
+
+use std::fmt::Write;
+
+/// Sample rate used by `--raw-output-compat`
+pub const RAW_COMPAT_SAMPLE_RATE: u32 = 48000;
+
+/// Build shell commands that listen on `port` and play a raw S16LE stream
+///
+/// The output lists a `gst-launch-1.0` pipeline using `tcpserversrc`, one
+/// reading the stream from standard input via `fdsrc`, and an `ffplay`
+/// command, each on its own line preceded by a comment.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::pipeline::pipeline_commands;
+///
+/// let commands = pipeline_commands(8080, 48000, 2);
+/// assert!(commands.contains("tcpserversrc host=0.0.0.0 port=8080"));
+/// assert!(commands.contains("ffplay -f s16le -ar 48000 -ch_layout stereo"));
+/// ```
+pub fn pipeline_commands(port: u16, sample_rate: u32, channels: u16) -> String {
+    let parse = format!(
+        "rawaudioparse use-sink-caps=false format=pcm pcm-format=s16le \
+         sample-rate={sample_rate} num-channels={channels}"
+    );
+    let layout = match channels {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        n => format!("{n}c"),
+    };
+
+    let mut commands = String::new();
+    let _ = writeln!(commands, "# GStreamer, listening for the transmitter");
+    let _ = writeln!(
+        commands,
+        "gst-launch-1.0 tcpserversrc host=0.0.0.0 port={port} ! {parse} ! audioconvert ! autoaudiosink"
+    );
+    let _ = writeln!(commands, "# GStreamer, reading the stream from stdin");
+    let _ = writeln!(
+        commands,
+        "nc -l {port} | gst-launch-1.0 fdsrc fd=0 ! {parse} ! audioconvert ! autoaudiosink"
+    );
+    let _ = writeln!(commands, "# ffmpeg");
+    let _ = writeln!(
+        commands,
+        "ffplay -f s16le -ar {sample_rate} -ch_layout {layout} -nodisp 'tcp://0.0.0.0:{port}?listen'"
+    );
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_commands_format() {
+        let commands = pipeline_commands(9000, 44100, 1);
+        assert!(commands.contains("sample-rate=44100 num-channels=1"));
+        assert!(commands.contains("nc -l 9000 | gst-launch-1.0 fdsrc fd=0"));
+        assert!(commands.contains("-ch_layout mono"));
+        assert!(commands.contains("'tcp://0.0.0.0:9000?listen'"));
+        assert_eq!(commands.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_pipeline_commands_multichannel_layout() {
+        assert!(pipeline_commands(8080, 48000, 6).contains("-ch_layout 6c"));
+    }
+}
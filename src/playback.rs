@@ -0,0 +1,265 @@
+//! Receiver `--backend device`: playback to an audio output device
+//!
+//! Instead of creating a PulseAudio virtual microphone, the receiver can
+//! play the received audio to an output device of the audio host, chosen
+//! with `--output-device`. Played to the input end of a virtual audio
+//! cable (VB-Cable's "CABLE Input", BlackHole, or an ALSA `snd-aloop`
+//! loopback), the audio comes out of the cable's other end, which
+//! applications see as a microphone.
+//!
+//! Each connection opens the device with the stream's sample rate and
+//! channel count if the device supports them, and otherwise with its
+//! default format, converting the channel count and resampling with
+//! [`crate::resample`]. Received audio waits in a queue that the device's
+//! output callback drains; the queue counts as backlog for
+//! `--max-latency-ms`, and on underrun the device plays silence.
+
+use crate::resample::Resampler;
+use crate::{AudioConfig, AudioFormat};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use log::{error, info};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Most audio queued for the device, in milliseconds
+///
+/// Anything older is dropped, so a device whose clock runs slower than the
+/// transmitter's does not fall further and further behind when
+/// `--max-latency-ms` is off.
+const MAX_QUEUE_MS: u64 = 2000;
+
+/// Writer side of a device playing a stream, see [`open_playback`]
+#[derive(Debug)]
+pub struct Playback {
+    /// Output samples, -1.0 to 1.0, interleaved in the device's channels
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    format: AudioFormat,
+    /// Channels of the stream and of the device
+    channels: (usize, usize),
+    /// Sample rates of the stream and of the device
+    rates: (u32, u32),
+    /// Converter to the device's rate, when it differs from the stream's
+    resampler: Option<Resampler>,
+    /// Samples kept at most in `queue`
+    capacity: usize,
+}
+
+impl Playback {
+    fn new(audio_config: &AudioConfig, device_rate: u32, device_channels: u16) -> Self {
+        let channels = (
+            usize::from(audio_config.channels.max(1)),
+            usize::from(device_channels.max(1)),
+        );
+        let resampler = (audio_config.sample_rate != device_rate).then(|| {
+            Resampler::new(
+                audio_config.sample_rate,
+                device_rate,
+                audio_config.channels,
+                audio_config.format.sample_size(),
+            )
+        });
+        Self {
+            queue: Arc::default(),
+            format: audio_config.format.clone(),
+            channels,
+            rates: (audio_config.sample_rate, device_rate),
+            resampler,
+            capacity: (u64::from(device_rate) * MAX_QUEUE_MS / 1000) as usize * channels.1,
+        }
+    }
+
+    /// Queue decoded audio in the stream's format for the device
+    ///
+    /// A trailing partial frame is ignored.
+    pub fn write(&mut self, pcm: &[u8]) {
+        let sample_size = self.format.sample_size();
+        let frame_size = sample_size * self.channels.0;
+        let whole = pcm.len() - pcm.len() % frame_size;
+        let mut samples: Vec<f32> = pcm[..whole]
+            .chunks_exact(sample_size)
+            .map(|sample| match sample {
+                [a, b] => f32::from(i16::from_le_bytes([*a, *b])) / 32768.0,
+                [a, b, c] => (i32::from_le_bytes([0, *a, *b, *c]) >> 8) as f32 / 8_388_608.0,
+                [a, b, c, d] => f32::from_le_bytes([*a, *b, *c, *d]),
+                _ => 0.0,
+            })
+            .collect();
+        if let Some(resampler) = &mut self.resampler {
+            samples = resampler.push_samples(&samples);
+        }
+
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        map_channels(&samples, self.channels.0, self.channels.1, &mut queue);
+        if queue.len() > self.capacity {
+            let excess = queue.len() - self.capacity;
+            queue.drain(..excess);
+        }
+    }
+
+    /// Audio queued but not played yet, in bytes of the stream's format
+    pub fn queued_bytes(&self) -> usize {
+        let queued = self
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len();
+        let frames = queued / self.channels.1;
+        let frames =
+            (frames as u64 * u64::from(self.rates.0) / u64::from(self.rates.1.max(1))) as usize;
+        frames * self.channels.0 * self.format.sample_size()
+    }
+}
+
+/// Start playing a stream in `audio_config` on `device`
+///
+/// # Returns
+///
+/// The running output stream, which must be kept alive for playback to
+/// continue, and the [`Playback`] to write the stream's audio to. Returns
+/// an error if the device cannot be opened or only takes sample formats
+/// other than f32, i16, u16 and i32.
+pub fn open_playback(
+    device: &cpal::Device,
+    audio_config: &AudioConfig,
+) -> anyhow::Result<(cpal::Stream, Playback)> {
+    let rate = cpal::SampleRate(audio_config.sample_rate);
+    let supported = device
+        .supported_output_configs()
+        .ok()
+        .and_then(|mut configs| {
+            configs.find_map(|range| {
+                (range.channels() == audio_config.channels)
+                    .then(|| range.try_with_sample_rate(rate))
+                    .flatten()
+            })
+        });
+    let supported = match supported {
+        Some(supported) => supported,
+        None => device.default_output_config()?,
+    };
+    let sample_format = supported.sample_format();
+    let config = cpal::StreamConfig {
+        channels: supported.channels(),
+        sample_rate: supported.sample_rate(),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let playback = Playback::new(audio_config, config.sample_rate.0, config.channels);
+
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_output_stream::<f32>(device, &config, &playback)?,
+        cpal::SampleFormat::I16 => build_output_stream::<i16>(device, &config, &playback)?,
+        cpal::SampleFormat::U16 => build_output_stream::<u16>(device, &config, &playback)?,
+        cpal::SampleFormat::I32 => build_output_stream::<i32>(device, &config, &playback)?,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unsupported output device format: {sample_format:?}"
+            ));
+        }
+    };
+    stream.play()?;
+    info!(
+        "Playing on {} at {} Hz, {} channel(s)",
+        device.name().unwrap_or_else(|_| "(unnamed)".to_string()),
+        config.sample_rate.0,
+        config.channels
+    );
+
+    Ok((stream, playback))
+}
+
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    playback: &Playback,
+) -> anyhow::Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let queue = Arc::clone(&playback.queue);
+    let stream = device.build_output_stream(
+        config,
+        move |output: &mut [T], _| {
+            let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+            for sample in output.iter_mut() {
+                let value = queue.pop_front().unwrap_or(0.0);
+                *sample = T::from_sample(value.clamp(-1.0, 1.0));
+            }
+        },
+        |err| error!("Output device error: {err}"),
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+/// Append `samples` with `from` channels to `out` with `to` channels
+///
+/// Mono is copied to every channel and every channel mixed into mono;
+/// otherwise the first channels are kept and any further ones are silent.
+fn map_channels(samples: &[f32], from: usize, to: usize, out: &mut VecDeque<f32>) {
+    if from == to {
+        out.extend(samples);
+        return;
+    }
+    for frame in samples.chunks_exact(from) {
+        match (from, to) {
+            (1, _) => out.extend(std::iter::repeat_n(frame[0], to)),
+            (_, 1) => out.push_back(frame.iter().sum::<f32>() / from as f32),
+            _ => out.extend((0..to).map(|channel| frame.get(channel).copied().unwrap_or(0.0))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_channels() {
+        let map = |samples: &[f32], from, to| {
+            let mut out = VecDeque::new();
+            map_channels(samples, from, to, &mut out);
+            Vec::from(out)
+        };
+        assert_eq!(map(&[0.5, -0.5], 1, 2), [0.5, 0.5, -0.5, -0.5]);
+        assert_eq!(map(&[0.5, -0.25, 1.0, 0.0], 2, 1), [0.125, 0.5]);
+        assert_eq!(
+            map(&[0.1, 0.2, 0.3, 0.4], 2, 4),
+            [0.1, 0.2, 0.0, 0.0, 0.3, 0.4, 0.0, 0.0]
+        );
+        assert_eq!(map(&[0.1, 0.2, 0.3], 3, 2), [0.1, 0.2]);
+    }
+
+    #[test]
+    fn test_write_converts_to_the_device_format() {
+        let s16 = AudioConfig {
+            sample_rate: 48000,
+            channels: 2,
+            format: AudioFormat::S16LE,
+        };
+        let mut playback = Playback::new(&s16, 48000, 1);
+        let pcm: Vec<u8> = [16384i16, 0, -32768, -32768, 7]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        playback.write(&pcm);
+        assert_eq!(
+            Vec::from(playback.queue.lock().unwrap().clone()),
+            [0.25, -1.0]
+        );
+        // Counted in stream bytes, without the partial frame
+        assert_eq!(playback.queued_bytes(), 8);
+
+        // One second of 44.1 kHz becomes about one second at 48 kHz
+        let s24 = AudioConfig {
+            sample_rate: 44100,
+            channels: 2,
+            format: AudioFormat::S24LE,
+        };
+        let mut playback = Playback::new(&s24, 48000, 2);
+        playback.write(&vec![0; 44100 * 6]);
+        let queued = playback.queue.lock().unwrap().len();
+        assert!(queued.abs_diff(48000 * 2) <= 32 * 2, "{queued}");
+        assert!(playback.queued_bytes().abs_diff(44100 * 6) <= 32 * 6);
+    }
+}
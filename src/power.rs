@@ -0,0 +1,232 @@
+//! Battery-aware pausing of the transmitter
+//!
+//! With `--power-save auto` the transmitter checks the laptop's batteries in
+//! sysfs (`/sys/class/power_supply`) and pauses capture and streaming while
+//! it runs on battery at or below `--power-save-threshold` percent. It
+//! resumes once the laptop is plugged in or charged above the threshold.
+
+use crate::queue::CaptureReceiver;
+use log::{info, warn};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::time::{Duration, Instant};
+
+/// Directory the kernel lists power supplies in
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Interval between battery checks
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether to pause streaming on low battery
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::power::PowerSave;
+///
+/// assert_eq!("auto".parse::<PowerSave>().unwrap(), PowerSave::Auto);
+/// assert!("on".parse::<PowerSave>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerSave {
+    /// Pause while on battery at or below the threshold
+    Auto,
+    /// Stream regardless of the battery
+    #[default]
+    Off,
+}
+
+impl fmt::Display for PowerSave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PowerSave::Auto => "auto",
+            PowerSave::Off => "off",
+        })
+    }
+}
+
+impl FromStr for PowerSave {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(PowerSave::Auto),
+            "off" => Ok(PowerSave::Off),
+            _ => Err(anyhow::anyhow!(
+                "Unknown power save mode '{s}' (expected auto or off)"
+            )),
+        }
+    }
+}
+
+/// Charge of the batteries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus {
+    /// A battery is discharging
+    pub discharging: bool,
+    /// Lowest charge of all batteries in percent
+    pub capacity: u8,
+}
+
+/// Read the battery status from a sysfs power supply directory
+///
+/// Returns `None` if there is no battery, e.g. on a desktop.
+pub fn battery_status(power_supply_dir: &Path) -> Option<BatteryStatus> {
+    let read = |supply: &Path, attribute: &str| {
+        std::fs::read_to_string(supply.join(attribute))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+
+    let mut status: Option<BatteryStatus> = None;
+    for entry in std::fs::read_dir(power_supply_dir).ok()?.flatten() {
+        let supply = entry.path();
+        if read(&supply, "type").as_deref() != Some("Battery") {
+            continue;
+        }
+        let Some(capacity) = read(&supply, "capacity").and_then(|value| value.parse().ok()) else {
+            continue;
+        };
+        let discharging = read(&supply, "status").as_deref() == Some("Discharging");
+        status = Some(match status {
+            Some(status) => BatteryStatus {
+                discharging: status.discharging || discharging,
+                capacity: status.capacity.min(capacity),
+            },
+            None => BatteryStatus {
+                discharging,
+                capacity,
+            },
+        });
+    }
+    status
+}
+
+/// Decides when the transmitter pauses for the battery, see the module
+/// documentation
+#[derive(Debug)]
+pub(crate) struct PowerSaver {
+    threshold: u8,
+    power_supply_dir: PathBuf,
+    next_check: Instant,
+    low: bool,
+}
+
+impl PowerSaver {
+    /// Pause at or below `threshold` percent, if this machine has a battery
+    pub(crate) fn new(threshold: u8) -> Option<Self> {
+        if battery_status(Path::new(POWER_SUPPLY_DIR)).is_none() {
+            warn!("No battery found, --power-save has no effect");
+            return None;
+        }
+        Some(Self {
+            threshold,
+            power_supply_dir: PathBuf::from(POWER_SUPPLY_DIR),
+            next_check: Instant::now(),
+            low: false,
+        })
+    }
+
+    /// Whether the battery is low, checking it when a check is due
+    pub(crate) fn battery_low(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_check {
+            return self.low;
+        }
+        self.next_check = now + POWER_CHECK_INTERVAL;
+        let low = battery_status(&self.power_supply_dir)
+            .is_some_and(|status| status.discharging && status.capacity <= self.threshold);
+        if low != self.low {
+            if low {
+                info!(
+                    "Battery at or below {}%, pausing the stream",
+                    self.threshold
+                );
+            } else {
+                info!("Battery no longer low, resuming the stream");
+            }
+            self.low = low;
+        }
+        low
+    }
+
+    /// Wait until the battery is no longer low, discarding captured audio
+    /// meanwhile
+    ///
+    /// Also returns once capture has stopped.
+    pub(crate) async fn wait_for_power(&mut self, rx: &mut CaptureReceiver) {
+        while self.battery_low() {
+            let check = tokio::time::sleep_until(self.next_check);
+            tokio::pin!(check);
+            loop {
+                tokio::select! {
+                    () = &mut check => break,
+                    packet = rx.recv() => {
+                        if packet.is_none() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_supply(dir: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let supply = dir.join(name);
+        std::fs::create_dir_all(&supply).unwrap();
+        for (attribute, value) in attributes {
+            std::fs::write(supply.join(attribute), format!("{value}\n")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_battery_status() {
+        let dir = std::env::temp_dir().join(format!("rsonance_power_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        add_supply(&dir, "AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(battery_status(&dir), None);
+
+        add_supply(
+            &dir,
+            "BAT0",
+            &[("type", "Battery"), ("status", "Full"), ("capacity", "90")],
+        );
+        add_supply(
+            &dir,
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "15"),
+            ],
+        );
+        assert_eq!(
+            battery_status(&dir),
+            Some(BatteryStatus {
+                discharging: true,
+                capacity: 15
+            })
+        );
+
+        let mut saver = PowerSaver {
+            threshold: 20,
+            power_supply_dir: dir.clone(),
+            next_check: Instant::now(),
+            low: false,
+        };
+        assert!(saver.battery_low());
+        add_supply(&dir, "BAT1", &[("status", "Charging")]);
+        // Not checked again before the interval has passed
+        assert!(saver.battery_low());
+        saver.next_check = Instant::now();
+        assert!(!saver.battery_low());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
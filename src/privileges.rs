@@ -0,0 +1,157 @@
+//! Dropping root privileges once the receiver is set up
+//!
+//! A receiver started as root, e.g. to bind a port below 1024, switches to
+//! `--user` once its listeners, virtual microphone and control socket exist,
+//! so the code handling transmitters' streams runs unprivileged. The group
+//! is `--group` or the user's primary group, and the supplementary groups
+//! are the user's own. Files the receiver still rewrites or removes later
+//! (the FIFO, sockets and state file) are handed to the user first.
+
+use crate::access::{lookup_group, lookup_user};
+use log::info;
+use std::ffi::{CStr, CString};
+use std::os::unix::fs::lchown;
+use std::path::Path;
+
+/// Size of the buffer for the strings of a passwd entry
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// User and group to switch to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub uid: u32,
+    pub gid: u32,
+    /// User name, for looking up its supplementary groups
+    name: Option<CString>,
+}
+
+impl Identity {
+    /// Resolve `user` and `group`, given by name or numeric id
+    ///
+    /// Without a group the user's primary group is used, so a numeric user
+    /// id that has no passwd entry needs one.
+    pub fn resolve(user: &str, group: Option<&str>) -> anyhow::Result<Self> {
+        let uid = lookup_user(user)?;
+        let entry = passwd_entry(uid);
+        let gid = match (group, &entry) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some((gid, _))) => *gid,
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "User {user} has no passwd entry, give its group with --group"
+                ));
+            }
+        };
+        Ok(Self {
+            uid,
+            gid,
+            name: entry.map(|(_, name)| name),
+        })
+    }
+
+    /// Give `path` to the user, if it exists
+    pub fn hand_over(&self, path: &str) -> anyhow::Result<()> {
+        if !Path::new(path).exists() {
+            return Ok(());
+        }
+        lchown(path, Some(self.uid), Some(self.gid))
+            .map_err(|e| anyhow::anyhow!("Failed to give {path} to user {}: {e}", self.uid))
+    }
+
+    /// Switch the whole process to the user and group for good
+    pub fn switch_to(&self) -> anyhow::Result<()> {
+        // SAFETY: the name is a valid C string and the calls only change the
+        // credentials of the process
+        let rc = unsafe {
+            match &self.name {
+                Some(name) => libc::initgroups(name.as_ptr(), self.gid as _),
+                None => libc::setgroups(0, std::ptr::null()),
+            }
+        };
+        if rc != 0 {
+            return Err(anyhow::anyhow!(
+                "Failed to set the supplementary groups: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: setgid and setuid have no memory safety preconditions
+        if unsafe { libc::setgid(self.gid) } != 0 {
+            return Err(anyhow::anyhow!(
+                "Failed to switch to group {}: {}",
+                self.gid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: as above
+        if unsafe { libc::setuid(self.uid) } != 0 {
+            return Err(anyhow::anyhow!(
+                "Failed to switch to user {}: {}",
+                self.uid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: as above; succeeding here would mean root can be regained
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(anyhow::anyhow!("Root privileges could not be dropped"));
+        }
+        info!("Dropped privileges to user {} group {}", self.uid, self.gid);
+        Ok(())
+    }
+}
+
+/// Whether the process runs as root
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Primary group and name of the user with id `uid`, if it has a passwd
+/// entry
+fn passwd_entry(uid: u32) -> Option<(u32, CString)> {
+    let mut buffer = vec![0u8; ENTRY_BUFFER_SIZE];
+    // SAFETY: an all-zero passwd is a valid value for getpwuid_r to fill in
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and the
+    // buffer length matches the buffer
+    let rc = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut entry,
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 || found.is_null() {
+        return None;
+    }
+    // SAFETY: getpwuid_r succeeded, so pw_name points to a C string in
+    // `buffer`, which is still alive
+    let name = unsafe { CStr::from_ptr(entry.pw_name) }.to_owned();
+    Some((entry.pw_gid, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_identity() {
+        let root = Identity::resolve("root", None).unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(root.name.as_deref(), Some(c"root"));
+        assert_eq!(Identity::resolve("0", None).unwrap(), root);
+
+        let nobody_group = Identity::resolve("root", Some("65534")).unwrap();
+        assert_eq!(nobody_group.gid, 65534);
+
+        // An id without a passwd entry needs an explicit group
+        assert!(Identity::resolve("4000000", None).is_err());
+        assert_eq!(
+            Identity::resolve("4000000", Some("4000000")).unwrap().gid,
+            4000000
+        );
+        assert!(Identity::resolve("no_such_user_rsonance", None).is_err());
+    }
+}
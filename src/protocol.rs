@@ -0,0 +1,799 @@
+//! Framed wire protocol between transmitter and receiver
+//!
+//! A transmitter opens every connection with a [`StreamHeader`] describing
+//! the stream, then sends the audio in length-prefixed frames, so the
+//! receiver no longer has to be told the codec, rate and channel count and
+//! can set up its virtual microphone to match.
+//!
+//! # Format
+//!
+//! The header is [`HEADER_LEN`] bytes: the magic `RSNC`, the protocol
+//! version (`u8`), the codec (`u8`: 0 s16le, 1 s24le, 2 g711u, 3 g711a,
+//! 4 f32le), the
+//! decoded sample format (`u8`: 0 s16le, 1 s24le, 2 f32le), a reserved zero
+//! byte, the decoded sample rate in Hz (`u32`) and channel count (`u16`),
+//! and the transmitter's client id (`u64`, see [`client_id`]).
+//! A transmitter run with `--name` or `--tag` sends version
+//! [`METADATA_VERSION`] instead, whose header is followed by a [`Metadata`]
+//! block: its length (`u16`, at most [`MAX_METADATA_LEN`]) and one
+//! `key=value` line per entry in UTF-8, the display name under the key
+//! `name`. Streams without metadata keep version [`VERSION`], so older
+//! receivers still take them.
+//! Each frame follows as its payload length (`u32`) and the encoded audio.
+//! All integers are little-endian. A frame holds whatever one batch of the
+//! transmitter encoded, or exactly `--frame-ms` of audio, see
+//! [`FrameSplitter`].
+//!
+//! A connection that does not start with the magic is a raw stream in the
+//! receiver's `--codec`, as sent by `rsonance replay`, transmitters run with
+//! `--raw-output-compat` and older versions.
+
+use crate::codec::Codec;
+use crate::{AudioConfig, AudioFormat};
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, ErrorKind, Read};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Magic bytes at the start of a framed stream
+pub const MAGIC: &[u8; 4] = b"RSNC";
+
+/// Protocol version sent by this transmitter and understood by this receiver
+pub const VERSION: u8 = 2;
+
+/// Protocol version of headers followed by a [`Metadata`] block
+pub const METADATA_VERSION: u8 = 3;
+
+/// Largest metadata block accepted, in bytes
+pub const MAX_METADATA_LEN: usize = 1024;
+
+/// Most tags accepted besides the name
+const MAX_TAGS: usize = 16;
+
+/// Longest display name, in characters
+const MAX_NAME_LEN: usize = 64;
+
+/// Longest tag key, in characters
+const MAX_KEY_LEN: usize = 32;
+
+/// Longest tag value, in characters
+const MAX_VALUE_LEN: usize = 128;
+
+/// Size of the stream header in bytes
+pub const HEADER_LEN: usize = 22;
+
+/// Size of a frame's length prefix in bytes
+const LENGTH_LEN: usize = 4;
+
+/// Largest frame payload accepted, far above any batch a transmitter sends
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Highest channel count accepted in a header
+const MAX_CHANNELS: u16 = 32;
+
+/// Sample rates accepted in a header, in Hz
+///
+/// Bounds what a header can make the receiver set up; WAV and FLAC
+/// recordings could not represent much higher rates.
+pub const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8000..=384_000;
+
+/// Format of the stream announced at the start of a connection
+///
+/// `config` is the PCM format the audio has after decoding, which the
+/// receiver's virtual microphone is set up with.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::Codec;
+/// use rsonance::protocol::StreamHeader;
+///
+/// let header = StreamHeader::new(Codec::S16LE, 48000, 1);
+/// assert_eq!(StreamHeader::parse(&header.encode()).unwrap(), header);
+/// assert!(StreamHeader::parse(b"not a header!!").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    /// Encoding of the audio in the frames
+    pub codec: Codec,
+    /// Format of the audio after decoding
+    pub config: AudioConfig,
+    /// Transmitter process that sent the stream, see [`client_id`]
+    pub client_id: u64,
+    /// Display name and tags of the transmitter, empty unless given
+    pub metadata: Metadata,
+}
+
+/// Display name and tags a transmitter describes itself with (`--name`,
+/// `--tag`), sent after the stream header
+///
+/// Names and values are free text without control characters; keys are
+/// ASCII letters, digits, `_`, `-` and `.`.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::{Metadata, parse_tag};
+///
+/// let metadata = Metadata {
+///     name: Some("Stage left".to_string()),
+///     tags: vec![parse_tag("room=hall").unwrap()],
+/// };
+/// metadata.validate().unwrap();
+/// assert_eq!(metadata.to_string(), "\"Stage left\" room=hall");
+/// assert!(parse_tag("room").is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Name to show for the transmitter
+    pub name: Option<String>,
+    /// Key/value pairs, in the order given
+    pub tags: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Whether there is neither a name nor tags
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.tags.is_empty()
+    }
+
+    /// Check the limits on names, keys and values, and that the encoded
+    /// block fits in [`MAX_METADATA_LEN`]
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(name) = &self.name {
+            check_text("name", name, MAX_NAME_LEN)?;
+        }
+        if self.tags.len() > MAX_TAGS {
+            return Err(anyhow::anyhow!(
+                "{} tags given, at most {MAX_TAGS} are allowed",
+                self.tags.len()
+            ));
+        }
+        for (index, (key, value)) in self.tags.iter().enumerate() {
+            check_key(key)?;
+            if key == "name" {
+                return Err(anyhow::anyhow!(
+                    "The name is not a tag, give it with --name"
+                ));
+            }
+            check_text(&format!("tag {key}"), value, MAX_VALUE_LEN)?;
+            if self.tags[..index].iter().any(|(other, _)| other == key) {
+                return Err(anyhow::anyhow!("Tag {key} is given twice"));
+            }
+        }
+        if self.encode().len() > MAX_METADATA_LEN {
+            return Err(anyhow::anyhow!(
+                "Name and tags take more than {MAX_METADATA_LEN} bytes"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The block as sent after the header, without its length
+    fn encode(&self) -> Vec<u8> {
+        let name = self.name.iter().map(|name| ("name", name.as_str()));
+        let tags = self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+        name.chain(tags)
+            .flat_map(|(key, value)| format!("{key}={value}\n").into_bytes())
+            .collect()
+    }
+
+    /// Parse and validate a block received from a transmitter
+    fn parse(block: &[u8]) -> anyhow::Result<Self> {
+        let text = std::str::from_utf8(block)
+            .map_err(|_| anyhow::anyhow!("Stream metadata is not UTF-8"))?;
+        let mut metadata = Metadata::default();
+        for line in text.lines() {
+            let (key, value) = parse_tag(line)?;
+            if key == "name" && metadata.name.is_none() {
+                metadata.name = Some(value);
+            } else {
+                metadata.tags.push((key, value));
+            }
+        }
+        metadata.validate()?;
+        Ok(metadata)
+    }
+}
+
+/// The name in quotes, followed by the tags as `key=value`
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name.iter().map(|name| format!("{name:?}"));
+        let tags = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"));
+        f.write_str(&name.chain(tags).collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Split a `KEY=VALUE` tag, as given to `--tag`
+///
+/// The key is checked here; the value with [`Metadata::validate`].
+pub fn parse_tag(tag: &str) -> anyhow::Result<(String, String)> {
+    let Some((key, value)) = tag.split_once('=') else {
+        return Err(anyhow::anyhow!("Tag '{tag}' is not KEY=VALUE"));
+    };
+    check_key(key)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Check a tag key for its length and characters
+fn check_key(key: &str) -> anyhow::Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !key.chars().all(valid) {
+        return Err(anyhow::anyhow!(
+            "Invalid tag key '{key}', use up to {MAX_KEY_LEN} letters, digits, '_', '-' or '.'"
+        ));
+    }
+    Ok(())
+}
+
+/// Check a name or tag value for its length and control characters
+fn check_text(what: &str, text: &str, max_len: usize) -> anyhow::Result<()> {
+    if text.is_empty() || text.chars().count() > max_len {
+        return Err(anyhow::anyhow!(
+            "The {what} has to be 1 to {max_len} characters long"
+        ));
+    }
+    if text.chars().any(char::is_control) {
+        return Err(anyhow::anyhow!(
+            "The {what} cannot contain control characters"
+        ));
+    }
+    Ok(())
+}
+
+/// Random id of this process as a transmitter, the same for all its
+/// connections
+///
+/// The receiver lets a connection with the id of its active transmitter
+/// replace it, as after a reconnect, and refuses connections from other
+/// transmitters while one is streaming.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::client_id;
+///
+/// assert_eq!(client_id(), client_id());
+/// ```
+pub fn client_id() -> u64 {
+    static ID: OnceLock<u64> = OnceLock::new();
+    *ID.get_or_init(|| {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        RandomState::new().hash_one((std::process::id(), now))
+    })
+}
+
+impl StreamHeader {
+    /// Header for audio captured at `sample_rate` Hz with `channels`
+    /// channels and sent with `codec` by this process
+    pub fn new(codec: Codec, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            codec,
+            config: codec.decoded_config(sample_rate, channels),
+            client_id: client_id(),
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// The header as sent on the wire, followed by the metadata block if
+    /// there is any
+    pub fn encode(&self) -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4] = if self.metadata.is_empty() {
+            VERSION
+        } else {
+            METADATA_VERSION
+        };
+        header[5] = match self.codec {
+            Codec::S16LE => 0,
+            Codec::S24LE => 1,
+            Codec::G711U => 2,
+            Codec::G711A => 3,
+            Codec::F32LE => 4,
+        };
+        header[6] = match self.config.format {
+            AudioFormat::S16LE => 0,
+            AudioFormat::S24LE => 1,
+            AudioFormat::F32LE => 2,
+        };
+        header[8..12].copy_from_slice(&self.config.sample_rate.to_le_bytes());
+        header[12..14].copy_from_slice(&self.config.channels.to_le_bytes());
+        header[14..22].copy_from_slice(&self.client_id.to_le_bytes());
+        if !self.metadata.is_empty() {
+            let block = self.metadata.encode();
+            header.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            header.extend_from_slice(&block);
+        }
+        header
+    }
+
+    /// Parse and validate a header received from a transmitter
+    ///
+    /// Returns an error for a bad magic, an unsupported version, a sample
+    /// rate outside [`SAMPLE_RATES`], a format the codec cannot produce, or
+    /// missing or invalid metadata.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(anyhow::anyhow!("Not a rsonance stream header"));
+        }
+        if bytes[4] != VERSION && bytes[4] != METADATA_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported protocol version {} (expected {VERSION} or {METADATA_VERSION}), update both sides",
+                bytes[4]
+            ));
+        }
+        let codec = match bytes[5] {
+            0 => Codec::S16LE,
+            1 => Codec::S24LE,
+            2 => Codec::G711U,
+            3 => Codec::G711A,
+            4 => Codec::F32LE,
+            id => return Err(anyhow::anyhow!("Unknown codec {id} in stream header")),
+        };
+        let format = match bytes[6] {
+            0 => AudioFormat::S16LE,
+            1 => AudioFormat::S24LE,
+            2 => AudioFormat::F32LE,
+            id => {
+                return Err(anyhow::anyhow!(
+                    "Unknown sample format {id} in stream header"
+                ));
+            }
+        };
+        let sample_rate = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        let channels = u16::from_le_bytes([bytes[12], bytes[13]]);
+        if !SAMPLE_RATES.contains(&sample_rate) || channels == 0 || channels > MAX_CHANNELS {
+            return Err(anyhow::anyhow!(
+                "Invalid stream format in header: {sample_rate} Hz, {channels} channel(s)"
+            ));
+        }
+
+        let mut client_id = [0u8; 8];
+        client_id.copy_from_slice(&bytes[14..22]);
+        let metadata = if bytes[4] == METADATA_VERSION {
+            let block = bytes
+                .get(HEADER_LEN..HEADER_LEN + 2)
+                .map(|len| usize::from(u16::from_le_bytes([len[0], len[1]])))
+                .and_then(|len| bytes.get(HEADER_LEN + 2..HEADER_LEN + 2 + len))
+                .ok_or_else(|| anyhow::anyhow!("Stream header without its metadata"))?;
+            Metadata::parse(block)?
+        } else {
+            Metadata::default()
+        };
+        let header = Self {
+            codec,
+            config: AudioConfig {
+                sample_rate,
+                channels,
+                format,
+            },
+            client_id: u64::from_le_bytes(client_id),
+            metadata,
+        };
+        if header.config != codec.decoded_config(sample_rate, channels) {
+            return Err(anyhow::anyhow!(
+                "Stream header announces {} {}, which {codec} does not produce",
+                header.config.format.as_pa_format(),
+                describe(&header.config)
+            ));
+        }
+        Ok(header)
+    }
+}
+
+/// `rate Hz, channels channel(s)` of `config`, for messages
+pub fn describe(config: &AudioConfig) -> String {
+    format!("{} Hz, {} channel(s)", config.sample_rate, config.channels)
+}
+
+/// Prefix `payload` with its length, ready to be sent as one frame
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::frame;
+///
+/// assert_eq!(frame(&[7, 8]), vec![2, 0, 0, 0, 7, 8]);
+/// ```
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(LENGTH_LEN + payload.len());
+    framed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Frame `audio` as consecutive frames of `frame_len` bytes, the last one
+/// possibly shorter
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::frame_chunks;
+///
+/// assert_eq!(frame_chunks(&[7, 8, 9], 2), vec![2, 0, 0, 0, 7, 8, 1, 0, 0, 0, 9]);
+/// ```
+pub fn frame_chunks(audio: &[u8], frame_len: usize) -> Vec<u8> {
+    audio.chunks(frame_len.max(1)).flat_map(frame).collect()
+}
+
+/// Cuts encoded audio into frames of a fixed length (`--frame-ms`)
+///
+/// Batches reach the sender in whatever sizes the capture callbacks and
+/// batching produce. The splitter passes on only whole frames and keeps the
+/// rest for the next batch, so every frame on the wire holds the same
+/// duration of audio.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::FrameSplitter;
+///
+/// let mut splitter = FrameSplitter::new(4);
+/// assert!(splitter.push(&[1, 2, 3]).is_empty());
+/// assert_eq!(splitter.push(&[4, 5, 6, 7, 8, 9]), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+/// ```
+#[derive(Debug)]
+pub struct FrameSplitter {
+    frame_len: usize,
+    /// Start of the next frame
+    pending: Vec<u8>,
+}
+
+impl FrameSplitter {
+    /// Splitter into frames of `frame_len` bytes
+    pub fn new(frame_len: usize) -> Self {
+        Self {
+            frame_len: frame_len.max(1),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Splitter into frames of `frame_ms` of the stream `codec` sends for
+    /// audio captured at `sample_rate` Hz with `channels` channels
+    ///
+    /// The frame length is rounded down to whole samples. Returns an error if
+    /// that leaves no sample at all.
+    pub fn for_duration(
+        codec: Codec,
+        sample_rate: u32,
+        channels: u16,
+        frame_ms: u64,
+    ) -> anyhow::Result<Self> {
+        let (frame_rate, frame_size) = codec.wire_format(sample_rate, channels);
+        let samples = frame_rate as u64 * frame_ms / 1000;
+        if samples == 0 {
+            return Err(anyhow::anyhow!(
+                "A frame of {frame_ms} ms holds no audio at {frame_rate} Hz"
+            ));
+        }
+        Ok(Self::new(samples as usize * frame_size))
+    }
+
+    /// Length of a frame in bytes
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    /// Take in encoded audio and return the whole frames completed so far,
+    /// back to back
+    pub fn push(&mut self, audio: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(audio);
+        let whole = self.pending.len() - self.pending.len() % self.frame_len;
+        self.pending.drain(..whole).collect()
+    }
+}
+
+/// Read the start of a connection and tell a framed stream from a raw one
+///
+/// Reads no further than needed: a raw stream is recognized as soon as its
+/// first bytes differ from the magic.
+///
+/// # Returns
+///
+/// The header of a framed stream, or `None` for a raw one, together with the
+/// bytes consumed. For a raw stream these are the first bytes of its audio.
+/// An invalid header is an [`ErrorKind::InvalidData`] error.
+pub fn read_header(stream: &mut impl Read) -> io::Result<(Option<StreamHeader>, Vec<u8>)> {
+    let mut start = [0u8; HEADER_LEN];
+    let mut len = 0;
+    while len < MAGIC.len() {
+        match stream.read(&mut start[len..MAGIC.len()]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if start[..len] != MAGIC[..len] {
+            break;
+        }
+    }
+    if &start[..len] != MAGIC {
+        return Ok((None, start[..len].to_vec()));
+    }
+
+    // The version decides the header's length, so a mismatch is reported
+    // before reading on
+    let incomplete =
+        |e: io::Error| io::Error::new(e.kind(), format!("incomplete stream header: {e}"));
+    stream
+        .read_exact(&mut start[MAGIC.len()..MAGIC.len() + 1])
+        .map_err(incomplete)?;
+    let version = start[MAGIC.len()];
+    if version != VERSION && version != METADATA_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Unsupported protocol version {} (expected {VERSION} or {METADATA_VERSION}), update both sides",
+                start[MAGIC.len()]
+            ),
+        ));
+    }
+    stream
+        .read_exact(&mut start[MAGIC.len() + 1..])
+        .map_err(incomplete)?;
+    let mut start = start.to_vec();
+    if version == METADATA_VERSION {
+        let mut prefix = [0u8; 2];
+        stream.read_exact(&mut prefix).map_err(incomplete)?;
+        let len = usize::from(u16::from_le_bytes(prefix));
+        if len > MAX_METADATA_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Stream metadata of {len} bytes is too long"),
+            ));
+        }
+        let mut block = vec![0u8; len];
+        stream.read_exact(&mut block).map_err(incomplete)?;
+        start.extend_from_slice(&prefix);
+        start.extend_from_slice(&block);
+    }
+    let header =
+        StreamHeader::parse(&start).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok((Some(header), start))
+}
+
+/// Splits the bytes of a framed stream back into audio
+///
+/// Bytes may arrive in any split; a frame is passed on once it is complete.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::protocol::{Deframer, frame};
+///
+/// let mut stream = frame(&[1, 2, 3]);
+/// stream.extend(frame(&[4]));
+/// let mut deframer = Deframer::default();
+/// assert_eq!(deframer.push(&stream[..5]).unwrap(), &[] as &[u8]);
+/// assert_eq!(deframer.push(&stream[5..]).unwrap(), &[1, 2, 3, 4]);
+/// ```
+#[derive(Debug, Default)]
+pub struct Deframer {
+    /// Start of a frame whose end has not arrived yet
+    pending: Vec<u8>,
+    /// Payload of the frames completed by the last push
+    audio: Vec<u8>,
+}
+
+impl Deframer {
+    /// Take in received bytes and return the audio of the frames they
+    /// complete
+    ///
+    /// Returns an error for a frame longer than [`MAX_FRAME_LEN`], which
+    /// means the stream is corrupt.
+    pub fn push(&mut self, data: &[u8]) -> io::Result<&[u8]> {
+        self.audio.clear();
+        self.pending.extend_from_slice(data);
+        let mut offset = 0;
+        while let Some(prefix) = self.pending.get(offset..offset + LENGTH_LEN) {
+            let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+            if len > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("frame of {len} bytes exceeds the limit, the stream is corrupt"),
+                ));
+            }
+            let start = offset + LENGTH_LEN;
+            let Some(payload) = self.pending.get(start..start + len) else {
+                break;
+            };
+            self.audio.extend_from_slice(payload);
+            offset = start + len;
+        }
+        self.pending.drain(..offset);
+        Ok(&self.audio)
+    }
+
+    /// Bytes of an incomplete frame held back
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        for header in [
+            StreamHeader::new(Codec::S16LE, 48000, 2),
+            StreamHeader::new(Codec::S24LE, 96000, 1),
+            StreamHeader::new(Codec::F32LE, 48000, 2),
+            StreamHeader::new(Codec::G711A, 44100, 2),
+        ] {
+            assert_eq!(StreamHeader::parse(&header.encode()).unwrap(), header);
+        }
+        let other = StreamHeader {
+            client_id: 0x0102_0304_0506_0708,
+            ..StreamHeader::new(Codec::S16LE, 48000, 2)
+        };
+        assert_eq!(StreamHeader::parse(&other.encode()).unwrap(), other);
+        // G.711 is always decoded to 8 kHz mono
+        let g711 = StreamHeader::new(Codec::G711U, 48000, 2);
+        assert_eq!((g711.config.sample_rate, g711.config.channels), (8000, 1));
+    }
+
+    #[test]
+    fn test_invalid_headers_are_rejected() {
+        let valid = StreamHeader::new(Codec::S16LE, 48000, 2).encode();
+        let corrupt = |index: usize, value: u8| {
+            let mut header = valid.clone();
+            header[index] = value;
+            StreamHeader::parse(&header)
+        };
+        // A version 1 header, from before the client id
+        assert!(corrupt(4, 1).is_err());
+        assert!(corrupt(5, 9).is_err());
+        // S16LE audio decoded to S24LE
+        assert!(corrupt(6, 1).is_err());
+        assert!(corrupt(12, 0).is_err());
+        // Sample rates outside 8 kHz to 384 kHz
+        let rate = |sample_rate: u32| {
+            let mut header = valid.clone();
+            header[8..12].copy_from_slice(&sample_rate.to_le_bytes());
+            StreamHeader::parse(&header)
+        };
+        assert!(rate(0).is_err());
+        assert!(rate(7999).is_err());
+        assert!(rate(8000).is_ok());
+        assert!(rate(384_000).is_ok());
+        assert!(rate(384_001).is_err());
+        assert!(rate(u32::MAX).is_err());
+        assert!(StreamHeader::parse(&valid[..10]).is_err());
+    }
+
+    #[test]
+    fn test_read_header_tells_raw_from_framed() {
+        let header = StreamHeader::new(Codec::S24LE, 48000, 2);
+        let mut framed = header.encode().to_vec();
+        framed.extend(frame(&[1, 2, 3]));
+        let mut reader = &framed[..];
+        let (parsed, consumed) = read_header(&mut reader).unwrap();
+        assert_eq!(parsed.as_ref(), Some(&header));
+        assert_eq!(consumed.len(), HEADER_LEN);
+        assert_eq!(reader, &frame(&[1, 2, 3])[..]);
+
+        // Raw audio is passed on as it was read
+        let mut reader = &[b'R', b'S', 0, 0, 5][..];
+        assert_eq!(
+            read_header(&mut reader).unwrap(),
+            (None, vec![b'R', b'S', 0, 0])
+        );
+        assert_eq!(reader, &[5]);
+        let mut reader = &[b'R'][..];
+        assert_eq!(read_header(&mut reader).unwrap(), (None, vec![b'R']));
+
+        let mut truncated = &header.encode()[..8];
+        assert!(read_header(&mut truncated).is_err());
+        let mut invalid = header.encode();
+        invalid[4] = 0;
+        let error = read_header(&mut &invalid[..]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_metadata_follows_a_version_3_header() {
+        let plain = StreamHeader::new(Codec::S16LE, 48000, 2);
+        assert_eq!(plain.encode()[4], VERSION);
+        let header = StreamHeader {
+            metadata: Metadata {
+                name: Some("Bühne links".to_string()),
+                tags: vec![
+                    parse_tag("room=hall").unwrap(),
+                    parse_tag("note=a=b").unwrap(),
+                ],
+            },
+            ..plain
+        };
+        let encoded = header.encode();
+        assert_eq!(encoded[4], METADATA_VERSION);
+        assert_eq!(
+            &encoded[HEADER_LEN + 2..],
+            "name=Bühne links\nroom=hall\nnote=a=b\n".as_bytes()
+        );
+        assert_eq!(StreamHeader::parse(&encoded).unwrap(), header);
+        assert_eq!(
+            header.metadata.to_string(),
+            "\"Bühne links\" room=hall note=a=b"
+        );
+
+        let mut framed = encoded.clone();
+        framed.extend(frame(&[1, 2]));
+        let mut reader = &framed[..];
+        let (parsed, consumed) = read_header(&mut reader).unwrap();
+        assert_eq!((parsed.unwrap(), consumed), (header, encoded.clone()));
+        assert_eq!(reader, &frame(&[1, 2])[..]);
+
+        // Truncated, too long, or with lines that are not tags
+        assert!(read_header(&mut &encoded[..encoded.len() - 1]).is_err());
+        assert!(StreamHeader::parse(&encoded[..HEADER_LEN]).is_err());
+        let mut oversized = encoded[..HEADER_LEN].to_vec();
+        oversized.extend_from_slice(&2000u16.to_le_bytes());
+        let error = read_header(&mut &oversized[..]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        for block in ["name=a\nname=b\n", "room\n", "bad key=x\n", "room=\n"] {
+            let mut invalid = encoded[..HEADER_LEN].to_vec();
+            invalid.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            invalid.extend_from_slice(block.as_bytes());
+            assert!(StreamHeader::parse(&invalid).is_err(), "{block:?}");
+        }
+    }
+
+    #[test]
+    fn test_metadata_limits() {
+        let tag = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let with = |name: Option<&str>, tags: Vec<(String, String)>| {
+            Metadata {
+                name: name.map(str::to_string),
+                tags,
+            }
+            .validate()
+        };
+        assert!(with(Some(&"n".repeat(64)), vec![]).is_ok());
+        assert!(with(Some(&"n".repeat(65)), vec![]).is_err());
+        assert!(with(Some("tab\there"), vec![]).is_err());
+        assert!(with(None, vec![tag("name", "x")]).is_err());
+        assert!(with(None, vec![tag("a", "1"), tag("a", "2")]).is_err());
+        let many: Vec<_> = (0..17).map(|i| tag(&format!("k{i}"), "v")).collect();
+        assert!(with(None, many[..16].to_vec()).is_ok());
+        assert!(with(None, many).is_err());
+        // Within the per-tag limits, but not the block's
+        let long: Vec<_> = (0..16)
+            .map(|i| tag(&format!("k{i}"), &"v".repeat(128)))
+            .collect();
+        assert!(with(None, long).is_err());
+        assert!(parse_tag("=x").is_err());
+        assert!(parse_tag(&format!("{}=x", "k".repeat(33))).is_err());
+    }
+
+    #[test]
+    fn test_deframer_rejects_oversized_frames() {
+        let mut deframer = Deframer::default();
+        assert_eq!(deframer.push(&frame(&[])).unwrap(), &[] as &[u8]);
+        assert_eq!(deframer.push(&[9, 0]).unwrap(), &[] as &[u8]);
+        assert_eq!(deframer.pending(), 2);
+        let oversized = ((MAX_FRAME_LEN + 1) as u32).to_le_bytes();
+        assert!(Deframer::default().push(&oversized).is_err());
+    }
+
+    #[test]
+    fn test_frame_length_follows_the_wire_format() {
+        let frame_len = |codec, rate, channels, ms| {
+            FrameSplitter::for_duration(codec, rate, channels, ms).map(|s| s.frame_len())
+        };
+        assert_eq!(frame_len(Codec::S16LE, 44100, 2, 10).unwrap(), 441 * 4);
+        assert_eq!(frame_len(Codec::S24LE, 48000, 2, 1).unwrap(), 48 * 6);
+        // G.711 is sent as 8 kHz mono, one byte per sample
+        assert_eq!(frame_len(Codec::G711U, 48000, 2, 20).unwrap(), 160);
+        assert!(frame_len(Codec::S16LE, 44100, 2, 0).is_err());
+    }
+}
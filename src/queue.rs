@@ -0,0 +1,224 @@
+//! Transmitter capture queue, bounded by `--max-queue-ms`
+//!
+//! Captured audio waits here until the connection takes it. While the
+//! network stalls or the transmitter reconnects nothing is taken, so an
+//! unbounded queue grows for as long as the outage lasts, and everything in
+//! it is sent late once the connection recovers. This queue holds at most
+//! `--max-queue-ms` of audio and drops the oldest beyond that, so memory
+//! stays bounded and the stream resumes with recent audio.
+//!
+//! Dropping happens in the capture callback, which only takes a short lock
+//! and never waits; the drops are logged on the receiving side the next
+//! time it takes audio.
+
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+use tokio::sync::mpsc::error::{SendError, TryRecvError};
+
+/// Queue for captured audio holding at most `limit` bytes (0 = no limit)
+///
+/// `bytes_per_second` of captured audio is only used to log dropped audio
+/// in milliseconds.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::queue::capture_queue;
+///
+/// let (tx, mut rx) = capture_queue(8, 1000);
+/// for packet in 1..=3u8 {
+///     tx.send(vec![packet; 4]).unwrap();
+/// }
+/// // The oldest packet made way for the newest
+/// assert_eq!(rx.try_recv().unwrap(), [2; 4]);
+/// assert_eq!(rx.try_recv().unwrap(), [3; 4]);
+/// assert!(rx.is_empty());
+/// ```
+pub fn capture_queue(limit: usize, bytes_per_second: usize) -> (CaptureSender, CaptureReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            packets: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+            senders: 1,
+            receiver: true,
+        }),
+        limit: if limit == 0 { usize::MAX } else { limit },
+        notify: Notify::new(),
+    });
+    (
+        CaptureSender {
+            shared: Arc::clone(&shared),
+        },
+        CaptureReceiver {
+            shared,
+            bytes_per_second: bytes_per_second.max(1),
+        },
+    )
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    limit: usize,
+    /// Wakes the receiver for a new packet or the last sender leaving
+    notify: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    packets: VecDeque<Vec<u8>>,
+    /// Bytes in `packets`
+    bytes: usize,
+    /// Bytes dropped since the receiver last logged it
+    dropped: usize,
+    senders: usize,
+    /// Whether the receiver still exists
+    receiver: bool,
+}
+
+/// Capture side of [`capture_queue`]
+#[derive(Debug)]
+pub struct CaptureSender {
+    shared: Arc<Shared>,
+}
+
+impl CaptureSender {
+    /// Queue a packet, dropping the oldest ones beyond the limit
+    ///
+    /// The packet just sent is always kept. Returns the packet back if the
+    /// receiver is gone.
+    pub fn send(&self, packet: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        let mut state = self.shared.lock();
+        if !state.receiver {
+            return Err(SendError(packet));
+        }
+        state.bytes += packet.len();
+        state.packets.push_back(packet);
+        while state.bytes > self.shared.limit && state.packets.len() > 1 {
+            let Some(oldest) = state.packets.pop_front() else {
+                break;
+            };
+            state.bytes -= oldest.len();
+            state.dropped += oldest.len();
+        }
+        drop(state);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for CaptureSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for CaptureSender {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.notify.notify_one();
+    }
+}
+
+/// Connection side of [`capture_queue`]
+#[derive(Debug)]
+pub struct CaptureReceiver {
+    shared: Arc<Shared>,
+    bytes_per_second: usize,
+}
+
+impl CaptureReceiver {
+    /// Wait for the next packet, or `None` once the queue is empty and every
+    /// sender is gone
+    ///
+    /// Cancel safe: a packet is only taken when this returns it.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.try_recv() {
+                Ok(packet) => return Some(packet),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.shared.notify.notified().await,
+            }
+        }
+    }
+
+    /// Take the next packet without waiting
+    pub fn try_recv(&mut self) -> Result<Vec<u8>, TryRecvError> {
+        let mut state = self.shared.lock();
+        let dropped = std::mem::take(&mut state.dropped);
+        let result = match state.packets.pop_front() {
+            Some(packet) => {
+                state.bytes -= packet.len();
+                Ok(packet)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        };
+        drop(state);
+        if dropped > 0 {
+            warn!(
+                "Capture queue full, dropped the oldest {} ms of audio",
+                dropped as u64 * 1000 / self.bytes_per_second as u64
+            );
+        }
+        result
+    }
+
+    /// Whether no packet is queued
+    pub fn is_empty(&self) -> bool {
+        self.shared.lock().packets.is_empty()
+    }
+}
+
+impl Drop for CaptureReceiver {
+    fn drop(&mut self) {
+        self.shared.lock().receiver = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_recv_waits_for_packets_and_ends_with_the_senders() {
+        let (tx, mut rx) = capture_queue(0, 1000);
+        let second = tx.clone();
+        let sender = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tx.send(vec![1; 1000]).unwrap();
+            second.send(vec![2; 1000]).unwrap();
+        });
+
+        assert_eq!(rx.recv().await.unwrap(), [1; 1000]);
+        sender.await.unwrap();
+        // Queued packets outlast the senders
+        assert_eq!(rx.recv().await.unwrap(), [2; 1000]);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_oversized_packet_is_kept_and_send_fails_without_receiver() {
+        let (tx, mut rx) = capture_queue(4, 1000);
+        tx.send(vec![1; 2]).unwrap();
+        tx.send(vec![2; 6]).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), [2; 6]);
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+        drop(rx);
+        assert!(tx.send(vec![3]).is_err());
+    }
+}
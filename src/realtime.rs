@@ -0,0 +1,194 @@
+//! Real-time scheduling for latency-sensitive audio threads
+//!
+//! Threads are promoted to `SCHED_RR` directly when the process is allowed to,
+//! otherwise through rtkit (`org.freedesktop.RealtimeKit1`, called via `busctl`)
+//! as desktop sessions usually run unprivileged. If both are refused, the
+//! thread's nice value is raised as a last resort.
+
+use log::{debug, info, warn};
+use std::process::Command;
+
+/// `SCHED_RR` priority requested for audio threads
+///
+/// Kept well below rtkit's default ceiling of 20 so that the sound server's
+/// own threads still take precedence.
+const REALTIME_PRIORITY: i32 = 10;
+
+/// Nice value used when real-time scheduling is unavailable
+const FALLBACK_NICE: i32 = -10;
+
+/// CPU time limit in microseconds a real-time thread may use without blocking
+///
+/// rtkit refuses to promote threads of processes without `RLIMIT_RTTIME`.
+/// The limit also keeps a runaway audio thread from locking up the machine.
+const RTTIME_LIMIT_US: libc::rlim_t = 200_000;
+
+/// Outcome of a real-time scheduling request
+///
+/// # Examples
+///
+/// ```no_run
+/// use rsonance::realtime::{promote_current_thread, RealtimeResult};
+///
+/// if promote_current_thread("example") == RealtimeResult::Unchanged {
+///     log::warn!("Running with normal scheduling");
+/// }
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum RealtimeResult {
+    /// The thread was switched to `SCHED_RR` directly
+    Direct,
+    /// The thread was switched to `SCHED_RR` by rtkit
+    RtKit,
+    /// Real-time scheduling was refused; the thread's nice value was raised instead
+    Niced,
+    /// The thread's scheduling could not be changed
+    Unchanged,
+}
+
+/// Raise the scheduling priority of the calling thread
+///
+/// Tries, in order, a direct `SCHED_RR` switch, an rtkit request, and a nice
+/// value increase. The outcome is logged with `label` identifying the thread.
+///
+/// # Arguments
+///
+/// * `label` - Human-readable thread name used in log messages (e.g. "FIFO writer")
+///
+/// # Returns
+///
+/// The scheduling change that was applied
+///
+/// # Requirements
+///
+/// - Direct promotion needs `CAP_SYS_NICE` or a non-zero `RLIMIT_RTPRIO`
+/// - rtkit promotion needs `rtkit-daemon` running and `busctl` in PATH
+pub fn promote_current_thread(label: &str) -> RealtimeResult {
+    // SAFETY: gettid has no preconditions and always succeeds.
+    let tid = unsafe { libc::gettid() };
+
+    let result = if set_round_robin() {
+        RealtimeResult::Direct
+    } else if request_rtkit(tid) {
+        RealtimeResult::RtKit
+    } else if set_nice(tid) {
+        RealtimeResult::Niced
+    } else {
+        RealtimeResult::Unchanged
+    };
+
+    match result {
+        RealtimeResult::Direct | RealtimeResult::RtKit => {
+            info!("{label} thread running with SCHED_RR priority {REALTIME_PRIORITY} ({result:?})")
+        }
+        RealtimeResult::Niced => {
+            warn!("Real-time scheduling refused for {label} thread, using nice {FALLBACK_NICE}")
+        }
+        RealtimeResult::Unchanged => {
+            warn!("Could not raise scheduling priority of {label} thread")
+        }
+    }
+
+    result
+}
+
+/// Switch the calling thread to `SCHED_RR`, returning whether it succeeded
+fn set_round_robin() -> bool {
+    let param = libc::sched_param {
+        sched_priority: REALTIME_PRIORITY,
+    };
+    // SAFETY: `param` is a valid sched_param and pthread_self() is the calling thread.
+    let rc = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_RR, &param) };
+    if rc != 0 {
+        debug!(
+            "pthread_setschedparam failed: {}",
+            std::io::Error::from_raw_os_error(rc)
+        );
+    }
+    rc == 0
+}
+
+/// Ask rtkit to make thread `tid` real-time, returning whether it succeeded
+fn request_rtkit(tid: libc::pid_t) -> bool {
+    if !limit_rttime() {
+        return false;
+    }
+
+    let output = Command::new("busctl")
+        .args([
+            "--system",
+            "call",
+            "org.freedesktop.RealtimeKit1",
+            "/org/freedesktop/RealtimeKit1",
+            "org.freedesktop.RealtimeKit1",
+            "MakeThreadRealtime",
+            "tu",
+            &tid.to_string(),
+            &REALTIME_PRIORITY.to_string(),
+        ])
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            debug!(
+                "rtkit request failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            debug!("Could not run busctl: {e}");
+            false
+        }
+    }
+}
+
+/// Ensure `RLIMIT_RTTIME` is set, as rtkit requires, returning whether it is
+fn limit_rttime() -> bool {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid, writable rlimit.
+    if unsafe { libc::getrlimit(libc::RLIMIT_RTTIME, &mut limit) } != 0 {
+        return false;
+    }
+    if limit.rlim_max <= RTTIME_LIMIT_US {
+        return true;
+    }
+
+    let limit = libc::rlimit {
+        rlim_cur: RTTIME_LIMIT_US,
+        rlim_max: RTTIME_LIMIT_US,
+    };
+    // SAFETY: `limit` is a valid rlimit.
+    unsafe { libc::setrlimit(libc::RLIMIT_RTTIME, &limit) == 0 }
+}
+
+/// Lower the nice value of thread `tid`, returning whether it succeeded
+fn set_nice(tid: libc::pid_t) -> bool {
+    // SAFETY: setpriority only reads its integer arguments.
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, FALLBACK_NICE) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promote_current_thread() {
+        // Run on a scratch thread so the test harness threads are unaffected;
+        // any outcome is acceptable depending on privileges and rtkit availability
+        let result = std::thread::spawn(|| promote_current_thread("test"))
+            .join()
+            .unwrap();
+        assert!(matches!(
+            result,
+            RealtimeResult::Direct
+                | RealtimeResult::RtKit
+                | RealtimeResult::Niced
+                | RealtimeResult::Unchanged
+        ));
+    }
+}
@@ -1,208 +1,3030 @@
 //! Audio receiver module that creates a virtual microphone and receives audio streams
 
+use crate::access::{FileMode, FileOwner, apply_access};
+use crate::audit::{AuditEvent, AuditLog, AuditRecord, ConnectionSummary};
+use crate::codec::{Codec, convert_pcm};
+use crate::continuity::{MIN_OVERLAP_MS, OverlapTrimmer, StreamHistory};
+use crate::control::{self, ClientInfo, ControlServer};
+use crate::demand::{RecorderState, spawn_recorder_watch};
+use crate::device::{DeviceSelector, find_output_device};
+use crate::discovery::{Advertisement, Advertiser};
+use crate::dump::DumpWriter;
+use crate::events::{EventBus, ReceiverEvent};
+use crate::guard::{Admission, PeerGuard};
+use crate::health::HealthProbe;
+use crate::jitter::{Adjustment, JitterBuffer};
+use crate::listen::{ListenAddr, Listener, PeerCredentials, Stream};
+use crate::manifest::{LevelStats, RecordingManifest};
+use crate::metrics::{self, MetricsServer, Totals};
+use crate::playback::{Playback, open_playback};
+use crate::privileges::{Identity, is_root};
+use crate::protocol::{self, Deframer, Metadata, StreamHeader};
+use crate::realtime::promote_current_thread;
+use crate::record::{Recorder, Rotation, create_file};
+use crate::sandbox::{self, Delegate};
+use crate::secondary::PrimaryWatch;
+use crate::session::SessionSummary;
+use crate::sink::CommandSink;
+use crate::spectrum::{Spectrum, SpectrumAnalyzer};
+use crate::state::{Resource, StateFile, cleanup_stale};
+use crate::stats::spawn_stats_thread;
+use crate::transcribe::{CommandTap, TapResampler, TranscriptionTap};
+use crate::udp::{self, Arrival, SequenceTracker, Transport};
+use crate::voice::{RecordWhen, VoiceGate};
 use crate::{
-    AudioConfig, VirtualMicResult, cleanup_virtual_microphone,
-    setup_virtual_microphone_with_config, validate_buffer_size,
+    AudioConfig, BufferSize, VirtualMicResult, bytes_to_ms, cleanup_monitor_sink,
+    cleanup_virtual_microphone_with_name, free_source_name, get_module_id_for_source,
+    monitor_sink_name, setup_monitor_sink, setup_virtual_microphone_with_config,
+    source_sample_spec, validate_buffer_size_for_format, validate_fifo_path,
+    validate_microphone_name, validate_node_latency,
 };
+use cpal::traits::DeviceTrait;
 use log::{debug, error, info, warn};
 use signal_hook::{consts::SIGINT, iterator::Signals};
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
 use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError, Weak, mpsc};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the receiver delivers audio when no `--pipe-to` command is given
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::receiver::Backend;
+///
+/// let backend: Backend = "null".parse().unwrap();
+/// assert_eq!(backend, Backend::Null);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// PulseAudio/PipeWire virtual microphone fed through the FIFO
+    #[default]
+    Pulse,
+    /// Regular file at the FIFO path, for testing without a sound server
+    Null,
+    /// Output device of the audio host, such as the input of a virtual
+    /// audio cable, see [`crate::playback`]
+    Device,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Pulse => "pulse",
+            Backend::Null => "null",
+            Backend::Device => "device",
+        })
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pulse" => Ok(Backend::Pulse),
+            "null" => Ok(Backend::Null),
+            "device" => Ok(Backend::Device),
+            _ => Err(anyhow::anyhow!(
+                "Unknown backend '{s}' (expected pulse, null or device)"
+            )),
+        }
+    }
+}
+
+/// Configuration for the receiver
+///
+/// Holds the network, virtual microphone, and scheduling settings used by
+/// [`run_receiver`] and [`Receiver`]. The defaults match the CLI defaults.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::receiver::ReceiverConfig;
+///
+/// let config = ReceiverConfig {
+///     port: 9000,
+///     ..ReceiverConfig::default()
+/// };
+/// assert_eq!(config.host, "0.0.0.0");
+/// assert_eq!(config.port, 9000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReceiverConfig {
+    /// Host address to bind to (e.g., "0.0.0.0" or "127.0.0.1")
+    pub host: String,
+    /// Port number to listen on
+    pub port: u16,
+    /// Addresses to listen on instead of `host` and `port`, see
+    /// [`crate::listen`]
+    pub listen: Vec<ListenAddr>,
+    /// Receive over TCP or as sequenced UDP datagrams, see [`crate::udp`]
+    pub transport: Transport,
+    /// Size of the socket reads (affects latency); durations are measured in
+    /// the wire format of [`ReceiverConfig::codec`]
+    pub buffer_size: BufferSize,
+    /// Accept buffers above [`crate::max_buffer_size`] for the stream format
+    pub allow_large_buffers: bool,
+    /// Name of the virtual microphone to create
+    pub microphone_name: String,
+    /// Path where the FIFO pipe will be created (the output file with
+    /// [`Backend::Null`])
+    pub fifo_path: String,
+    /// Owner and group given to the FIFO, see [`crate::access`]
+    pub fifo_owner: Option<FileOwner>,
+    /// Permissions given to the FIFO, see [`crate::access`]
+    pub fifo_mode: Option<FileMode>,
+    /// Audio output used when no `pipe_to` command is set
+    pub backend: Backend,
+    /// Output device played to by [`Backend::Device`] (default: the host's
+    /// default output device)
+    pub output_device: Option<DeviceSelector>,
+    /// Optional PipeWire `node.latency` for the virtual source (e.g. "256/48000")
+    pub node_latency: Option<String>,
+    /// Also create a null sink playing the virtual microphone, see
+    /// [`crate::setup_monitor_sink`]
+    pub with_monitor_sink: bool,
+    /// Request real-time scheduling for the FIFO writer threads
+    pub realtime: bool,
+    /// Maximum audio in milliseconds allowed to queue up in the socket and FIFO
+    /// before the backlog is dropped to return to live audio (0 disables the guard)
+    pub max_latency_ms: u64,
+    /// Audio in milliseconds collected from a new connection before any of it
+    /// is written to the output (0 starts playback immediately); must stay
+    /// below `max_latency_ms`
+    pub prebuffer_ms: u64,
+    /// Audio in milliseconds the jitter buffer aims to hold between the
+    /// network and the output, played out by a thread of its own (0 writes
+    /// the audio as it arrives), see [`crate::jitter`]
+    pub jitter_buffer_ms: u64,
+    /// Lowest target the jitter buffer adapts down to
+    pub jitter_min_ms: u64,
+    /// Most audio the jitter buffer holds before dropping the oldest
+    pub jitter_max_ms: u64,
+    /// Audio in milliseconds kept from the stream to recognise the part of a
+    /// reconnecting transmitter's replay that was already received, see
+    /// [`crate::continuity`] (0 disables the check)
+    pub continuity_ms: u64,
+    /// Run as the secondary of the receiver whose virtual microphone has this
+    /// name, playing audio only while that source is missing, see
+    /// [`crate::secondary`]
+    pub secondary_for: Option<String>,
+    /// Tell transmitters whether an application records from the virtual
+    /// microphone, for transmitters with `--on-demand`, see [`crate::demand`]
+    pub on_demand: bool,
+    /// Interval in seconds between resource usage reports (0 disables them)
+    pub stats_interval: u64,
+    /// Keep per-band levels of each stream for the control socket, see
+    /// [`crate::spectrum`]
+    pub spectrum: bool,
+    /// Wire encoding expected from transmitters, see [`crate::codec`]
+    pub codec: Codec,
+    /// Shell command receiving the audio as 16 kHz mono S16LE on stdin, see
+    /// [`crate::transcribe`]
+    pub transcribe_cmd: Option<String>,
+    /// Shell command receiving the audio on stdin instead of the virtual
+    /// microphone, see [`crate::sink`]
+    pub pipe_to: Option<String>,
+    /// File recording the raw bytes received from every transmitter, see
+    /// [`crate::dump`]
+    pub debug_dump: Option<String>,
+    /// WAV or FLAC file archiving the received audio, see [`crate::record`]
+    pub record: Option<String>,
+    /// Start a new recording file at this size in MiB (0 = no limit)
+    pub record_max_mb: u64,
+    /// Start a new recording file after this many seconds (0 = no limit)
+    pub record_max_secs: u64,
+    /// Delete the oldest recording files to keep them all under this many
+    /// MiB (0 = no limit)
+    pub record_max_total_mb: u64,
+    /// Pause recording while the disk has less than this many MiB free
+    /// (0 = no limit)
+    pub record_min_free_mb: u64,
+    /// Whether `record` keeps everything or only the audio around speech,
+    /// see [`crate::voice`]
+    pub record_when: RecordWhen,
+    /// Audio kept before speech with [`RecordWhen::Voice`], in milliseconds
+    pub record_preroll_ms: u64,
+    /// Audio kept after speech with [`RecordWhen::Voice`], in milliseconds
+    pub record_postroll_ms: u64,
+    /// File the connection attempts are appended to as JSON lines, see
+    /// [`crate::audit`]
+    pub audit_log: Option<String>,
+    /// Path of the control socket (`None` disables it), see [`crate::control`]
+    pub control_socket: Option<String>,
+    /// Address to serve Prometheus metrics on over HTTP (`None` disables
+    /// it), see [`crate::metrics`]
+    pub metrics_addr: Option<String>,
+    /// Name to advertise the receiver under on the local network over mDNS
+    /// (`None` disables it), see [`crate::discovery`]
+    pub advertise: Option<String>,
+    /// File recording the modules, FIFOs and sockets this receiver created,
+    /// so they can be removed after a crash (`None` disables it), see
+    /// [`crate::state`]
+    pub state_file: Option<String>,
+    /// User to switch to once set up, when started as root, see
+    /// [`crate::privileges`]
+    pub user: Option<String>,
+    /// Group to switch to along with `user` (default: its primary group)
+    pub group: Option<String>,
+    /// Filter the system calls of the threads reading transmitters' streams,
+    /// see [`crate::sandbox`]
+    pub sandbox: bool,
+    /// Connections accepted from one address per minute (0 = no limit), see
+    /// [`crate::guard`]
+    pub max_connection_rate: u32,
+    /// Strikes within ten minutes (connections over the rate limit, malformed
+    /// streams) after which an address is banned (0 = never)
+    pub ban_after: u32,
+    /// Length of a ban in seconds
+    pub ban_secs: u64,
+    /// Enable verbose logging output
+    pub verbose: bool,
+}
+
+impl ReceiverConfig {
+    /// Addresses to listen on: `listen`, or `host` and `port` if it is empty
+    fn listen_addrs(&self) -> Vec<ListenAddr> {
+        if self.listen.is_empty() {
+            vec![ListenAddr::Tcp {
+                host: self.host.clone(),
+                port: self.port,
+            }]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    /// Whether audio goes to a virtual microphone through the FIFO
+    fn uses_virtual_microphone(&self) -> bool {
+        self.pipe_to.is_none() && self.backend == Backend::Pulse
+    }
+
+    /// Frame rate and frame size of the stream on the wire
+    fn wire_format(&self) -> (u32, usize) {
+        let audio_config = self.codec.audio_config();
+        self.codec
+            .wire_format(audio_config.sample_rate, audio_config.channels)
+    }
+
+    /// Size of the socket reads in bytes
+    fn buffer_bytes(&self) -> usize {
+        let (frame_rate, frame_size) = self.wire_format();
+        self.buffer_size.to_bytes(frame_rate, frame_size)
+    }
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            listen: Vec::new(),
+            transport: Transport::Tcp,
+            buffer_size: BufferSize::default(),
+            allow_large_buffers: false,
+            microphone_name: "rsonance_virtual_microphone".to_string(),
+            fifo_path: "/tmp/rsonance_audio_pipe".to_string(),
+            fifo_owner: None,
+            fifo_mode: None,
+            backend: Backend::default(),
+            output_device: None,
+            node_latency: None,
+            with_monitor_sink: false,
+            realtime: false,
+            max_latency_ms: 1000,
+            prebuffer_ms: 0,
+            jitter_buffer_ms: 0,
+            jitter_min_ms: 20,
+            jitter_max_ms: 500,
+            continuity_ms: 1000,
+            secondary_for: None,
+            on_demand: false,
+            stats_interval: 0,
+            spectrum: false,
+            codec: Codec::S16LE,
+            transcribe_cmd: None,
+            pipe_to: None,
+            debug_dump: None,
+            record: None,
+            record_max_mb: 0,
+            record_max_secs: 0,
+            record_max_total_mb: 0,
+            record_min_free_mb: 100,
+            record_when: RecordWhen::Always,
+            record_preroll_ms: 500,
+            record_postroll_ms: 1500,
+            audit_log: None,
+            control_socket: Some(control::DEFAULT_CONTROL_SOCKET.to_string()),
+            metrics_addr: None,
+            advertise: None,
+            state_file: Some(crate::state::default_state_path()),
+            user: None,
+            group: None,
+            sandbox: false,
+            max_connection_rate: 30,
+            ban_after: 5,
+            ban_secs: 600,
+            verbose: false,
+        }
+    }
+}
 
 /// Run the receiver with the given configuration
 ///
 /// This function sets up a virtual microphone, binds to the specified address/port,
-/// and handles incoming audio streams from transmitter clients.
+/// and handles incoming audio streams from transmitter clients until Ctrl+C,
+/// then cleans up. Use [`Receiver`] to stop the receiver from code instead.
 ///
 /// # Arguments
 ///
-/// * `host` - Host address to bind to (e.g., "0.0.0.0" or "127.0.0.1")
-/// * `port` - Port number to listen on
-/// * `buffer_size` - Audio buffer size in bytes (affects latency)
-/// * `microphone_name` - Name of the virtual microphone to create
-/// * `fifo_path` - Path where the FIFO pipe will be created
-/// * `verbose` - Enable verbose logging output
+/// * `config` - Receiver configuration, see [`ReceiverConfig`]
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on successful completion, or an error if setup fails
+/// Returns `Ok(())` once cleaned up after Ctrl+C, or an error if setup fails
 ///
 /// # Limitations
 ///
-/// Only one active transmitter connection is supported at a time. The receiver
-/// accepts multiple simultaneous TCP connections and spawns a thread per client,
-/// but all clients write to the same FIFO pipe. Concurrent connections will
-/// produce corrupted audio. A future version may enforce single-client access
-/// explicitly (e.g., reject or queue additional connections).
+/// Only one active transmitter connection is supported at a time. A
+/// connection from the same transmitter (the client id in its stream
+/// header, or the address of a raw stream) replaces the active one, whose
+/// handler exits, so a transmitter reconnecting after a new DHCP lease takes
+/// over without the stale handler lingering on the FIFO. A different
+/// transmitter is refused as busy while the active one streams, and only
+/// takes over once that one has sent nothing for 5 seconds.
+///
+/// # Example
+///
+/// ```no_run
+/// use rsonance::receiver::{run_receiver, ReceiverConfig};
+///
+/// run_receiver(ReceiverConfig {
+///     microphone_name: "my_virtual_mic".to_string(),
+///     fifo_path: "/tmp/my_audio_pipe".to_string(),
+///     verbose: true,
+///     ..ReceiverConfig::default()
+/// }).unwrap();
+/// ```
+pub fn run_receiver(config: ReceiverConfig) -> anyhow::Result<()> {
+    run_receiver_with_tap(config, None)
+}
+
+/// Run the receiver, passing received audio to a transcription tap
+///
+/// Behaves like [`run_receiver`], and additionally hands every block of
+/// received audio to `tap` as 16 kHz mono samples. If `tap` is `None` and
+/// `config.transcribe_cmd` is set, a [`CommandTap`] running that command is
+/// used instead.
+///
+/// # Arguments
+///
+/// * `config` - Receiver configuration, see [`ReceiverConfig`]
+/// * `tap` - Receiver of 16 kHz mono audio, see [`crate::transcribe`]
 ///
 /// # Example
 ///
 /// ```no_run
-/// rsonance::receiver::run_receiver(
-///     "0.0.0.0".to_string(),
-///     8080,
-///     4096,
-///     "my_virtual_mic".to_string(),
-///     "/tmp/my_audio_pipe".to_string(),
-///     true
-/// ).unwrap();
+/// use rsonance::receiver::{run_receiver_with_tap, ReceiverConfig};
+/// use std::sync::Arc;
+///
+/// let tap = Arc::new(|samples: &[i16]| {
+///     // Feed the samples to a speech recogniser
+///     let _ = samples;
+/// });
+/// run_receiver_with_tap(ReceiverConfig::default(), Some(tap)).unwrap();
 /// ```
-pub fn run_receiver(
-    host: String,
-    port: u16,
-    buffer_size: usize,
-    microphone_name: String,
-    fifo_path: String,
-    verbose: bool,
+pub fn run_receiver_with_tap(
+    config: ReceiverConfig,
+    tap: Option<Arc<dyn TranscriptionTap>>,
 ) -> anyhow::Result<()> {
+    // Registered first, so Ctrl+C during setup still cleans up
+    let mut signals = Signals::new([SIGINT])?;
+    let mut receiver = match tap {
+        Some(tap) => Receiver::with_tap(config, tap),
+        None => Receiver::new(config),
+    };
+    receiver.start()?;
+    info!("Press Ctrl+C to stop and cleanup");
+
+    if let Some(sig) = signals.forever().next() {
+        info!("\nReceived signal {sig:?}, cleaning up...");
+    }
+    receiver.shutdown()
+}
+
+/// Set up the receiver and start accepting transmitters on background threads
+fn start_receiver(
+    mut config: ReceiverConfig,
+    tap: Option<Arc<dyn TranscriptionTap>>,
+    events: Arc<EventBus>,
+) -> anyhow::Result<Running> {
     // Validate buffer size
-    validate_buffer_size(buffer_size)?;
+    let (frame_rate, frame_size) = config.wire_format();
+    let buffer_bytes = validate_buffer_size_for_format(
+        config.buffer_bytes(),
+        frame_rate,
+        frame_size,
+        config.allow_large_buffers,
+    )?;
+    if let Some(latency) = &config.node_latency {
+        validate_node_latency(latency)?;
+    }
+    if config.max_latency_ms > 0 && config.prebuffer_ms >= config.max_latency_ms {
+        return Err(anyhow::anyhow!(
+            "Pre-buffer of {} ms must be below the maximum latency of {} ms, or the backlog guard drops it",
+            config.prebuffer_ms,
+            config.max_latency_ms
+        ));
+    }
+    if config.jitter_buffer_ms > 0
+        && !(config.jitter_min_ms..=config.jitter_max_ms).contains(&config.jitter_buffer_ms)
+    {
+        return Err(anyhow::anyhow!(
+            "Jitter buffer of {} ms must lie between its minimum of {} ms and maximum of {} ms",
+            config.jitter_buffer_ms,
+            config.jitter_min_ms,
+            config.jitter_max_ms
+        ));
+    }
+    if config.uses_virtual_microphone() {
+        validate_microphone_name(&config.microphone_name)?;
+        validate_fifo_path(&config.fifo_path)?;
+    }
+    if config.pipe_to.is_some() && (config.fifo_owner.is_some() || config.fifo_mode.is_some()) {
+        return Err(anyhow::anyhow!(
+            "--fifo-owner and --fifo-mode apply to the FIFO, which --pipe-to does not use"
+        ));
+    }
+    if config.output_device.is_some() && config.backend != Backend::Device {
+        return Err(anyhow::anyhow!("--output-device needs --backend device"));
+    }
+    if config.transport == Transport::Udp && config.on_demand {
+        return Err(anyhow::anyhow!(
+            "--on-demand answers transmitters over TCP and cannot be combined with --transport udp"
+        ));
+    }
+    if let Some(primary) = &config.secondary_for {
+        validate_microphone_name(primary)?;
+        if *primary == config.microphone_name {
+            return Err(anyhow::anyhow!(
+                "--secondary-for names this receiver's own microphone '{primary}'"
+            ));
+        }
+    }
+
+    let identity = match &config.user {
+        Some(_) if !is_root() => {
+            return Err(anyhow::anyhow!(
+                "--user drops root privileges, start the receiver as root to use it"
+            ));
+        }
+        Some(user) => Some(Identity::resolve(user, config.group.as_deref())?),
+        None if config.group.is_some() => {
+            return Err(anyhow::anyhow!("--group needs --user"));
+        }
+        None => None,
+    };
+    if config.sandbox {
+        sandbox::probe().map_err(|e| anyhow::anyhow!("--sandbox is not available: {e}"))?;
+    }
+
+    info!("Virtual microphone server starting...");
+
+    if config.verbose {
+        info!("Configuration:");
+        for addr in config.listen_addrs() {
+            info!("  Listen: {addr}");
+        }
+        info!("  Transport: {}", config.transport);
+        info!(
+            "  Buffer size: {buffer_bytes} bytes ({:.1} ms)",
+            bytes_to_ms(buffer_bytes, frame_rate, frame_size)
+        );
+        info!("  Microphone name: {}", config.microphone_name);
+        info!("  FIFO path: {}", config.fifo_path);
+        info!("  Codec: {}", config.codec);
+        if let Some(command) = &config.pipe_to {
+            info!("  Output command: {command}");
+        }
+        if let Some(latency) = &config.node_latency {
+            info!("  Node latency: {latency}");
+        }
+        if config.with_monitor_sink {
+            info!(
+                "  Monitor sink: {}",
+                monitor_sink_name(&config.microphone_name)
+            );
+        }
+        info!("  Real-time scheduling: {}", config.realtime);
+        info!("  Sandbox: {}", config.sandbox);
+        if config.max_connection_rate > 0 {
+            info!(
+                "  Connection rate limit: {} per minute",
+                config.max_connection_rate
+            );
+        }
+        if config.ban_after > 0 {
+            info!(
+                "  Ban: {} s after {} strikes",
+                config.ban_secs, config.ban_after
+            );
+        }
+        if config.max_latency_ms > 0 {
+            info!("  Max latency: {} ms", config.max_latency_ms);
+        }
+        if config.prebuffer_ms > 0 {
+            info!("  Pre-buffer: {} ms", config.prebuffer_ms);
+        }
+        if config.jitter_buffer_ms > 0 {
+            info!(
+                "  Jitter buffer: {} ms ({}-{} ms)",
+                config.jitter_buffer_ms, config.jitter_min_ms, config.jitter_max_ms
+            );
+        }
+        if config.continuity_ms > 0 {
+            info!("  Continuity check: {} ms", config.continuity_ms);
+        }
+        if let Some(primary) = &config.secondary_for {
+            info!("  Secondary for: {primary}");
+        }
+        if let Some(path) = &config.control_socket {
+            info!("  Control socket: {path}");
+        }
+        if let Some(name) = &config.advertise {
+            info!("  Advertised as: {name}");
+        }
+        if let Some(path) = &config.debug_dump {
+            info!("  Debug dump: {path}");
+        }
+        if let Some(path) = &config.record {
+            info!("  Recording: {path}");
+        }
+        if let Some(path) = &config.audit_log {
+            info!("  Audit log: {path}");
+        }
+        if let Some(path) = &config.state_file {
+            info!("  State file: {path}");
+        }
+    }
+
+    // Bind before touching PulseAudio so a busy port fails without side effects
+    let (listeners, datagram_sockets) = match config.transport {
+        Transport::Tcp => (
+            config
+                .listen_addrs()
+                .into_iter()
+                .map(|addr| Ok((Listener::bind(&addr)?, addr)))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            Vec::new(),
+        ),
+        Transport::Udp => (
+            Vec::new(),
+            config
+                .listen_addrs()
+                .into_iter()
+                .map(|addr| Ok((udp::bind(&addr)?, addr)))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        ),
+    };
+    let advertiser = match &config.advertise {
+        Some(name) => {
+            let port = config
+                .listen_addrs()
+                .iter()
+                .find_map(|addr| match addr {
+                    ListenAddr::Tcp { port, .. } => Some(*port),
+                    ListenAddr::Unix(_) => None,
+                })
+                .ok_or_else(|| {
+                    anyhow::anyhow!("--advertise needs a port to advertise, not only Unix sockets")
+                })?;
+            Some(Advertiser::start(Advertisement {
+                name: name.clone(),
+                port,
+                transport: config.transport,
+            })?)
+        }
+        None => None,
+    };
+    let unix_sockets: Vec<String> = listeners
+        .iter()
+        .filter_map(|(_, addr)| match addr {
+            ListenAddr::Unix(path) => Some(path.clone()),
+            ListenAddr::Tcp { .. } => None,
+        })
+        .collect();
+
+    // Remove whatever a receiver killed without cleaning up left behind
+    let state = config.state_file.as_ref().map(StateFile::new);
+    if let Some(state) = &state {
+        match cleanup_stale(state) {
+            Ok(report) if report.removed > 0 => info!(
+                "Removed {} resources left behind by a previous receiver",
+                report.removed
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to clean up after previous receivers: {e}"),
+        }
+    }
+
+    let dump = match &config.debug_dump {
+        Some(path) => Some(Arc::new(DumpWriter::create(path, config.codec)?)),
+        None => None,
+    };
+    let manifest = match (&config.debug_dump, &dump) {
+        (Some(path), Some(dump)) => Some(Arc::new(RecordingManifest::create(
+            path,
+            config.codec,
+            dump.started(),
+        )?)),
+        _ => None,
+    };
+    let recorder = match &config.record {
+        Some(path) => {
+            let recorder = Recorder::new(
+                path,
+                Rotation {
+                    max_bytes: config.record_max_mb * 1024 * 1024,
+                    max_secs: config.record_max_secs,
+                    max_total_bytes: config.record_max_total_mb * 1024 * 1024,
+                    min_free_bytes: config.record_min_free_mb * 1024 * 1024,
+                },
+            )?;
+            Some(Arc::new(match config.record_when {
+                RecordWhen::Always => recorder,
+                RecordWhen::Voice => recorder.voice_gate(VoiceGate::new(
+                    Duration::from_millis(config.record_preroll_ms),
+                    Duration::from_millis(config.record_postroll_ms),
+                )),
+            }))
+        }
+        None => None,
+    };
+    let audit = config
+        .audit_log
+        .as_deref()
+        .map(AuditLog::open)
+        .transpose()?;
+    if config.pipe_to.is_none() && config.backend == Backend::Null {
+        File::create(&config.fifo_path).map_err(|e| {
+            anyhow::anyhow!("Failed to create output file {}: {e}", config.fifo_path)
+        })?;
+        apply_fifo_access(&config)?;
+        info!(
+            "Null backend: writing received audio to {}",
+            config.fifo_path
+        );
+    }
+    if config.pipe_to.is_none() && config.backend == Backend::Device {
+        // Checked now, the device is only opened once a transmitter connects
+        let device = find_output_device(config.output_device.as_ref())?;
+        info!(
+            "Device backend: playing received audio on {}",
+            device.name().unwrap_or_else(|_| "(unnamed)".to_string())
+        );
+    }
+    if config.uses_virtual_microphone() {
+        info!("Setting up virtual microphone...");
+        use_free_microphone_name(&mut config);
+        create_virtual_microphone(&config)?;
+        info!("Virtual microphone created successfully");
+        apply_fifo_access(&config)?;
+        record_virtual_microphone(&config);
+        if config.with_monitor_sink {
+            create_monitor_sink(&config, &config.codec.audio_config());
+        }
+    } else if config.with_monitor_sink {
+        warn!("--with-monitor-sink needs the virtual microphone, not creating a monitor sink");
+    }
+
+    if config.stats_interval > 0 {
+        spawn_stats_thread(Duration::from_secs(config.stats_interval));
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    for (_, addr) in &listeners {
+        info!("Server listening on {addr}...");
+    }
+    for (_, addr) in &datagram_sockets {
+        info!("Server receiving UDP on {addr}...");
+    }
+    for path in &unix_sockets {
+        record_resource(state.as_ref(), Resource::Socket(path.clone()));
+    }
+    if config.uses_virtual_microphone() {
+        info!("Virtual microphone '{}' created", config.microphone_name);
+        info!("Remote desktop software can now use this as a microphone input");
+    }
+
+    let registry = Arc::new(ClientRegistry {
+        events,
+        ..ClientRegistry::with_codec(config.codec)
+    });
+    if config.stats_interval > 0 {
+        spawn_queue_report(
+            Arc::downgrade(&registry),
+            Duration::from_secs(config.stats_interval),
+        );
+    }
+    if config.on_demand {
+        if config.uses_virtual_microphone() {
+            spawn_recorder_watch(config.microphone_name.clone(), Arc::downgrade(&registry));
+        } else {
+            // Audio without a virtual microphone is always consumed
+            registry.set_recorder_state(RecorderState::Recording);
+        }
+    }
+    let health = Arc::new(if config.uses_virtual_microphone() {
+        HealthProbe::for_microphone(&config.microphone_name, &config.fifo_path)
+    } else if config.pipe_to.is_none() && config.backend == Backend::Null {
+        HealthProbe::for_file(&config.fifo_path)
+    } else {
+        HealthProbe::default()
+    });
+    let control = match &config.control_socket {
+        Some(path) => {
+            let server = control::serve(
+                path,
+                Arc::clone(&registry),
+                Arc::clone(&health),
+                manifest.clone(),
+                recorder.clone(),
+            )?;
+            record_resource(state.as_ref(), Resource::Socket(path.clone()));
+            Some(server)
+        }
+        None => None,
+    };
+    let metrics = config
+        .metrics_addr
+        .as_deref()
+        .map(|addr| metrics::serve(addr, Arc::clone(&registry), Arc::clone(&health)))
+        .transpose()?;
+    // Everything from here on, including the commands below, runs unprivileged
+    if let Some(identity) = &identity {
+        drop_privileges(&config, identity, &unix_sockets)?;
+    }
+    let sink = match &config.pipe_to {
+        Some(command) => Some(Arc::new(CommandSink::spawn(command)?)),
+        None => None,
+    };
+    let tap = match (tap, &config.transcribe_cmd) {
+        (Some(tap), _) => Some(tap),
+        (None, Some(command)) => {
+            Some(Arc::new(CommandTap::spawn(command)?) as Arc<dyn TranscriptionTap>)
+        }
+        (None, None) => None,
+    };
+    let secondary = config.secondary_for.clone().map(PrimaryWatch::spawn);
+    let history = (config.continuity_ms > 0).then(|| {
+        let (frame_rate, frame_size) = config.wire_format();
+        StreamHistory::new((frame_rate as u64 * config.continuity_ms / 1000) as usize * frame_size)
+    });
+
+    let guard = PeerGuard::new(
+        config.max_connection_rate,
+        config.ban_after,
+        Duration::from_secs(config.ban_secs),
+    );
+    let shared = Arc::new(Shared {
+        guard,
+        audit,
+        config,
+        registry,
+        tap,
+        sink,
+        dump,
+        manifest,
+        recorder,
+        secondary,
+        history,
+        next_connection_id: AtomicU64::new(1),
+        running,
+        listeners: Mutex::default(),
+        handlers: Mutex::new(0),
+        handlers_done: Condvar::new(),
+    });
+    let mut accept_threads = listeners
+        .into_iter()
+        .map(|(listener, addr)| {
+            shared.watch_listener(&listener)?;
+            let shared = Arc::clone(&shared);
+            let accepting = health.add_listener(&addr.to_string());
+            Ok(thread::spawn(move || {
+                shared.accept_loop(listener, &addr, &accepting)
+            }))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    accept_threads.extend(datagram_sockets.into_iter().map(|(socket, addr)| {
+        let shared = Arc::clone(&shared);
+        let receiving = health.add_listener(&format!("{addr} (udp)"));
+        thread::spawn(move || shared.datagram_loop(socket, &addr, &receiving))
+    }));
+
+    Ok(Running {
+        shared,
+        accept_threads,
+        control,
+        metrics,
+        advertiser,
+        state,
+        unix_sockets,
+    })
+}
+
+/// Time [`Receiver::shutdown`] waits for connection handlers to finish
+const HANDLER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A receiver running on background threads
+///
+/// Unlike [`run_receiver`], which runs until Ctrl+C, this lets an
+/// application start a receiver and shut it down again: [`Receiver::start`]
+/// sets up the virtual microphone and listeners and returns, and
+/// [`Receiver::shutdown`] disconnects the transmitters and removes what
+/// `start` created. Dropping a running receiver shuts it down too.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rsonance::receiver::{Receiver, ReceiverConfig};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let mut receiver = Receiver::new(ReceiverConfig {
+///     microphone_name: "my_virtual_mic".to_string(),
+///     ..ReceiverConfig::default()
+/// });
+/// receiver.start()?;
+/// // ...
+/// println!("{} transmitter(s) connected", receiver.clients().len());
+/// for event in receiver.events().try_iter() {
+///     println!("{event:?}");
+/// }
+/// receiver.shutdown()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Receiver {
+    config: ReceiverConfig,
+    tap: Option<Arc<dyn TranscriptionTap>>,
+    events: Arc<EventBus>,
+    running: Option<Running>,
+}
+
+impl Receiver {
+    /// Receiver with the given configuration, not started yet
+    pub fn new(config: ReceiverConfig) -> Self {
+        Self {
+            config,
+            tap: None,
+            events: Arc::default(),
+            running: None,
+        }
+    }
+
+    /// Receiver handing the received audio to `tap` as well, see
+    /// [`run_receiver_with_tap`]
+    pub fn with_tap(config: ReceiverConfig, tap: Arc<dyn TranscriptionTap>) -> Self {
+        Self {
+            config,
+            tap: Some(tap),
+            events: Arc::default(),
+            running: None,
+        }
+    }
+
+    /// Set up the receiver and start accepting transmitters
+    ///
+    /// Returns once the listeners are bound and the virtual microphone
+    /// exists; transmitters are handled on background threads. Returns an
+    /// error if setup fails or the receiver is already running.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        if self.running.is_some() {
+            return Err(anyhow::anyhow!("The receiver is already running"));
+        }
+        self.running = Some(start_receiver(
+            self.config.clone(),
+            self.tap.clone(),
+            Arc::clone(&self.events),
+        )?);
+        Ok(())
+    }
+
+    /// Name of the virtual microphone, which may differ from the configured
+    /// one if a source with that name already existed at start
+    pub fn microphone_name(&self) -> &str {
+        match &self.running {
+            Some(running) => &running.shared.config.microphone_name,
+            None => &self.config.microphone_name,
+        }
+    }
+
+    /// The connected transmitters, ordered by connection id
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        self.running
+            .as_ref()
+            .map(|running| running.shared.registry.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Subscribe to transmitters connecting, being refused or kicked, and
+    /// disconnecting, see [`crate::events`]
+    ///
+    /// The subscription gets every event from now on, over any number of
+    /// starts and shutdowns, until it is dropped.
+    pub fn events(&self) -> mpsc::Receiver<ReceiverEvent> {
+        self.events.subscribe()
+    }
+
+    /// Parameters of the stream of the transmitter with connection id `id`,
+    /// see [`ClientRegistry::session`]
+    pub fn session(&self, id: u64) -> Option<SessionSummary> {
+        self.running
+            .as_ref()
+            .and_then(|running| running.shared.registry.session(id))
+    }
+
+    /// Stop accepting transmitters, disconnect the connected ones and remove
+    /// the virtual microphone, FIFO and sockets
+    ///
+    /// Returns once the connection handlers have finished, or after a short
+    /// timeout if one is stuck writing its audio. Shutting down a receiver
+    /// that is not running does nothing.
+    pub fn shutdown(&mut self) -> anyhow::Result<()> {
+        match self.running.take() {
+            Some(running) => running.shutdown(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            error!("Failed to shut down the receiver: {e}");
+        }
+    }
+}
+
+/// What a started receiver needs to shut down again
+struct Running {
+    shared: Arc<Shared>,
+    /// Threads accepting connections or receiving datagrams
+    accept_threads: Vec<thread::JoinHandle<()>>,
+    control: Option<ControlServer>,
+    metrics: Option<MetricsServer>,
+    advertiser: Option<Advertiser>,
+    state: Option<StateFile>,
+    unix_sockets: Vec<String>,
+}
+
+impl Running {
+    fn shutdown(self) -> anyhow::Result<()> {
+        let shared = self.shared;
+        shared.running.store(false, Ordering::SeqCst);
+        if let Some(advertiser) = self.advertiser {
+            advertiser.close();
+        }
+        for listener in shared
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            listener.shutdown();
+        }
+        for accept_thread in self.accept_threads {
+            let _ = accept_thread.join();
+        }
+        if let Some(control) = self.control {
+            control.close();
+        }
+        if let Some(metrics) = self.metrics {
+            metrics.close();
+        }
+
+        shared.registry.disconnect_all();
+        let handlers = shared
+            .handlers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (handlers, timeout) = shared
+            .handlers_done
+            .wait_timeout_while(handlers, HANDLER_SHUTDOWN_TIMEOUT, |active| *active > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        if timeout.timed_out() {
+            warn!("{} connection handler(s) did not finish in time", *handlers);
+        }
+        drop(handlers);
+        if let Some(recorder) = &shared.recorder {
+            recorder.finish();
+        }
+
+        let config = &shared.config;
+        let microphone = config.uses_virtual_microphone();
+        // The loopback has to go before the source it reads from
+        if microphone
+            && config.with_monitor_sink
+            && let Err(e) = cleanup_monitor_sink(&config.microphone_name)
+        {
+            error!("Error removing monitor sink: {e}");
+        }
+        if microphone {
+            if let Err(e) = cleanup_virtual_microphone_with_name(&config.microphone_name) {
+                error!("Error cleaning up virtual microphone: {e}");
+            } else {
+                info!("Virtual microphone cleaned up successfully");
+            }
+            if Path::new(&config.fifo_path).exists()
+                && let Err(e) = std::fs::remove_file(&config.fifo_path)
+            {
+                error!("Error removing audio pipe: {e}");
+            }
+        }
+        for path in &self.unix_sockets {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Some(state) = &self.state {
+            state.forget_own()?;
+        }
+        Ok(())
+    }
+}
+
+/// State shared by the accept loops of all listeners and the connection handlers
+struct Shared {
+    config: ReceiverConfig,
+    /// Rate limit and bans of transmitter addresses
+    guard: PeerGuard,
+    audit: Option<AuditLog>,
+    registry: Arc<ClientRegistry>,
+    tap: Option<Arc<dyn TranscriptionTap>>,
+    sink: Option<Arc<CommandSink>>,
+    dump: Option<Arc<DumpWriter>>,
+    /// Sidecar manifest of the debug dump
+    manifest: Option<Arc<RecordingManifest>>,
+    recorder: Option<Arc<Recorder>>,
+    secondary: Option<PrimaryWatch>,
+    /// End of the stream received so far, for the continuity check
+    history: Option<StreamHistory>,
+    /// Connection ids are unique across listeners
+    next_connection_id: AtomicU64,
+    running: Arc<AtomicBool>,
+    /// Handles of the current listening sockets, shut down to wake the
+    /// accept loops on shutdown
+    listeners: Mutex<Vec<Listener>>,
+    /// Number of connection handlers still running
+    handlers: Mutex<usize>,
+    handlers_done: Condvar,
+}
+
+impl Shared {
+    /// Append `record` to the audit log, if there is one
+    fn audit(&self, record: &AuditRecord) {
+        if let Some(audit) = &self.audit {
+            audit.record(record);
+        }
+    }
+
+    /// Keep a handle of `listener` to wake its accept loop on shutdown
+    ///
+    /// A listener added after shutdown has begun is shut down right away.
+    fn watch_listener(&self, listener: &Listener) -> std::io::Result<()> {
+        let handle = listener.try_clone()?;
+        let mut listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !self.running.load(Ordering::SeqCst) {
+            handle.shutdown();
+        }
+        listeners.push(handle);
+        Ok(())
+    }
+
+    /// Accept transmitters on `listener` until the receiver stops
+    ///
+    /// Every connection is registered and handled on its own thread,
+    /// whichever listener it arrived on. `accepting` is cleared
+    /// while the listener is being rebound, for the health check.
+    fn accept_loop(
+        self: Arc<Self>,
+        mut listener: Listener,
+        addr: &ListenAddr,
+        accepting: &AtomicBool,
+    ) {
+        let mut backoff = ACCEPT_BACKOFF_MIN;
+
+        while self.running.load(Ordering::SeqCst) {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => {
+                    backoff = ACCEPT_BACKOFF_MIN;
+                    accepted
+                }
+                Err(e) if is_transient_accept_error(&e) => {
+                    warn!("Failed to accept connection ({e}), retrying in {backoff:?}");
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                    continue;
+                }
+                // Woken up by shutdown
+                Err(_) if !self.running.load(Ordering::SeqCst) => break,
+                Err(e) => {
+                    error!("Listener on {addr} failed ({e}), rebinding");
+                    accepting.store(false, Ordering::SeqCst);
+                    match rebind(addr, &self.running) {
+                        Some(new_listener) => listener = new_listener,
+                        None => break,
+                    }
+                    accepting.store(true, Ordering::SeqCst);
+                    if let Err(e) = self.watch_listener(&listener) {
+                        warn!("Listener on {addr} cannot be woken on shutdown: {e}");
+                    }
+                    continue;
+                }
+            };
+            let identity = stream.peer_credentials();
+            self.handle_connection(stream, peer, identity, addr);
+        }
+    }
+
+    /// Receive datagrams on `socket` until the receiver stops, see
+    /// [`crate::udp`]
+    ///
+    /// The datagrams of a transmitter are put in order and fed to a regular
+    /// connection handler as a framed stream, so a UDP transmitter is
+    /// admitted, registered, kicked and audited like a TCP one. While one
+    /// is streaming, datagrams from other addresses are ignored.
+    /// `receiving` is cleared while receiving fails, for the health check.
+    fn datagram_loop(
+        self: Arc<Self>,
+        socket: UdpSocket,
+        addr: &ListenAddr,
+        receiving: &AtomicBool,
+    ) {
+        let mut datagram = vec![0; 65536];
+        let mut session: Option<DatagramSession> = None;
+        // Address whose connection was refused, ignored until the time given
+        let mut refused: Option<(SocketAddr, Instant)> = None;
+
+        while self.running.load(Ordering::SeqCst) {
+            let received = socket.recv_from(&mut datagram);
+            if session
+                .as_ref()
+                .is_some_and(|current| current.last_datagram.elapsed() >= udp::SESSION_TIMEOUT)
+                && let Some(current) = session.take()
+            {
+                current.end("Transmitter stopped sending");
+            }
+            let (len, from) = match received {
+                Ok(received) => {
+                    receiving.store(true, Ordering::SeqCst);
+                    received
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    receiving.store(false, Ordering::SeqCst);
+                    warn!("Failed to receive on {addr} ({e}), retrying in {ACCEPT_BACKOFF_MAX:?}");
+                    thread::sleep(ACCEPT_BACKOFF_MAX);
+                    continue;
+                }
+            };
+            if refused.is_some_and(|(peer, until)| peer == from && Instant::now() < until) {
+                continue;
+            }
+            let (header, sequence, audio) = match udp::parse_datagram(&datagram[..len]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    debug!("Ignoring datagram from {from}: {e}");
+                    if self.guard.strike(from.ip()) {
+                        warn!(
+                            "Banned {} for {} s: repeated malformed datagrams",
+                            from.ip(),
+                            self.config.ban_secs
+                        );
+                    }
+                    continue;
+                }
+            };
+
+            if session.as_ref().is_some_and(|current| current.peer != from) {
+                continue;
+            }
+            let current = match &mut session {
+                Some(current) if current.header == header => current,
+                _ => {
+                    if let Some(previous) = session.take() {
+                        previous.end("Transmitter changed its stream format");
+                    }
+                    match self.start_session(from, header, addr) {
+                        Some(started) => session.insert(started),
+                        None => {
+                            refused = Some((from, Instant::now() + REFUSED_DATAGRAM_PAUSE));
+                            continue;
+                        }
+                    }
+                }
+            };
+            current.last_datagram = Instant::now();
+            match current.tracker.arrive(sequence) {
+                Arrival::InOrder => {}
+                Arrival::AfterLoss(lost) => {
+                    debug!("[{}] {lost} datagram(s) lost", current.connection);
+                    current.lost += u64::from(lost);
+                    self.registry.add_lost_datagrams(u64::from(lost));
+                }
+                Arrival::Late => {
+                    current.late += 1;
+                    self.registry.add_lost_datagrams(1);
+                    continue;
+                }
+            }
+            if let Err(e) = current.writer.write_all(&protocol::frame(audio))
+                && let Some(current) = session.take()
+            {
+                current.end(&format!("Connection handler stopped ({e})"));
+            }
+        }
+    }
+
+    /// Start handling the datagrams from `peer` as a new connection
+    ///
+    /// Returns `None` if the connection was refused or could not be set up.
+    fn start_session(
+        self: &Arc<Self>,
+        peer: SocketAddr,
+        header: StreamHeader,
+        addr: &ListenAddr,
+    ) -> Option<DatagramSession> {
+        let pair = UnixStream::pair().and_then(|(mut writer, reader)| {
+            // The handler expects a stream that starts with its header
+            writer.write_all(&header.encode())?;
+            Ok((writer, reader))
+        });
+        let (writer, reader) = match pair {
+            Ok(pair) => pair,
+            Err(e) => {
+                error!("Failed to set up a stream for the UDP transmitter {peer}: {e}");
+                return None;
+            }
+        };
+        let connection = self.handle_connection(Stream::Unix(reader), Some(peer), None, addr)?;
+        Some(DatagramSession {
+            connection,
+            peer,
+            header,
+            writer,
+            tracker: SequenceTracker::default(),
+            last_datagram: Instant::now(),
+            lost: 0,
+            late: 0,
+        })
+    }
+
+    /// Admit, register and start handling a transmitter connection that
+    /// arrived on `addr`
+    ///
+    /// The connection becomes the active one once its stream header is read,
+    /// see [`ClientRegistry::claim`], unless its peer is over the rate limit
+    /// or banned. Returns the connection's context, or `None` if
+    /// it was refused.
+    fn handle_connection(
+        self: &Arc<Self>,
+        stream: Stream,
+        peer: Option<SocketAddr>,
+        identity: Option<PeerCredentials>,
+        addr: &ListenAddr,
+    ) -> Option<ConnectionContext> {
+        let connected_at = Instant::now();
+        let mut record = AuditRecord {
+            connection: None,
+            listener: addr.to_string(),
+            peer,
+            identity,
+            event: AuditEvent::Accepted,
+        };
+        // Refused before registering, so no handler is started
+        if let Some(ip) = peer.map(|peer| peer.ip()) {
+            let refused = match self.guard.admit(ip) {
+                Admission::Allowed => None,
+                Admission::RateLimited { banned: false } => {
+                    warn!("Refused connection from {ip}: over --max-connection-rate");
+                    Some("rate_limited")
+                }
+                Admission::RateLimited { banned: true } => {
+                    warn!(
+                        "Banned {ip} for {} s: too many connections over --max-connection-rate",
+                        self.config.ban_secs
+                    );
+                    Some("rate_limited")
+                }
+                Admission::Banned(left) => {
+                    debug!(
+                        "Refused connection from banned {ip} ({} s left)",
+                        left.as_secs()
+                    );
+                    Some("banned")
+                }
+            };
+            if let Some(reason) = refused {
+                record.event = AuditEvent::Refused(reason);
+                self.audit(&record);
+                self.registry
+                    .events
+                    .emit(ReceiverEvent::Refused { peer, reason });
+                return None;
+            }
+        }
+
+        let connection = ConnectionContext {
+            id: self.next_connection_id.fetch_add(1, Ordering::SeqCst),
+            peer,
+        };
+        info!("[{connection}] Transmitter connected on {addr}");
+
+        let stats = match self.registry.register(&connection, &stream) {
+            Ok(stats) => stats,
+            Err(e) => {
+                error!("[{connection}] Failed to register connection, closing it: {e}");
+                record.event = AuditEvent::Refused("error");
+                self.audit(&record);
+                self.registry.events.emit(ReceiverEvent::Refused {
+                    peer,
+                    reason: "error",
+                });
+                return None;
+            }
+        };
+        record.connection = Some(connection.id);
+        self.audit(&record);
+        self.registry.events.emit(ReceiverEvent::Connected {
+            id: connection.id,
+            peer,
+        });
+        if let Some(manifest) = &self.manifest {
+            manifest.connected(connection.id, record.peer, record.identity);
+        }
+        let receiver = Arc::clone(self);
+
+        *self.handlers.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        let context = connection.clone();
+        thread::spawn(move || {
+            let result = handle_audio_stream(
+                stream,
+                &receiver.config,
+                &connection,
+                &stats,
+                &receiver.registry,
+                receiver.tap.as_deref(),
+                receiver.sink.as_deref(),
+                receiver.dump.as_deref(),
+                receiver.recorder.as_deref(),
+                receiver.secondary.as_ref(),
+                receiver.history.as_ref(),
+            );
+            let summary = ConnectionSummary {
+                duration: connected_at.elapsed(),
+                bytes_received: stats.bytes_received.load(Ordering::Relaxed),
+            };
+            let disconnected = ReceiverEvent::Disconnected {
+                id: connection.id,
+                duration: summary.duration,
+                bytes_received: summary.bytes_received,
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            };
+            record.event = match result {
+                Ok(()) => AuditEvent::Closed(summary),
+                Err(e) if e.is::<Busy>() => {
+                    warn!("[{connection}] Refused: {e}");
+                    AuditEvent::Refused("busy")
+                }
+                Err(e) => {
+                    error!("[{connection}] Error handling audio stream: {e:#}");
+                    if !is_malformed(&e) {
+                        AuditEvent::Failed(summary, format!("{e:#}"))
+                    } else {
+                        if let Some(peer) = connection.peer
+                            && receiver.guard.strike(peer.ip())
+                        {
+                            warn!(
+                                "[{connection}] Banned {} for {} s: repeated malformed streams",
+                                peer.ip(),
+                                receiver.config.ban_secs
+                            );
+                        }
+                        AuditEvent::Malformed(summary, format!("{e:#}"))
+                    }
+                }
+            };
+            receiver.audit(&record);
+            if let Some(manifest) = &receiver.manifest {
+                let error = match &record.event {
+                    AuditEvent::Malformed(_, e) | AuditEvent::Failed(_, e) => Some(e.clone()),
+                    _ => None,
+                };
+                manifest.disconnected(connection.id, stats.levels(), error);
+            }
+            receiver.registry.unregister(connection.id);
+            receiver.registry.events.emit(disconnected);
+            *receiver
+                .handlers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) -= 1;
+            receiver.handlers_done.notify_all();
+        });
+        Some(context)
+    }
+}
+
+/// A UDP transmitter whose datagrams are fed to a connection handler
+struct DatagramSession {
+    connection: ConnectionContext,
+    peer: SocketAddr,
+    header: StreamHeader,
+    /// Sending end of the stream the connection handler reads
+    writer: UnixStream,
+    tracker: SequenceTracker,
+    last_datagram: Instant,
+    /// Datagrams skipped over in the sequence
+    lost: u64,
+    /// Datagrams discarded for arriving after a later one
+    late: u64,
+}
+
+impl DatagramSession {
+    /// Close the handler's stream, ending the connection
+    fn end(self, reason: &str) {
+        info!(
+            "[{}] {reason}: {} datagram(s) lost, {} arrived late",
+            self.connection, self.lost, self.late
+        );
+    }
+}
+
+/// Whether a connection handler failed because the transmitter sent data
+/// that is not a valid stream
+fn is_malformed(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == ErrorKind::InvalidData)
+    })
+}
+
+/// Initial delay before accepting again after a transient accept error
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+
+/// Longest delay between accept retries
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Time datagrams from an address are ignored after its connection was
+/// refused, so a banned transmitter is not refused once per datagram
+const REFUSED_DATAGRAM_PAUSE: Duration = Duration::from_secs(1);
+
+/// Delay between attempts to bind the listening socket again
+const REBIND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether an accept error is transient and the listener is still usable
+///
+/// Aborted handshakes and resource exhaustion (too many open files, no
+/// buffer space) go away on their own; anything else means the listening
+/// socket itself is broken.
+fn is_transient_accept_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    ) || matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM | libc::EPROTO)
+    )
+}
+
+/// Bind `addr` again, retrying until it succeeds or the receiver stops
+///
+/// Returns the new listener, or `None` if the receiver was stopped first
+fn rebind(addr: &ListenAddr, running: &AtomicBool) -> Option<Listener> {
+    while running.load(Ordering::SeqCst) {
+        match Listener::bind(addr) {
+            Ok(listener) => {
+                info!("Server listening on {addr} again");
+                return Some(listener);
+            }
+            Err(e) => {
+                warn!("Failed to bind {addr} ({e}), retrying in {REBIND_INTERVAL:?}");
+                thread::sleep(REBIND_INTERVAL);
+            }
+        }
+    }
+    None
+}
+
+/// Identifies a transmitter connection in log messages
+///
+/// Every log line about a connection is prefixed with its context, so output
+/// from overlapping or successive connections can be told apart.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionContext {
+    /// Sequential connection number, starting at 1 for each receiver run
+    pub(crate) id: u64,
+    /// Remote address of the transmitter, if known
+    pub(crate) peer: Option<SocketAddr>,
+}
+
+impl fmt::Display for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "conn {} {peer}", self.id),
+            None => write!(f, "conn {}", self.id),
+        }
+    }
+}
+
+/// Transmitters currently connected to the receiver
+///
+/// Shared between the accept loop, the per-connection handlers, and the
+/// control socket. Only one transmitter is active at a time: a connection
+/// [claimed](Self::claim) by the same transmitter shuts down its old one, so
+/// that handler sees end-of-stream and releases the FIFO, and one from
+/// another transmitter is refused.
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: Mutex<HashMap<u64, RegisteredClient>>,
+    /// Wire encoding of clients that send a raw stream
+    codec: Codec,
+    /// Recorder state reported to transmitters, or `None` if the receiver
+    /// does not report it, see [`crate::demand`]
+    recorder_state: Mutex<Option<RecorderState>>,
+    /// Traffic of every connection so far, for [`crate::metrics`]
+    totals: Arc<TotalCounters>,
+    /// Subscriptions to the connections' events, see [`Receiver::events`]
+    events: Arc<EventBus>,
+}
+
+/// Registry entry for a connected transmitter
+#[derive(Debug)]
+struct RegisteredClient {
+    connection: ConnectionContext,
+    connected_at: Instant,
+    /// Handle used to disconnect the client
+    stream: Stream,
+    /// Traffic and buffer occupancy, updated by the connection handler
+    stats: Arc<ConnectionStats>,
+    /// Transmitter streaming on the connection, once its header has been
+    /// read, see [`ClientRegistry::claim`]
+    key: Option<ClientKey>,
+}
+
+/// Which transmitter a connection comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ClientKey {
+    /// Client id announced in the stream header
+    Id(u64),
+    /// Raw stream, known only by its address (`None` for a Unix socket)
+    Raw(Option<IpAddr>),
+}
+
+/// A connection refused because another transmitter is streaming
+#[derive(Debug)]
+pub(crate) struct Busy {
+    /// The active transmitter's connection
+    active: ConnectionContext,
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "another transmitter is streaming [{}]", self.active)
+    }
+}
+
+impl std::error::Error for Busy {}
+
+/// Time without data after which the active transmitter no longer keeps
+/// others out, so a connection that died without closing does not block
+/// the receiver
+const STALE_CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most addresses remembered to count reconnects; the oldest are forgotten
+/// first
+const MAX_KNOWN_PEERS: usize = 1024;
+
+/// Traffic of a connection and the audio it has waiting
+///
+/// Updated by the connection handler after every read, so the control socket
+/// and the stats report can show which transmitter uses how much bandwidth,
+/// and how close a stream is to an underrun or how much latency it is
+/// building up.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionStats {
+    /// Bytes read from the socket, including audio dropped to catch up
+    bytes_received: AtomicU64,
+    /// Milliseconds of audio received by the kernel but not read yet
+    socket_ms: AtomicU64,
+    /// Milliseconds of audio written to the FIFO or `--pipe-to` command but
+    /// not consumed yet
+    output_ms: AtomicU64,
+    /// Wire encoding announced in the stream header, if any
+    codec: OnceLock<Codec>,
+    /// Name and tags sent after the stream header, if any
+    metadata: OnceLock<Metadata>,
+    /// Parameters of the stream, once its header has been read
+    session: OnceLock<SessionSummary>,
+    /// Levels of the decoded audio, kept for the recording manifest
+    levels: Mutex<LevelStats>,
+    /// Spectrum of the decoded audio, with `--spectrum`
+    spectrum: Mutex<Option<SpectrumAnalyzer>>,
+    /// The receiver's totals, kept up to date along with this connection's
+    totals: Arc<TotalCounters>,
+    /// When data last arrived
+    last_received: Mutex<Option<Instant>>,
+}
+
+/// Counters over the lifetime of the receiver, across connections
+#[derive(Debug, Default)]
+struct TotalCounters {
+    connections: AtomicU64,
+    reconnects: AtomicU64,
+    bytes_received: AtomicU64,
+    dropped_ms: AtomicU64,
+    datagrams_lost: AtomicU64,
+    /// Addresses that have connected, `None` for Unix sockets, to count
+    /// reconnects
+    peers: Mutex<KnownPeers>,
+}
+
+/// The last [`MAX_KNOWN_PEERS`] addresses that have connected
+#[derive(Debug, Default)]
+struct KnownPeers {
+    known: HashSet<Option<IpAddr>>,
+    /// The same addresses, oldest first
+    order: VecDeque<Option<IpAddr>>,
+}
+
+impl KnownPeers {
+    /// Remember `peer`, returning whether it had connected before
+    fn insert(&mut self, peer: Option<IpAddr>) -> bool {
+        if self.known.contains(&peer) {
+            return true;
+        }
+        if self.order.len() >= MAX_KNOWN_PEERS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.known.remove(&oldest);
+        }
+        self.known.insert(peer);
+        self.order.push_back(peer);
+        false
+    }
+}
+
+impl ConnectionStats {
+    pub(crate) fn add_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+        self.totals
+            .bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Note that data arrived just now
+    fn touch(&self) {
+        *self
+            .last_received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+
+    /// Whether nothing has arrived for [`STALE_CLIENT_TIMEOUT`]
+    fn is_stale(&self) -> bool {
+        self.last_received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none_or(|at| at.elapsed() >= STALE_CLIENT_TIMEOUT)
+    }
+
+    /// Count audio dropped to keep the latency down
+    pub(crate) fn add_dropped(&self, ms: u64) {
+        self.totals.dropped_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_codec(&self, codec: Codec) {
+        let _ = self.codec.set(codec);
+    }
+
+    pub(crate) fn set_metadata(&self, metadata: Metadata) {
+        let _ = self.metadata.set(metadata);
+    }
+
+    pub(crate) fn set_session(&self, session: SessionSummary) {
+        let _ = self.session.set(session);
+    }
+
+    pub(crate) fn set_queues(&self, socket_ms: f64, output_ms: f64) {
+        self.socket_ms.store(socket_ms as u64, Ordering::Relaxed);
+        self.output_ms.store(output_ms as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_levels(&self, s16le: &[u8]) {
+        self.levels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(s16le);
+    }
+
+    pub(crate) fn levels(&self) -> LevelStats {
+        *self.levels.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn start_spectrum(&self, audio_config: &AudioConfig) {
+        *self.spectrum.lock().unwrap_or_else(PoisonError::into_inner) = Some(
+            SpectrumAnalyzer::new(audio_config.sample_rate, audio_config.channels),
+        );
+    }
+
+    pub(crate) fn add_spectrum(&self, s16le: &[u8]) {
+        if let Some(analyzer) = self
+            .spectrum
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            analyzer.push(s16le);
+        }
+    }
+
+    fn spectrum(&self) -> Option<Spectrum> {
+        self.spectrum
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .and_then(SpectrumAnalyzer::spectrum)
+    }
+}
 
-    info!("Virtual microphone server starting...");
+impl ClientRegistry {
+    /// Create an empty registry for a receiver expecting `codec` in raw
+    /// streams
+    pub fn with_codec(codec: Codec) -> Self {
+        Self {
+            clients: Mutex::default(),
+            codec,
+            recorder_state: Mutex::default(),
+            totals: Arc::default(),
+            events: Arc::default(),
+        }
+    }
 
-    if verbose {
-        info!("Configuration:");
-        info!("  Host: {host}");
-        info!("  Port: {port}");
-        info!("  Buffer size: {buffer_size} bytes");
-        info!("  Microphone name: {microphone_name}");
-        info!("  FIFO path: {fifo_path}");
+    /// Counters since the receiver started, for [`crate::metrics`]
+    pub fn totals(&self) -> Totals {
+        let totals = &self.totals;
+        Totals {
+            connections: totals.connections.load(Ordering::Relaxed),
+            reconnects: totals.reconnects.load(Ordering::Relaxed),
+            bytes_received: totals.bytes_received.load(Ordering::Relaxed),
+            dropped_ms: totals.dropped_ms.load(Ordering::Relaxed),
+            datagrams_lost: totals.datagrams_lost.load(Ordering::Relaxed),
+        }
     }
 
-    info!("Setting up virtual microphone...");
-    let result = setup_virtual_microphone_with_config(
-        &microphone_name,
-        &fifo_path,
-        &AudioConfig::default(),
-    )?;
-    match result {
-        VirtualMicResult::Success => {
-            info!("Virtual microphone created successfully");
+    /// Count UDP datagrams that were lost or arrived too late to play
+    pub(crate) fn add_lost_datagrams(&self, count: u64) {
+        self.totals
+            .datagrams_lost
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Tell every connected transmitter whether the audio is being recorded
+    ///
+    /// Transmitters connecting later are told on registration.
+    pub(crate) fn set_recorder_state(&self, state: RecorderState) {
+        let mut clients = self.lock();
+        let mut current = self
+            .recorder_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if current.replace(state) == Some(state) {
+            return;
         }
-        VirtualMicResult::Failed => {
-            warn!("Failed to create virtual microphone");
+        match state {
+            RecorderState::Recording => info!("The microphone is being recorded"),
+            RecorderState::Idle => info!("Nothing records the microphone"),
+        }
+        for client in clients.values_mut() {
+            send_recorder_state(client, state);
         }
     }
 
-    // Set up signal handling for cleanup
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    let fifo_path_cleanup = fifo_path.clone();
+    /// List the connected transmitters, ordered by connection id
+    pub fn snapshot(&self) -> Vec<ClientInfo> {
+        let mut clients: Vec<ClientInfo> = self
+            .lock()
+            .values()
+            .map(|client| ClientInfo {
+                id: client.connection.id,
+                peer: client.connection.peer,
+                codec: client.stats.codec.get().unwrap_or(&self.codec).to_string(),
+                connected_for: client.connected_at.elapsed(),
+                bytes_received: client.stats.bytes_received.load(Ordering::Relaxed),
+                socket_queue_ms: client.stats.socket_ms.load(Ordering::Relaxed),
+                output_queue_ms: client.stats.output_ms.load(Ordering::Relaxed),
+                metadata: client.stats.metadata.get().cloned().unwrap_or_default(),
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
 
-    let mut signals = Signals::new([SIGINT])?;
-    thread::spawn(move || {
-        if let Some(sig) = signals.forever().next() {
-            info!("\nReceived signal {sig:?}, cleaning up...");
+    /// Parameters of the stream of the transmitter with connection id `id`
+    ///
+    /// Returns `None` if no such client is connected or its stream has not
+    /// started yet
+    pub fn session(&self, id: u64) -> Option<SessionSummary> {
+        self.lock()
+            .get(&id)
+            .and_then(|client| client.stats.session.get().cloned())
+    }
 
-            // Cleanup virtual microphone
-            if let Err(e) = cleanup_virtual_microphone() {
-                error!("Error cleaning up virtual microphone: {e}");
-            } else {
-                info!("Virtual microphone cleaned up successfully");
-            }
+    /// Per-band levels of the stream of the transmitter with connection id
+    /// `id`
+    ///
+    /// Returns `None` if no such client is connected, the receiver runs
+    /// without `--spectrum`, or not enough audio has arrived yet
+    pub fn spectrum(&self, id: u64) -> Option<Spectrum> {
+        self.lock()
+            .get(&id)
+            .and_then(|client| client.stats.spectrum())
+    }
 
-            // Clean up FIFO
-            if Path::new(&fifo_path_cleanup).exists()
-                && let Err(e) = std::fs::remove_file(&fifo_path_cleanup)
-            {
-                error!("Error removing audio pipe: {e}");
+    /// Disconnect the transmitter with connection id `id`
+    ///
+    /// Returns `true` if such a client was connected
+    pub fn kick(&self, id: u64) -> bool {
+        match self.lock().remove(&id) {
+            Some(client) => {
+                info!("[{}] Disconnected by control command", client.connection);
+                let _ = client.stream.shutdown(Shutdown::Both);
+                self.events.emit(ReceiverEvent::Kicked { id });
+                true
             }
+            None => false,
+        }
+    }
 
-            r.store(false, Ordering::SeqCst);
-            std::process::exit(0);
+    /// Register a new transmitter connection
+    ///
+    /// The connection only becomes the active client once its handler has
+    /// read the stream header and [claimed](Self::claim) it.
+    ///
+    /// # Arguments
+    ///
+    /// * `connection` - Log context of the new connection
+    /// * `stream` - The newly accepted connection
+    ///
+    /// Returns the stats for the handler to update once the new connection is
+    /// registered, or an error if the stream handle could not be cloned
+    pub(crate) fn register(
+        &self,
+        connection: &ConnectionContext,
+        stream: &Stream,
+    ) -> anyhow::Result<Arc<ConnectionStats>> {
+        let handle = stream.try_clone()?;
+        let stats = Arc::new(ConnectionStats {
+            totals: Arc::clone(&self.totals),
+            ..ConnectionStats::default()
+        });
+        self.totals.connections.fetch_add(1, Ordering::Relaxed);
+        let known = self
+            .totals
+            .peers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(connection.peer.map(|peer| peer.ip()));
+        if known {
+            self.totals.reconnects.fetch_add(1, Ordering::Relaxed);
         }
-    });
+        let mut clients = self.lock();
+        let mut client = RegisteredClient {
+            connection: connection.clone(),
+            connected_at: Instant::now(),
+            stream: handle,
+            stats: Arc::clone(&stats),
+            key: None,
+        };
+        let recorder_state = *self
+            .recorder_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(state) = recorder_state {
+            send_recorder_state(&mut client, state);
+        }
+        clients.insert(connection.id, client);
 
-    let bind_addr = format!("{host}:{port}");
-    let listener = TcpListener::bind(&bind_addr)?;
-    info!("Server listening on {bind_addr}...");
-    info!("Virtual microphone '{microphone_name}' created");
-    info!("Remote desktop software can now use this as a microphone input");
-    info!("Press Ctrl+C to stop and cleanup");
+        Ok(stats)
+    }
 
-    for stream in listener.incoming() {
-        if !running.load(Ordering::SeqCst) {
-            break;
+    /// Make the connection `connection` the active client, streaming as
+    /// `key`
+    ///
+    /// An active connection from the same transmitter is shut down and
+    /// removed, as after a reconnect. While a different transmitter streams,
+    /// the connection is refused instead, unless that transmitter has sent
+    /// nothing for [`STALE_CLIENT_TIMEOUT`].
+    pub(crate) fn claim(&self, connection: &ConnectionContext, key: ClientKey) -> Result<(), Busy> {
+        let mut clients = self.lock();
+        let mut replaced = Vec::new();
+        for (id, client) in clients.iter() {
+            let Some(active) = client.key.filter(|_| *id != connection.id) else {
+                continue;
+            };
+            if active != key && !client.stats.is_stale() {
+                return Err(Busy {
+                    active: client.connection.clone(),
+                });
+            }
+            replaced.push(*id);
+        }
+        for id in replaced {
+            if let Some(previous) = clients.remove(&id) {
+                info!(
+                    "[{connection}] Replaces previous connection [{}]",
+                    previous.connection
+                );
+                let _ = previous.stream.shutdown(Shutdown::Both);
+            }
+        }
+        if let Some(client) = clients.get_mut(&connection.id) {
+            client.key = Some(key);
+            client.stats.touch();
+        }
+        Ok(())
+    }
+
+    /// Disconnect every transmitter, when the receiver shuts down
+    pub(crate) fn disconnect_all(&self) {
+        for (_, client) in self.lock().drain() {
+            let _ = client.stream.shutdown(Shutdown::Both);
         }
+    }
 
-        let stream = stream?;
-        let fifo_path = fifo_path.clone();
+    /// Remove the connection with id `id` once its handler has finished
+    pub(crate) fn unregister(&self, id: u64) {
+        self.lock().remove(&id);
+    }
 
-        thread::spawn(move || {
-            if let Err(e) = handle_audio_stream(stream, fifo_path, buffer_size) {
-                error!("Error handling audio stream: {e}");
-            }
-        });
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, RegisteredClient>> {
+        // The map stays consistent even if a holder panicked
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
     }
+}
 
-    Ok(())
+/// Tell a transmitter whether its audio is being recorded
+///
+/// A failed write is left for the connection handler to notice.
+fn send_recorder_state(client: &mut RegisteredClient, state: RecorderState) {
+    if let Err(e) = client.stream.write_all(state.line()) {
+        debug!(
+            "[{}] Failed to send the recorder state: {e}",
+            client.connection
+        );
+    }
+}
+
+/// Start a background thread that logs the queue depth of every connected
+/// transmitter every `interval`, next to the process stats
+fn spawn_queue_report(registry: Weak<ClientRegistry>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            // The receiver has shut down
+            let Some(registry) = registry.upgrade() else {
+                break;
+            };
+            for client in registry.snapshot() {
+                let connection = ConnectionContext {
+                    id: client.id,
+                    peer: client.peer,
+                };
+                info!(
+                    "[{connection}] Received {:.1} MiB at {:.1} kbit/s on average, queued audio: socket {} ms, output {} ms",
+                    client.bytes_received as f64 / (1024.0 * 1024.0),
+                    client.average_bitrate() / 1000.0,
+                    client.socket_queue_ms,
+                    client.output_queue_ms
+                );
+                if let Some(spectrum) = registry.spectrum(client.id) {
+                    info!("[{connection}] Spectrum (dBFS): {spectrum}");
+                }
+            }
+        }
+    });
 }
 
+/// Maximum number of consecutive FIFO recoveries without a successful write
+///
+/// Bounds the retry loop if the sound server keeps rejecting the pipe source.
+const MAX_FIFO_RECOVERIES: u32 = 3;
+
+/// Interval at which the jitter buffer is played out
+const PLAYOUT_PERIOD_MS: u64 = 10;
+
+/// Delay of the playout behind its clock, such as after a write blocked on a
+/// suspended source, beyond which the clock is restarted rather than the
+/// missed audio made up
+const MAX_PLAYOUT_LAG: Duration = Duration::from_millis(200);
+
 /// Handle an individual audio stream from a transmitter client
 ///
 /// This function reads audio data from a TCP stream and writes it to the FIFO pipe
 /// that feeds the virtual microphone.
 ///
+/// If the FIFO is missing (`ENOENT`) or its reader went away (`EPIPE`), e.g.
+/// because something deleted the pipe or unloaded the module, the FIFO and
+/// virtual microphone are recreated and streaming continues. The audio chunk
+/// that failed to write is dropped.
+///
+/// When a command sink is given, the audio goes to it instead of the FIFO.
+///
 /// # Arguments
 ///
-/// * `tcp_stream` - The TCP connection from the transmitter
-/// * `fifo_path` - Path to the FIFO pipe for audio data
-/// * `buffer_size` - Size of the buffer for reading audio data
+/// * `stream` - The TCP or Unix socket connection from the transmitter
+/// * `config` - Receiver configuration (FIFO path, buffer size, microphone settings)
+/// * `connection` - Log context identifying this connection
+/// * `stats` - Traffic and buffer occupancy to keep up to date for the stats
+///   report and the control socket
+/// * `registry` - Connected transmitters, which the connection is claimed in
+///   once its header is read
+/// * `tap` - Transcription tap receiving the decoded audio, if any
+/// * `sink` - Command receiving the audio instead of the FIFO (`--pipe-to`)
+/// * `dump` - Debug dump recording the received bytes (`--debug-dump`)
+/// * `recorder` - Archive of the decoded audio (`--record`)
+/// * `secondary` - Primary receiver watch holding back the audio while the
+///   primary plays it (`--secondary-for`)
+/// * `history` - End of the stream received over earlier connections, to
+///   drop audio a reconnecting transmitter replays twice (`--continuity-ms`)
 ///
 /// Returns `Ok(())` on successful completion, or an error if the stream fails
+#[allow(clippy::too_many_arguments)]
 fn handle_audio_stream(
-    mut tcp_stream: TcpStream,
-    fifo_path: String,
-    buffer_size: usize,
+    mut stream: Stream,
+    config: &ReceiverConfig,
+    connection: &ConnectionContext,
+    stats: &ConnectionStats,
+    registry: &ClientRegistry,
+    tap: Option<&dyn TranscriptionTap>,
+    sink: Option<&CommandSink>,
+    dump: Option<&DumpWriter>,
+    recorder: Option<&Recorder>,
+    secondary: Option<&PrimaryWatch>,
+    history: Option<&StreamHistory>,
 ) -> anyhow::Result<()> {
-    debug!("Starting audio stream handler");
-    debug!("FIFO path: {fifo_path}");
-    debug!("Using buffer size: {buffer_size} bytes");
+    debug!("[{connection}] Starting audio stream handler");
+    if config.uses_virtual_microphone() {
+        debug!("[{connection}] FIFO path: {}", config.fifo_path);
+    }
+    debug!(
+        "[{connection}] Using buffer size: {} bytes",
+        config.buffer_bytes()
+    );
 
     // The FIFO should already exist, created by the virtual microphone setup
-    if !Path::new(&fifo_path).exists() {
-        return Err(anyhow::anyhow!("FIFO pipe does not exist at {fifo_path}"));
+    if config.uses_virtual_microphone() && !Path::new(&config.fifo_path).exists() {
+        warn!(
+            "[{connection}] FIFO pipe missing at {}, recreating it",
+            config.fifo_path
+        );
+        recover_virtual_microphone(config, &config.codec.audio_config()).map_err(|e| {
+            anyhow::anyhow!(
+                "FIFO pipe does not exist at {} and could not be recreated: {e}",
+                config.fifo_path
+            )
+        })?;
     }
 
-    let mut buffer = vec![0u8; buffer_size];
+    // Set by the stream handler once the start of the stream tells its
+    // format, for the jitter buffer's playout thread
+    let stream_format = OnceLock::new();
+    let playout_cell = OnceLock::new();
+    // This thread reloads the microphone, opens the output and restarts
+    // commands for the stream handler, which may not do so itself with
+    // --sandbox
+    let (delegate, delegate_queue) = Delegate::new();
+    // The stream handler hands the output over to the playout thread
+    let (playout_output, playout_outputs) = mpsc::channel();
+    let (stream_format, playout_cell) = (&stream_format, &playout_cell);
 
-    let pipe_writer = thread::spawn(move || -> anyhow::Result<()> {
-        let mut fifo = OpenOptions::new().write(true).open(&fifo_path)?;
+    thread::scope(|scope| {
+        if config.jitter_buffer_ms > 0 {
+            let delegate = delegate.clone();
+            scope.spawn(move || {
+                // Nothing comes if the stream ends before its format is known
+                let Ok(output) = playout_outputs.recv() else {
+                    return;
+                };
+                let (Some(audio_config), Some(playout)) = (stream_format.get(), playout_cell.get())
+                else {
+                    return;
+                };
+                if let Err(e) = play_out(
+                    output,
+                    playout,
+                    &delegate,
+                    config,
+                    audio_config,
+                    connection,
+                    stats,
+                ) {
+                    error!("[{connection}] Jitter buffer playout failed: {e}");
+                    playout.failed.store(true, Ordering::SeqCst);
+                }
+            });
+        }
+        let pipe_writer = scope.spawn(move || -> anyhow::Result<()> {
+            if config.realtime {
+                promote_current_thread(&format!("FIFO writer [{connection}]"));
+            }
+            // Before the first byte from the transmitter is read
+            if config.sandbox {
+                sandbox::restrict_current_thread()?;
+                debug!("[{connection}] Stream handling sandboxed");
+            }
 
-        loop {
-            match tcp_stream.read(&mut buffer) {
-                Ok(0) => {
-                    info!("Client disconnected");
-                    break;
+            // A framed stream announces its format, a raw one is in --codec
+            let mut deframer = None;
+            let mut start = Vec::new();
+            let (header, consumed) = protocol::read_header(&mut stream)?;
+            let key = match &header {
+                Some(header) => ClientKey::Id(header.client_id),
+                None => ClientKey::Raw(connection.peer.map(|peer| peer.ip())),
+            };
+            registry.claim(connection, key)?;
+            let source = header.as_ref().map(|header| header.client_id);
+            let (codec, audio_config) = match (header, consumed) {
+                (Some(header), consumed) => {
+                    stats.add_received(consumed.len());
+                    if let Some(dump) = dump {
+                        dump.record(connection.id, &consumed);
+                    }
+                    debug!(
+                        "[{connection}] Transmitter streams {} ({})",
+                        header.codec,
+                        protocol::describe(&header.config)
+                    );
+                    stats.set_codec(header.codec);
+                    if !header.metadata.is_empty() {
+                        info!("[{connection}] Transmitter {}", header.metadata);
+                        stats.set_metadata(header.metadata);
+                    }
+                    deframer = Some(Deframer::default());
+                    (header.codec, header.config)
+                }
+                (None, consumed) => {
+                    start = consumed;
+                    (config.codec, config.codec.audio_config())
+                }
+            };
+            let audio_config: &AudioConfig = stream_format.get_or_init(|| audio_config);
+
+            if config.uses_virtual_microphone() {
+                delegate.run(move || {
+                    if microphone_has_format(config, audio_config) {
+                        return Ok(());
+                    }
+                    info!(
+                        "[{connection}] Setting up the virtual microphone for {} {}",
+                        audio_config.format.as_pa_format(),
+                        protocol::describe(audio_config)
+                    );
+                    recover_virtual_microphone(config, audio_config)
+                })??;
+            } else if sink.is_some() && *audio_config != config.codec.audio_config() {
+                warn!(
+                    "[{connection}] Transmitter streams {}, the --pipe-to command gets it as is but expects {}",
+                    audio_config.sample_spec(),
+                    config.codec.audio_config().sample_spec()
+                );
+            }
+
+            let mut session =
+                SessionSummary::new(codec, audio_config, config.transport, deframer.is_some());
+            let (wire_rate, wire_frame_size) = config.wire_format();
+            session.add_latency(
+                "read buffer",
+                bytes_to_ms(config.buffer_bytes(), wire_rate, wire_frame_size) as u64,
+            );
+            session.add_latency("pre-buffer", config.prebuffer_ms);
+            session.add_latency("jitter buffer", config.jitter_buffer_ms);
+            info!("[{connection}] Session: {session}");
+            stats.set_session(session);
+            if config.spectrum {
+                stats.start_spectrum(audio_config);
+            }
+
+            let mut buffer = vec![0u8; config.buffer_bytes()];
+            let mut decoded = Vec::new();
+            // Backlog is measured in encoded frames, so FIFO bytes are converted to
+            // the wire frame size before being compared with the socket queue
+            let (frame_rate, frame_size) =
+                codec.wire_format(audio_config.sample_rate, audio_config.channels);
+            let max_backlog =
+                (frame_rate as u64 * config.max_latency_ms / 1000) as usize * frame_size;
+            // Decoded audio held back until the pre-buffer is full
+            let prebuffer_bytes = (audio_config.sample_rate as u64 * config.prebuffer_ms / 1000)
+                as usize
+                * audio_config.frame_size();
+            let mut prebuffer = (prebuffer_bytes > 0).then(Vec::new);
+            // Holds back the start of the stream until it is known how much of it
+            // repeats audio received before the reconnect
+            let min_overlap = (frame_rate as u64 * MIN_OVERLAP_MS / 1000) as usize * frame_size;
+            let mut trimmer =
+                history.and_then(|history| history.trimmer(source, frame_size, min_overlap));
+            let log_overlap = |overlap: usize| {
+                if overlap > 0 {
+                    info!(
+                        "[{connection}] Skipped {} ms of replayed audio already received before the reconnect",
+                        (overlap / frame_size) as u64 * 1000 / frame_rate as u64
+                    );
                 }
-                Ok(n) => {
-                    debug!("Received {n} bytes of audio data, writing to FIFO");
-                    if let Err(e) = fifo.write_all(&buffer[..n]) {
-                        error!("Failed to write to audio pipe: {e}");
+            };
+            let mut transcription = tap.map(|tap| {
+                (
+                    tap,
+                    TapResampler::new(audio_config.sample_rate, audio_config.channels),
+                )
+            });
+
+            // The device's output stream plays for as long as it is kept
+            let (mut output, _device_stream) = delegate.run(move || -> anyhow::Result<_> {
+                Ok(match sink {
+                    Some(sink) => (AudioOutput::Command(sink), None),
+                    None if config.backend == Backend::Null => (
+                        AudioOutput::File(
+                            OpenOptions::new()
+                                .append(true)
+                                .create(true)
+                                .open(&config.fifo_path)?,
+                        ),
+                        None,
+                    ),
+                    None if config.backend == Backend::Device => {
+                        let device = find_output_device(config.output_device.as_ref())?;
+                        let (stream, playback) = open_playback(&device, audio_config)?;
+                        (AudioOutput::Device(playback), Some(stream))
+                    }
+                    None => (
+                        AudioOutput::Fifo(open_fifo(config, audio_config, connection)?),
+                        None,
+                    ),
+                })
+            })??;
+            // With a jitter buffer the stream handler queues the audio, and a
+            // thread of its own writes it to the output
+            let playout = (config.jitter_buffer_ms > 0).then(|| {
+                playout_cell.get_or_init(|| Playout {
+                    buffer: Mutex::new(JitterBuffer::new(
+                        config.jitter_buffer_ms,
+                        config.jitter_min_ms,
+                        config.jitter_max_ms,
+                        audio_config.sample_rate,
+                        audio_config.frame_size(),
+                    )),
+                    ended: AtomicBool::new(false),
+                    failed: AtomicBool::new(false),
+                })
+            });
+            if let Some(playout) = playout {
+                let _ =
+                    playout_output.send(std::mem::replace(&mut output, AudioOutput::Jitter(playout)));
+            }
+            let mut recoveries = 0;
+            let mut invalid = None;
+
+            loop {
+                // The first bytes of a raw stream were read with the header check
+                let read = match start.len() {
+                    0 => stream.read(&mut buffer),
+                    len => {
+                        buffer[..len].copy_from_slice(&start);
+                        start.clear();
+                        Ok(len)
+                    }
+                };
+                match read {
+                    Ok(0) => {
+                        info!("[{connection}] Client disconnected");
+                        // A stream shorter than the pre-buffer is still played
+                        let mut pending = prebuffer.take().unwrap_or_default();
+                        if let Some((rest, overlap)) = trimmer.take().map(OverlapTrimmer::finish) {
+                            log_overlap(overlap);
+                            if let Some(history) = history {
+                                history.push(&rest);
+                            }
+                            if secondary.is_none_or(|watch| watch.is_active()) {
+                                codec.decode(&rest, &mut pending);
+                            }
+                        }
+                        if let Err(e) = output.write_all(&pending, &delegate) {
+                            error!("[{connection}] Failed to write to audio pipe: {e}");
+                        }
+                        break;
+                    }
+                    Ok(n) => {
+                        debug!("[{connection}] Received {n} bytes of audio data, writing to output");
+                        stats.add_received(n);
+                        stats.set_queues(
+                            bytes_to_ms(queued_bytes(&stream), frame_rate, frame_size),
+                            bytes_to_ms(
+                                output.queued_bytes(),
+                                audio_config.sample_rate,
+                                audio_config.frame_size(),
+                            ),
+                        );
+                        if let Some(dump) = dump {
+                            dump.record(connection.id, &buffer[..n]);
+                        }
+                        let payload = match &mut deframer {
+                            Some(deframer) => match deframer.push(&buffer[..n]) {
+                                Ok(audio) => audio,
+                                Err(e) => {
+                                    invalid = Some(e);
+                                    break;
+                                }
+                            },
+                            None => &buffer[..n],
+                        };
+                        // No frame completed yet
+                        if payload.is_empty() {
+                            continue;
+                        }
+                        let trimmed;
+                        let received = match trimmer.as_mut().map(|t| t.push(payload)) {
+                            None => payload,
+                            Some(None) => continue,
+                            Some(Some((rest, overlap))) => {
+                                trimmer = None;
+                                log_overlap(overlap);
+                                trimmed = rest;
+                                &trimmed[..]
+                            }
+                        };
+                        if let Some(history) = history {
+                            history.push(received);
+                        }
+                        // The primary receiver plays the same stream
+                        if secondary.is_some_and(|watch| !watch.is_active()) {
+                            continue;
+                        }
+                        let audio = if matches!(codec, Codec::S16LE | Codec::S24LE | Codec::F32LE) {
+                            received
+                        } else {
+                            decoded.clear();
+                            codec.decode(received, &mut decoded);
+                            &decoded[..]
+                        };
+                        if config.debug_dump.is_some() || config.spectrum {
+                            let converted;
+                            let s16le = if matches!(codec, Codec::S24LE | Codec::F32LE) {
+                                converted = convert_pcm(audio, codec.sample_size(), 2);
+                                &converted[..]
+                            } else {
+                                audio
+                            };
+                            if config.debug_dump.is_some() {
+                                stats.add_levels(s16le);
+                            }
+                            if config.spectrum {
+                                stats.add_spectrum(s16le);
+                            }
+                        }
+                        if let Some(recorder) = recorder {
+                            recorder.write_with(
+                                audio_config,
+                                audio,
+                                |path| {
+                                    let path = path.to_string();
+                                    delegate
+                                        .run(move || create_file(&path))
+                                        .unwrap_or_else(|e| Err(io::Error::other(e)))
+                                },
+                                |path| {
+                                    let path = path.to_string();
+                                    delegate
+                                        .run(move || std::fs::remove_file(path))
+                                        .unwrap_or_else(|e| Err(io::Error::other(e)))
+                                },
+                            );
+                        }
+                        if let Some((tap, resampler)) = &mut transcription {
+                            let samples = if matches!(codec, Codec::S24LE | Codec::F32LE) {
+                                resampler.push(&convert_pcm(audio, codec.sample_size(), 2))
+                            } else {
+                                resampler.push(audio)
+                            };
+                            if !samples.is_empty() {
+                                tap.on_audio(&samples);
+                            }
+                        }
+                        let filled;
+                        let audio = match prebuffer.take() {
+                            Some(mut pending) => {
+                                pending.extend_from_slice(audio);
+                                if pending.len() < prebuffer_bytes {
+                                    prebuffer = Some(pending);
+                                    continue;
+                                }
+                                debug!(
+                                    "[{connection}] Pre-buffered {} ms, starting playback",
+                                    config.prebuffer_ms
+                                );
+                                filled = pending;
+                                &filled[..]
+                            }
+                            None => audio,
+                        };
+                        match output.write_all(audio, &delegate) {
+                            Ok(()) if max_backlog > 0 => {
+                                recoveries = 0;
+                                let pipe_backlog =
+                                    output.queued_bytes() / audio_config.frame_size() * frame_size;
+                                let dropped = fast_forward(
+                                    &mut stream,
+                                    pipe_backlog,
+                                    max_backlog,
+                                    frame_size,
+                                    &mut buffer,
+                                    |dropped| {
+                                        stats.add_received(dropped.len());
+                                        if let Some(dump) = dump {
+                                            dump.record(connection.id, dropped);
+                                        }
+                                        // A corrupt frame ends the connection with the next read
+                                        let dropped = match &mut deframer {
+                                            Some(deframer) => {
+                                                deframer.push(dropped).unwrap_or_default()
+                                            }
+                                            None => dropped,
+                                        };
+                                        if let Some(history) = history {
+                                            history.push(dropped);
+                                        }
+                                    },
+                                )?;
+                                if dropped > 0 {
+                                    let dropped_ms =
+                                        (dropped / frame_size) as u64 * 1000 / frame_rate as u64;
+                                    warn!(
+                                        "[{connection}] Audio backlog exceeded {} ms, dropped {dropped_ms} ms to catch up",
+                                        config.max_latency_ms,
+                                    );
+                                    stats.add_dropped(dropped_ms);
+                                }
+                            }
+                            Ok(()) => recoveries = 0,
+                            Err(e)
+                                if matches!(output, AudioOutput::Fifo(_))
+                                    && is_fifo_lost(&e)
+                                    && recoveries < MAX_FIFO_RECOVERIES =>
+                            {
+                                warn!(
+                                    "[{connection}] Audio pipe lost ({e}), recreating virtual microphone"
+                                );
+                                recoveries += 1;
+                                output = AudioOutput::Fifo(reopen_fifo(
+                                    &delegate,
+                                    config,
+                                    audio_config,
+                                    connection,
+                                )?);
+                            }
+                            Err(e) => {
+                                error!("[{connection}] Failed to write to audio pipe: {e}");
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("[{connection}] Socket read error: {e}");
                         break;
                     }
                 }
-                Err(e) => {
-                    error!("TCP read error: {e}");
-                    break;
+            }
+            if let Some(playout) = playout {
+                playout.ended.store(true, Ordering::SeqCst);
+            }
+            if let Some(dump) = dump {
+                dump.record(connection.id, &[]);
+            }
+            match invalid {
+                Some(e) => Err(anyhow::Error::new(e).context("Invalid stream")),
+                None => Ok(()),
+            }
+        });
+
+        delegate_queue.serve();
+        pipe_writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Pipe writer thread panicked"))?
+    })
+}
+
+/// Destination of the received audio
+enum AudioOutput<'a> {
+    /// FIFO read by the virtual microphone
+    Fifo(File),
+    /// Command started with `--pipe-to`
+    Command(&'a CommandSink),
+    /// Regular file written by [`Backend::Null`]
+    File(File),
+    /// Output device played by [`Backend::Device`]
+    Device(Playback),
+    /// Jitter buffer played out to one of the others by [`play_out`]
+    Jitter(&'a Playout),
+}
+
+impl<'a> AudioOutput<'a> {
+    /// Write `audio`, having `delegate` restart an output command that exited
+    fn write_all(&mut self, audio: &[u8], delegate: &Delegate<'a>) -> std::io::Result<()> {
+        match self {
+            AudioOutput::Fifo(file) | AudioOutput::File(file) => file.write_all(audio),
+            AudioOutput::Device(playback) => {
+                playback.write(audio);
+                Ok(())
+            }
+            AudioOutput::Command(sink) => {
+                if !sink.is_running() {
+                    delegate
+                        .run(|| sink.ensure_running())
+                        .map_err(std::io::Error::other)??;
+                }
+                sink.write_all(audio)
+            }
+            AudioOutput::Jitter(playout) => {
+                if playout.failed.load(Ordering::SeqCst) {
+                    return Err(std::io::Error::other("jitter buffer playout stopped"));
                 }
+                playout.lock().push(audio);
+                Ok(())
             }
         }
-        Ok(())
-    });
+    }
+
+    /// Bytes written but not yet consumed by the reader
+    fn queued_bytes(&self) -> usize {
+        match self {
+            AudioOutput::Fifo(fifo) => queued_bytes(fifo),
+            AudioOutput::Command(sink) => sink.queued_bytes(),
+            AudioOutput::File(_) => 0,
+            AudioOutput::Device(playback) => playback.queued_bytes(),
+            AudioOutput::Jitter(playout) => playout.lock().queued_bytes(),
+        }
+    }
+}
+
+/// Jitter buffer shared by the stream handler and the thread playing it out
+struct Playout {
+    buffer: Mutex<JitterBuffer>,
+    /// The stream has ended; what is left is played at once
+    ended: AtomicBool,
+    /// Writing to the output failed, so the stream handler stops too
+    failed: AtomicBool,
+}
+
+impl Playout {
+    fn lock(&self) -> MutexGuard<'_, JitterBuffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Write the audio of `playout` to `output` at the stream's sample rate,
+/// until the stream has ended and the rest is written
+///
+/// Returns an error if the output cannot be written or recovered.
+fn play_out<'a>(
+    mut output: AudioOutput<'a>,
+    playout: &Playout,
+    delegate: &Delegate<'a>,
+    config: &'a ReceiverConfig,
+    audio_config: &'a AudioConfig,
+    connection: &'a ConnectionContext,
+    stats: &ConnectionStats,
+) -> anyhow::Result<()> {
+    if config.realtime {
+        promote_current_thread(&format!("Jitter buffer playout [{connection}]"));
+    }
+    if config.sandbox {
+        sandbox::restrict_current_thread()?;
+    }
+    let period = Duration::from_millis(PLAYOUT_PERIOD_MS);
+    let frame_size = audio_config.frame_size();
+    let mut clock = Instant::now();
+    let mut ticks = 0u64;
+    let mut played = 0u64;
+    let mut audio = Vec::new();
+    let mut recoveries = 0;
+    // Drops of the jitter buffer already counted in the stats
+    let mut counted_dropped_ms = 0;
+
+    loop {
+        ticks += 1;
+        let deadline = clock + period * ticks as u32;
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        // Counted from the clock, so rounding to whole frames does not drift
+        let due = u64::from(audio_config.sample_rate) * ticks * PLAYOUT_PERIOD_MS / 1000;
+        audio.clear();
+        let ended = playout.ended.load(Ordering::SeqCst);
+        let mut buffer = playout.lock();
+        let adjustment = if ended {
+            audio = buffer.drain();
+            None
+        } else {
+            buffer.pull((due - played) as usize * frame_size, &mut audio)
+        };
+        played = due;
+        match adjustment {
+            Some(Adjustment::Raised(target)) => warn!(
+                "[{connection}] Jitter buffer ran dry, playing silence until it holds {target} ms"
+            ),
+            Some(Adjustment::Lowered(target)) => {
+                debug!("[{connection}] Jitter buffer steady, target lowered to {target} ms")
+            }
+            None => {}
+        }
+        stats.add_dropped(buffer.dropped_ms() - counted_dropped_ms);
+        counted_dropped_ms = buffer.dropped_ms();
+        if ended {
+            let summary = format!(
+                "[{connection}] Jitter buffer: ran dry {} times, {} ms of silence inserted, {} ms dropped, target {} ms",
+                buffer.underruns(),
+                buffer.silence_ms(),
+                buffer.dropped_ms(),
+                buffer.target_ms()
+            );
+            if buffer.underruns() > 0 || buffer.dropped_ms() > 0 {
+                info!("{summary}");
+            } else {
+                debug!("{summary}");
+            }
+        }
+        drop(buffer);
+
+        if !audio.is_empty() {
+            match output.write_all(&audio, delegate) {
+                Ok(()) => recoveries = 0,
+                Err(e)
+                    if matches!(output, AudioOutput::Fifo(_))
+                        && is_fifo_lost(&e)
+                        && recoveries < MAX_FIFO_RECOVERIES =>
+                {
+                    warn!("[{connection}] Audio pipe lost ({e}), recreating virtual microphone");
+                    recoveries += 1;
+                    output =
+                        AudioOutput::Fifo(reopen_fifo(delegate, config, audio_config, connection)?);
+                }
+                Err(e) => return Err(anyhow::anyhow!("Failed to write to audio pipe: {e}")),
+            }
+        }
+        if ended {
+            return Ok(());
+        }
+        if Instant::now() > deadline + MAX_PLAYOUT_LAG {
+            debug!("[{connection}] Jitter buffer playout fell behind, restarting its clock");
+            clock = Instant::now();
+            ticks = 0;
+            played = 0;
+        }
+    }
+}
+
+/// Recreate the virtual microphone and open its FIFO again, on the thread
+/// serving `delegate`, after the FIFO was lost
+fn reopen_fifo<'a>(
+    delegate: &Delegate<'a>,
+    config: &'a ReceiverConfig,
+    audio_config: &'a AudioConfig,
+    connection: &'a ConnectionContext,
+) -> anyhow::Result<File> {
+    delegate.run(|| {
+        recover_virtual_microphone(config, audio_config)?;
+        open_fifo(config, audio_config, connection)
+    })?
+}
+
+/// Drop audio queued in the socket if the total backlog exceeds `max_backlog`
+///
+/// The backlog is the data waiting in the socket receive queue plus
+/// `pipe_backlog`, the data written to the FIFO but not yet read by the sound
+/// server. Data already in the FIFO cannot be taken back, so only the socket
+/// queue is discarded, in whole frames to keep the stream channel-aligned.
+///
+/// # Arguments
+///
+/// * `stream` - Transmitter connection to discard queued audio from
+/// * `pipe_backlog` - Bytes currently pending in the FIFO
+/// * `max_backlog` - Maximum total backlog in bytes
+/// * `frame_size` - Size of one audio frame in bytes
+/// * `scratch` - Buffer used to read and discard data
+/// * `on_drop` - Called with each block of discarded data
+///
+/// # Returns
+///
+/// The number of bytes dropped, or an error if reading from the socket fails
+fn fast_forward(
+    stream: &mut Stream,
+    pipe_backlog: usize,
+    max_backlog: usize,
+    frame_size: usize,
+    scratch: &mut [u8],
+    mut on_drop: impl FnMut(&[u8]),
+) -> std::io::Result<usize> {
+    let socket_backlog = queued_bytes(stream);
+    if socket_backlog + pipe_backlog <= max_backlog {
+        return Ok(0);
+    }
+
+    let mut remaining = socket_backlog - socket_backlog % frame_size;
+    let mut dropped = 0;
+    while remaining > 0 {
+        let len = remaining.min(scratch.len());
+        let n = stream.read(&mut scratch[..len])?;
+        if n == 0 {
+            break;
+        }
+        on_drop(&scratch[..n]);
+        remaining -= n;
+        dropped += n;
+    }
+
+    Ok(dropped)
+}
+
+/// Number of bytes waiting to be read from a socket or pipe (0 if unknown)
+fn queued_bytes(fd: &impl AsRawFd) -> usize {
+    let mut queued: libc::c_int = 0;
+    // SAFETY: FIONREAD writes a single c_int to the valid pointer passed.
+    let rc = unsafe { libc::ioctl(fd.as_raw_fd(), libc::FIONREAD, &mut queued) };
+    if rc == 0 { queued.max(0) as usize } else { 0 }
+}
+
+/// Open the FIFO for writing, recreating it first for audio in `audio_config`
+/// if it has disappeared
+fn open_fifo(
+    config: &ReceiverConfig,
+    audio_config: &AudioConfig,
+    connection: &ConnectionContext,
+) -> anyhow::Result<File> {
+    match OpenOptions::new().write(true).open(&config.fifo_path) {
+        Ok(fifo) => Ok(fifo),
+        Err(e) if is_fifo_lost(&e) => {
+            warn!("[{connection}] Cannot open audio pipe ({e}), recreating virtual microphone");
+            recover_virtual_microphone(config, audio_config)?;
+            Ok(OpenOptions::new().write(true).open(&config.fifo_path)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the virtual microphone takes audio in `audio_config`
+///
+/// If the sound server cannot be asked, the microphone is assumed to match.
+fn microphone_has_format(config: &ReceiverConfig, audio_config: &AudioConfig) -> bool {
+    match source_sample_spec(&config.microphone_name) {
+        Ok(Some(spec)) => spec == audio_config.sample_spec(),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Failed to look up the virtual microphone's format: {e}");
+            true
+        }
+    }
+}
+
+/// Whether an I/O error means the FIFO or its reader is gone
+fn is_fifo_lost(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::NotFound | ErrorKind::BrokenPipe)
+}
+
+/// Add the virtual microphone's FIFO and module to the state file
+fn record_virtual_microphone(config: &ReceiverConfig) {
+    let state = config.state_file.as_ref().map(StateFile::new);
+    record_resource(state.as_ref(), Resource::Fifo(config.fifo_path.clone()));
+    match get_module_id_for_source(&config.microphone_name) {
+        Ok(Some(id)) => record_resource(
+            state.as_ref(),
+            Resource::Module {
+                id,
+                name: config.microphone_name.clone(),
+            },
+        ),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to look up the virtual microphone module: {e}"),
+    }
+}
 
-    pipe_writer
-        .join()
-        .map_err(|_| anyhow::anyhow!("Pipe writer thread panicked"))??;
+/// Hand the files the receiver still changes to `identity` and switch to it
+fn drop_privileges(
+    config: &ReceiverConfig,
+    identity: &Identity,
+    unix_sockets: &[String],
+) -> anyhow::Result<()> {
+    let mut paths: Vec<&str> = unix_sockets.iter().map(String::as_str).collect();
+    // An explicit --fifo-owner is kept
+    if config.pipe_to.is_none() && config.fifo_owner.is_none() {
+        paths.push(&config.fifo_path);
+    }
+    paths.extend(config.control_socket.as_deref());
+    paths.extend(config.state_file.as_deref());
+    for path in paths {
+        identity.hand_over(path)?;
+    }
+    identity.switch_to()
+}
 
+/// Give the FIFO the owner and permissions of `--fifo-owner` and
+/// `--fifo-mode`
+fn apply_fifo_access(config: &ReceiverConfig) -> anyhow::Result<()> {
+    if config.fifo_owner.is_none() && config.fifo_mode.is_none() {
+        return Ok(());
+    }
+    apply_access(&config.fifo_path, config.fifo_owner, config.fifo_mode)?;
+    debug!("Set the owner and permissions of {}", config.fifo_path);
     Ok(())
 }
 
+/// Rename the virtual microphone if a source with its name already exists
+///
+/// Another receiver or program owns that source, so the first free
+/// `<name>_N` is used instead, and the FIFO path gets the same suffix if a
+/// file is in the way there. Loading a second module with the same name
+/// would fail.
+fn use_free_microphone_name(config: &mut ReceiverConfig) {
+    let name = match free_source_name(&config.microphone_name) {
+        Ok(name) => name,
+        Err(e) => {
+            warn!("Failed to list sources: {e}");
+            return;
+        }
+    };
+    let Some(suffix) = name.strip_prefix(config.microphone_name.as_str()) else {
+        return;
+    };
+    if suffix.is_empty() {
+        return;
+    }
+
+    warn!(
+        "A source named '{}' already exists, creating '{name}' instead",
+        config.microphone_name
+    );
+    if Path::new(&config.fifo_path).exists() {
+        config.fifo_path = format!("{}{suffix}", config.fifo_path);
+        info!("Using FIFO {} for '{name}'", config.fifo_path);
+    }
+    config.microphone_name = name;
+}
+
+/// Number of attempts to create the virtual microphone at startup
+const MIC_SETUP_ATTEMPTS: u32 = 5;
+
+/// Delay before the second attempt, doubled for every further one
+const MIC_SETUP_BACKOFF_MIN: Duration = Duration::from_millis(500);
+
+/// Create the virtual microphone, retrying while the failure may be transient
+///
+/// A sound server that is still starting (e.g. right after login) gets a few
+/// seconds to come up. Failures that cannot go away on their own, such as a
+/// name conflict, abort at once. The error explains what the user can do.
+fn create_virtual_microphone(config: &ReceiverConfig) -> anyhow::Result<()> {
+    let mut backoff = MIC_SETUP_BACKOFF_MIN;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let result = setup_virtual_microphone_with_config(
+            &config.microphone_name,
+            &config.fifo_path,
+            &config.codec.audio_config(),
+            config.node_latency.as_deref(),
+        )?;
+        let failure = match result {
+            VirtualMicResult::Success => return Ok(()),
+            VirtualMicResult::Failed(failure) => failure,
+        };
+
+        if !failure.is_transient() || attempt == MIC_SETUP_ATTEMPTS {
+            // Without a reader, opening the FIFO for writing would block
+            let _ = std::fs::remove_file(&config.fifo_path);
+            return Err(anyhow::anyhow!(
+                "Failed to create virtual microphone '{}' after {attempt} attempt(s): {failure}\nHint: {}",
+                config.microphone_name,
+                failure.guidance()
+            ));
+        }
+        warn!(
+            "Failed to create virtual microphone ({failure}), retrying in {backoff:?} ({attempt}/{MIC_SETUP_ATTEMPTS})"
+        );
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+}
+
+/// Create the monitor sink of `--with-monitor-sink` and add its modules to the
+/// state file
+///
+/// A failure is logged rather than returned: the virtual microphone works
+/// without the sink.
+fn create_monitor_sink(config: &ReceiverConfig, audio_config: &AudioConfig) {
+    match setup_monitor_sink(&config.microphone_name, audio_config) {
+        Ok(ids) => {
+            let state = config.state_file.as_ref().map(StateFile::new);
+            for id in ids {
+                record_resource(
+                    state.as_ref(),
+                    Resource::Module {
+                        id,
+                        name: monitor_sink_name(&config.microphone_name),
+                    },
+                );
+            }
+        }
+        Err(e) => warn!("Failed to create monitor sink: {e}"),
+    }
+}
+
+fn record_resource(state: Option<&StateFile>, resource: Resource) {
+    if let Some(state) = state
+        && let Err(e) = state.record(resource)
+    {
+        warn!("Failed to update state file: {e}");
+    }
+}
+
+/// Recreate the FIFO and reload the virtual microphone module for audio in
+/// `audio_config`
+///
+/// Any module still registered under the microphone name is unloaded first
+/// so the source is not loaded twice. If the module cannot be loaded, the new
+/// FIFO is removed again: without a reader, opening it for writing would block.
+/// The monitor sink of `--with-monitor-sink` is recreated along with it, as
+/// its loopback goes away with the old source.
+fn recover_virtual_microphone(
+    config: &ReceiverConfig,
+    audio_config: &AudioConfig,
+) -> anyhow::Result<()> {
+    if config.with_monitor_sink {
+        cleanup_monitor_sink(&config.microphone_name)?;
+    }
+    cleanup_virtual_microphone_with_name(&config.microphone_name)?;
+
+    let result = setup_virtual_microphone_with_config(
+        &config.microphone_name,
+        &config.fifo_path,
+        audio_config,
+        config.node_latency.as_deref(),
+    );
+
+    match result {
+        Ok(VirtualMicResult::Success) => {
+            info!("Virtual microphone '{}' recreated", config.microphone_name);
+            record_virtual_microphone(config);
+            apply_fifo_access(config)?;
+            if config.with_monitor_sink {
+                create_monitor_sink(config, audio_config);
+            }
+            Ok(())
+        }
+        Ok(VirtualMicResult::Failed(failure)) => {
+            let _ = std::fs::remove_file(&config.fifo_path);
+            Err(anyhow::anyhow!(
+                "Failed to reload virtual microphone: {failure} ({})",
+                failure.guidance()
+            ))
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&config.fifo_path);
+            Err(e)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,7 +3048,24 @@ mod tests {
         let server_stream = handle.join().unwrap();
 
         // Test with non-existent FIFO
-        let result = handle_audio_stream(server_stream, "/tmp/non_existent_fifo".to_string(), 4096);
+        let config = ReceiverConfig {
+            fifo_path: "/tmp/non_existent_fifo".to_string(),
+            ..ReceiverConfig::default()
+        };
+        let connection = ConnectionContext { id: 1, peer: None };
+        let result = handle_audio_stream(
+            server_stream.into(),
+            &config,
+            &connection,
+            &ConnectionStats::default(),
+            &ClientRegistry::default(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
 
         assert!(result.is_err());
         assert!(
@@ -235,6 +3074,147 @@ mod tests {
                 .to_string()
                 .contains("FIFO pipe does not exist")
         );
+        // A failed recovery must not leave a reader-less FIFO behind
+        assert!(!std::path::Path::new("/tmp/non_existent_fifo").exists());
+    }
+
+    #[test]
+    fn test_connection_context_display() {
+        let connection = ConnectionContext {
+            id: 7,
+            peer: Some("192.168.1.20:51234".parse().unwrap()),
+        };
+        assert_eq!(connection.to_string(), "conn 7 192.168.1.20:51234");
+
+        let connection = ConnectionContext { id: 8, peer: None };
+        assert_eq!(connection.to_string(), "conn 8");
+    }
+
+    #[test]
+    fn test_is_fifo_lost() {
+        assert!(is_fifo_lost(&std::io::Error::from(ErrorKind::NotFound)));
+        assert!(is_fifo_lost(&std::io::Error::from(ErrorKind::BrokenPipe)));
+        assert!(!is_fifo_lost(&std::io::Error::from(
+            ErrorKind::PermissionDenied
+        )));
+    }
+
+    #[test]
+    fn test_claim_replaces_only_the_same_transmitter() {
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let registry = ClientRegistry::default();
+        let connect = |id: u64| {
+            let client = TcpStream::connect(addr).unwrap();
+            let (server, peer) = listener.accept().unwrap();
+            let connection = ConnectionContext {
+                id,
+                peer: Some(peer),
+            };
+            registry.register(&connection, &server.into()).unwrap();
+            (client, connection)
+        };
+
+        let (mut first_client, first) = connect(1);
+        registry.claim(&first, ClientKey::Id(7)).unwrap();
+
+        // Another transmitter is refused while the first one streams
+        let (_second_client, second) = connect(2);
+        let busy = registry.claim(&second, ClientKey::Id(8)).unwrap_err();
+        assert!(busy.to_string().contains("conn 1"), "{busy}");
+        registry.unregister(2);
+
+        // The same transmitter reconnecting replaces its old connection,
+        // whose peer sees end-of-stream
+        let (_third_client, third) = connect(3);
+        registry.claim(&third, ClientKey::Id(7)).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(first_client.read(&mut buf).unwrap(), 0);
+
+        let clients = registry.snapshot();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].id, 3);
+        assert_eq!(clients[0].peer, third.peer);
+
+        registry.unregister(3);
+        assert!(registry.snapshot().is_empty());
+        assert!(!registry.kick(3));
+    }
+
+    #[test]
+    fn test_known_peers_are_bounded() {
+        let mut peers = KnownPeers::default();
+        let ip = |n: usize| Some(IpAddr::from([10, 0, (n / 256) as u8, (n % 256) as u8]));
+        assert!(!peers.insert(ip(0)));
+        assert!(peers.insert(ip(0)));
+        for n in 1..=MAX_KNOWN_PEERS {
+            assert!(!peers.insert(ip(n)));
+        }
+        // The oldest address was forgotten to make room
+        assert_eq!(peers.known.len(), MAX_KNOWN_PEERS);
+        assert!(!peers.insert(ip(0)));
+        assert!(peers.insert(ip(MAX_KNOWN_PEERS)));
+    }
+
+    #[test]
+    fn test_is_transient_accept_error() {
+        use std::io::Error;
+
+        assert!(is_transient_accept_error(&Error::from(
+            ErrorKind::ConnectionAborted
+        )));
+        assert!(is_transient_accept_error(&Error::from_raw_os_error(
+            libc::EMFILE
+        )));
+        assert!(is_transient_accept_error(&Error::from_raw_os_error(
+            libc::ENFILE
+        )));
+        assert!(!is_transient_accept_error(&Error::from_raw_os_error(
+            libc::EBADF
+        )));
+        assert!(!is_transient_accept_error(&Error::from_raw_os_error(
+            libc::EINVAL
+        )));
+    }
+
+    #[test]
+    fn test_rebind() {
+        let running = AtomicBool::new(true);
+        let addr = "127.0.0.1:0".parse().unwrap();
+        assert!(rebind(&addr, &running).is_some());
+
+        running.store(false, Ordering::SeqCst);
+        assert!(rebind(&addr, &running).is_none());
+    }
+
+    #[test]
+    fn test_fast_forward_drops_socket_backlog() {
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server: Stream = listener.accept().unwrap().0.into();
+        let mut scratch = vec![0u8; 256];
+
+        client.write_all(&[7u8; 1002]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while queued_bytes(&server) < 1002 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Within the limit nothing is dropped
+        assert_eq!(
+            fast_forward(&mut server, 0, 2000, 4, &mut scratch, |_| {}).unwrap(),
+            0
+        );
+        // The FIFO backlog counts toward the limit; whole frames are dropped
+        assert_eq!(
+            fast_forward(&mut server, 1500, 2000, 4, &mut scratch, |_| {}).unwrap(),
+            1000
+        );
+        assert_eq!(queued_bytes(&server), 2);
     }
 
     #[test]
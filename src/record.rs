@@ -0,0 +1,1439 @@
+//! Receiver `--record`: archive the received audio as WAV or FLAC files
+//!
+//! The receiver tees the decoded audio of every stream, in the format the
+//! transmitter negotiated, into a file: FLAC if the path ends in `.flac`,
+//! WAV otherwise. A transmitter that reconnects with the same format
+//! continues the same file. A new file is started when the format changes,
+//! and with `--record-max-mb` or `--record-max-secs` whenever the current
+//! one reaches that size or duration. The first file is the given path,
+//! later ones are numbered: `archive.wav`, `archive-2.wav`, `archive-3.wav`.
+//! A receiver restarted with the same path continues the numbering after
+//! the files already there rather than overwriting them.
+//!
+//! Two limits keep a long recording from filling the disk. With
+//! `--record-max-total-mb`, the oldest files of the recording are deleted
+//! once all of them together would exceed that size, so the most recent
+//! audio is kept. With `--record-min-free-mb`, recording pauses whenever the
+//! file system holds less free space than that, checked every second, and
+//! resumes in a new file once there is enough again.
+//!
+//! Headers are kept valid while recording, so a file is playable up to its
+//! last write even if the receiver is killed: WAV sizes are updated after
+//! every write, and FLAC leaves the total length unknown until the file is
+//! finished.
+//!
+//! The FLAC encoder is a small one: fixed-size blocks of 4096 frames, each
+//! channel coded independently with the best fixed predictor and partitioned
+//! Rice codes, and no MD5 signature, so files come out somewhat larger
+//! than from the reference encoder. 32-bit float streams can only be
+//! recorded as WAV.
+
+use crate::audit::json_string;
+use crate::voice::VoiceGate;
+use crate::{AudioConfig, AudioFormat};
+use log::{debug, error, info};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Frames per FLAC block
+const FLAC_BLOCK_SIZE: usize = 4096;
+
+/// Most Rice partitions per subframe, as a power of two
+const MAX_PARTITION_ORDER: usize = 8;
+
+/// Largest WAV data chunk, as its size is a 32-bit field
+const WAV_MAX_DATA: u64 = u32::MAX as u64 - 36;
+
+/// Size of the WAV header up to the audio data
+const WAV_HEADER_SIZE: u64 = 44;
+
+/// How often the free space is checked with a `min_free_bytes` limit
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the recorder starts a new file, and how much disk it may use
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::record::Rotation;
+///
+/// let rotation = Rotation { max_secs: 3600, ..Rotation::default() };
+/// assert!(rotation.is_due(10_000_000, 3600.0));
+/// assert!(!rotation.is_due(10_000_000, 3599.0));
+/// assert!(!Rotation::default().is_due(u64::MAX, f64::MAX));
+///
+/// // No single file outgrows the total
+/// let capped = Rotation { max_total_bytes: 1 << 30, ..Rotation::default() };
+/// assert!(capped.is_due(1 << 30, 0.0));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Largest file in bytes (0 = no limit)
+    pub max_bytes: u64,
+    /// Longest file in seconds of audio (0 = no limit)
+    pub max_secs: u64,
+    /// Most bytes the files of the recording may take together, the oldest
+    /// being deleted to stay under it (0 = no limit)
+    pub max_total_bytes: u64,
+    /// Free bytes to leave on the file system: recording pauses below it
+    /// (0 = no limit)
+    pub min_free_bytes: u64,
+}
+
+impl Rotation {
+    /// Whether a file of `bytes` holding `secs` of audio is complete
+    pub fn is_due(&self, bytes: u64, secs: f64) -> bool {
+        (self.max_bytes > 0 && bytes >= self.max_bytes)
+            || (self.max_secs > 0 && secs >= self.max_secs as f64)
+            || (self.max_total_bytes > 0 && bytes >= self.max_total_bytes)
+    }
+}
+
+/// Records received audio into a series of WAV or FLAC files
+#[derive(Debug)]
+pub struct Recorder {
+    path: String,
+    flac: bool,
+    rotation: Rotation,
+    /// The directory the files are in, for checking its free space
+    directory: File,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    current: Option<Recording>,
+    /// Number of the next file, 1 being the given path
+    next_file: u32,
+    /// Format the last file failed to open for, not retried until it changes
+    failed: Option<AudioConfig>,
+    /// Completed files still on disk, oldest first, with their sizes
+    completed: VecDeque<(String, u64)>,
+    /// Whether recording is paused for lack of free space
+    low_space: bool,
+    /// When the free space was last checked
+    space_checked: Option<Instant>,
+    /// Holds back the audio between speech (`--record-when voice`)
+    gate: Option<VoiceGate>,
+}
+
+/// The file being written
+#[derive(Debug)]
+struct Recording {
+    path: String,
+    config: AudioConfig,
+    writer: Writer,
+    /// Frames written so far
+    frames: u64,
+    /// Markers set in the file, as offsets in seconds and labels
+    markers: Vec<(f64, String)>,
+}
+
+impl Recorder {
+    /// Record to `path`, or to numbered files next to it once `rotation`
+    /// starts a new one
+    ///
+    /// Nothing is created before the first audio arrives, but the directory
+    /// must exist. Files of an earlier recording to the same path are never
+    /// overwritten: numbering continues after the last of them, and they
+    /// count towards `max_total_bytes`.
+    pub fn new(path: &str, rotation: Rotation) -> anyhow::Result<Self> {
+        let parent = Path::new(path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        if !parent.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Cannot record to {path}: {} is not a directory",
+                parent.display()
+            ));
+        }
+        let directory =
+            File::open(parent).map_err(|e| anyhow::anyhow!("Cannot record to {path}: {e}"))?;
+        let flac = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"));
+        let completed = earlier_files(path, parent)
+            .map_err(|e| anyhow::anyhow!("Cannot record to {path}: {e}"))?;
+        let next_file = completed
+            .back()
+            .and_then(|(last, _)| file_number(path, last))
+            .map_or(1, |last| last + 1);
+        if !completed.is_empty() {
+            info!(
+                "Keeping {} files of an earlier recording to {path}, continuing with {}",
+                completed.len(),
+                numbered_path(path, next_file)
+            );
+        }
+        Ok(Self {
+            path: path.to_string(),
+            flac,
+            rotation,
+            directory,
+            state: Mutex::new(State {
+                next_file,
+                completed,
+                ..State::default()
+            }),
+        })
+    }
+
+    /// Record only what `gate` passes on, see [`crate::voice`]
+    pub fn voice_gate(mut self, gate: VoiceGate) -> Self {
+        self.state
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .gate = Some(gate);
+        self
+    }
+
+    /// Record `pcm` in `config`
+    ///
+    /// Errors are logged rather than returned, so a full disk does not stop
+    /// the stream.
+    pub fn write(&self, config: &AudioConfig, pcm: &[u8]) {
+        self.write_with(config, pcm, create_file, |path| std::fs::remove_file(path));
+    }
+
+    /// Like [`Recorder::write`], creating new files with `create` and
+    /// deleting old ones with `remove`
+    ///
+    /// Threads that may not open files themselves (`--sandbox`) pass a
+    /// `create` and a `remove` that do it on another thread.
+    pub fn write_with(
+        &self,
+        config: &AudioConfig,
+        pcm: &[u8],
+        create: impl FnOnce(&str) -> io::Result<File>,
+        remove: impl FnMut(&str) -> io::Result<()>,
+    ) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let frame_size = config.format.sample_size() * usize::from(config.channels.max(1));
+        let gated;
+        let pcm = match state.gate.as_mut() {
+            Some(gate) => {
+                gated = gate.push(config, pcm);
+                &gated[..]
+            }
+            None => pcm,
+        };
+        let pcm = &pcm[..pcm.len() - pcm.len() % frame_size];
+        if pcm.is_empty() {
+            return;
+        }
+        if self.check_space(&mut state) {
+            return;
+        }
+
+        let complete = state.current.as_ref().is_some_and(|recording| {
+            recording.config != *config
+                || self.rotation.is_due(
+                    recording.writer.len(),
+                    recording.frames as f64 / f64::from(config.sample_rate.max(1)),
+                )
+                || (!self.flac && recording.writer.len() + pcm.len() as u64 > WAV_MAX_DATA)
+        });
+        if complete {
+            self.finish_locked(&mut state);
+        }
+        if state.current.is_none() {
+            if state.failed.as_ref() == Some(config) {
+                return;
+            }
+            match self.open(state.next_file, config, create) {
+                Ok(recording) => {
+                    info!(
+                        "Recording {} to {}",
+                        crate::protocol::describe(config),
+                        recording.path
+                    );
+                    state.next_file += 1;
+                    state.failed = None;
+                    state.current = Some(recording);
+                }
+                Err(e) => {
+                    error!("Recording stopped: {e}");
+                    state.failed = Some(config.clone());
+                    return;
+                }
+            }
+        }
+
+        let Some(recording) = state.current.as_mut() else {
+            return;
+        };
+        if let Err(e) = recording.writer.write(pcm) {
+            error!("Failed to write recording {}: {e}", recording.path);
+        }
+        recording.frames += (pcm.len() / frame_size) as u64;
+        self.remove_oldest(&mut state, remove);
+    }
+
+    /// Complete the current file, when the receiver shuts down
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        self.finish_locked(&mut state);
+    }
+
+    fn finish_locked(&self, state: &mut State) {
+        if let Some(mut recording) = state.current.take() {
+            match recording.writer.finish() {
+                Ok(()) => info!(
+                    "Recorded {:.1} s to {}",
+                    recording.frames as f64 / f64::from(recording.config.sample_rate.max(1)),
+                    recording.path
+                ),
+                Err(e) => error!("Failed to complete recording {}: {e}", recording.path),
+            }
+            state
+                .completed
+                .push_back((recording.path, recording.writer.len()));
+        }
+    }
+
+    /// Whether recording is paused for lack of free space, completing the
+    /// current file when it runs out
+    fn check_space(&self, state: &mut State) -> bool {
+        if self.rotation.min_free_bytes == 0
+            || state
+                .space_checked
+                .is_some_and(|checked| checked.elapsed() < SPACE_CHECK_INTERVAL)
+        {
+            return state.low_space;
+        }
+        state.space_checked = Some(Instant::now());
+        let free = match free_space(&self.directory) {
+            Ok(free) => free,
+            Err(e) => {
+                debug!("Cannot check the free space for {}: {e}", self.path);
+                return state.low_space;
+            }
+        };
+        let low = free < self.rotation.min_free_bytes;
+        if low && !state.low_space {
+            self.finish_locked(state);
+            error!(
+                "Recording paused: {} MiB left next to {}, below the {} MiB to keep free",
+                free >> 20,
+                self.path,
+                self.rotation.min_free_bytes >> 20
+            );
+        } else if !low && state.low_space {
+            info!("Recording resumed: {} MiB free", free >> 20);
+        }
+        state.low_space = low;
+        low
+    }
+
+    /// Delete the oldest completed files while the recording takes more
+    /// than its `max_total_bytes`
+    fn remove_oldest(&self, state: &mut State, mut remove: impl FnMut(&str) -> io::Result<()>) {
+        if self.rotation.max_total_bytes == 0 {
+            return;
+        }
+        let mut total = state
+            .current
+            .as_ref()
+            .map_or(0, |recording| recording.writer.len())
+            + state.completed.iter().map(|(_, size)| size).sum::<u64>();
+        while total > self.rotation.max_total_bytes {
+            let Some((path, size)) = state.completed.pop_front() else {
+                break;
+            };
+            match remove(&path) {
+                Ok(()) => info!("Deleted {path} to keep the recording under its total size"),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => error!("Failed to delete old recording {path}: {e}"),
+            }
+            let markers = markers_path(&path);
+            if Path::new(&markers).exists()
+                && let Err(e) = remove(&markers)
+            {
+                error!("Failed to delete {markers}: {e}");
+            }
+            total -= size;
+        }
+    }
+
+    fn open(
+        &self,
+        number: u32,
+        config: &AudioConfig,
+        create: impl FnOnce(&str) -> io::Result<File>,
+    ) -> anyhow::Result<Recording> {
+        let path = numbered_path(&self.path, number);
+        let file = create(&path).map_err(|e| anyhow::anyhow!("Cannot create {path}: {e}"))?;
+        let writer = if self.flac {
+            Writer::Flac(FlacWriter::new(file, config)?)
+        } else {
+            Writer::Wav(WavWriter::new(file, config)?)
+        };
+        Ok(Recording {
+            path,
+            config: config.clone(),
+            writer,
+            frames: 0,
+            markers: Vec::new(),
+        })
+    }
+
+    /// Add a marker labelled `label` at the end of the file being written,
+    /// to the markers file next to it (see [`markers_path`])
+    ///
+    /// # Returns
+    ///
+    /// The file and the offset of the marker into it in seconds, or `None`
+    /// if no file is open: before audio arrives, or while paused for lack
+    /// of space
+    pub fn mark(&self, label: &str) -> Option<(String, f64)> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let recording = state.current.as_mut()?;
+        let offset = recording.frames as f64 / f64::from(recording.config.sample_rate.max(1));
+        recording.markers.push((offset, label.to_string()));
+        if let Err(e) = write_markers(&recording.path, &recording.markers) {
+            error!("Failed to write {}: {e}", markers_path(&recording.path));
+        }
+        Some((recording.path.clone(), offset))
+    }
+}
+
+/// Create file `path` of a recording, failing if it exists rather than
+/// overwriting an earlier recording
+pub fn create_file(path: &str) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// The files of a recording to `path` already in `directory`, by number,
+/// with their sizes
+fn earlier_files(path: &str, directory: &Path) -> io::Result<VecDeque<(String, u64)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let Some(number) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| file_number(path, name))
+        else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((number, numbered_path(path, number), metadata.len()));
+        }
+    }
+    files.sort_unstable();
+    Ok(files
+        .into_iter()
+        .map(|(_, path, size)| (path, size))
+        .collect())
+}
+
+/// Number of file `name` of a recording to `path`, if it is one
+fn file_number(path: &str, name: &str) -> Option<u32> {
+    let name = Path::new(name).file_name()?.to_str()?;
+    let base = Path::new(path).file_name()?.to_str()?;
+    if name == base {
+        return Some(1);
+    }
+    let stem = Path::new(base).file_stem()?.to_str()?;
+    let number: u32 = name
+        .strip_prefix(stem)?
+        .strip_prefix('-')?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()?;
+    // Only the exact spelling numbered_path gives, not `-02` or `-2.bak`
+    (number >= 2 && Path::new(&numbered_path(path, number)).file_name()? == name).then_some(number)
+}
+
+/// Path of the markers file of recording file `path`
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::record::markers_path;
+///
+/// assert_eq!(markers_path("/srv/mic-2.flac"), "/srv/mic-2.flac.json");
+/// ```
+pub fn markers_path(path: &str) -> String {
+    format!("{path}.json")
+}
+
+/// Rewrite the markers file of recording file `path`, through a temporary
+/// file so it is never seen half written
+fn write_markers(path: &str, markers: &[(f64, String)]) -> io::Result<()> {
+    let lines: Vec<String> = markers
+        .iter()
+        .map(|(offset, label)| {
+            format!(
+                "    {{\"offset_secs\":{offset:.3},\"label\":{}}}",
+                json_string(label)
+            )
+        })
+        .collect();
+    let json = format!(
+        "{{\n  \"recording\": {},\n  \"markers\": [\n{}\n  ]\n}}\n",
+        json_string(path),
+        lines.join(",\n")
+    );
+    let markers = markers_path(path);
+    let temporary = format!("{markers}.tmp");
+    std::fs::write(&temporary, json)?;
+    std::fs::rename(&temporary, &markers)
+}
+
+/// Bytes available to unprivileged users on the file system holding `file`
+fn free_space(file: &File) -> io::Result<u64> {
+    // SAFETY: an all-zero statvfs is a valid value for fstatvfs to fill in
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the descriptor is open and `stats` is valid for the call.
+    if unsafe { libc::fstatvfs(file.as_raw_fd(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Path of file `number` of a recording to `path`
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::record::numbered_path;
+///
+/// assert_eq!(numbered_path("/srv/mic.flac", 1), "/srv/mic.flac");
+/// assert_eq!(numbered_path("/srv/mic.flac", 2), "/srv/mic-2.flac");
+/// assert_eq!(numbered_path("archive", 3), "archive-3");
+/// ```
+pub fn numbered_path(path: &str, number: u32) -> String {
+    if number <= 1 {
+        return path.to_string();
+    }
+    let file = Path::new(path);
+    match (file.file_stem(), file.extension()) {
+        (Some(stem), Some(extension)) => file
+            .with_file_name(format!(
+                "{}-{number}.{}",
+                stem.to_string_lossy(),
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{path}-{number}"),
+    }
+}
+
+#[derive(Debug)]
+enum Writer {
+    Wav(WavWriter),
+    Flac(FlacWriter),
+}
+
+impl Writer {
+    fn write(&mut self, pcm: &[u8]) -> io::Result<()> {
+        match self {
+            Writer::Wav(writer) => writer.write(pcm),
+            Writer::Flac(writer) => writer.write(pcm),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Wav(_) => Ok(()),
+            Writer::Flac(writer) => writer.finish(),
+        }
+    }
+
+    /// Bytes in the file
+    fn len(&self) -> u64 {
+        match self {
+            Writer::Wav(writer) => WAV_HEADER_SIZE + writer.data,
+            Writer::Flac(writer) => writer.written,
+        }
+    }
+}
+
+/// WAV file with a canonical 44-byte header
+#[derive(Debug)]
+struct WavWriter {
+    file: File,
+    /// Bytes of audio data written
+    data: u64,
+}
+
+impl WavWriter {
+    fn new(mut file: File, config: &AudioConfig) -> io::Result<Self> {
+        let sample_size = config.format.sample_size() as u16;
+        let block_align = sample_size * config.channels;
+        // 1 is integer PCM, 3 IEEE float
+        let format_tag: u16 = if config.format == AudioFormat::F32LE {
+            3
+        } else {
+            1
+        };
+        let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&36u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&config.channels.to_le_bytes());
+        header.extend_from_slice(&config.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(config.sample_rate * u32::from(block_align)).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&(sample_size * 8).to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(Self { file, data: 0 })
+    }
+
+    fn write(&mut self, pcm: &[u8]) -> io::Result<()> {
+        self.file.write_all(pcm)?;
+        self.data += pcm.len() as u64;
+        // Keep the sizes current, so the file plays even if it is never
+        // finished
+        let data = self.data.min(WAV_MAX_DATA) as u32;
+        self.file.write_all_at(&(data + 36).to_le_bytes(), 4)?;
+        self.file.write_all_at(&data.to_le_bytes(), 40)
+    }
+}
+
+/// FLAC file, see the module documentation for what the encoder does
+#[derive(Debug)]
+struct FlacWriter {
+    file: File,
+    sample_rate: u32,
+    channels: usize,
+    /// Bits per sample, 16 or 24
+    bits: u32,
+    /// Samples of the block being filled, interleaved
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_frames: u64,
+    /// Smallest and largest encoded frame in bytes
+    frame_sizes: (usize, usize),
+    /// Bytes in the file
+    written: u64,
+}
+
+impl FlacWriter {
+    fn new(file: File, config: &AudioConfig) -> anyhow::Result<Self> {
+        let bits = match config.format {
+            AudioFormat::S16LE => 16,
+            AudioFormat::S24LE => 24,
+            AudioFormat::F32LE => {
+                return Err(anyhow::anyhow!(
+                    "FLAC cannot hold 32-bit float audio, record to a .wav file"
+                ));
+            }
+        };
+        if !(1..=8).contains(&config.channels) {
+            return Err(anyhow::anyhow!(
+                "FLAC holds 1 to 8 channels, not {}",
+                config.channels
+            ));
+        }
+        let mut writer = Self {
+            file,
+            sample_rate: config.sample_rate,
+            channels: usize::from(config.channels),
+            bits,
+            pending: Vec::with_capacity(FLAC_BLOCK_SIZE * usize::from(config.channels)),
+            frame_number: 0,
+            total_frames: 0,
+            frame_sizes: (usize::MAX, 0),
+            written: 0,
+        };
+        let header = writer.header();
+        writer.file.write_all(&header)?;
+        writer.written = header.len() as u64;
+        Ok(writer)
+    }
+
+    /// `fLaC` and the STREAMINFO block
+    fn header(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.put(FLAC_BLOCK_SIZE as u64, 16);
+        bits.put(FLAC_BLOCK_SIZE as u64, 16);
+        let (min_frame, max_frame) = match self.frame_sizes {
+            (usize::MAX, _) => (0, 0),
+            sizes => sizes,
+        };
+        bits.put(min_frame as u64, 24);
+        bits.put(max_frame as u64, 24);
+        bits.put(u64::from(self.sample_rate), 20);
+        bits.put(self.channels as u64 - 1, 3);
+        bits.put(u64::from(self.bits) - 1, 5);
+        bits.put(self.total_frames, 36);
+        // No MD5 signature
+        bits.put(0, 64);
+        bits.put(0, 64);
+        let info = bits.into_bytes();
+
+        let mut header = b"fLaC".to_vec();
+        // Last metadata block, type STREAMINFO
+        header.push(0x80);
+        header.extend_from_slice(&(info.len() as u32).to_be_bytes()[1..]);
+        header.extend_from_slice(&info);
+        header
+    }
+
+    fn write(&mut self, pcm: &[u8]) -> io::Result<()> {
+        let sample_size = self.bits as usize / 8;
+        for sample in pcm.chunks_exact(sample_size) {
+            self.pending.push(match *sample {
+                [a, b] => i32::from(i16::from_le_bytes([a, b])),
+                [a, b, c] => i32::from_le_bytes([0, a, b, c]) >> 8,
+                _ => 0,
+            });
+            if self.pending.len() == FLAC_BLOCK_SIZE * self.channels {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the pending samples as one frame
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let frame = encode_frame(&self.pending, self.channels, self.bits, self.frame_number);
+        self.file.write_all(&frame)?;
+        self.written += frame.len() as u64;
+        self.frame_number += 1;
+        self.total_frames += (self.pending.len() / self.channels) as u64;
+        self.frame_sizes = (
+            self.frame_sizes.0.min(frame.len()),
+            self.frame_sizes.1.max(frame.len()),
+        );
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write the last partial block and the final STREAMINFO
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.file.write_all_at(&self.header(), 0)
+    }
+}
+
+/// Encode interleaved `samples` as one FLAC frame with fixed block size
+/// numbering
+fn encode_frame(samples: &[i32], channels: usize, bits: u32, number: u64) -> Vec<u8> {
+    let block_size = samples.len() / channels;
+    let mut out = BitWriter::default();
+    // Sync code, fixed block size
+    out.put(0xFFF8, 16);
+    let block_code = if block_size == FLAC_BLOCK_SIZE {
+        0b1100
+    } else if block_size <= 256 {
+        0b0110
+    } else {
+        0b0111
+    };
+    out.put(block_code, 4);
+    // Sample rate from STREAMINFO
+    out.put(0, 4);
+    out.put(channels as u64 - 1, 4);
+    out.put(if bits == 24 { 0b110 } else { 0b100 }, 3);
+    out.put(0, 1);
+    out.put_utf8(number);
+    match block_code {
+        0b0110 => out.put(block_size as u64 - 1, 8),
+        0b0111 => out.put(block_size as u64 - 1, 16),
+        _ => {}
+    }
+    let crc = crc8(&out.bytes);
+    out.put(u64::from(crc), 8);
+
+    let mut channel = Vec::with_capacity(block_size);
+    for index in 0..channels {
+        channel.clear();
+        channel.extend(
+            samples
+                .iter()
+                .skip(index)
+                .step_by(channels)
+                .map(|&s| i64::from(s)),
+        );
+        encode_subframe(&mut out, &channel, bits);
+    }
+    out.align();
+    let crc = crc16(&out.bytes);
+    out.put(u64::from(crc), 16);
+    out.into_bytes()
+}
+
+/// Encode one channel of a block as a constant, fixed-predictor or verbatim
+/// subframe, whichever is smallest
+fn encode_subframe(out: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        out.put(0b0000_0000, 8);
+        out.put_signed(samples[0], bits);
+        return;
+    }
+
+    let verbatim = samples.len() as u64 * u64::from(bits);
+    let best = (0..=4usize.min(samples.len() - 1))
+        .map(|order| {
+            let folded: Vec<u64> = fixed_residuals(samples, order)
+                .into_iter()
+                .map(|r| ((r << 1) ^ (r >> 63)) as u64)
+                .collect();
+            let coding = rice_coding(&folded, samples.len(), order);
+            let size = (order * bits as usize) as u64 + 2 + coding.size;
+            (size, order, coding, folded)
+        })
+        .min_by_key(|(size, ..)| *size);
+
+    match best {
+        Some((size, order, coding, folded)) if size < verbatim => {
+            out.put(0b0001_0000 | (order as u64) << 1, 8);
+            for &sample in &samples[..order] {
+                out.put_signed(sample, bits);
+            }
+            // Rice coding with 4-bit parameters
+            out.put(0, 2);
+            out.put(u64::from(coding.order), 4);
+            let mut residuals = folded.iter();
+            for (partition, &parameter) in coding.parameters.iter().enumerate() {
+                let mut count = samples.len() >> coding.order;
+                if partition == 0 {
+                    count -= order;
+                }
+                out.put(u64::from(parameter), 4);
+                for &folded in residuals.by_ref().take(count) {
+                    out.put_unary(folded >> parameter);
+                    out.put(folded & ((1 << parameter) - 1), parameter);
+                }
+            }
+        }
+        _ => {
+            out.put(0b0000_0010, 8);
+            for &sample in samples {
+                out.put_signed(sample, bits);
+            }
+        }
+    }
+}
+
+/// Residuals of the fixed predictor of `order` after its warm-up samples
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Rice coding of a subframe's residuals in `2^order` partitions, each
+/// with its own parameter
+#[derive(Debug)]
+struct RiceCoding {
+    order: u32,
+    parameters: Vec<u32>,
+    /// Coded size in bits, from the partition order on
+    size: u64,
+}
+
+/// Best partitioning of the zigzag-folded residuals of a block of
+/// `block_size` samples after `predictor_order` warm-up samples
+fn rice_coding(folded: &[u64], block_size: usize, predictor_order: usize) -> RiceCoding {
+    let mut best: Option<RiceCoding> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << order;
+        if !block_size.is_multiple_of(partitions) || block_size / partitions <= predictor_order {
+            break;
+        }
+        let length = block_size / partitions;
+        let mut coding = RiceCoding {
+            order: order as u32,
+            parameters: Vec::with_capacity(partitions),
+            size: 4,
+        };
+        let mut start = 0;
+        for partition in 0..partitions {
+            let end = (partition + 1) * length - predictor_order;
+            let (parameter, size) = rice_parameter(&folded[start..end]);
+            coding.parameters.push(parameter);
+            coding.size += 4 + size;
+            start = end;
+        }
+        if best.as_ref().is_none_or(|best| coding.size < best.size) {
+            best = Some(coding);
+        }
+    }
+    best.unwrap_or(RiceCoding {
+        order: 0,
+        parameters: vec![0],
+        size: 8,
+    })
+}
+
+/// Best Rice parameter for one partition and its coded size in bits
+///
+/// Only the parameters next to the binary logarithm of the mean are tried,
+/// which is where the optimum lies.
+fn rice_parameter(folded: &[u64]) -> (u32, u64) {
+    let count = folded.len() as u64;
+    let mean = folded.iter().sum::<u64>() / count.max(1);
+    let estimate = 63 - mean.max(1).leading_zeros();
+    (estimate.saturating_sub(1).min(14)..=(estimate + 1).min(14))
+        .map(|parameter| {
+            let quotients: u64 = folded.iter().map(|&u| u >> parameter).sum();
+            (parameter, count * u64::from(parameter + 1) + quotients)
+        })
+        .min_by_key(|&(_, size)| size)
+        .unwrap_or((0, count))
+}
+
+/// Most significant bit first writer
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits not yet in `bytes`, in the low `pending` bits
+    accumulator: u64,
+    pending: u32,
+}
+
+impl BitWriter {
+    /// Append the low `count` bits of `value`, at most 32 at a time
+    fn put(&mut self, value: u64, count: u32) {
+        if count > 32 {
+            self.put(value >> 32, count - 32);
+            self.put(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        if count == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << count) | (value & ((1 << count) - 1));
+        self.pending += count;
+        while self.pending >= 8 {
+            self.pending -= 8;
+            self.bytes.push((self.accumulator >> self.pending) as u8);
+        }
+        self.accumulator &= (1 << self.pending) - 1;
+    }
+
+    fn put_signed(&mut self, value: i64, count: u32) {
+        self.put(value as u64, count);
+    }
+
+    /// `value` zeros followed by a one
+    fn put_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.put(0, 32);
+            value -= 32;
+        }
+        self.put(1, value as u32 + 1);
+    }
+
+    /// FLAC's UTF-8-like coding of frame numbers
+    fn put_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.put(value, 8);
+            return;
+        }
+        let bits = 64 - value.leading_zeros();
+        // Each continuation byte carries 6 bits, the first byte 6 - n
+        let continuations = (1..=6).find(|&n| bits <= 6 - n + 6 * n).unwrap_or(6);
+        let lead = (0xFF00u64 >> (continuations + 1)) & 0xFF;
+        self.put(lead | (value >> (6 * continuations)), 8);
+        for n in (0..continuations).rev() {
+            self.put(0x80 | ((value >> (6 * n)) & 0x3F), 8);
+        }
+    }
+
+    /// Pad with zeros to a whole byte
+    fn align(&mut self) {
+        if self.pending > 0 {
+            self.put(0, 8 - self.pending);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// CRC-8 of FLAC frame headers, polynomial x^8 + x^2 + x + 1
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16 of FLAC frames, polynomial x^16 + x^15 + x^2 + 1
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("rsonance-record-{}-{name}", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn stereo(format: AudioFormat) -> AudioConfig {
+        AudioConfig {
+            sample_rate: 48000,
+            channels: 2,
+            format,
+        }
+    }
+
+    /// A chirp plus a little noise, which exercises every predictor order
+    fn test_signal(frames: usize, channels: usize) -> Vec<i32> {
+        let mut noise = 1u32;
+        (0..frames * channels)
+            .map(|i| {
+                noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let t = (i / channels) as f64 / 48000.0;
+                let chirp = (std::f64::consts::TAU * (200.0 + 4000.0 * t) * t).sin();
+                (chirp * 12000.0) as i32 + (noise >> 28) as i32 - 8 + (i % channels) as i32
+            })
+            .collect()
+    }
+
+    /// Minimal FLAC decoder for the subset [`encode_frame`] writes
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn get(&mut self, count: u32) -> u64 {
+            (0..count).fold(0, |value, _| {
+                let bit = self.bytes[self.position / 8] >> (7 - self.position % 8) & 1;
+                self.position += 1;
+                value << 1 | u64::from(bit)
+            })
+        }
+
+        fn get_signed(&mut self, count: u32) -> i64 {
+            let value = self.get(count);
+            ((value << (64 - count)) as i64) >> (64 - count)
+        }
+
+        fn get_unary(&mut self) -> u64 {
+            let mut zeros = 0;
+            while self.get(1) == 0 {
+                zeros += 1;
+            }
+            zeros
+        }
+    }
+
+    fn decode_flac(file: &[u8]) -> (u32, usize, u32, u64, Vec<i32>) {
+        assert_eq!(&file[..5], b"fLaC\x80");
+        let mut info = BitReader {
+            bytes: &file[8..42],
+            position: 32 + 48,
+        };
+        let sample_rate = info.get(20) as u32;
+        let channels = info.get(3) as usize + 1;
+        let bits = info.get(5) as u32 + 1;
+        let total = info.get(36);
+        let samples = decode_frames(&file[42..], channels, bits);
+        (sample_rate, channels, bits, total, samples)
+    }
+
+    fn decode_frames(file: &[u8], channels: usize, bits: u32) -> Vec<i32> {
+        let mut samples = Vec::new();
+        let mut start = 0;
+        while start < file.len() {
+            let mut frame = BitReader {
+                bytes: &file[start..],
+                position: 0,
+            };
+            assert_eq!(frame.get(16), 0xFFF8);
+            let block_code = frame.get(4);
+            frame.get(4 + 4 + 3 + 1);
+            // Frame number
+            let lead = frame.get(8) as u8;
+            for _ in 0..lead.leading_ones().saturating_sub(1) {
+                frame.get(8);
+            }
+            let block_size = match block_code {
+                0b1100 => FLAC_BLOCK_SIZE,
+                0b0110 => frame.get(8) as usize + 1,
+                _ => frame.get(16) as usize + 1,
+            };
+            let header_len = frame.position / 8;
+            assert_eq!(frame.get(8) as u8, crc8(&file[start..start + header_len]));
+
+            let mut channel_samples = Vec::new();
+            for _ in 0..channels {
+                let kind = frame.get(8);
+                let mut channel: Vec<i64> = Vec::new();
+                match kind {
+                    0 => channel = vec![frame.get_signed(bits); block_size],
+                    2 => (0..block_size).for_each(|_| channel.push(frame.get_signed(bits))),
+                    _ => {
+                        let order = ((kind >> 1) & 0b111) as usize;
+                        (0..order).for_each(|_| channel.push(frame.get_signed(bits)));
+                        assert_eq!(frame.get(2), 0);
+                        let partition_order = frame.get(4);
+                        let mut parameter = 0;
+                        for i in order..block_size {
+                            if i == order || i % (block_size >> partition_order) == 0 {
+                                parameter = frame.get(4) as u32;
+                            }
+                            let folded = frame.get_unary() << parameter | frame.get(parameter);
+                            let residual = (folded >> 1) as i64 ^ -((folded & 1) as i64);
+                            let s = |back: usize| channel[i - back];
+                            let predicted = match order {
+                                0 => 0,
+                                1 => s(1),
+                                2 => 2 * s(1) - s(2),
+                                3 => 3 * s(1) - 3 * s(2) + s(3),
+                                _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+                            };
+                            channel.push(predicted + residual);
+                        }
+                    }
+                }
+                channel_samples.push(channel);
+            }
+            frame.position = frame.position.div_ceil(8) * 8;
+            let end = frame.position / 8;
+            assert_eq!(frame.get(16) as u16, crc16(&file[start..start + end]));
+            for i in 0..block_size {
+                samples.extend(channel_samples.iter().map(|channel| channel[i] as i32));
+            }
+            start += end + 2;
+        }
+        samples
+    }
+
+    #[test]
+    fn test_flac_round_trip() {
+        for (format, bits) in [(AudioFormat::S16LE, 16), (AudioFormat::S24LE, 24)] {
+            let path = temp_path(&format!("round-trip-{bits}.flac"));
+            let samples: Vec<i32> = test_signal(FLAC_BLOCK_SIZE * 3 + 1000, 2)
+                .into_iter()
+                .map(|s| s << (bits - 16))
+                .collect();
+            let pcm: Vec<u8> = samples
+                .iter()
+                .flat_map(|&s| s.to_le_bytes()[..bits as usize / 8].to_vec())
+                .collect();
+
+            let recorder = Recorder::new(&path, Rotation::default()).unwrap();
+            // Writes that split frames and blocks anywhere
+            for chunk in pcm.chunks(1000 * bits as usize / 8 * 2) {
+                recorder.write(&stereo(format.clone()), chunk);
+            }
+            // Silence codes as constant subframes
+            let silence = vec![0; FLAC_BLOCK_SIZE * 2 * bits as usize / 8];
+            recorder.write(&stereo(format.clone()), &silence);
+            recorder.finish();
+
+            let file = std::fs::read(&path).unwrap();
+            let (rate, channels, decoded_bits, total, decoded) = decode_flac(&file);
+            assert_eq!((rate, channels, decoded_bits), (48000, 2, bits));
+            assert_eq!(total, (samples.len() / 2 + FLAC_BLOCK_SIZE) as u64);
+            assert_eq!(decoded[..samples.len()], samples[..]);
+            assert!(decoded[samples.len()..].iter().all(|&s| s == 0));
+            // Smaller than the PCM it holds
+            assert!(
+                file.len() < pcm.len() * 3 / 4,
+                "{} of {}",
+                file.len(),
+                pcm.len()
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn test_frame_numbers_and_short_blocks() {
+        let mut bits = BitWriter::default();
+        bits.put_utf8(0x7F);
+        bits.put_utf8(0x80);
+        bits.put_utf8(0x1_0000);
+        assert_eq!(
+            bits.into_bytes(),
+            [0x7F, 0xC2, 0x80, 0xF0, 0x90, 0x80, 0x80]
+        );
+
+        // A 5-frame block announces its size in the header
+        let frame = encode_frame(&[1, 2, 3, 4, 5], 1, 16, 300);
+        assert_eq!(decode_frames(&frame, 1, 16), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_wav_rotates_and_changes_files_with_the_format() {
+        let path = temp_path("rotate.wav");
+        let rotation = Rotation {
+            max_secs: 1,
+            ..Rotation::default()
+        };
+        let recorder = Recorder::new(&path, rotation).unwrap();
+        let second = vec![0u8; 48000 * 4];
+        recorder.write(&stereo(AudioFormat::S16LE), &second);
+        recorder.write(&stereo(AudioFormat::S16LE), &second[..400]);
+        let mono = AudioConfig {
+            channels: 1,
+            ..stereo(AudioFormat::S16LE)
+        };
+        recorder.write(&mono, &second[..2 * 480]);
+        drop(recorder);
+
+        let sizes: Vec<(u32, u32)> = (1..=3)
+            .map(|number| {
+                let file = std::fs::read(numbered_path(&path, number)).unwrap();
+                let _ = std::fs::remove_file(numbered_path(&path, number));
+                assert_eq!(&file[..4], b"RIFF");
+                let field = |offset: usize| {
+                    u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+                };
+                assert_eq!(field(4) as usize, file.len() - 8);
+                (u32::from(file[22]), field(40))
+            })
+            .collect();
+        assert_eq!(sizes, [(2, 48000 * 4), (2, 400), (1, 960)]);
+    }
+
+    #[test]
+    fn test_oldest_files_are_deleted_to_stay_under_the_total() {
+        let path = temp_path("total.wav");
+        let second = vec![0u8; 48000 * 4];
+        let file_size = WAV_HEADER_SIZE + second.len() as u64;
+        let rotation = Rotation {
+            max_secs: 1,
+            max_total_bytes: 2 * file_size + 100,
+            ..Rotation::default()
+        };
+        let recorder = Recorder::new(&path, rotation).unwrap();
+        for _ in 0..4 {
+            recorder.write(&stereo(AudioFormat::S16LE), &second);
+            recorder.mark("second");
+        }
+        drop(recorder);
+
+        // Markers go with their file
+        let kept: Vec<(bool, bool)> = (1..=4)
+            .map(|number| {
+                let file = numbered_path(&path, number);
+                (
+                    Path::new(&file).exists(),
+                    Path::new(&markers_path(&file)).exists(),
+                )
+            })
+            .collect();
+        for number in 1..=4 {
+            let _ = std::fs::remove_file(numbered_path(&path, number));
+            let _ = std::fs::remove_file(markers_path(&numbered_path(&path, number)));
+        }
+        assert_eq!(
+            kept,
+            [(false, false), (false, false), (true, true), (true, true)]
+        );
+    }
+
+    #[test]
+    fn test_a_new_recorder_keeps_the_files_of_the_last_one() {
+        let path = temp_path("restart.wav");
+        let second = vec![0u8; 48000 * 4];
+        let file_size = WAV_HEADER_SIZE + second.len() as u64;
+        let rotation = Rotation {
+            max_secs: 1,
+            ..Rotation::default()
+        };
+        let first = Recorder::new(&path, rotation).unwrap();
+        first.write(&stereo(AudioFormat::S16LE), &second);
+        drop(first);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), file_size);
+
+        // The restarted recorder continues with file 2, and counts file 1
+        // towards the total
+        let rotation = Rotation {
+            max_total_bytes: 2 * file_size + 100,
+            ..rotation
+        };
+        let second_run = Recorder::new(&path, rotation).unwrap();
+        assert_eq!(second_run.state.lock().unwrap().next_file, 2);
+        second_run.write(&stereo(AudioFormat::S16LE), &second[..400]);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            file_size,
+            "the first file was overwritten"
+        );
+        second_run.write(&stereo(AudioFormat::S16LE), &second);
+        drop(second_run);
+        assert!(!Path::new(&path).exists());
+        let kept = std::fs::metadata(numbered_path(&path, 2)).unwrap().len();
+        let _ = std::fs::remove_file(numbered_path(&path, 2));
+        assert_eq!(kept, WAV_HEADER_SIZE + 400 + second.len() as u64);
+
+        // Never over an existing file
+        std::fs::write(&path, b"kept").unwrap();
+        assert!(create_file(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"kept");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_markers_are_kept_next_to_the_file() {
+        let path = temp_path("markers.wav");
+        let recorder = Recorder::new(&path, Rotation::default()).unwrap();
+        assert!(recorder.mark("too early").is_none());
+        let half_second = vec![0u8; 48000 * 2];
+        recorder.write(&stereo(AudioFormat::S16LE), &half_second);
+        assert_eq!(recorder.mark("Q&A \"starts\""), Some((path.clone(), 0.5)));
+        recorder.write(&stereo(AudioFormat::S16LE), &half_second);
+        assert_eq!(recorder.mark("end"), Some((path.clone(), 1.0)));
+        drop(recorder);
+
+        let json = std::fs::read_to_string(markers_path(&path)).unwrap();
+        let _ = std::fs::remove_file(markers_path(&path));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            json,
+            format!(
+                "{{\n  \"recording\": {},\n  \"markers\": [\n    \
+                 {{\"offset_secs\":0.500,\"label\":\"Q&A \\\"starts\\\"\"}},\n    \
+                 {{\"offset_secs\":1.000,\"label\":\"end\"}}\n  ]\n}}\n",
+                json_string(&path)
+            )
+        );
+    }
+
+    #[test]
+    fn test_voice_gate_records_only_around_speech() {
+        let path = temp_path("voice.wav");
+        let recorder = Recorder::new(&path, Rotation::default())
+            .unwrap()
+            .voice_gate(VoiceGate::new(Duration::from_millis(100), Duration::ZERO));
+        let config = stereo(AudioFormat::S16LE);
+        recorder.write(&config, &vec![0u8; 48000 * 4]);
+        assert!(recorder.mark("silence").is_none());
+        assert!(!std::path::Path::new(&path).exists());
+
+        let speech: Vec<u8> = (0..48000 * 3 / 10)
+            .flat_map(|i| {
+                let sample = if i % 48 < 24 { 8000i16 } else { -8000 };
+                [sample.to_le_bytes(), sample.to_le_bytes()].concat()
+            })
+            .collect();
+        recorder.write(&config, &speech);
+        recorder.write(&config, &vec![0u8; 48000 * 4]);
+        assert_eq!(recorder.mark("after"), Some((path.clone(), 0.4)));
+        drop(recorder);
+
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(markers_path(&path));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(file.len() - 44, 48000 * 4 * 4 / 10);
+        assert!(file[44..44 + 48000 * 4 / 10].iter().all(|&byte| byte == 0));
+        assert_eq!(file[44 + 48000 * 4 / 10..], speech[..]);
+    }
+
+    #[test]
+    fn test_file_numbers() {
+        assert_eq!(file_number("/srv/mic.flac", "mic.flac"), Some(1));
+        assert_eq!(file_number("/srv/mic.flac", "mic-12.flac"), Some(12));
+        assert_eq!(file_number("archive", "archive-3"), Some(3));
+        assert_eq!(file_number("my.take.wav", "my.take-2.wav"), Some(2));
+        for other in [
+            "mic-02.flac",
+            "mic-1.flac",
+            "mic-2.flac.bak",
+            "mic-x.flac",
+            "mic.wav",
+        ] {
+            assert_eq!(file_number("/srv/mic.flac", other), None, "{other}");
+        }
+    }
+
+    #[test]
+    fn test_recording_pauses_while_the_disk_is_full() {
+        let path = temp_path("space.wav");
+        let rotation = Rotation {
+            min_free_bytes: 1,
+            ..Rotation::default()
+        };
+        let mut recorder = Recorder::new(&path, rotation).unwrap();
+        let audio = vec![0u8; 4800];
+        let write_after_check = |recorder: &mut Recorder, min_free_bytes| {
+            recorder.rotation.min_free_bytes = min_free_bytes;
+            recorder.state.lock().unwrap().space_checked = None;
+            recorder.write(&stereo(AudioFormat::S16LE), &audio);
+        };
+
+        write_after_check(&mut recorder, 1);
+        assert!(recorder.state.lock().unwrap().current.is_some());
+        // Less free space than asked for completes the file and pauses
+        write_after_check(&mut recorder, u64::MAX);
+        assert!(recorder.state.lock().unwrap().current.is_none());
+        assert!(!Path::new(&numbered_path(&path, 2)).exists());
+        // Enough again resumes in a new file
+        write_after_check(&mut recorder, 1);
+        drop(recorder);
+
+        let sizes: Vec<u64> = (1..=2)
+            .map(|number| {
+                let file = numbered_path(&path, number);
+                let size = std::fs::metadata(&file).unwrap().len();
+                let _ = std::fs::remove_file(&file);
+                size
+            })
+            .collect();
+        assert_eq!(sizes, [WAV_HEADER_SIZE + 4800, WAV_HEADER_SIZE + 4800]);
+    }
+
+    #[test]
+    fn test_flac_refuses_float_audio() {
+        let path = temp_path("float.flac");
+        let recorder = Recorder::new(&path, Rotation::default()).unwrap();
+        recorder.write(&stereo(AudioFormat::F32LE), &[0; 64]);
+        assert!(recorder.state.lock().unwrap().current.is_none());
+        let _ = std::fs::remove_file(&path);
+
+        assert!(Recorder::new("/nonexistent/dir/mic.wav", Rotation::default()).is_err());
+    }
+}
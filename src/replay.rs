@@ -0,0 +1,321 @@
+//! Replay of debug dumps into a running receiver
+//!
+//! `rsonance replay <dump>` acts as a transmitter: it connects to a receiver
+//! and sends the recorded bytes with their original timing, so the receiver
+//! decodes, buffers and plays them exactly as it did for the live stream.
+//! Each recorded connection is replayed on its own TCP connection, in order,
+//! which also reproduces transmitters reconnecting or taking over.
+//!
+//! A 16-bit PCM WAV file can be replayed instead of a dump; it is sent as a
+//! single connection, encoded with the chosen codec. Either input can be
+//! sent faster than real time to run long captures through the receiver
+//! quickly.
+
+use crate::codec::{Codec, Encoder, convert_pcm};
+use crate::dump::{DumpReader, DumpRecord};
+use log::{info, warn};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Duration of the blocks a WAV file is sent in
+const WAV_BLOCK_MS: u64 = 10;
+
+/// Configuration for [`run_replay`]
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Path of the dump written with `--debug-dump`
+    pub path: String,
+    /// Receiver address to connect to
+    pub host: String,
+    /// Receiver port to connect to
+    pub port: u16,
+    /// Wire encoding used when replaying a WAV file (dumps carry their own)
+    pub codec: Codec,
+    /// Playback speed relative to real time (0 sends as fast as possible)
+    pub speed: f64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            codec: Codec::S16LE,
+            speed: 1.0,
+        }
+    }
+}
+
+/// Send a debug dump or WAV file to a receiver with its original timing
+///
+/// The receiver has to be started with the `--codec` the dump was recorded
+/// with, or the one given for a WAV file; it is logged at startup.
+///
+/// # Returns
+///
+/// `Ok(())` once the whole input has been sent, or an error if it cannot be
+/// read or the receiver cannot be reached
+pub fn run_replay(config: &ReplayConfig) -> anyhow::Result<()> {
+    if !(config.speed >= 0.0 && config.speed.is_finite()) {
+        return Err(anyhow::anyhow!("Invalid replay speed: {}", config.speed));
+    }
+    let file = File::open(&config.path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {e}", config.path))?;
+    let mut input = BufReader::new(file);
+
+    let (codec, records): (Codec, Box<dyn Iterator<Item = anyhow::Result<DumpRecord>>>) =
+        if input.fill_buf()?.starts_with(b"RIFF") {
+            let records = wav_records(input, config.codec)?;
+            (config.codec, Box::new(records.into_iter().map(Ok)))
+        } else {
+            let reader = DumpReader::new(input)?;
+            (reader.codec(), Box::new(reader))
+        };
+
+    let server_addr = format!("{}:{}", config.host, config.port);
+    info!(
+        "Replaying {} to {server_addr} at {}x speed (receiver must use --codec {codec})",
+        config.path, config.speed
+    );
+
+    let started = Instant::now();
+    let mut current: Option<(u64, TcpStream)> = None;
+    let mut records_sent = 0;
+
+    for record in records {
+        let record = record?;
+        if config.speed > 0.0 {
+            let due = started + record.offset.div_f64(config.speed);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        records_sent += 1;
+
+        if record.data.is_empty() {
+            if current
+                .as_ref()
+                .is_some_and(|(id, _)| *id == record.connection)
+            {
+                info!("Recorded connection {} ended", record.connection);
+                current = None;
+            }
+            continue;
+        }
+
+        let stream = match &mut current {
+            Some((id, stream)) if *id == record.connection => stream,
+            _ => {
+                info!("Replaying recorded connection {}", record.connection);
+                let stream = TcpStream::connect(&server_addr)
+                    .map_err(|e| anyhow::anyhow!("Failed to connect to {server_addr}: {e}"))?;
+                &mut current.insert((record.connection, stream)).1
+            }
+        };
+        stream.write_all(&record.data).map_err(|e| {
+            anyhow::anyhow!(
+                "Receiver closed replayed connection {}: {e}",
+                record.connection
+            )
+        })?;
+    }
+
+    info!("Replay finished: {records_sent} records");
+    Ok(())
+}
+
+/// Format of the audio in a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WavFormat {
+    sample_rate: u32,
+    channels: u16,
+}
+
+/// Read a 16-bit PCM WAV file, returning its format and S16LE samples
+fn read_wav(mut reader: impl Read) -> anyhow::Result<(WavFormat, Vec<u8>)> {
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(anyhow::anyhow!("Not a WAV file"));
+    }
+
+    let mut format = None;
+    loop {
+        let mut header = [0u8; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| anyhow::anyhow!("WAV file has no data chunk"))?;
+        let len = u32::from_le_bytes(header[4..8].try_into()?);
+
+        match &header[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; len as usize];
+                reader.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(anyhow::anyhow!("Truncated WAV format chunk"));
+                }
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                // 0xFFFE is WAVE_FORMAT_EXTENSIBLE, used by many tools for plain PCM too
+                if !matches!(tag, 1 | 0xFFFE) || bits != 16 {
+                    return Err(anyhow::anyhow!(
+                        "Only 16-bit PCM WAV files can be replayed (format {tag:#x}, {bits} bits)"
+                    ));
+                }
+                format = Some(WavFormat {
+                    channels: u16::from_le_bytes([fmt[2], fmt[3]]),
+                    sample_rate: u32::from_le_bytes(fmt[4..8].try_into()?),
+                });
+                if len % 2 == 1 {
+                    reader.read_exact(&mut [0u8; 1])?;
+                }
+            }
+            b"data" => {
+                let format =
+                    format.ok_or_else(|| anyhow::anyhow!("WAV data chunk before format chunk"))?;
+                // Streamed WAVs may leave the length unset, so read up to the end
+                let mut data = Vec::new();
+                reader.take(u64::from(len)).read_to_end(&mut data)?;
+                return Ok((format, data));
+            }
+            _ => {
+                let skip = u64::from(len) + u64::from(len % 2);
+                std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink())?;
+            }
+        }
+    }
+}
+
+/// Split a WAV file into timed blocks encoded with `codec`, as one connection
+fn wav_records(reader: impl Read, codec: Codec) -> anyhow::Result<Vec<DumpRecord>> {
+    let (format, data) = read_wav(reader)?;
+    let expected = codec.audio_config();
+    if matches!(codec, Codec::S16LE | Codec::S24LE | Codec::F32LE)
+        && (format.sample_rate != expected.sample_rate || format.channels != expected.channels)
+    {
+        warn!(
+            "WAV file is {} Hz with {} channels, the receiver plays {} Hz with {} channels",
+            format.sample_rate, format.channels, expected.sample_rate, expected.channels
+        );
+    }
+
+    let frame_size = usize::from(format.channels.max(1)) * 2;
+    let block_frames = (u64::from(format.sample_rate) * WAV_BLOCK_MS / 1000).max(1) as usize;
+    let mut encoder = Encoder::new(codec, format.sample_rate, format.channels);
+    let mut records: Vec<DumpRecord> = data
+        .chunks(block_frames * frame_size)
+        .enumerate()
+        .map(|(index, block)| DumpRecord {
+            connection: 1,
+            offset: Duration::from_millis(index as u64 * WAV_BLOCK_MS),
+            data: encoder.encode(convert_pcm(block, 2, codec.capture_sample_size())),
+        })
+        .filter(|record| !record.data.is_empty())
+        .collect();
+    if let Some(last) = records.last() {
+        records.push(DumpRecord {
+            connection: 1,
+            offset: last.offset,
+            data: Vec::new(),
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::dump::DumpWriter;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_replay_sends_each_connection() {
+        let path = format!("/tmp/rsonance_replay_test_{}", std::process::id());
+        let writer = DumpWriter::create(&path, Codec::S16LE).unwrap();
+        writer.record(1, &[1, 2]);
+        writer.record(1, &[3]);
+        writer.record(1, &[]);
+        writer.record(2, &[4]);
+        drop(writer);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            (0..2)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut received = Vec::new();
+                    stream.read_to_end(&mut received).unwrap();
+                    received
+                })
+                .collect::<Vec<_>>()
+        });
+
+        run_replay(&ReplayConfig {
+            path: path.clone(),
+            port,
+            speed: 0.0,
+            ..ReplayConfig::default()
+        })
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(server.join().unwrap(), vec![vec![1, 2, 3], vec![4]]);
+    }
+
+    fn wav(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 8 + 16 + 8 + 3 + 1 + 8 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt \x10\0\0\0\x01\0");
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * u32::from(channels) * 2).to_le_bytes());
+        wav.extend_from_slice(&(channels * 2).to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        // An odd-sized chunk to skip, padded to an even length
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[test]
+    fn test_read_wav() {
+        let (format, data) = read_wav(&wav(8000, 1, &[1, -2, 3])[..]).unwrap();
+        assert_eq!(
+            format,
+            WavFormat {
+                sample_rate: 8000,
+                channels: 1
+            }
+        );
+        assert_eq!(data, vec![1, 0, 0xFE, 0xFF, 3, 0]);
+
+        let mut float = wav(8000, 1, &[0]);
+        float[20] = 3;
+        assert!(read_wav(&float[..]).is_err());
+        assert!(read_wav(&b"RSDUMP01"[..]).is_err());
+    }
+
+    #[test]
+    fn test_wav_records_are_timed_blocks() {
+        let samples: Vec<i16> = (0..250).collect();
+        let records = wav_records(&wav(10000, 1, &samples)[..], Codec::S16LE).unwrap();
+
+        // 10 ms blocks of 100 frames, then the end of the connection
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].offset, Duration::from_millis(10));
+        assert_eq!(records[1].data.len(), 200);
+        assert_eq!(records[2].data.len(), 100);
+        assert!(records[3].data.is_empty());
+        assert!(records.iter().all(|record| record.connection == 1));
+    }
+}
@@ -0,0 +1,263 @@
+//! Transmitter `--sample-rate` conversion of the captured audio
+//!
+//! A capture device runs at its own rate, commonly 48 kHz, which is then the
+//! rate of the stream. With `--sample-rate` the transmitter converts the
+//! captured audio to the given rate instead, so the stream (and the
+//! receiver's virtual microphone, which follows the stream header) runs at
+//! the rate the applications on the receiving side expect.
+//!
+//! The conversion is band-limited interpolation with a Hann-windowed sinc
+//! of [`TAPS`] taps, looked up in a table of [`PHASES`] fractional
+//! positions. When converting down, the cutoff moves below the new Nyquist
+//! frequency so that content above it is filtered rather than aliased. Each
+//! output frame waits for the half of the filter after it, adding well under
+//! a millisecond of latency.
+
+use std::f64::consts::PI;
+
+/// Input frames each output sample is computed from
+pub const TAPS: usize = 32;
+
+/// Fractional positions the filter is tabulated at
+pub const PHASES: usize = 256;
+
+/// Fraction of the lower Nyquist frequency passed, leaving the rest of the
+/// band for the filter's transition
+const PASSBAND: f64 = 0.92;
+
+/// Converts interleaved little-endian PCM from one sample rate to another
+///
+/// Works on S16LE, packed S24LE or F32LE samples; state carries over between
+/// blocks, so block boundaries are seamless.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::resample::Resampler;
+///
+/// // One second of 48 kHz stereo S16LE silence becomes 44.1 kHz
+/// let mut resampler = Resampler::new(48000, 44100, 2, 2);
+/// let mut out = 0;
+/// for _ in 0..10 {
+///     out += resampler.push(&[0; 48000 * 4 / 10]).len();
+/// }
+/// assert!((44100 * 4 - out) <= 32 * 4, "{out} bytes");
+/// ```
+#[derive(Debug)]
+pub struct Resampler {
+    channels: usize,
+    /// Bytes per sample: 2 for S16LE, 3 for S24LE, 4 for F32LE
+    sample_size: usize,
+    /// Input frames per output frame
+    step: f64,
+    /// Position of the next output frame, in input frames from the start
+    /// of `input`
+    position: f64,
+    /// Interleaved input samples not yet consumed, starting with the frames
+    /// the next output needs from before its position
+    input: Vec<f32>,
+    /// `PHASES + 1` rows of `TAPS` coefficients
+    table: Vec<f32>,
+}
+
+impl Resampler {
+    /// Converter from `from` Hz to `to` Hz for `channels` channels of
+    /// `sample_size`-byte samples
+    pub fn new(from: u32, to: u32, channels: u16, sample_size: usize) -> Self {
+        let channels = usize::from(channels.max(1));
+        let step = f64::from(from.max(1)) / f64::from(to.max(1));
+        let cutoff = PASSBAND * step.recip().min(1.0);
+        let half = (TAPS / 2) as f64;
+        let mut table = Vec::with_capacity((PHASES + 1) * TAPS);
+        for phase in 0..=PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..TAPS)
+                .map(|tap| {
+                    // Distance of the tap from the output position
+                    let x = tap as f64 - (half - 1.0) - fraction;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (PI * cutoff * x).sin() / (PI * cutoff * x)
+                    };
+                    let window = 0.5 + 0.5 * (PI * x / half).cos();
+                    sinc * window
+                })
+                .collect();
+            // Unity gain at DC for every phase
+            let sum: f64 = row.iter().sum();
+            table.extend(row.iter().map(|c| (c / sum) as f32));
+        }
+        Self {
+            channels,
+            sample_size: if matches!(sample_size, 3 | 4) {
+                sample_size
+            } else {
+                2
+            },
+            step,
+            position: (TAPS / 2 - 1) as f64,
+            input: vec![0.0; (TAPS / 2 - 1) * channels],
+            table,
+        }
+    }
+
+    /// Convert a block of audio, returning the output it completes
+    ///
+    /// A trailing partial frame is ignored.
+    pub fn push(&mut self, pcm: &[u8]) -> Vec<u8> {
+        let frame_size = self.channels * self.sample_size;
+        let whole = pcm.len() - pcm.len() % frame_size;
+        let samples: Vec<f32> = pcm[..whole]
+            .chunks_exact(self.sample_size)
+            .map(|sample| match sample {
+                [a, b] => f32::from(i16::from_le_bytes([*a, *b])),
+                [a, b, c] => (i32::from_le_bytes([0, *a, *b, *c]) >> 8) as f32,
+                [a, b, c, d] => f32::from_le_bytes([*a, *b, *c, *d]),
+                _ => 0.0,
+            })
+            .collect();
+        let mut out = Vec::new();
+        for value in self.push_samples(&samples) {
+            self.write_sample(value, &mut out);
+        }
+        out
+    }
+
+    /// Convert a block of interleaved samples, returning the output it
+    /// completes
+    ///
+    /// The samples may be of any scale, such as -1.0 to 1.0; the sample
+    /// size given to [`Resampler::new`] only matters to [`Resampler::push`].
+    /// A trailing partial frame is ignored.
+    pub fn push_samples(&mut self, samples: &[f32]) -> Vec<f32> {
+        let whole = samples.len() - samples.len() % self.channels;
+        self.input.extend_from_slice(&samples[..whole]);
+
+        let frames = self.input.len() / self.channels;
+        let mut out = Vec::new();
+        // The last tap of the next output has to have arrived
+        while (self.position.floor() as usize) + TAPS / 2 < frames {
+            let index = self.position.floor() as usize;
+            let phase = ((self.position - index as f64) * PHASES as f64).round() as usize;
+            let coefficients = &self.table[phase * TAPS..(phase + 1) * TAPS];
+            let first = index + 1 - TAPS / 2;
+            for channel in 0..self.channels {
+                out.push(
+                    coefficients
+                        .iter()
+                        .enumerate()
+                        .map(|(tap, c)| c * self.input[(first + tap) * self.channels + channel])
+                        .sum(),
+                );
+            }
+            self.position += self.step;
+        }
+
+        // Keep the frames the next output still needs
+        let keep_from = (self.position.floor() as usize + 1)
+            .saturating_sub(TAPS / 2)
+            .min(frames);
+        self.input.drain(..keep_from * self.channels);
+        self.position -= keep_from as f64;
+        out
+    }
+
+    fn write_sample(&self, value: f32, out: &mut Vec<u8>) {
+        if self.sample_size == 4 {
+            out.extend_from_slice(&value.to_le_bytes());
+            return;
+        }
+        let value = value.round();
+        if self.sample_size == 3 {
+            let sample = value.clamp(-8_388_608.0, 8_388_607.0) as i32;
+            out.extend_from_slice(&sample.to_le_bytes()[..3]);
+        } else {
+            let sample = value.clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16;
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, frequency: f64, frames: usize) -> Vec<u8> {
+        (0..frames)
+            .flat_map(|n| {
+                let value = 10000.0 * (2.0 * PI * frequency * n as f64 / f64::from(rate)).sin();
+                (value as i16).to_le_bytes()
+            })
+            .collect()
+    }
+
+    fn samples(s16le: &[u8]) -> Vec<f64> {
+        s16le
+            .chunks_exact(2)
+            .map(|b| f64::from(i16::from_le_bytes([b[0], b[1]])))
+            .collect()
+    }
+
+    fn rms(samples: &[f64]) -> f64 {
+        (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_resampled_sine_keeps_frequency_and_level() {
+        let mut resampler = Resampler::new(48000, 44100, 1, 2);
+        // In uneven blocks, to cross block boundaries at every phase
+        let input = sine(48000, 1000.0, 48000);
+        let output: Vec<u8> = input
+            .chunks(2 * 441)
+            .flat_map(|block| resampler.push(block))
+            .collect();
+        let output = samples(&output);
+        assert!(output.len().abs_diff(44100) <= TAPS, "{}", output.len());
+
+        // Compare with a 1 kHz sine generated at 44.1 kHz
+        let expected: Vec<f64> = (0..output.len())
+            .map(|n| 10000.0 * (2.0 * PI * 1000.0 * n as f64 / 44100.0).sin())
+            .collect();
+        let error: Vec<f64> = output[100..]
+            .iter()
+            .zip(&expected[100..])
+            .map(|(a, b)| a - b)
+            .collect();
+        assert!(rms(&error) < 100.0, "error {}", rms(&error));
+    }
+
+    #[test]
+    fn test_downsampling_filters_what_the_new_rate_cannot_carry() {
+        // 20 kHz is above the 11.025 kHz Nyquist frequency of 22.05 kHz
+        let mut resampler = Resampler::new(48000, 22050, 1, 2);
+        let output = samples(&resampler.push(&sine(48000, 20000.0, 48000)));
+        assert!(rms(&output[100..]) < 200.0, "{}", rms(&output[100..]));
+    }
+
+    #[test]
+    fn test_s24le_stereo_channels_stay_apart() {
+        let mut resampler = Resampler::new(44100, 48000, 2, 3);
+        // Left constant at +1000000, right at -1000000
+        let frame = [0x40, 0x42, 0x0f, 0xc0, 0xbd, 0xf0];
+        let input: Vec<u8> = frame.iter().copied().cycle().take(6 * 4410).collect();
+        let output = resampler.push(&input);
+        assert_eq!(output.len() % 6, 0);
+        let settled = &output[6 * 100..6 * 101];
+        let left = i32::from_le_bytes([0, settled[0], settled[1], settled[2]]) >> 8;
+        let right = i32::from_le_bytes([0, settled[3], settled[4], settled[5]]) >> 8;
+        assert_eq!((left, right), (1_000_000, -1_000_000));
+    }
+
+    #[test]
+    fn test_f32le_keeps_full_precision() {
+        let mut resampler = Resampler::new(48000, 96000, 1, 4);
+        let input: Vec<u8> = (0..4800)
+            .flat_map(|_| 0.123_456_7f32.to_le_bytes())
+            .collect();
+        let output = resampler.push(&input);
+        assert_eq!(output.len() % 4, 0);
+        let settled = f32::from_le_bytes(output[4 * 100..4 * 101].try_into().unwrap());
+        assert!((settled - 0.123_456_7).abs() < 1e-6, "{settled}");
+    }
+}
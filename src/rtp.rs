@@ -0,0 +1,312 @@
+//! AES67-compatible RTP multicast output
+//!
+//! A constrained alternative to the TCP stream for feeding pro-audio AoIP
+//! consoles on the local network: 48 kHz linear PCM (L16 or L24) in RTP
+//! packets of 1 ms (48 frames), sent to a multicast group. RTP timestamps are
+//! derived from the system clock as TAI, so when the clock is disciplined by
+//! PTP (e.g. `ptp4l` plus `phc2sys`) they line up with the network's media
+//! clock as the SDP's `a=mediaclk:direct=0` promises.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sample rate required by the AES67 interoperability profile
+pub const AES67_SAMPLE_RATE: u32 = 48000;
+
+/// Frames per packet for the 1 ms packet time AES67 receivers must support
+pub const FRAMES_PER_PACKET: usize = 48;
+
+/// Dynamic RTP payload type announced in the SDP
+const PAYLOAD_TYPE: u8 = 96;
+
+/// Multicast TTL, enough to cross a few routed hops on a studio network
+pub const MULTICAST_TTL: u32 = 16;
+
+/// Current TAI-UTC offset in seconds, applied to the UTC system clock
+const TAI_OFFSET_SECS: u64 = 37;
+
+/// RTP payload encoding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RtpEncoding {
+    /// 16-bit big-endian linear PCM
+    L16,
+    /// 24-bit big-endian linear PCM (the 16-bit capture padded with zeros)
+    #[default]
+    L24,
+}
+
+impl RtpEncoding {
+    /// Size of one encoded sample in bytes
+    pub fn sample_size(&self) -> usize {
+        match self {
+            RtpEncoding::L16 => 2,
+            RtpEncoding::L24 => 3,
+        }
+    }
+}
+
+impl fmt::Display for RtpEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RtpEncoding::L16 => "L16",
+            RtpEncoding::L24 => "L24",
+        })
+    }
+}
+
+impl FromStr for RtpEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "l16" => Ok(RtpEncoding::L16),
+            "l24" => Ok(RtpEncoding::L24),
+            _ => Err(anyhow::anyhow!(
+                "Unknown RTP encoding '{s}' (expected l16 or l24)"
+            )),
+        }
+    }
+}
+
+/// Settings for AES67 RTP output
+#[derive(Debug, Clone)]
+pub struct Aes67Config {
+    /// Multicast group and port to send to (e.g. 239.69.1.10:5004)
+    pub destination: SocketAddr,
+    /// Payload encoding
+    pub encoding: RtpEncoding,
+}
+
+impl Aes67Config {
+    /// Check that the destination is a multicast address
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.destination.ip().is_multicast() {
+            return Err(anyhow::anyhow!(
+                "AES67 destination {} is not a multicast address",
+                self.destination
+            ));
+        }
+        Ok(())
+    }
+
+    /// Session description for receivers that cannot discover the stream themselves
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Address of the sending host, for the `o=` line
+    /// * `channels` - Number of audio channels in the stream
+    /// * `session_id` - Session identifier, e.g. the packetizer's SSRC
+    pub fn sdp(&self, origin: IpAddr, channels: u16, session_id: u32) -> String {
+        let ip_version = if origin.is_ipv4() { "IP4" } else { "IP6" };
+        let group_version = if self.destination.is_ipv4() {
+            "IP4"
+        } else {
+            "IP6"
+        };
+        format!(
+            "v=0\r\n\
+             o=- {session_id} 0 IN {ip_version} {origin}\r\n\
+             s=rsonance\r\n\
+             c=IN {group_version} {}/{MULTICAST_TTL}\r\n\
+             t=0 0\r\n\
+             m=audio {} RTP/AVP {PAYLOAD_TYPE}\r\n\
+             a=rtpmap:{PAYLOAD_TYPE} {}/{AES67_SAMPLE_RATE}/{channels}\r\n\
+             a=ptime:1\r\n\
+             a=ts-refclk:ptp=IEEE1588-2008:traceable\r\n\
+             a=mediaclk:direct=0\r\n",
+            self.destination.ip(),
+            self.destination.port(),
+            self.encoding,
+        )
+    }
+}
+
+/// Splits captured S16LE audio into 1 ms RTP packets
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::rtp::{RtpEncoding, RtpPacketizer};
+///
+/// let mut packetizer = RtpPacketizer::new(RtpEncoding::L16, 2, 1, 0);
+/// // 1 ms of 48 kHz stereo S16LE is 192 bytes
+/// let packets = packetizer.push(&[0u8; 192]);
+/// assert_eq!(packets.len(), 1);
+/// assert_eq!(packets[0].len(), 12 + 192);
+/// ```
+#[derive(Debug)]
+pub struct RtpPacketizer {
+    encoding: RtpEncoding,
+    channels: usize,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    /// S16LE audio not yet filling a whole packet
+    pending: Vec<u8>,
+}
+
+impl RtpPacketizer {
+    /// Create a packetizer whose first packet carries `timestamp`
+    pub fn new(encoding: RtpEncoding, channels: u16, ssrc: u32, timestamp: u32) -> Self {
+        Self {
+            encoding,
+            channels: usize::from(channels.max(1)),
+            ssrc,
+            sequence: 0,
+            timestamp,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Create a packetizer with a time-derived SSRC, timestamped from the system clock
+    pub fn starting_now(encoding: RtpEncoding, channels: u16) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let ssrc = now.subsec_nanos() ^ std::process::id().rotate_left(16);
+        Self::new(encoding, channels, ssrc, media_clock_now())
+    }
+
+    /// Synchronization source identifier of the stream
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// Append S16LE audio and return every complete packet it produced
+    pub fn push(&mut self, s16le: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(s16le);
+
+        let packet_bytes = FRAMES_PER_PACKET * self.channels * 2;
+        let complete = self.pending.len() / packet_bytes;
+        let packets = self
+            .pending
+            .chunks_exact(packet_bytes)
+            .map(|samples| {
+                let packet = build_packet(
+                    self.encoding,
+                    self.sequence,
+                    self.timestamp,
+                    self.ssrc,
+                    samples,
+                );
+                self.sequence = self.sequence.wrapping_add(1);
+                self.timestamp = self.timestamp.wrapping_add(FRAMES_PER_PACKET as u32);
+                packet
+            })
+            .collect();
+        self.pending.drain(..complete * packet_bytes);
+
+        packets
+    }
+}
+
+/// Build one RTP packet from S16LE `samples`
+fn build_packet(
+    encoding: RtpEncoding,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    samples: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(12 + samples.len() / 2 * encoding.sample_size());
+    // Version 2, no padding, no extension, no CSRCs, marker clear
+    packet.extend_from_slice(&[0x80, PAYLOAD_TYPE]);
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(&timestamp.to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+
+    for sample in samples.chunks_exact(2) {
+        let [high, low] = i16::from_le_bytes([sample[0], sample[1]]).to_be_bytes();
+        packet.extend_from_slice(&[high, low]);
+        if encoding == RtpEncoding::L24 {
+            packet.push(0);
+        }
+    }
+    packet
+}
+
+/// RTP timestamp of the 48 kHz media clock at the current TAI time
+fn media_clock_now() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let tai_nanos = now.as_nanos() + u128::from(TAI_OFFSET_SECS) * 1_000_000_000;
+    (tai_nanos * u128::from(AES67_SAMPLE_RATE) / 1_000_000_000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s16le(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_packet_header_and_counters() {
+        let mut packetizer = RtpPacketizer::new(RtpEncoding::L16, 1, 0xDEADBEEF, u32::MAX - 10);
+        let packets = packetizer.push(&[0u8; FRAMES_PER_PACKET * 2 * 2 + 10]);
+        assert_eq!(packets.len(), 2);
+
+        assert_eq!(packets[0][0], 0x80);
+        assert_eq!(packets[0][1], PAYLOAD_TYPE);
+        assert_eq!(&packets[0][2..4], &0u16.to_be_bytes());
+        assert_eq!(&packets[0][4..8], &(u32::MAX - 10).to_be_bytes());
+        assert_eq!(&packets[0][8..12], &0xDEADBEEFu32.to_be_bytes());
+
+        // Sequence and timestamp advance per packet, wrapping around
+        assert_eq!(&packets[1][2..4], &1u16.to_be_bytes());
+        assert_eq!(&packets[1][4..8], &37u32.to_be_bytes());
+
+        // The partial packet is kept for the next push
+        let packets = packetizer.push(&[0u8; FRAMES_PER_PACKET * 2 - 10]);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(&packets[0][2..4], &2u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_payload_encoding() {
+        let samples: Vec<i16> = std::iter::once(0x1234)
+            .chain(std::iter::once(-2))
+            .chain(std::iter::repeat_n(0, FRAMES_PER_PACKET - 2))
+            .collect();
+
+        let packet = RtpPacketizer::new(RtpEncoding::L16, 1, 0, 0).push(&s16le(&samples));
+        assert_eq!(packet[0].len(), 12 + FRAMES_PER_PACKET * 2);
+        assert_eq!(&packet[0][12..16], &[0x12, 0x34, 0xFF, 0xFE]);
+
+        let packet = RtpPacketizer::new(RtpEncoding::L24, 1, 0, 0).push(&s16le(&samples));
+        assert_eq!(packet[0].len(), 12 + FRAMES_PER_PACKET * 3);
+        assert_eq!(&packet[0][12..18], &[0x12, 0x34, 0x00, 0xFF, 0xFE, 0x00]);
+    }
+
+    #[test]
+    fn test_encoding_parse() {
+        assert_eq!("l24".parse::<RtpEncoding>().unwrap(), RtpEncoding::L24);
+        assert_eq!("L16".parse::<RtpEncoding>().unwrap(), RtpEncoding::L16);
+        assert!("l32".parse::<RtpEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_config_validate_and_sdp() {
+        let config = Aes67Config {
+            destination: "239.69.1.10:5004".parse().unwrap(),
+            encoding: RtpEncoding::L24,
+        };
+        config.validate().unwrap();
+
+        let sdp = config.sdp("192.168.1.5".parse().unwrap(), 2, 42);
+        assert!(sdp.contains("o=- 42 0 IN IP4 192.168.1.5\r\n"));
+        assert!(sdp.contains("c=IN IP4 239.69.1.10/16\r\n"));
+        assert!(sdp.contains("m=audio 5004 RTP/AVP 96\r\n"));
+        assert!(sdp.contains("a=rtpmap:96 L24/48000/2\r\n"));
+
+        let unicast = Aes67Config {
+            destination: "192.168.1.20:5004".parse().unwrap(),
+            ..config
+        };
+        assert!(unicast.validate().is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Receiver `--sandbox` syscall filter for the threads reading transmitters
+//!
+//! Each connection's stream is read, deframed and decoded by a writer thread
+//! that handles nothing but untrusted network data. With `--sandbox` that
+//! thread installs a seccomp filter before its first read, so a bug in the
+//! stream handling cannot start programs, open or change files, make new
+//! network connections, or change privileges. The filter allows only the
+//! calls that thread needs to read, decode and play a stream, listed in
+//! `ALLOWED`; any other call fails with `EPERM`.
+//!
+//! The filter only covers the calling thread, and is installed before the
+//! stream header is read. Work the writer still needs, such as setting up
+//! the virtual microphone for the stream's format, opening the output,
+//! recreating a lost virtual microphone or restarting the `--pipe-to`
+//! command, is handed to the connection's other, unfiltered thread through
+//! a [`Delegate`]. That thread never reads from the network itself.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// `AUDIT_ARCH_*` value seccomp reports for the architecture built for
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// System calls numbered from here on are x32 calls on x86_64
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// System calls the filter allows: those the writer thread makes to read,
+/// decode and play a stream, and to pass work to its [`Delegate`]
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED: &[libc::c_long] = &[
+    // Reading the stream and writing the audio out
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_pwrite64,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_lseek,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_close,
+    libc::SYS_shutdown,
+    libc::SYS_ioctl,
+    libc::SYS_fcntl,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_ppoll,
+    // Checking the free space left for --record on its open directory
+    libc::SYS_fstatfs,
+    // Checking on the --pipe-to command
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    // Memory, locks and the thread itself
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_tgkill,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    // Time
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+];
+
+/// Older forms of the calls above that only x86_64 still has
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_LEGACY: &[libc::c_long] = &[libc::SYS_poll, libc::SYS_time];
+#[cfg(target_arch = "aarch64")]
+const ALLOWED_LEGACY: &[libc::c_long] = &[];
+
+/// Install the syscall filter on the calling thread
+///
+/// The filter cannot be removed again and is inherited by threads and
+/// processes the thread starts. Other threads of the process are not
+/// affected.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn restrict_current_thread() -> anyhow::Result<()> {
+    let mut filter = filter_program();
+    let program = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+
+    // SAFETY: prctl with PR_SET_NO_NEW_PRIVS takes no pointers.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to set no_new_privs: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    // SAFETY: `program` points to `filter`, which outlives the call; the
+    // kernel copies the program.
+    let rc = unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &program as *const libc::sock_fprog,
+        )
+    };
+    if rc != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to install the seccomp filter: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn restrict_current_thread() -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "--sandbox is only supported on x86_64 and aarch64"
+    ))
+}
+
+/// Check that a thread can be sandboxed, by sandboxing a short-lived one
+///
+/// Lets the receiver refuse to start rather than fail every connection
+/// when the kernel has no seccomp support.
+pub fn probe() -> anyhow::Result<()> {
+    thread::spawn(restrict_current_thread)
+        .join()
+        .map_err(|_| anyhow::anyhow!("Sandbox probe thread panicked"))?
+}
+
+/// The BPF program: allow the calls in [`ALLOWED`] made through this
+/// architecture's syscall table, deny the rest
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn filter_program() -> Vec<libc::sock_filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    // Offsets of `nr` and `arch` in struct seccomp_data
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    let load = |offset| statement(BPF_LD | BPF_W | BPF_ABS, offset);
+    let deny = statement(
+        BPF_RET | BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    );
+
+    let mut program = vec![
+        load(ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        deny,
+        load(NR_OFFSET),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(BPF_JMP | libc::BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        deny,
+    ]);
+    let allow = statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW);
+    for &nr in ALLOWED.iter().chain(ALLOWED_LEGACY) {
+        program.extend([jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1), allow]);
+    }
+    program.push(deny);
+    program
+}
+
+/// BPF instruction without a jump
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+/// BPF instruction skipping `jt` instructions if its test holds, else `jf`
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// A unit of work handed to the unfiltered thread
+type Job<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Runs work on another thread that a sandboxed thread may not do itself
+///
+/// Created together with a [`DelegateQueue`], which the other thread serves
+/// until every `Delegate` is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::sandbox::Delegate;
+/// use std::thread;
+///
+/// let (delegate, queue) = Delegate::new();
+/// thread::scope(|scope| {
+///     scope.spawn(move || assert_eq!(delegate.run(|| 2 + 2).unwrap(), 4));
+///     queue.serve();
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Delegate<'a> {
+    jobs: Sender<Job<'a>>,
+}
+
+/// Receiving end of a [`Delegate`]
+#[derive(Debug)]
+pub struct DelegateQueue<'a> {
+    jobs: Receiver<Job<'a>>,
+}
+
+impl<'a> Delegate<'a> {
+    /// A delegate and the queue to serve on the other thread
+    pub fn new() -> (Self, DelegateQueue<'a>) {
+        let (jobs, queue) = mpsc::channel();
+        (Self { jobs }, DelegateQueue { jobs: queue })
+    }
+
+    /// Run `work` on the thread serving the queue and wait for its result
+    ///
+    /// Returns an error if that thread no longer serves the queue.
+    pub fn run<T: Send + 'a>(&self, work: impl FnOnce() -> T + Send + 'a) -> anyhow::Result<T> {
+        let (result, reply) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::new(move || {
+                let _ = result.send(work());
+            }))
+            .map_err(|_| anyhow::anyhow!("Delegate thread is gone"))?;
+        reply
+            .recv()
+            .map_err(|_| anyhow::anyhow!("Delegated work did not finish"))
+    }
+}
+
+impl DelegateQueue<'_> {
+    /// Run the delegated work as it arrives, until every [`Delegate`] is
+    /// dropped
+    pub fn serve(self) {
+        for job in self.jobs {
+            job();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_sandboxed_thread_cannot_exec_or_open_files() {
+        let path = std::env::temp_dir().join(format!("rsonance_sandbox_{}", std::process::id()));
+        let sandboxed = thread::spawn({
+            let path = path.clone();
+            move || {
+                restrict_current_thread().unwrap();
+                (
+                    Command::new("true").status().is_err(),
+                    std::fs::write(&path, b"x").is_err(),
+                    std::net::TcpStream::connect("127.0.0.1:9").is_err(),
+                    // SAFETY: io_uring_setup with a null params pointer is
+                    // rejected before the kernel reads it.
+                    unsafe {
+                        libc::syscall(libc::SYS_io_uring_setup, 1, std::ptr::null_mut::<u8>())
+                    } == -1
+                        && std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+                )
+            }
+        });
+        assert_eq!(sandboxed.join().unwrap(), (true, true, true, true));
+        assert!(!path.exists());
+
+        // Other threads are not affected
+        assert!(Command::new("true").status().unwrap().success());
+    }
+}
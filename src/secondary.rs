@@ -0,0 +1,192 @@
+//! Secondary receiver mode for redundant setups
+//!
+//! For broadcast-critical use a transmitter can stream to two receivers at
+//! once with `--duplicate-to`, both feeding the same downstream mixer. The
+//! second receiver runs with `--secondary-for <SOURCE>`: it reads the stream
+//! like any other receiver but only plays it while the primary receiver's
+//! virtual microphone `SOURCE` is missing from the sound server, so the mixer
+//! never gets the audio twice.
+
+use crate::{SoundServerEventStream, spawn_sound_server_watch, subscribe_sound_server};
+use log::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// Interval between checks for the primary receiver's source while the
+/// sound server's events are unavailable
+pub const PRIMARY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks whether the primary receiver's source exists
+///
+/// A background thread checks for the source whenever the sound server
+/// reports a source being added or removed. While it cannot subscribe to
+/// the events, e.g. during a sound server restart, it checks every
+/// [`PRIMARY_CHECK_INTERVAL`] instead. When a check fails, e.g. because
+/// `pactl` timed out, the previous state is kept, so a hiccup does not make
+/// both receivers play at once.
+#[derive(Debug)]
+pub struct PrimaryWatch {
+    primary_present: Arc<AtomicBool>,
+}
+
+impl PrimaryWatch {
+    /// Start watching for the PulseAudio source named `source`
+    pub fn spawn(source: String) -> Self {
+        Self::spawn_with(
+            source,
+            PRIMARY_CHECK_INTERVAL,
+            |source| Ok(crate::source_names(&crate::list_sources()?).contains(&source)),
+            subscribe_sound_server,
+        )
+    }
+
+    /// Start watching with a custom check for the source and event source
+    fn spawn_with(
+        source: String,
+        interval: Duration,
+        probe: impl Fn(&str) -> anyhow::Result<bool> + Send + 'static,
+        subscribe: impl Fn() -> anyhow::Result<SoundServerEventStream> + Send + 'static,
+    ) -> Self {
+        let present = probe(&source).unwrap_or_else(|e| {
+            warn!("Failed to look for the primary source '{source}': {e}");
+            true
+        });
+        if present {
+            info!("Primary source '{source}' is present, holding back audio as the secondary");
+        } else {
+            warn!("Primary source '{source}' is missing, playing audio as the secondary");
+        }
+
+        let primary_present = Arc::new(AtomicBool::new(present));
+        let watched = Arc::downgrade(&primary_present);
+        spawn_sound_server_watch(
+            interval,
+            |event| event.facility == "source" && event.kind != "change",
+            subscribe,
+            move || check(&source, &watched, &probe),
+        );
+
+        Self { primary_present }
+    }
+
+    /// Whether received audio should be played, i.e. the primary is missing
+    pub fn is_active(&self) -> bool {
+        !self.primary_present.load(Ordering::Relaxed)
+    }
+}
+
+/// Look for the primary source and update `watched`
+///
+/// Returns `false` once the [`PrimaryWatch`] has been dropped.
+fn check(
+    source: &str,
+    watched: &Weak<AtomicBool>,
+    probe: &impl Fn(&str) -> anyhow::Result<bool>,
+) -> bool {
+    let Some(primary_present) = watched.upgrade() else {
+        return false;
+    };
+    let present = match probe(source) {
+        Ok(present) => present,
+        Err(e) => {
+            debug!("Failed to look for the primary source '{source}': {e}");
+            return true;
+        }
+    };
+    match (primary_present.swap(present, Ordering::Relaxed), present) {
+        (true, false) => {
+            warn!("Primary source '{source}' disappeared, taking over as the secondary")
+        }
+        (false, true) => info!("Primary source '{source}' is back, holding back audio again"),
+        _ => {}
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SoundServerEvent;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_primary_watch_polls_without_events() {
+        let present = Arc::new(AtomicBool::new(true));
+        let failing = Arc::new(AtomicBool::new(false));
+        let (probe_present, probe_failing) = (Arc::clone(&present), Arc::clone(&failing));
+        let watch = PrimaryWatch::spawn_with(
+            "primary_mic".to_string(),
+            Duration::from_millis(10),
+            move |source| {
+                assert_eq!(source, "primary_mic");
+                if probe_failing.load(Ordering::Relaxed) {
+                    return Err(anyhow::anyhow!("pactl timed out"));
+                }
+                Ok(probe_present.load(Ordering::Relaxed))
+            },
+            || Err(anyhow::anyhow!("no sound server")),
+        );
+        assert!(!watch.is_active());
+
+        present.store(false, Ordering::Relaxed);
+        wait_for(|| watch.is_active());
+
+        // A failed check keeps the secondary playing
+        failing.store(true, Ordering::Relaxed);
+        present.store(true, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(50));
+        assert!(watch.is_active());
+
+        failing.store(false, Ordering::Relaxed);
+        wait_for(|| !watch.is_active());
+    }
+
+    #[test]
+    fn test_primary_watch_checks_on_source_events() {
+        let present = Arc::new(AtomicBool::new(true));
+        let checks = Arc::new(AtomicUsize::new(0));
+        let (probe_present, probe_checks) = (Arc::clone(&present), Arc::clone(&checks));
+        let (events, received) = mpsc::channel();
+        let received = Mutex::new(Some(received));
+        let watch = PrimaryWatch::spawn_with(
+            "primary_mic".to_string(),
+            Duration::from_secs(3600),
+            move |_| {
+                probe_checks.fetch_add(1, Ordering::Relaxed);
+                Ok(probe_present.load(Ordering::Relaxed))
+            },
+            move || {
+                let received = received.lock().unwrap().take().unwrap();
+                Ok(Box::new(received.into_iter()) as SoundServerEventStream)
+            },
+        );
+        let event = |line| SoundServerEvent::parse(line).unwrap();
+        // Once on start and once more after subscribing
+        wait_for(|| checks.load(Ordering::Relaxed) == 2);
+
+        // Other changes do not trigger a check
+        present.store(false, Ordering::Relaxed);
+        events.send(event("Event 'new' on sink-input #4")).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!watch.is_active());
+
+        events.send(event("Event 'remove' on source #2")).unwrap();
+        wait_for(|| watch.is_active());
+        present.store(true, Ordering::Relaxed);
+        events.send(event("Event 'new' on source #5")).unwrap();
+        wait_for(|| !watch.is_active());
+    }
+}
@@ -0,0 +1,247 @@
+//! Summary of the parameters a stream was set up with
+//!
+//! Once a stream's header has been read, the receiver logs one line with
+//! the format, codec, bitrate, transport and the latency each buffering
+//! stage is expected to add, and answers the control socket's `session <id>`
+//! command with it. The transmitter logs the same summary for its side once
+//! connected. Streams are never encrypted, which the summary says so that
+//! nobody has to guess; use a VPN or SSH tunnel across untrusted networks.
+//!
+//! # Format
+//!
+//! On the control socket a summary is one tab-separated line: codec, sample
+//! rate, channels, sample format, bitrate in bit/s, transport, `header` or
+//! `raw`, and the latency stages as comma-separated `stage=ms` pairs (`-`
+//! if there are none).
+
+use crate::AudioConfig;
+use crate::codec::Codec;
+use crate::udp::Transport;
+use std::fmt;
+
+/// Expected latency of one buffering stage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyStage {
+    /// What buffers the audio, e.g. "jitter buffer"
+    pub stage: String,
+    pub ms: u64,
+}
+
+/// Parameters of a stream as agreed between transmitter and receiver
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::Codec;
+/// use rsonance::protocol::StreamHeader;
+/// use rsonance::session::SessionSummary;
+/// use rsonance::udp::Transport;
+///
+/// let header = StreamHeader::new(Codec::S16LE, 48000, 2);
+/// let mut summary = SessionSummary::new(Codec::S16LE, &header.config, Transport::Tcp, true);
+/// summary.add_latency("jitter buffer", 60);
+/// assert_eq!(summary.bitrate, 1_536_000);
+/// assert_eq!(
+///     summary.to_string(),
+///     "s16le at 48000 Hz, 2 channel(s), s16le, 1536.0 kbit/s over tcp (announced in the header), unencrypted, latency budget 60 ms (jitter buffer 60 ms)"
+/// );
+/// assert_eq!(SessionSummary::from_line(&summary.to_line()).unwrap(), summary);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// Wire encoding of the stream
+    pub codec: Codec,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Sample format of the decoded audio (e.g. "s16le")
+    pub format: String,
+    /// Audio on the wire in bits per second, without framing
+    pub bitrate: u64,
+    pub transport: Transport,
+    /// Whether the stream announced its format in a header; a raw stream is
+    /// taken to be in the receiver's `--codec`
+    pub announced: bool,
+    /// Expected latency of the buffering stages on this side, in order
+    pub latency: Vec<LatencyStage>,
+}
+
+impl SessionSummary {
+    /// Summary of a stream in `codec` decoding to `config`, without latency
+    /// stages
+    pub fn new(codec: Codec, config: &AudioConfig, transport: Transport, announced: bool) -> Self {
+        let (frame_rate, frame_size) = codec.wire_format(config.sample_rate, config.channels);
+        Self {
+            codec,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+            format: config.format.as_pa_format().to_string(),
+            bitrate: u64::from(frame_rate) * frame_size as u64 * 8,
+            transport,
+            announced,
+            latency: Vec::new(),
+        }
+    }
+
+    /// Add a buffering stage expected to delay the audio by `ms`, unless it
+    /// is 0
+    pub fn add_latency(&mut self, stage: &str, ms: u64) {
+        if ms > 0 {
+            self.latency.push(LatencyStage {
+                stage: stage.to_string(),
+                ms,
+            });
+        }
+    }
+
+    /// Expected latency of all stages together, in milliseconds
+    pub fn latency_ms(&self) -> u64 {
+        self.latency.iter().map(|stage| stage.ms).sum()
+    }
+
+    /// Encode as a tab-separated control protocol line (without newline)
+    pub fn to_line(&self) -> String {
+        let latency = if self.latency.is_empty() {
+            "-".to_string()
+        } else {
+            self.latency
+                .iter()
+                .map(|stage| format!("{}={}", stage.stage, stage.ms))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{latency}",
+            self.codec,
+            self.sample_rate,
+            self.channels,
+            self.format,
+            self.bitrate,
+            self.transport,
+            if self.announced { "header" } else { "raw" }
+        )
+    }
+
+    /// Decode a line produced by [`SessionSummary::to_line`]
+    ///
+    /// # Returns
+    ///
+    /// The decoded summary, or an error if the line is malformed
+    pub fn from_line(line: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [
+            codec,
+            sample_rate,
+            channels,
+            format,
+            bitrate,
+            transport,
+            announced,
+            latency,
+        ] = fields[..]
+        else {
+            return Err(anyhow::anyhow!("Malformed session line: {line}"));
+        };
+
+        let latency = match latency {
+            "-" => Vec::new(),
+            latency => latency
+                .split(',')
+                .map(|stage| {
+                    let (stage, ms) = stage
+                        .split_once('=')
+                        .ok_or_else(|| anyhow::anyhow!("Malformed latency stage: {stage}"))?;
+                    Ok(LatencyStage {
+                        stage: stage.to_string(),
+                        ms: ms.parse()?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+        };
+        Ok(Self {
+            codec: codec.parse()?,
+            sample_rate: sample_rate.parse()?,
+            channels: channels.parse()?,
+            format: format.to_string(),
+            bitrate: bitrate.parse()?,
+            transport: transport.parse()?,
+            announced: match announced {
+                "header" => true,
+                "raw" => false,
+                _ => return Err(anyhow::anyhow!("Malformed session line: {line}")),
+            },
+            latency,
+        })
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} Hz, {} channel(s), {}, {:.1} kbit/s over {} ({}), unencrypted, latency budget {} ms",
+            self.codec,
+            self.sample_rate,
+            self.channels,
+            self.format,
+            self.bitrate as f64 / 1000.0,
+            self.transport,
+            if self.announced {
+                "announced in the header"
+            } else {
+                "raw, format assumed"
+            },
+            self.latency_ms()
+        )?;
+        if !self.latency.is_empty() {
+            let stages: Vec<String> = self
+                .latency
+                .iter()
+                .map(|stage| format!("{} {} ms", stage.stage, stage.ms))
+                .collect();
+            write!(f, " ({})", stages.join(" + "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_line_round_trip() {
+        let mut summary = SessionSummary::new(
+            Codec::G711U,
+            &Codec::G711U.audio_config(),
+            Transport::Udp,
+            false,
+        );
+        assert_eq!(summary.bitrate, 64_000);
+        assert_eq!(
+            SessionSummary::from_line(&summary.to_line()).unwrap(),
+            summary
+        );
+
+        summary.add_latency("read buffer", 20);
+        summary.add_latency("pre-buffer", 0);
+        summary.add_latency("jitter buffer", 60);
+        assert_eq!(summary.latency_ms(), 80);
+        assert!(
+            summary
+                .to_string()
+                .ends_with("latency budget 80 ms (read buffer 20 ms + jitter buffer 60 ms)")
+        );
+        assert_eq!(
+            SessionSummary::from_line(&summary.to_line()).unwrap(),
+            summary
+        );
+
+        assert!(SessionSummary::from_line("s16le\t48000\t2").is_err());
+        assert!(
+            SessionSummary::from_line("s16le\t48000\t2\ts16le\t1536000\ttcp\tmaybe\t-").is_err()
+        );
+        assert!(
+            SessionSummary::from_line("s16le\t48000\t2\ts16le\t1536000\ttcp\traw\tbuffer").is_err()
+        );
+    }
+}
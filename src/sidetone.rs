@@ -0,0 +1,181 @@
+//! Sidetone: local playback of the captured microphone on the transmitter
+//!
+//! Captured audio is copied into a short queue that an output stream on the
+//! default playback device drains, attenuated by a fixed gain. The output uses
+//! the capture stream's sample rate and channel count, so no resampling is
+//! needed; devices that cannot play that format simply get no sidetone.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use log::{error, info};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Maximum amount of audio queued for sidetone playback, in milliseconds
+///
+/// Anything older is dropped so the speaker never hears themselves late.
+const MAX_QUEUE_MS: u32 = 50;
+
+/// Handle used by the capture callback to feed the sidetone output
+#[derive(Debug, Clone)]
+pub struct SidetoneTap {
+    queue: Arc<Mutex<VecDeque<i16>>>,
+    capacity: usize,
+}
+
+impl SidetoneTap {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Queue captured S16LE audio for playback, dropping the oldest samples beyond capacity
+    ///
+    /// Samples are dropped in whole frames only when the capacity is a multiple
+    /// of the channel count, which [`start_sidetone`] guarantees.
+    pub fn push(&self, s16le: &[u8]) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.extend(
+            s16le
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])),
+        );
+        if queue.len() > self.capacity {
+            let excess = queue.len() - self.capacity;
+            queue.drain(..excess);
+        }
+    }
+}
+
+/// Convert a gain in decibels to a linear amplitude factor
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::sidetone::db_to_gain;
+///
+/// assert!((db_to_gain(-20.0) - 0.1).abs() < 1e-6);
+/// assert_eq!(db_to_gain(0.0), 1.0);
+/// ```
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Start playing captured audio on the default output device
+///
+/// # Arguments
+///
+/// * `input_config` - Configuration of the capture stream; the output stream
+///   is opened with the same sample rate and channel count
+/// * `gain_db` - Playback level relative to the captured signal (e.g. -20.0)
+///
+/// # Returns
+///
+/// The running output stream, which must be kept alive for playback to
+/// continue, and the tap to feed from the capture callback. Returns an error
+/// if no output device is available or it cannot play the capture format.
+pub fn start_sidetone(
+    input_config: &cpal::StreamConfig,
+    gain_db: f32,
+) -> anyhow::Result<(cpal::Stream, SidetoneTap)> {
+    if !gain_db.is_finite() {
+        return Err(anyhow::anyhow!("Invalid sidetone level: {gain_db} dB"));
+    }
+
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| anyhow::anyhow!("No output device available for sidetone"))?;
+    let sample_format = device.default_output_config()?.sample_format();
+    let config = cpal::StreamConfig {
+        channels: input_config.channels,
+        sample_rate: input_config.sample_rate,
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let channels = usize::from(config.channels.max(1));
+    let frames = (config.sample_rate.0 * MAX_QUEUE_MS / 1000) as usize;
+    let tap = SidetoneTap::new(frames.max(1) * channels);
+    let gain = db_to_gain(gain_db);
+
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_output_stream::<f32>(&device, &config, &tap, gain)?,
+        cpal::SampleFormat::I16 => build_output_stream::<i16>(&device, &config, &tap, gain)?,
+        cpal::SampleFormat::U16 => build_output_stream::<u16>(&device, &config, &tap, gain)?,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unsupported sidetone output format: {sample_format:?}"
+            ));
+        }
+    };
+    stream.play()?;
+    info!("Sidetone enabled at {gain_db} dB");
+
+    Ok((stream, tap))
+}
+
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    tap: &SidetoneTap,
+    gain: f32,
+) -> anyhow::Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let queue = Arc::clone(&tap.queue);
+    let stream = device.build_output_stream(
+        config,
+        move |output: &mut [T], _| {
+            let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+            fill_output(output, &mut queue, gain);
+        },
+        |err| error!("Sidetone stream error: {err}"),
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+/// Fill `output` from `queue` with `gain` applied, padding with silence on underrun
+fn fill_output<T>(output: &mut [T], queue: &mut VecDeque<i16>, gain: f32)
+where
+    T: cpal::Sample + cpal::FromSample<f32>,
+{
+    for sample in output.iter_mut() {
+        let value = queue.pop_front().map_or(0.0, |s| s as f32 / 32768.0);
+        *sample = T::from_sample((value * gain).clamp(-1.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_to_gain() {
+        assert_eq!(db_to_gain(0.0), 1.0);
+        assert!((db_to_gain(-6.0) - 0.501).abs() < 0.001);
+        assert!((db_to_gain(-40.0) - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tap_drops_oldest_beyond_capacity() {
+        let tap = SidetoneTap::new(4);
+        let bytes: Vec<u8> = (1..=6i16).flat_map(i16::to_le_bytes).collect();
+        tap.push(&bytes);
+
+        let queue = tap.queue.lock().unwrap();
+        assert_eq!(queue.iter().copied().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_fill_output_applies_gain_and_pads_with_silence() {
+        let mut queue = VecDeque::from([16384i16, -16384]);
+        let mut output = [1.0f32; 4];
+        fill_output(&mut output, &mut queue, 0.5);
+
+        assert_eq!(output, [0.25, -0.25, 0.0, 0.0]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Command sink: received audio piped into an arbitrary command
+//!
+//! With `--pipe-to` the receiver writes the decoded audio to the standard
+//! input of a user-supplied command instead of the virtual microphone, so it
+//! can be recorded, re-encoded or forwarded (e.g. `ffmpeg -f s16le -i - ...`)
+//! without changes to rsonance. The audio is raw interleaved S16LE (S24LE
+//! with `--codec s24le`) in the codec's audio format, see
+//! [`crate::codec::Codec::audio_config`].
+
+use log::{info, warn};
+use std::io::{ErrorKind, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two starts of the command
+///
+/// Keeps a command that exits immediately from being restarted in a tight loop.
+const RESTART_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sink that writes audio to the standard input of a command
+///
+/// The command runs through `sh -c` and is shared by all transmitter
+/// connections, so it keeps running when a transmitter reconnects. If it
+/// exits, the audio that failed to write is dropped and the command is
+/// started again on the next write, at most once per second.
+///
+/// Writes block while the command is not reading, which applies backpressure
+/// to the transmitter the same way a slow virtual microphone does.
+#[derive(Debug)]
+pub struct CommandSink {
+    command: String,
+    state: Mutex<SinkState>,
+}
+
+#[derive(Debug, Default)]
+struct SinkState {
+    process: Option<(Child, ChildStdin)>,
+    last_start: Option<Instant>,
+}
+
+impl CommandSink {
+    /// Start `command` and return a sink feeding its standard input
+    ///
+    /// # Returns
+    ///
+    /// The sink, or an error if the command cannot be started
+    pub fn spawn(command: &str) -> anyhow::Result<Self> {
+        let sink = Self {
+            command: command.to_string(),
+            state: Mutex::new(SinkState::default()),
+        };
+        sink.lock().start(command)?;
+        info!("Output command started: {command}");
+        Ok(sink)
+    }
+
+    /// Write a block of audio to the command, restarting it if it has exited
+    ///
+    /// Returns an error only if the command cannot be restarted or the write
+    /// fails for a reason other than the command having exited.
+    pub fn write_all(&self, audio: &[u8]) -> std::io::Result<()> {
+        let mut state = self.lock();
+        self.restart_exited(&mut state)?;
+
+        let Some((child, stdin)) = &mut state.process else {
+            return Ok(());
+        };
+        match stdin.write_all(audio) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                match child.wait() {
+                    Ok(status) => warn!("Output command exited ({status}), restarting it"),
+                    Err(e) => warn!("Output command exited, restarting it ({e})"),
+                }
+                state.process = None;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the command is running, as far as the last write could tell
+    pub fn is_running(&self) -> bool {
+        self.lock().process.is_some()
+    }
+
+    /// Start the command again if it has exited
+    ///
+    /// [`CommandSink::write_all`] does this itself; a thread that may not
+    /// start processes (see [`crate::sandbox`]) has another thread call this
+    /// first.
+    pub fn ensure_running(&self) -> std::io::Result<()> {
+        self.restart_exited(&mut self.lock())
+    }
+
+    fn restart_exited(&self, state: &mut SinkState) -> std::io::Result<()> {
+        if state.process.is_none() {
+            if let Some(last_start) = state.last_start {
+                thread::sleep(RESTART_INTERVAL.saturating_sub(last_start.elapsed()));
+            }
+            state.start(&self.command)?;
+            info!("Output command restarted: {}", self.command);
+        }
+        Ok(())
+    }
+
+    /// Number of bytes written to the command but not yet read by it
+    pub fn queued_bytes(&self) -> usize {
+        let state = self.lock();
+        let Some((_, stdin)) = &state.process else {
+            return 0;
+        };
+        let fd: RawFd = stdin.as_raw_fd();
+        let mut queued: libc::c_int = 0;
+        // SAFETY: FIONREAD writes a single c_int to the valid pointer passed.
+        let rc = unsafe { libc::ioctl(fd, libc::FIONREAD, &mut queued) };
+        if rc == 0 { queued.max(0) as usize } else { 0 }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SinkState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SinkState {
+    fn start(&mut self, command: &str) -> std::io::Result<()> {
+        self.last_start = Some(Instant::now());
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("output command has no stdin"))?;
+        self.process = Some((child, stdin));
+        Ok(())
+    }
+}
+
+impl Drop for CommandSink {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some((mut child, stdin)) = state.process.take() {
+            // Closing stdin signals end of stream so the command can finish up
+            drop(stdin);
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_sink_writes_audio() {
+        let path = format!("/tmp/rsonance_sink_test_{}", std::process::id());
+        let sink = CommandSink::spawn(&format!("cat > {path}")).unwrap();
+        sink.write_all(&[1, 2, 3, 4]).unwrap();
+        drop(sink);
+
+        let contents = std::fs::read(&path).unwrap_or_default();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(contents, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_command_sink_restarts_exited_command() {
+        let path = format!("/tmp/rsonance_sink_restart_test_{}", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        // Each run appends one marker byte and exits without reading stdin
+        let sink = CommandSink::spawn(&format!("printf x >> {path}")).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while std::fs::read(&path).unwrap_or_default().len() < 2 && Instant::now() < deadline {
+            sink.write_all(&[0; 4096]).unwrap();
+        }
+        drop(sink);
+
+        let runs = std::fs::read(&path).unwrap_or_default().len();
+        let _ = std::fs::remove_file(&path);
+        assert!(runs >= 2, "command ran {runs} times");
+    }
+}
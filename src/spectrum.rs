@@ -0,0 +1,355 @@
+//! Receiver `--spectrum`: per-band levels of the received audio
+//!
+//! A remote operator can tell a lot about a microphone from its spectrum
+//! without listening in: mains hum shows up as a strong line at 50 or 60 Hz,
+//! and a failing capsule or a codec that cuts the highs as octave bands far
+//! below their neighbours at the top end. With `--spectrum` the receiver
+//! mixes each stream down to mono, runs an FFT over windows of about 200 to
+//! 350 ms, and keeps a smoothed level per octave band plus the level of the
+//! 50 and 60 Hz mains lines. `rsonance spectrum <id>` shows them through the
+//! control socket.
+//!
+//! Levels are in dBFS, scaled so that a full-scale sine wave reads 0 dBFS in
+//! its band; silence reads [`SILENCE_DBFS`].
+
+use std::f64::consts::{SQRT_2, TAU};
+use std::fmt;
+
+/// Center frequencies of the octave bands in Hz
+pub const BANDS: [f64; 10] = [
+    31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Level reported for a band without any signal
+pub const SILENCE_DBFS: f64 = -120.0;
+
+/// Weight of the newest window in the smoothed levels
+const SMOOTHING: f64 = 0.25;
+
+/// Distance in Hz from 50 or 60 Hz within which a peak counts as hum
+const HUM_TOLERANCE_HZ: f64 = 2.0;
+
+/// Smoothed spectrum of a stream, see [`SpectrumAnalyzer::spectrum`]
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::spectrum::{BandLevel, Spectrum};
+///
+/// let spectrum = Spectrum {
+///     bands: vec![
+///         BandLevel { center_hz: 63.0, dbfs: -31.5 },
+///         BandLevel { center_hz: 125.0, dbfs: -48.0 },
+///     ],
+///     hum_50hz_dbfs: -33.0,
+///     hum_60hz_dbfs: -90.5,
+/// };
+/// assert_eq!(Spectrum::from_line(&spectrum.to_line()).unwrap(), spectrum);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    /// Octave bands up to the stream's Nyquist frequency
+    pub bands: Vec<BandLevel>,
+    /// Level of the strongest line within 2 Hz of 50 Hz
+    pub hum_50hz_dbfs: f64,
+    /// Level of the strongest line within 2 Hz of 60 Hz
+    pub hum_60hz_dbfs: f64,
+}
+
+/// Level of one octave band
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandLevel {
+    pub center_hz: f64,
+    pub dbfs: f64,
+}
+
+impl Spectrum {
+    /// Encode as one tab-separated line for the control socket
+    pub fn to_line(&self) -> String {
+        let bands = self
+            .bands
+            .iter()
+            .map(|band| format!("{}={:.1}", band.center_hz, band.dbfs))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{bands}\t{:.1}\t{:.1}",
+            self.hum_50hz_dbfs, self.hum_60hz_dbfs
+        )
+    }
+
+    /// Decode a line produced by [`Spectrum::to_line`]
+    ///
+    /// # Returns
+    ///
+    /// The spectrum, or an error if the line is malformed
+    pub fn from_line(line: &str) -> anyhow::Result<Self> {
+        let malformed = || anyhow::anyhow!("Malformed spectrum line: {line}");
+        let [bands, hum_50hz, hum_60hz] = line.split('\t').collect::<Vec<_>>()[..] else {
+            return Err(malformed());
+        };
+        let bands = bands
+            .split(',')
+            .filter(|band| !band.is_empty())
+            .map(|band| {
+                let (center_hz, dbfs) = band.split_once('=').ok_or_else(malformed)?;
+                Ok(BandLevel {
+                    center_hz: center_hz.parse().map_err(|_| malformed())?,
+                    dbfs: dbfs.parse().map_err(|_| malformed())?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            bands,
+            hum_50hz_dbfs: hum_50hz.parse().map_err(|_| malformed())?,
+            hum_60hz_dbfs: hum_60hz.parse().map_err(|_| malformed())?,
+        })
+    }
+}
+
+impl fmt::Display for Spectrum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for band in &self.bands {
+            let center = if band.center_hz >= 1000.0 {
+                format!("{}k", band.center_hz / 1000.0)
+            } else {
+                band.center_hz.to_string()
+            };
+            write!(f, "{center} {:.0}, ", band.dbfs)?;
+        }
+        write!(
+            f,
+            "hum 50 Hz {:.0} / 60 Hz {:.0} dBFS",
+            self.hum_50hz_dbfs, self.hum_60hz_dbfs
+        )
+    }
+}
+
+/// Running spectrum analysis of one stream
+#[derive(Debug)]
+pub struct SpectrumAnalyzer {
+    sample_rate: u32,
+    channels: usize,
+    /// Samples of the window being filled, mixed down to mono
+    window: Vec<f64>,
+    hann: Vec<f64>,
+    /// `e^(-2πik/N)` for the first half of the FFT size
+    twiddles: Vec<(f64, f64)>,
+    /// Smoothed power of each band, then of the 50 and 60 Hz lines
+    powers: Option<Vec<f64>>,
+}
+
+impl SpectrumAnalyzer {
+    /// Analyze audio at `sample_rate` Hz with `channels` channels
+    ///
+    /// The FFT size is the power of two giving a resolution of 5 Hz or
+    /// finer, enough to tell 50 Hz hum from 60 Hz.
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let size = (sample_rate.max(1) as usize / 5)
+            .next_power_of_two()
+            .max(64);
+        let hann = (0..size)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f64 / size as f64).cos())
+            .collect();
+        let twiddles = (0..size / 2)
+            .map(|k| {
+                let angle = -TAU * k as f64 / size as f64;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        Self {
+            sample_rate: sample_rate.max(1),
+            channels: usize::from(channels.max(1)),
+            window: Vec::with_capacity(size),
+            hann,
+            twiddles,
+            powers: None,
+        }
+    }
+
+    /// Account for a block of S16LE audio
+    ///
+    /// A trailing partial frame is ignored.
+    pub fn push(&mut self, s16le: &[u8]) {
+        for frame in s16le.chunks_exact(2 * self.channels) {
+            let sum: f64 = frame
+                .chunks_exact(2)
+                .map(|sample| f64::from(i16::from_le_bytes([sample[0], sample[1]])))
+                .sum();
+            self.window.push(sum / (32768.0 * self.channels as f64));
+            if self.window.len() == self.hann.len() {
+                self.analyze();
+                self.window.clear();
+            }
+        }
+    }
+
+    /// Smoothed levels, or `None` until the first window is complete
+    pub fn spectrum(&self) -> Option<Spectrum> {
+        let powers = self.powers.as_ref()?;
+        let size = self.hann.len() as f64;
+        // A sine of amplitude A puts 3 N² A² / 32 into the bins of its half
+        // of a Hann-windowed spectrum
+        let dbfs = |power: f64| {
+            let level = 10.0 * (power * 32.0 / (3.0 * size * size)).log10();
+            level.clamp(SILENCE_DBFS, 0.0)
+        };
+        let (bands, hum) = powers.split_at(powers.len() - 2);
+        Some(Spectrum {
+            bands: BANDS
+                .iter()
+                .zip(bands)
+                .map(|(&center_hz, &power)| BandLevel {
+                    center_hz,
+                    dbfs: dbfs(power),
+                })
+                .collect(),
+            hum_50hz_dbfs: dbfs(hum[0]),
+            hum_60hz_dbfs: dbfs(hum[1]),
+        })
+    }
+
+    fn analyze(&mut self) {
+        let size = self.hann.len();
+        let mut bins: Vec<(f64, f64)> = self
+            .window
+            .iter()
+            .zip(&self.hann)
+            .map(|(sample, weight)| (sample * weight, 0.0))
+            .collect();
+        fft(&mut bins, &self.twiddles);
+
+        let resolution = f64::from(self.sample_rate) / size as f64;
+        let nyquist = f64::from(self.sample_rate) / 2.0;
+        let power = |bin: &(f64, f64)| bin.0 * bin.0 + bin.1 * bin.1;
+        let last = size / 2 - 1;
+        let bin_range = |low: f64, high: f64| {
+            (low / resolution).ceil() as usize..=((high / resolution).floor() as usize).min(last)
+        };
+
+        let mut powers: Vec<f64> = BANDS
+            .iter()
+            .take_while(|&&center| center / SQRT_2 < nyquist)
+            .map(|&center| {
+                bin_range(center / SQRT_2, center * SQRT_2)
+                    .map(|bin| power(&bins[bin]))
+                    .sum()
+            })
+            .collect();
+        for mains in [50.0, 60.0] {
+            // The strongest bin plus its neighbours, which the window spreads
+            // a line over
+            let peak = bin_range(mains - HUM_TOLERANCE_HZ, mains + HUM_TOLERANCE_HZ)
+                .max_by(|&a, &b| power(&bins[a]).total_cmp(&power(&bins[b])));
+            powers.push(peak.map_or(0.0, |peak| {
+                (peak.saturating_sub(1)..=(peak + 1).min(last))
+                    .map(|bin| power(&bins[bin]))
+                    .sum()
+            }));
+        }
+
+        match &mut self.powers {
+            Some(smoothed) => {
+                for (smoothed, power) in smoothed.iter_mut().zip(powers) {
+                    *smoothed += (power - *smoothed) * SMOOTHING;
+                }
+            }
+            None => self.powers = Some(powers),
+        }
+    }
+}
+
+/// In-place iterative radix-2 FFT; `bins.len()` must be a power of two with
+/// `twiddles` holding its first half of `e^(-2πik/N)`
+fn fft(bins: &mut [(f64, f64)], twiddles: &[(f64, f64)]) {
+    let size = bins.len();
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            bins.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= size {
+        let stride = size / len;
+        for start in (0..size).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = twiddles[k * stride];
+                let (er, ei) = bins[start + k];
+                let (or, oi) = bins[start + k + len / 2];
+                let (tr, ti) = (or * wr - oi * wi, or * wi + oi * wr);
+                bins[start + k] = (er + tr, ei + ti);
+                bins[start + k + len / 2] = (er - tr, ei - ti);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, frequency: f64, amplitude: f64, seconds: f64) -> Vec<u8> {
+        (0..(f64::from(sample_rate) * seconds) as usize)
+            .flat_map(|i| {
+                let value = (TAU * frequency * i as f64 / f64::from(sample_rate)).sin();
+                let sample = (value * amplitude * 32767.0).round() as i16;
+                // Stereo, identical on both channels
+                [sample.to_le_bytes(), sample.to_le_bytes()].concat()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sine_shows_in_its_band_at_its_level() {
+        let mut analyzer = SpectrumAnalyzer::new(48000, 2);
+        assert_eq!(analyzer.spectrum(), None);
+        analyzer.push(&sine(48000, 1000.0, 0.5, 1.0));
+        let spectrum = analyzer.spectrum().unwrap();
+
+        assert_eq!(spectrum.bands.len(), BANDS.len());
+        let level = |center: f64| {
+            spectrum
+                .bands
+                .iter()
+                .find(|band| band.center_hz == center)
+                .unwrap()
+                .dbfs
+        };
+        // Half scale is -6 dBFS
+        assert!((level(1000.0) + 6.0).abs() < 0.5, "{spectrum}");
+        assert!(level(125.0) < -80.0, "{spectrum}");
+        assert!(level(8000.0) < -80.0, "{spectrum}");
+        assert!(spectrum.hum_50hz_dbfs < -80.0, "{spectrum}");
+    }
+
+    #[test]
+    fn test_hum_is_told_apart_by_frequency() {
+        let mut analyzer = SpectrumAnalyzer::new(44100, 2);
+        analyzer.push(&sine(44100, 50.0, 0.1, 1.0));
+        let spectrum = analyzer.spectrum().unwrap();
+        assert!((spectrum.hum_50hz_dbfs + 20.0).abs() < 1.0, "{spectrum}");
+        assert!(
+            spectrum.hum_60hz_dbfs < spectrum.hum_50hz_dbfs - 20.0,
+            "{spectrum}"
+        );
+    }
+
+    #[test]
+    fn test_bands_stop_at_nyquist() {
+        let mut analyzer = SpectrumAnalyzer::new(8000, 1);
+        analyzer.push(&vec![0; 8000 * 2]);
+        let spectrum = analyzer.spectrum().unwrap();
+        // 4 kHz spans 2.8 to 5.7 kHz; 8 kHz starts above 4 kHz
+        assert_eq!(spectrum.bands.last().unwrap().center_hz, 4000.0);
+        assert!(spectrum.bands.iter().all(|band| band.dbfs == SILENCE_DBFS));
+    }
+}
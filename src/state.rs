@@ -0,0 +1,367 @@
+//! Record of the system resources a receiver created, for crash-safe cleanup
+//!
+//! The receiver adds every PulseAudio module, FIFO and socket it
+//! creates to a state file and removes its entries again on a clean
+//! shutdown. If it is killed instead, the entries stay behind and
+//! [`cleanup_stale`] (run on the next receiver start and by
+//! `rsonance cleanup`) removes the leaked resources.
+//!
+//! # Format
+//!
+//! One resource per line with tab-separated fields: the id of the process
+//! that created it, the kind (`module`, `fifo` or `socket`), and either the
+//! module index and the source or sink name it was loaded for, or the path.
+
+use crate::module_loaded_with;
+use log::{debug, info, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A system resource created by a receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resource {
+    /// PulseAudio module with its index and the source or sink name it was
+    /// loaded for
+    Module { id: String, name: String },
+    /// FIFO feeding the virtual microphone
+    Fifo(String),
+    /// Control socket
+    Socket(String),
+}
+
+/// A resource in the state file together with the process that created it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub pid: u32,
+    pub resource: Resource,
+}
+
+impl Entry {
+    /// Encode as a state file line (without newline)
+    pub fn to_line(&self) -> String {
+        match &self.resource {
+            Resource::Module { id, name } => format!("{}\tmodule\t{id}\t{name}", self.pid),
+            Resource::Fifo(path) => format!("{}\tfifo\t{path}", self.pid),
+            Resource::Socket(path) => format!("{}\tsocket\t{path}", self.pid),
+        }
+    }
+
+    /// Parse a state file line, or `None` if it is malformed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rsonance::state::{Entry, Resource};
+    ///
+    /// let entry = Entry::from_line("42\tfifo\t/tmp/pipe").unwrap();
+    /// assert_eq!(entry.resource, Resource::Fifo("/tmp/pipe".to_string()));
+    /// assert_eq!(Entry::from_line(&entry.to_line()), Some(entry));
+    /// ```
+    pub fn from_line(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let pid = fields.first()?.parse().ok()?;
+        let resource = match fields[1..] {
+            ["module", id, name] => Resource::Module {
+                id: id.to_string(),
+                name: name.to_string(),
+            },
+            ["fifo", path] => Resource::Fifo(path.to_string()),
+            ["socket", path] => Resource::Socket(path.to_string()),
+            _ => return None,
+        };
+        Some(Self { pid, resource })
+    }
+}
+
+/// Default state file: `$XDG_RUNTIME_DIR/rsonance.state`, or a per-user file in `/tmp`
+pub fn default_state_path() -> String {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir)
+            .join("rsonance.state")
+            .to_string_lossy()
+            .into_owned(),
+        // SAFETY: getuid has no preconditions and cannot fail
+        _ => format!("/tmp/rsonance_state_{}", unsafe { libc::getuid() }),
+    }
+}
+
+/// State file shared by all receivers of a user
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Path of the state file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record `resource` as created by this process
+    pub fn record(&self, resource: Resource) -> anyhow::Result<()> {
+        let entry = Entry {
+            pid: std::process::id(),
+            resource,
+        };
+        let line = entry.to_line();
+        if line.contains('\n') {
+            return Err(anyhow::anyhow!("Cannot record {line:?} in the state file"));
+        }
+        self.update(|entries| {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        })
+    }
+
+    /// Remove every entry recorded by this process, after a clean shutdown
+    pub fn forget_own(&self) -> anyhow::Result<()> {
+        let pid = std::process::id();
+        self.update(|entries| entries.retain(|entry| entry.pid != pid))
+    }
+
+    /// All entries in the state file
+    pub fn entries(&self) -> anyhow::Result<Vec<Entry>> {
+        match File::open(&self.path) {
+            Ok(mut file) => read_entries(&mut file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(anyhow::anyhow!(
+                "Failed to read state file {}: {e}",
+                self.path.display()
+            )),
+        }
+    }
+
+    /// Rewrite the state file under an exclusive lock
+    ///
+    /// The lock keeps concurrent receivers from losing each other's entries.
+    /// The file is emptied rather than removed, as another receiver may be
+    /// waiting for the lock on it.
+    fn update(&self, change: impl FnOnce(&mut Vec<Entry>)) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to open state file {}: {e}", self.path.display())
+            })?;
+        // SAFETY: the descriptor is valid while `file` is open; the lock is
+        // released when it is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let mut entries = read_entries(&mut file)?;
+        change(&mut entries);
+        let contents: String = entries.iter().map(|entry| entry.to_line() + "\n").collect();
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn read_entries(file: &mut File) -> anyhow::Result<Vec<Entry>> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let entry = Entry::from_line(line);
+            if entry.is_none() && !line.is_empty() {
+                warn!("Ignoring malformed state file line: {line:?}");
+            }
+            entry
+        })
+        .collect())
+}
+
+/// Whether a process with id `pid` is running
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Outcome of [`cleanup_stale`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Resources of exited receivers that were removed
+    pub removed: usize,
+    /// Resources left alone because their receiver is still running
+    pub in_use: usize,
+}
+
+/// Remove the resources recorded by receivers that are no longer running
+///
+/// A module is only unloaded if it still refers to the recorded name, and a
+/// path only removed if it is still a FIFO or socket, so resources that were
+/// reused since the crash are left alone. Entries of the calling process are
+/// kept.
+pub fn cleanup_stale(state: &StateFile) -> anyhow::Result<CleanupReport> {
+    let own_pid = std::process::id();
+    let mut report = CleanupReport::default();
+    let mut stale = Vec::new();
+    state.update(|entries| {
+        entries.retain(|entry| {
+            if entry.pid == own_pid || process_alive(entry.pid) {
+                report.in_use += 1;
+                true
+            } else {
+                stale.push(entry.clone());
+                false
+            }
+        })
+    })?;
+
+    for entry in stale {
+        debug!(
+            "Cleaning up after exited receiver {}: {:?}",
+            entry.pid, entry.resource
+        );
+        if remove_resource(&entry.resource) {
+            report.removed += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Remove a leaked resource, returning whether anything was removed
+fn remove_resource(resource: &Resource) -> bool {
+    match resource {
+        Resource::Module { id, name } => {
+            match module_loaded_with(id, name) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => {
+                    warn!("Failed to list PulseAudio modules: {e}");
+                    return false;
+                }
+            }
+            let unloaded = Command::new("pactl")
+                .args(["unload-module", id])
+                .status()
+                .is_ok_and(|status| status.success());
+            if unloaded {
+                info!("Unloaded leaked module {id} ({name})");
+            } else {
+                warn!("Failed to unload leaked module {id} ({name})");
+            }
+            unloaded
+        }
+        Resource::Fifo(path) => remove_special_file(path, |kind| kind.is_fifo()),
+        Resource::Socket(path) => remove_special_file(path, |kind| kind.is_socket()),
+    }
+}
+
+fn remove_special_file(path: &str, is_kind: impl Fn(std::fs::FileType) -> bool) -> bool {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if is_kind(metadata.file_type()) => match std::fs::remove_file(path) {
+            Ok(()) => {
+                info!("Removed leaked {path}");
+                true
+            }
+            Err(e) => {
+                warn!("Failed to remove leaked {path}: {e}");
+                false
+            }
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    fn temp_state(name: &str) -> StateFile {
+        let path = format!("/tmp/rsonance_state_test_{}_{name}", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        StateFile::new(path)
+    }
+
+    #[test]
+    fn test_entry_lines() {
+        let module = Entry {
+            pid: 7,
+            resource: Resource::Module {
+                id: "17".to_string(),
+                name: "mic".to_string(),
+            },
+        };
+        assert_eq!(module.to_line(), "7\tmodule\t17\tmic");
+        assert_eq!(Entry::from_line(&module.to_line()), Some(module));
+        assert_eq!(Entry::from_line("7\tmodule\t17"), None);
+        assert_eq!(Entry::from_line("x\tfifo\t/tmp/a"), None);
+    }
+
+    #[test]
+    fn test_record_and_forget() {
+        let state = temp_state("forget");
+        state.record(Resource::Fifo("/tmp/a".to_string())).unwrap();
+        state
+            .record(Resource::Socket("/tmp/b".to_string()))
+            .unwrap();
+        assert_eq!(state.entries().unwrap().len(), 2);
+
+        // Recording the same resource again does not duplicate it
+        state.record(Resource::Fifo("/tmp/a".to_string())).unwrap();
+        let entries = state.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.pid == std::process::id()));
+
+        state.forget_own().unwrap();
+        assert!(state.entries().unwrap().is_empty());
+        let _ = std::fs::remove_file(state.path());
+    }
+
+    #[test]
+    fn test_cleanup_removes_resources_of_exited_processes() {
+        let state = temp_state("cleanup");
+        let socket = format!("/tmp/rsonance_state_test_{}.sock", std::process::id());
+        let regular = format!("/tmp/rsonance_state_test_{}.file", std::process::id());
+        let _ = std::fs::remove_file(&socket);
+        drop(UnixListener::bind(&socket).unwrap());
+        std::fs::write(&regular, b"keep").unwrap();
+
+        // A process that has exited
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let lines = format!(
+            "{dead}\tsocket\t{socket}\n{dead}\tfifo\t{regular}\n{}\tsocket\t/tmp/live\n",
+            std::process::id()
+        );
+        std::fs::write(state.path(), lines).unwrap();
+
+        let report = cleanup_stale(&state).unwrap();
+        assert_eq!(
+            report,
+            CleanupReport {
+                removed: 1,
+                in_use: 1
+            }
+        );
+        assert!(!Path::new(&socket).exists());
+        // Not a FIFO any more, so it is not ours to remove
+        assert!(Path::new(&regular).exists());
+        assert_eq!(state.entries().unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&regular);
+        let _ = std::fs::remove_file(state.path());
+    }
+}
@@ -0,0 +1,267 @@
+//! Periodic resource usage reporting
+//!
+//! Samples CPU time, resident memory, and per-thread CPU usage of the running
+//! process from `/proc/self` and logs them at a fixed interval, so the cost of
+//! a stream can be checked on small devices before deploying it.
+
+use log::{debug, info, warn};
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// CPU usage of a single thread at a point in time
+#[derive(Debug, Clone)]
+pub struct ThreadSnapshot {
+    /// Kernel thread id
+    pub tid: u32,
+    /// Thread name as reported by the kernel (truncated to 15 bytes)
+    pub name: String,
+    /// User plus system CPU time in clock ticks
+    pub cpu_ticks: u64,
+}
+
+/// Resource usage of this process at a point in time
+///
+/// # Examples
+///
+/// ```no_run
+/// use rsonance::stats::ProcessSnapshot;
+///
+/// let before = ProcessSnapshot::take()?;
+/// std::thread::sleep(std::time::Duration::from_secs(1));
+/// let after = ProcessSnapshot::take()?;
+/// println!("CPU: {:.1}%", after.cpu_percent_since(&before));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProcessSnapshot {
+    /// When the snapshot was taken
+    pub taken_at: Instant,
+    /// User plus system CPU time of the whole process in clock ticks
+    pub cpu_ticks: u64,
+    /// Resident set size in bytes
+    pub rss_bytes: u64,
+    /// Per-thread CPU usage
+    pub threads: Vec<ThreadSnapshot>,
+}
+
+impl ProcessSnapshot {
+    /// Read the current resource usage from `/proc/self`
+    ///
+    /// Threads that exit while the snapshot is being taken are skipped.
+    ///
+    /// # Returns
+    ///
+    /// The snapshot, or an error if `/proc/self/stat` cannot be read or parsed
+    pub fn take() -> anyhow::Result<Self> {
+        let taken_at = Instant::now();
+        let stat = parse_stat(&fs::read_to_string("/proc/self/stat")?)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected /proc/self/stat format"))?;
+
+        let mut threads = Vec::new();
+        for entry in fs::read_dir("/proc/self/task")? {
+            let entry = entry?;
+            let Ok(tid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            if let Ok(contents) = fs::read_to_string(entry.path().join("stat"))
+                && let Some(thread_stat) = parse_stat(&contents)
+            {
+                threads.push(ThreadSnapshot {
+                    tid,
+                    name: thread_stat.name,
+                    cpu_ticks: thread_stat.cpu_ticks,
+                });
+            }
+        }
+
+        Ok(Self {
+            taken_at,
+            cpu_ticks: stat.cpu_ticks,
+            rss_bytes: stat.rss_pages * page_size(),
+            threads,
+        })
+    }
+
+    /// Process CPU usage since `earlier`, in percent of one core
+    pub fn cpu_percent_since(&self, earlier: &ProcessSnapshot) -> f64 {
+        cpu_percent(
+            self.cpu_ticks.saturating_sub(earlier.cpu_ticks),
+            self.taken_at.duration_since(earlier.taken_at),
+        )
+    }
+
+    /// CPU usage of each thread since `earlier`, in percent of one core
+    ///
+    /// Threads that did not exist in `earlier` are measured from zero.
+    pub fn thread_cpu_percent_since(
+        &self,
+        earlier: &ProcessSnapshot,
+    ) -> Vec<(&ThreadSnapshot, f64)> {
+        let elapsed = self.taken_at.duration_since(earlier.taken_at);
+        self.threads
+            .iter()
+            .map(|thread| {
+                let before = earlier
+                    .threads
+                    .iter()
+                    .find(|t| t.tid == thread.tid)
+                    .map_or(0, |t| t.cpu_ticks);
+                let ticks = thread.cpu_ticks.saturating_sub(before);
+                (thread, cpu_percent(ticks, elapsed))
+            })
+            .collect()
+    }
+}
+
+/// Start a background thread that logs resource usage every `interval`
+///
+/// Process-wide CPU and memory usage is logged at info level, per-thread CPU
+/// usage at debug level. The thread runs for the lifetime of the process.
+///
+/// # Arguments
+///
+/// * `interval` - Time between reports
+pub fn spawn_stats_thread(interval: Duration) {
+    thread::spawn(move || {
+        let mut previous = match ProcessSnapshot::take() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("Resource usage reporting unavailable: {e}");
+                return;
+            }
+        };
+
+        loop {
+            thread::sleep(interval);
+            let current = match ProcessSnapshot::take() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    warn!("Failed to sample resource usage: {e}");
+                    continue;
+                }
+            };
+
+            info!(
+                "Process stats: CPU {:.1}%, RSS {:.1} MiB, {} threads",
+                current.cpu_percent_since(&previous),
+                current.rss_bytes as f64 / (1024.0 * 1024.0),
+                current.threads.len()
+            );
+            for (thread, cpu) in current.thread_cpu_percent_since(&previous) {
+                debug!("  Thread {} ({}): CPU {cpu:.1}%", thread.tid, thread.name);
+            }
+
+            previous = current;
+        }
+    });
+}
+
+/// Fields of interest from a `/proc/<pid>/stat` or `/proc/<pid>/task/<tid>/stat` line
+#[derive(Debug, PartialEq)]
+struct Stat {
+    name: String,
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Parse a `/proc` stat line
+///
+/// The command name is enclosed in parentheses and may itself contain spaces
+/// or parentheses, so fields are counted from the last closing parenthesis.
+fn parse_stat(contents: &str) -> Option<Stat> {
+    let open = contents.find('(')?;
+    let close = contents.rfind(')')?;
+    let name = contents.get(open + 1..close)?.to_string();
+    // Fields after the name start at field 3 (state) of proc_pid_stat(5)
+    let fields: Vec<&str> = contents.get(close + 1..)?.split_whitespace().collect();
+
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let rss_pages: u64 = fields.get(21)?.parse().ok()?;
+
+    Some(Stat {
+        name,
+        cpu_ticks: utime + stime,
+        rss_pages,
+    })
+}
+
+/// Convert a CPU tick delta over `elapsed` wall time to percent of one core
+fn cpu_percent(ticks: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    ticks as f64 / clock_ticks_per_second() / secs * 100.0
+}
+
+fn clock_ticks_per_second() -> f64 {
+    // SAFETY: sysconf only reads its integer argument.
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as f64 } else { 100.0 }
+}
+
+fn page_size() -> u64 {
+    // SAFETY: sysconf only reads its integer argument.
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 { size as u64 } else { 4096 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let line = "1234 (rsonance) S 1 1234 1234 0 -1 4194304 500 0 0 0 \
+                    150 25 0 0 20 0 3 0 100 123456789 2048 18446744073709551615";
+        let stat = parse_stat(line).unwrap();
+        assert_eq!(
+            stat,
+            Stat {
+                name: "rsonance".to_string(),
+                cpu_ticks: 175,
+                rss_pages: 2048,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_stat_name_with_spaces_and_parens() {
+        let line = "42 (cpal (alsa) in) R 1 1 1 0 -1 0 0 0 0 0 \
+                    7 3 0 0 20 0 1 0 100 0 10 0";
+        let stat = parse_stat(line).unwrap();
+        assert_eq!(stat.name, "cpal (alsa) in");
+        assert_eq!(stat.cpu_ticks, 10);
+        assert_eq!(stat.rss_pages, 10);
+    }
+
+    #[test]
+    fn test_parse_stat_truncated() {
+        assert!(parse_stat("1 (x) S 1 2 3").is_none());
+        assert!(parse_stat("garbage").is_none());
+    }
+
+    #[test]
+    fn test_cpu_percent() {
+        let ticks_per_second = clock_ticks_per_second() as u64;
+        let half = cpu_percent(ticks_per_second / 2, Duration::from_secs(1));
+        assert!((half - 50.0).abs() < 1.0);
+        assert_eq!(cpu_percent(10, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_process_snapshot_take() {
+        let snapshot = ProcessSnapshot::take().unwrap();
+        assert!(snapshot.rss_bytes > 0);
+        assert!(!snapshot.threads.is_empty());
+
+        let later = ProcessSnapshot::take().unwrap();
+        assert!(later.cpu_percent_since(&snapshot) >= 0.0);
+        assert_eq!(
+            later.thread_cpu_percent_since(&snapshot).len(),
+            later.threads.len()
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Transcription tap: received audio as 16 kHz mono for speech recognition
+//!
+//! The receiver can hand every block of decoded audio to a
+//! [`TranscriptionTap`], converted to the 16 kHz mono S16 format speech
+//! recognisers such as whisper.cpp expect. [`CommandTap`] implements the tap
+//! by piping the audio into an external command (`--transcribe-cmd`), so
+//! captions can be produced live without capturing the virtual microphone a
+//! second time.
+
+use log::{debug, info, warn};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+
+/// Sample rate of audio delivered to transcription taps
+pub const TRANSCRIBE_SAMPLE_RATE: u32 = 16000;
+
+/// Number of audio blocks queued for a [`CommandTap`] before blocks are dropped
+const COMMAND_QUEUE_BLOCKS: usize = 256;
+
+/// Receives decoded audio as 16 kHz mono signed 16-bit samples
+///
+/// Called on the FIFO writer thread, so implementations must return quickly
+/// and hand any heavy work to another thread.
+///
+/// Closures taking `&[i16]` implement this trait.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::transcribe::TranscriptionTap;
+/// use std::sync::Arc;
+///
+/// let tap: Arc<dyn TranscriptionTap> = Arc::new(|samples: &[i16]| {
+///     log::debug!("{} samples for the recogniser", samples.len());
+/// });
+/// tap.on_audio(&[0; 160]);
+/// ```
+pub trait TranscriptionTap: Send + Sync {
+    /// Handle a block of 16 kHz mono samples
+    fn on_audio(&self, samples: &[i16]);
+}
+
+impl<F> TranscriptionTap for F
+where
+    F: Fn(&[i16]) + Send + Sync,
+{
+    fn on_audio(&self, samples: &[i16]) {
+        self(samples)
+    }
+}
+
+/// Tap that writes the audio as raw S16LE to the standard input of a command
+///
+/// The command runs through `sh -c` for the lifetime of the tap. Audio is
+/// passed to it through a bounded queue on a separate thread; if the command
+/// falls behind, blocks are dropped rather than stalling the receiver.
+#[derive(Debug)]
+pub struct CommandTap {
+    sender: SyncSender<Vec<i16>>,
+}
+
+impl CommandTap {
+    /// Start `command` and return a tap feeding its standard input
+    ///
+    /// # Returns
+    ///
+    /// The tap, or an error if the command cannot be started
+    pub fn spawn(command: &str) -> anyhow::Result<Self> {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start transcription command: {e}"))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Transcription command has no stdin"))?;
+        info!("Transcription command started: {command}");
+
+        let (sender, receiver) = mpsc::sync_channel::<Vec<i16>>(COMMAND_QUEUE_BLOCKS);
+        thread::spawn(move || {
+            for samples in receiver {
+                let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+                if let Err(e) = stdin.write_all(&bytes) {
+                    warn!("Transcription command stopped accepting audio: {e}");
+                    break;
+                }
+            }
+            drop(stdin);
+            match child.wait() {
+                Ok(status) => debug!("Transcription command exited: {status}"),
+                Err(e) => warn!("Failed to wait for transcription command: {e}"),
+            }
+        });
+
+        Ok(Self { sender })
+    }
+}
+
+impl TranscriptionTap for CommandTap {
+    fn on_audio(&self, samples: &[i16]) {
+        match self.sender.try_send(samples.to_vec()) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!(
+                    "Transcription command is behind, dropping {} samples",
+                    samples.len()
+                )
+            }
+        }
+    }
+}
+
+/// Converts interleaved S16LE audio to 16 kHz mono
+///
+/// Channels are averaged, then the signal is resampled by linear
+/// interpolation. Interpolation state carries over between blocks, so block
+/// boundaries do not produce clicks. No low-pass filter is applied when
+/// downsampling; content above 8 kHz aliases, which speech recognisers
+/// tolerate well.
+#[derive(Debug)]
+pub struct TapResampler {
+    channels: usize,
+    /// Input samples per output sample
+    step: f64,
+    /// Position of the next output sample, in input samples after `previous`
+    position: f64,
+    /// Last mono input sample of the previous block
+    previous: f32,
+}
+
+impl TapResampler {
+    /// Create a converter for audio at `sample_rate` Hz with `channels` channels
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            channels: usize::from(channels.max(1)),
+            step: f64::from(sample_rate.max(1)) / f64::from(TRANSCRIBE_SAMPLE_RATE),
+            position: 1.0,
+            previous: 0.0,
+        }
+    }
+
+    /// Convert a block of S16LE audio, returning the 16 kHz mono samples it yields
+    ///
+    /// A trailing partial frame is ignored.
+    pub fn push(&mut self, s16le: &[u8]) -> Vec<i16> {
+        let mono: Vec<f32> = s16le
+            .chunks_exact(self.channels * 2)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(2)
+                    .map(|bytes| f32::from(i16::from_le_bytes([bytes[0], bytes[1]])))
+                    .sum();
+                sum / self.channels as f32
+            })
+            .collect();
+
+        // Index 0 is `previous`, index k is mono[k - 1]
+        let len = mono.len() as f64;
+        let mut out = Vec::with_capacity((len / self.step) as usize + 1);
+        while self.position <= len {
+            let index = self.position.floor() as usize;
+            let frac = (self.position - index as f64) as f32;
+            let a = if index == 0 {
+                self.previous
+            } else {
+                mono[index - 1]
+            };
+            let b = mono.get(index).copied().unwrap_or(a);
+            out.push((a + (b - a) * frac) as i16);
+            self.position += self.step;
+        }
+
+        self.position -= len;
+        if let Some(&last) = mono.last() {
+            self.previous = last;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn s16le(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_resampler_passthrough_at_16k() {
+        let mut resampler = TapResampler::new(16000, 1);
+        assert_eq!(resampler.push(&s16le(&[1, 2, 3])), vec![1, 2, 3]);
+        assert_eq!(resampler.push(&s16le(&[4, 5])), vec![4, 5]);
+    }
+
+    #[test]
+    fn test_resampler_downmixes_and_decimates() {
+        // 48 kHz stereo to 16 kHz mono keeps every third frame
+        let mut resampler = TapResampler::new(48000, 2);
+        let frames: Vec<i16> = (0..12).flat_map(|i| [i * 10, i * 10 + 2]).collect();
+        assert_eq!(resampler.push(&s16le(&frames)), vec![1, 31, 61, 91]);
+    }
+
+    #[test]
+    fn test_resampler_upsamples_across_blocks() {
+        // 8 kHz to 16 kHz interpolates a midpoint between consecutive samples
+        let mut resampler = TapResampler::new(8000, 1);
+        assert_eq!(resampler.push(&s16le(&[100, 200])), vec![100, 150, 200]);
+        assert_eq!(resampler.push(&s16le(&[400])), vec![300, 400]);
+    }
+
+    #[test]
+    fn test_closure_tap() {
+        let received = Mutex::new(Vec::new());
+        let tap = |samples: &[i16]| received.lock().unwrap().extend_from_slice(samples);
+        tap.on_audio(&[1, 2]);
+        tap.on_audio(&[3]);
+        assert_eq!(*received.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_command_tap_writes_s16le() {
+        let path = format!("/tmp/rsonance_transcribe_test_{}", std::process::id());
+        let tap = CommandTap::spawn(&format!("cat > {path}")).unwrap();
+        tap.on_audio(&[1, -1]);
+        drop(tap);
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let mut contents = Vec::new();
+        while std::time::Instant::now() < deadline {
+            contents = std::fs::read(&path).unwrap_or_default();
+            if contents.len() == 4 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(contents, s16le(&[1, -1]));
+    }
+}
@@ -1,176 +1,2186 @@
 //! Audio transmitter module that captures microphone input and streams it to a remote receiver
 
-use crate::validate_buffer_size;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crate::anomaly::{Anomaly, AnomalyDetector, AnomalyEvent};
+use crate::codec::{Codec, Encoder, convert_pcm};
+use crate::dehum::{Dehum, Mains};
+use crate::demand::DemandReader;
+use crate::device::{DeviceSelector, find_input_device};
+use crate::hotkey::{Hotkey, spawn_mute_hotkey};
+use crate::meter::{LevelMeter, start_level_meter};
+use crate::mock::{MockSignal, SignalSource, spawn_mock_capture};
+use crate::pipeline::{RAW_COMPAT_SAMPLE_RATE, pipeline_commands};
+use crate::power::{PowerSave, PowerSaver};
+use crate::protocol::{FrameSplitter, Metadata, StreamHeader, frame, frame_chunks};
+use crate::queue::{CaptureReceiver, CaptureSender, capture_queue};
+use crate::realtime::promote_current_thread;
+use crate::resample::Resampler;
+use crate::rtp::{AES67_SAMPLE_RATE, Aes67Config, MULTICAST_TTL, RtpPacketizer};
+use crate::session::SessionSummary;
+use crate::sidetone::{SidetoneTap, start_sidetone};
+use crate::stats::spawn_stats_thread;
+use crate::udp::{Packetizer, Transport};
+use crate::{AudioConfig, BufferSize, bytes_to_ms, validate_buffer_size_for_format};
+use cpal::traits::{DeviceTrait, StreamTrait};
 use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant};
 
 /// Sealed trait for converting audio samples to signed 16-bit little-endian.
 ///
-/// Implemented for `f32`, `i16`, and `u16` — the three sample formats
+/// Implemented for `f32`, `i16`, `u16` and `i32` — the sample formats
 /// supported by cpal that this tool handles. This replaces the previous
-/// unsafe `TypeId`-based dispatch.
+/// unsafe `TypeId`-based dispatch. [`ToS16::to_s24`] serves the S24LE codec
+/// and [`ToS16::to_f32`] the F32LE codec.
 trait ToS16: cpal::Sample + cpal::SizedSample + Send + 'static {
     fn to_s16(self) -> i16;
+
+    /// Convert to a signed 24-bit sample in the low bits of an `i32`
+    fn to_s24(self) -> i32;
+
+    /// Convert to a float sample, -1.0 to 1.0 for integer formats
+    fn to_f32(self) -> f32;
+
+    /// Convert a slice of samples, appending the S16LE bytes to `out`
+    ///
+    /// Formats with a vectorised conversion override this; the default
+    /// converts sample by sample.
+    fn extend_s16le(data: &[Self], out: &mut Vec<u8>) {
+        extend_s16le_scalar(data, out);
+    }
+}
+
+/// Convert samples one at a time, appending the S16LE bytes to `out`
+fn extend_s16le_scalar<T: ToS16>(data: &[T], out: &mut Vec<u8>) {
+    for sample in data.iter().copied() {
+        out.extend_from_slice(&sample.to_s16().to_le_bytes());
+    }
+}
+
+impl ToS16 for f32 {
+    fn to_s16(self) -> i16 {
+        (self.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+    }
+
+    fn to_s24(self) -> i32 {
+        (self.clamp(-1.0, 1.0) * S24_MAX as f32) as i32
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn extend_s16le(data: &[Self], out: &mut Vec<u8>) {
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: NEON support was detected at runtime.
+            unsafe { neon::extend_f32_s16le(data, out) };
+            return;
+        }
+
+        extend_s16le_scalar(data, out);
+    }
+}
+
+/// NEON conversion paths for ARM devices such as the Raspberry Pi
+///
+/// The results are bit-identical to the scalar conversions: clamping happens
+/// before scaling, and float-to-int conversion truncates toward zero (NaN
+/// becomes 0) exactly like an `as` cast.
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::extend_s16le_scalar;
+    use std::arch::aarch64::{
+        vcvtq_s32_f32, vdupq_n_f32, vld1q_f32, vmaxq_f32, vminq_f32, vmulq_f32, vqmovn_s32,
+        vst1_s16,
+    };
+
+    /// Convert `f32` samples to S16LE four at a time
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support NEON.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn extend_f32_s16le(data: &[f32], out: &mut Vec<u8>) {
+        let chunks = data.chunks_exact(4);
+        let remainder = chunks.remainder();
+        let mut converted = [0i16; 4];
+
+        // SAFETY: every chunk holds exactly four f32 values to load, and
+        // `converted` holds exactly four i16 values to store.
+        unsafe {
+            let min = vdupq_n_f32(-1.0);
+            let max = vdupq_n_f32(1.0);
+            let scale = vdupq_n_f32(i16::MAX as f32);
+
+            for chunk in chunks {
+                let samples = vld1q_f32(chunk.as_ptr());
+                let scaled = vmulq_f32(vminq_f32(vmaxq_f32(samples, min), max), scale);
+                vst1_s16(converted.as_mut_ptr(), vqmovn_s32(vcvtq_s32_f32(scaled)));
+                for sample in converted {
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+
+        extend_s16le_scalar(remainder, out);
+    }
+}
+
+impl ToS16 for i16 {
+    fn to_s16(self) -> i16 {
+        self
+    }
+
+    fn to_s24(self) -> i32 {
+        i32::from(self) << 8
+    }
+
+    fn to_f32(self) -> f32 {
+        f32::from(self) / 32768.0
+    }
+}
+
+impl ToS16 for u16 {
+    fn to_s16(self) -> i16 {
+        (self as i32 - 32768) as i16
+    }
+
+    fn to_s24(self) -> i32 {
+        (self as i32 - 32768) << 8
+    }
+
+    fn to_f32(self) -> f32 {
+        (self as i32 - 32768) as f32 / 32768.0
+    }
+}
+
+/// 24-in-32 audio from pro-audio interfaces keeps its top bits
+impl ToS16 for i32 {
+    fn to_s16(self) -> i16 {
+        (self >> 16) as i16
+    }
+
+    fn to_s24(self) -> i32 {
+        self >> 8
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 2_147_483_648.0
+    }
+}
+
+/// Largest signed 24-bit sample value
+const S24_MAX: i32 = (1 << 23) - 1;
+
+/// Queued capture audio in milliseconds beyond which backpressure is handled
+/// according to [`BackpressurePolicy`]
+const BACKPRESSURE_LIMIT_MS: u64 = 200;
+
+/// What to do when the connection cannot keep up with the captured audio
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::transmitter::BackpressurePolicy;
+///
+/// let policy: BackpressurePolicy = "drop".parse().unwrap();
+/// assert_eq!(policy, BackpressurePolicy::Drop);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Keep the audio and send it late (latency grows until the link recovers,
+    /// up to `max_queue_ms`)
+    #[default]
+    Buffer,
+    /// Drop the oldest queued packets so that at most 200 ms remain queued
+    Drop,
+    /// Close the connection and reconnect, discarding the queued audio
+    Disconnect,
+}
+
+impl fmt::Display for BackpressurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackpressurePolicy::Buffer => "buffer",
+            BackpressurePolicy::Drop => "drop",
+            BackpressurePolicy::Disconnect => "disconnect",
+        })
+    }
+}
+
+impl FromStr for BackpressurePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffer" => Ok(BackpressurePolicy::Buffer),
+            "drop" => Ok(BackpressurePolicy::Drop),
+            "disconnect" => Ok(BackpressurePolicy::Disconnect),
+            _ => Err(anyhow::anyhow!(
+                "Unknown backpressure policy '{s}' (expected drop, buffer, or disconnect)"
+            )),
+        }
+    }
+}
+
+/// Configuration for the transmitter
+///
+/// Holds the connection, batching, and scheduling settings used by
+/// [`run_transmitter`]. The defaults match the CLI defaults.
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::transmitter::TransmitterConfig;
+///
+/// let config = TransmitterConfig {
+///     host: "192.168.1.100".to_string(),
+///     ..TransmitterConfig::default()
+/// };
+/// assert_eq!(config.port, 8080);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TransmitterConfig {
+    /// Server address to connect to (e.g., "127.0.0.1" or "192.168.1.100")
+    pub host: String,
+    /// Server port to connect to
+    pub port: u16,
+    /// Stream over TCP or as sequenced UDP datagrams, see [`crate::udp`]
+    pub transport: Transport,
+    /// Size of the batches written to the connection (affects latency);
+    /// durations are measured in the captured S16LE format
+    pub buffer_size: BufferSize,
+    /// Accept buffers above [`crate::max_buffer_size`] for the stream format
+    pub allow_large_buffers: bool,
+    /// Maximum number of reconnection attempts on failure
+    pub reconnect_attempts: u32,
+    /// Start capturing before the receiver is reachable and keep trying to
+    /// connect until it is, buffering the most recent audio meanwhile; also
+    /// used once `reconnect_attempts` run out
+    pub wait_for_server: bool,
+    /// Standby receiver (`HOST:PORT`) streamed to while the primary one is
+    /// unreachable, see [`Failback`]
+    pub standby: Option<String>,
+    /// Second receiver (`HOST:PORT`) sent a copy of the stream, for a
+    /// redundant receiver in [`crate::secondary`] mode, see
+    /// [`spawn_duplicate`]
+    pub duplicate_to: Option<String>,
+    /// Codec the `duplicate_to` copy is encoded with instead of `codec`, to
+    /// compare the two, see [`CompareEncoder`]
+    pub compare_codec: Option<Codec>,
+    /// Only capture and stream while an application records from the
+    /// receiver's virtual microphone, see [`crate::demand`]
+    pub on_demand: bool,
+    /// Pause capture and streaming on low battery, see [`crate::power`]
+    pub power_save: PowerSave,
+    /// Battery charge in percent at or below which `power_save` pauses
+    pub power_save_threshold: u8,
+    /// Maximum time in milliseconds to hold captured audio while coalescing it
+    /// into a `buffer_size` write (0 sends each capture callback immediately)
+    pub max_batch_delay_ms: u64,
+    /// Send the audio in frames of this many milliseconds, whatever the
+    /// capture callback and batch sizes (0 sends each batch as one frame),
+    /// see [`FrameSplitter`]
+    pub frame_ms: u64,
+    /// Request real-time scheduling for the audio capture callback thread
+    pub realtime: bool,
+    /// Interval in seconds between resource usage reports (0 disables them)
+    pub stats_interval: u64,
+    /// Spread each batch over its real-time duration instead of sending it in
+    /// one burst, see [`Pacer`]
+    pub pacing: bool,
+    /// Handling of audio that queues up while the connection is too slow
+    pub on_backpressure: BackpressurePolicy,
+    /// Most captured audio in milliseconds waiting for the connection; the
+    /// oldest is dropped beyond it (0 = no limit), see [`crate::queue`]
+    pub max_queue_ms: u64,
+    /// Milliseconds of recently sent audio replayed after a reconnect
+    /// (0 disables the resend buffer)
+    pub resend_ms: u64,
+    /// Remove hum at this mains frequency and its harmonics from the
+    /// captured audio, see [`crate::dehum`]
+    pub dehum: Option<Mains>,
+    /// Play the captured audio back on the local output device at this level
+    /// in dB (e.g. -20.0), see [`crate::sidetone`]
+    pub sidetone_db: Option<f32>,
+    /// Draw a live input level bar on stderr, see [`crate::meter`]
+    pub level_meter: bool,
+    /// Key combination sending silence instead of the microphone until it
+    /// is pressed again, see [`crate::hotkey`]
+    pub mute_hotkey: Option<Hotkey>,
+    /// Wire encoding of the stream, announced to the receiver in the
+    /// stream header
+    pub codec: Codec,
+    /// Pick the codec matching the input device's sample format instead of
+    /// `codec`, so its samples are sent without conversion, see
+    /// [`passthrough_codec`]
+    pub passthrough: bool,
+    /// Display name and tags announced to the receiver after the stream
+    /// header, see [`Metadata`]
+    pub metadata: Metadata,
+    /// Stream at this sample rate in Hz, resampling the captured audio to it
+    /// (None streams at the capture device's rate), see [`crate::resample`]
+    pub sample_rate: Option<u32>,
+    /// Send AES67 RTP multicast instead of streaming to a receiver, see [`crate::rtp`]
+    pub aes67: Option<Aes67Config>,
+    /// Send plain 48 kHz S16LE for external tools such as GStreamer or ffmpeg,
+    /// without replaying audio after reconnects, see [`crate::pipeline`]
+    pub raw_output_compat: bool,
+    /// Input device to capture from (None uses the host's default), see
+    /// [`crate::device`]
+    pub device: Option<DeviceSelector>,
+    /// Stream a generated test signal instead of capturing from an input
+    /// device, see [`crate::mock`]
+    pub mock_input: bool,
+    /// Signal generated by `mock_input`
+    pub mock_signal: MockSignal,
+    /// Interval in milliseconds at which the mock signal restarts, with
+    /// silence between (0 = continuous)
+    pub mock_repeat_ms: u64,
+    /// Enable verbose logging output
+    pub verbose: bool,
+}
+
+impl Default for TransmitterConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            transport: Transport::Tcp,
+            buffer_size: BufferSize::default(),
+            allow_large_buffers: false,
+            reconnect_attempts: 5,
+            wait_for_server: false,
+            standby: None,
+            duplicate_to: None,
+            compare_codec: None,
+            on_demand: false,
+            power_save: PowerSave::Off,
+            power_save_threshold: 20,
+            max_batch_delay_ms: 5,
+            frame_ms: 0,
+            realtime: false,
+            stats_interval: 0,
+            pacing: false,
+            on_backpressure: BackpressurePolicy::Buffer,
+            max_queue_ms: 2000,
+            resend_ms: 250,
+            dehum: None,
+            sidetone_db: None,
+            level_meter: false,
+            mute_hotkey: None,
+            codec: Codec::S16LE,
+            passthrough: false,
+            metadata: Metadata::default(),
+            sample_rate: None,
+            aes67: None,
+            raw_output_compat: false,
+            device: None,
+            mock_input: false,
+            mock_signal: MockSignal::Sine,
+            mock_repeat_ms: 0,
+            verbose: false,
+        }
+    }
+}
+
+/// Run the transmitter with the given configuration
+///
+/// This function captures audio from the default microphone and streams it to
+/// a remote receiver over TCP. It supports automatic reconnection if the connection
+/// is lost.
+///
+/// # Arguments
+///
+/// * `config` - Transmitter configuration, see [`TransmitterConfig`]
+///
+/// # Returns
+///
+/// Returns `Ok(())` on successful completion, or an error if audio capture or connection fails
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use rsonance::transmitter::{run_transmitter, TransmitterConfig};
+///
+/// run_transmitter(TransmitterConfig {
+///     host: "127.0.0.1".to_string(),
+///     verbose: true,
+///     ..TransmitterConfig::default()
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn run_transmitter(config: TransmitterConfig) -> anyhow::Result<()> {
+    transmit(config, None).await
+}
+
+/// Run the transmitter, pausing while `control` asks for it
+async fn transmit(
+    config: TransmitterConfig,
+    mut control: Option<watch::Receiver<RunState>>,
+) -> anyhow::Result<()> {
+    let TransmitterConfig {
+        host,
+        port,
+        transport,
+        buffer_size,
+        allow_large_buffers,
+        reconnect_attempts,
+        wait_for_server,
+        standby,
+        duplicate_to,
+        compare_codec,
+        on_demand,
+        power_save,
+        power_save_threshold,
+        max_batch_delay_ms,
+        frame_ms,
+        realtime,
+        stats_interval,
+        pacing,
+        on_backpressure,
+        max_queue_ms,
+        resend_ms,
+        dehum,
+        sidetone_db,
+        level_meter,
+        mute_hotkey,
+        codec,
+        passthrough,
+        metadata,
+        sample_rate,
+        aes67,
+        raw_output_compat,
+        device,
+        mock_input,
+        mock_signal,
+        mock_repeat_ms,
+        verbose,
+    } = config;
+
+    let server_addr = format!("{host}:{port}");
+
+    if let Some(aes67) = &aes67 {
+        aes67.validate()?;
+        if codec != Codec::S16LE {
+            return Err(anyhow::anyhow!(
+                "AES67 output carries linear PCM and cannot be combined with --codec {codec}"
+            ));
+        }
+    }
+    if on_demand && (aes67.is_some() || raw_output_compat) {
+        return Err(anyhow::anyhow!(
+            "--on-demand needs a rsonance receiver to report when its audio is recorded"
+        ));
+    }
+    if power_save == PowerSave::Auto && aes67.is_some() {
+        return Err(anyhow::anyhow!(
+            "--power-save pauses a receiver stream and cannot be combined with AES67 output"
+        ));
+    }
+    if transport == Transport::Udp {
+        let unsupported = [
+            (aes67.is_some(), "--aes67"),
+            (raw_output_compat, "--raw-output-compat"),
+            (standby.is_some(), "--standby"),
+            (duplicate_to.is_some(), "--duplicate-to"),
+            (on_demand, "--on-demand"),
+            (wait_for_server, "--wait-for-server"),
+            (power_save == PowerSave::Auto, "--power-save auto"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+            return Err(anyhow::anyhow!(
+                "{option} needs the TCP transport and cannot be combined with --transport udp"
+            ));
+        }
+    }
+    if frame_ms > 0 && (aes67.is_some() || raw_output_compat) {
+        return Err(anyhow::anyhow!(
+            "--frame-ms sets the frames of a rsonance stream and cannot be combined with {}",
+            if raw_output_compat {
+                "--raw-output-compat"
+            } else {
+                "AES67 output"
+            }
+        ));
+    }
+    if frame_ms > MAX_FRAME_MS {
+        return Err(anyhow::anyhow!(
+            "Frames of {frame_ms} ms are too long, the maximum is {MAX_FRAME_MS} ms"
+        ));
+    }
+    if let Some(rate) = sample_rate {
+        if aes67.is_some() || raw_output_compat {
+            return Err(anyhow::anyhow!(
+                "--sample-rate cannot be combined with {}, which always sends 48 kHz",
+                if raw_output_compat {
+                    "--raw-output-compat"
+                } else {
+                    "AES67 output"
+                }
+            ));
+        }
+        if !SAMPLE_RATES.contains(&rate) {
+            return Err(anyhow::anyhow!(
+                "Sample rate {rate} Hz is out of range, it has to be {} to {} Hz",
+                SAMPLE_RATES.start(),
+                SAMPLE_RATES.end()
+            ));
+        }
+    }
+    if power_save_threshold > 100 {
+        return Err(anyhow::anyhow!(
+            "Power save threshold must be a percentage, got {power_save_threshold}"
+        ));
+    }
+    if let Some(compare) = compare_codec {
+        if duplicate_to.is_none() {
+            return Err(anyhow::anyhow!(
+                "--compare-codec encodes the --duplicate-to copy and needs --duplicate-to"
+            ));
+        }
+        if raw_output_compat {
+            return Err(anyhow::anyhow!(
+                "--compare-codec needs the stream header, which --raw-output-compat leaves out"
+            ));
+        }
+        if compare == codec {
+            return Err(anyhow::anyhow!(
+                "--compare-codec {compare} is the codec of the stream already"
+            ));
+        }
+    }
+    if passthrough {
+        let conflicts = [
+            (codec != Codec::S16LE, "--codec"),
+            (sample_rate.is_some(), "--sample-rate"),
+            (dehum.is_some(), "--dehum"),
+            (raw_output_compat, "--raw-output-compat"),
+            (aes67.is_some(), "AES67 output"),
+        ];
+        if let Some((_, option)) = conflicts.iter().find(|(set, _)| *set) {
+            return Err(anyhow::anyhow!(
+                "--passthrough sends the device's samples unchanged and cannot be combined with {option}"
+            ));
+        }
+    }
+    if !metadata.is_empty() {
+        metadata.validate()?;
+        let unsupported = [
+            (raw_output_compat, "--raw-output-compat"),
+            (aes67.is_some(), "AES67 output"),
+            (transport == Transport::Udp, "--transport udp"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+            return Err(anyhow::anyhow!(
+                "--name and --tag are sent after the stream header of a TCP stream and cannot be combined with {option}"
+            ));
+        }
+    }
+    if raw_output_compat && codec != Codec::S16LE {
+        return Err(anyhow::anyhow!(
+            "--raw-output-compat sends S16LE and cannot be combined with --codec {codec}"
+        ));
+    }
+    // Replayed audio would reach external tools as a glitch rather than fill a gap
+    let resend_ms = if raw_output_compat { 0 } else { resend_ms };
+
+    let (device, config, sample_format) = if mock_input {
+        (None, mock_config(aes67.is_some(), raw_output_compat), None)
+    } else {
+        let device = find_input_device(device.as_ref())?;
+        if verbose {
+            info!(
+                "Using input device: {}",
+                device.name().unwrap_or_else(|_| "(unnamed)".to_string())
+            );
+        }
+        let config = capture_config(&device, aes67.is_some(), raw_output_compat)?;
+        let sample_format = config.sample_format();
+        (Some(device), config.into(), Some(sample_format))
+    };
+    // The mock signal is generated as S16LE
+    let codec = match sample_format {
+        Some(format) if passthrough => {
+            let native = passthrough_codec(format)?;
+            info!("Passthrough: sending the device's {format:?} samples as {native}");
+            if compare_codec == Some(native) {
+                return Err(anyhow::anyhow!(
+                    "--compare-codec {native} is the codec --passthrough picked for this device"
+                ));
+            }
+            native
+        }
+        _ => codec,
+    };
+
+    // The device keeps capturing at its own rate; everything downstream of
+    // the capture callback works at the stream's rate
+    let capture_config = config.clone();
+    let mut config = config;
+    let resampler = match sample_rate {
+        Some(rate) if rate != config.sample_rate.0 => {
+            config.sample_rate = cpal::SampleRate(rate);
+            Some(Resampler::new(
+                capture_config.sample_rate.0,
+                rate,
+                config.channels,
+                codec.capture_sample_size(),
+            ))
+        }
+        _ => None,
+    };
+
+    // Buffers are sized in captured audio, before encoding
+    let capture_frame_size = usize::from(config.channels.max(1)) * codec.capture_sample_size();
+    let buffer_size = validate_buffer_size_for_format(
+        buffer_size.to_bytes(config.sample_rate.0, capture_frame_size),
+        config.sample_rate.0,
+        capture_frame_size,
+        allow_large_buffers,
+    )?;
+
+    if verbose {
+        match sample_format {
+            Some(format) => info!(
+                "Using audio format: {format:?} at {} Hz with {} channels",
+                config.sample_rate.0, config.channels
+            ),
+            None => info!(
+                "Using mock input: {mock_signal} signal at {} Hz with {} channels",
+                config.sample_rate.0, config.channels
+            ),
+        }
+        if resampler.is_some() {
+            info!(
+                "Resampling the captured {} Hz audio to {} Hz",
+                capture_config.sample_rate.0, config.sample_rate.0
+            );
+        }
+        if let Some(mains) = dehum {
+            info!("Removing {mains} Hz mains hum and its harmonics");
+        }
+        debug!(
+            "Buffer size: {buffer_size} bytes ({:.1} ms)",
+            bytes_to_ms(buffer_size, config.sample_rate.0, capture_frame_size)
+        );
+        debug!("Max reconnection attempts: {reconnect_attempts}");
+        debug!("Wait for server: {wait_for_server}");
+        debug!("On demand: {on_demand}");
+        debug!("Power save: {power_save} (at or below {power_save_threshold}%)");
+        if let Some(standby) = &standby {
+            debug!("Standby receiver: {standby}");
+        }
+        if let Some(duplicate) = &duplicate_to {
+            debug!("Duplicate receiver: {duplicate}");
+        }
+        if let Some(compare) = compare_codec {
+            debug!("Duplicate codec: {compare}");
+        }
+        debug!("Max batch delay: {max_batch_delay_ms} ms");
+        if frame_ms > 0 {
+            debug!("Frame length: {frame_ms} ms");
+        }
+        debug!("Resend buffer: {resend_ms} ms");
+        debug!("Pacing: {pacing}");
+        debug!("On backpressure: {on_backpressure}");
+        debug!("Codec: {codec}");
+        debug!("Transport: {transport}");
+    }
+
+    let frame_len = match frame_ms {
+        0 => None,
+        ms => Some(
+            FrameSplitter::for_duration(codec, config.sample_rate.0, config.channels, ms)?
+                .frame_len(),
+        ),
+    };
+
+    if stats_interval > 0 {
+        spawn_stats_thread(Duration::from_secs(stats_interval));
+    }
+
+    // External tools expect the plain stream without a header or framing
+    let header = (!raw_output_compat).then(|| StreamHeader {
+        metadata,
+        ..StreamHeader::new(codec, config.sample_rate.0, config.channels)
+    });
+    let header = header.as_ref();
+
+    // Set while streaming to the standby receiver
+    let mut failback = None;
+
+    // With --wait-for-server the connection is made once capture is running
+    let tcp_stream = match aes67 {
+        Some(_) => None,
+        None if wait_for_server || transport == Transport::Udp => None,
+        None => {
+            info!("Connecting to server at {server_addr}...");
+            let stream = match connect(&server_addr, header).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("{e}");
+                    let Some(stream) = connect_standby(standby.as_deref(), header).await else {
+                        return Err(e);
+                    };
+                    failback = Some(Failback::new(&server_addr, header));
+                    stream
+                }
+            };
+            info!("Connected to server successfully");
+            Some(stream)
+        }
+    };
+
+    let capture_bytes_per_second = config.sample_rate.0 as usize * capture_frame_size;
+    let (tx, mut rx) = capture_queue(
+        (config.sample_rate.0 as u64 * max_queue_ms / 1000) as usize * capture_frame_size,
+        capture_bytes_per_second,
+    );
+
+    // The output stream has to stay alive for as long as sidetone should play
+    let (_sidetone_stream, sidetone) =
+        match sidetone_db.map(|db| start_sidetone(&capture_config, db)) {
+            Some(Ok((stream, tap))) => (Some(stream), Some(tap)),
+            Some(Err(e)) => {
+                warn!("Sidetone unavailable: {e}");
+                (None, None)
+            }
+            None => (None, None),
+        };
+
+    let meter = if level_meter {
+        let meter = start_level_meter();
+        if meter.is_none() {
+            warn!("Level meter needs stderr to be a terminal, not showing it");
+        }
+        meter
+    } else {
+        None
+    };
+    let muted = mute_hotkey.as_ref().map(spawn_mute_hotkey).transpose()?;
+    let capture_sample_size = codec.capture_sample_size();
+    let mut taps = CaptureTaps {
+        dehum: dehum.map(|mains| {
+            Dehum::new(
+                mains,
+                capture_config.sample_rate.0,
+                config.channels,
+                codec.capture_sample_size(),
+            )
+        }),
+        sidetone,
+        meter,
+        anomaly: AnomalyDetector::new(capture_config.sample_rate.0, config.channels),
+        muted,
+        sample_size: capture_sample_size,
+        resampler,
+    };
+
+    // The input stream has to stay alive for as long as capture should run
+    let input_stream = match (device, sample_format) {
+        (Some(device), Some(sample_format)) => {
+            let err_fn = move |err| {
+                error!("Audio stream error: {err}");
+            };
+
+            let stream = match sample_format {
+                cpal::SampleFormat::F32 => {
+                    build_input_stream::<f32>(&device, &capture_config, tx, err_fn, realtime, taps)?
+                }
+                cpal::SampleFormat::I16 => {
+                    build_input_stream::<i16>(&device, &capture_config, tx, err_fn, realtime, taps)?
+                }
+                cpal::SampleFormat::U16 => {
+                    build_input_stream::<u16>(&device, &capture_config, tx, err_fn, realtime, taps)?
+                }
+                cpal::SampleFormat::I32 => {
+                    build_input_stream::<i32>(&device, &capture_config, tx, err_fn, realtime, taps)?
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unsupported sample format: {:?}",
+                        sample_format
+                    ));
+                }
+            };
+            stream.play()?;
+            Some(stream)
+        }
+        _ => {
+            let source = SignalSource::new(capture_config.sample_rate.0, config.channels)
+                .signal(mock_signal)
+                .repeat_ms(mock_repeat_ms);
+            spawn_mock_capture(source, move |audio| {
+                let audio = convert_pcm(&audio, 2, capture_sample_size);
+                tx.send(taps.process(audio)).is_ok()
+            });
+            None
+        }
+    };
+    info!("Started streaming microphone audio... Press Ctrl+C to stop.");
+
+    let max_batch_delay = Duration::from_millis(max_batch_delay_ms);
+    let mut session = SessionSummary::new(
+        codec,
+        &StreamHeader::new(codec, config.sample_rate.0, config.channels).config,
+        transport,
+        header.is_some(),
+    );
+    session.add_latency(
+        "capture buffer",
+        bytes_to_ms(buffer_size, config.sample_rate.0, capture_frame_size) as u64,
+    );
+    session.add_latency("batching", max_batch_delay_ms);
+    session.add_latency("frames", frame_ms);
+    if let Some(aes67) = &aes67 {
+        return send_aes67(
+            aes67,
+            &mut rx,
+            config.channels,
+            buffer_size,
+            max_batch_delay,
+        )
+        .await;
+    }
+    if transport == Transport::Udp {
+        info!("Session: {session}");
+        return send_udp(
+            &server_addr,
+            codec,
+            &config,
+            &mut rx,
+            buffer_size,
+            max_batch_delay,
+            frame_len,
+            control,
+        )
+        .await;
+    }
+    let framing = match (header, frame_len) {
+        (None, _) => Framing::Raw,
+        (Some(_), None) => Framing::Batch,
+        (Some(_), Some(len)) => Framing::Fixed(len),
+    };
+
+    // Audio taken off the channel but not sent yet, sent before the next batch
+    let mut carry: Option<Vec<u8>> = None;
+    let wait_buffer_limit =
+        (config.sample_rate.0 as u64 * WAIT_BUFFER_MS / 1000) as usize * capture_frame_size;
+    let mut tcp_stream = match tcp_stream {
+        Some(stream) => stream,
+        None => {
+            info!("Waiting for the receiver at {server_addr}...");
+            let Some((stream, buffered)) =
+                wait_for_server_connection(&server_addr, header, &mut rx, wait_buffer_limit).await
+            else {
+                return Ok(());
+            };
+            info!("Connected to server successfully");
+            carry = (!buffered.is_empty()).then_some(buffered);
+            stream
+        }
+    };
+    info!("Session: {session}");
+
+    let mut reconnect_attempts_count = 0;
+    let max_reconnect_attempts = reconnect_attempts;
+    let mut encoder = Encoder::new(codec, config.sample_rate.0, config.channels);
+    let (frame_rate, frame_size) = codec.wire_format(config.sample_rate.0, config.channels);
+    let mut resend_buffer = ResendBuffer::new(resend_ms, frame_rate, frame_size);
+    let mut pacer = pacing.then(|| Pacer::new(frame_rate, frame_size));
+    let mut splitter = frame_len.map(FrameSplitter::new);
+    let mut compare = compare_codec
+        .map(|compare| {
+            CompareEncoder::new(
+                compare,
+                codec,
+                config.sample_rate.0,
+                config.channels,
+                frame_ms,
+            )
+        })
+        .transpose()?;
+    let duplicate = duplicate_to.map(|addr| match &compare {
+        Some(compare) => {
+            info!(
+                "Comparing codecs: {codec} to {server_addr}, {} to {addr}",
+                compare.header.codec
+            );
+            spawn_duplicate(addr, Some(compare.header.clone()), compare.framing)
+        }
+        None => spawn_duplicate(addr, header.cloned(), framing),
+    });
+    let mut demand = on_demand.then(DemandReader::default);
+    let mut power_saver = match power_save {
+        PowerSave::Auto => PowerSaver::new(power_save_threshold),
+        PowerSave::Off => None,
+    };
+    if on_demand {
+        info!(
+            "Streaming only while the receiver reports a recorder (needs a receiver with --on-demand)"
+        );
+    }
+
+    // Backpressure is measured on the captured S16LE audio, before encoding
+    let backpressure_limit =
+        (config.sample_rate.0 as u64 * BACKPRESSURE_LIMIT_MS / 1000) as usize * capture_frame_size;
+    let mut dropped_total = 0;
+
+    loop {
+        // A paused embedded transmitter keeps its connection but sends nothing
+        if let Some(control) = &mut control
+            && *control.borrow() == RunState::Paused
+        {
+            if let Some(stream) = &input_stream {
+                let _ = stream.pause();
+            }
+            carry = None;
+            wait_while_paused(control, &mut rx).await;
+            if let Some(stream) = &input_stream {
+                let _ = stream.play();
+            }
+        }
+
+        // With --power-save nothing is captured or sent on low battery
+        if let Some(power_saver) = &mut power_saver
+            && power_saver.battery_low()
+        {
+            if let Some(stream) = &input_stream {
+                let _ = stream.pause();
+            }
+            carry = None;
+            power_saver.wait_for_power(&mut rx).await;
+            if let Some(stream) = &input_stream {
+                let _ = stream.play();
+            }
+        }
+
+        // With --on-demand nothing is captured or sent while nothing records
+        let mut result = match &mut demand {
+            Some(demand) if !demand.recording() => {
+                if let Some(stream) = &input_stream {
+                    let _ = stream.pause();
+                }
+                carry = None;
+                let waited = demand.wait_for_recorder(&tcp_stream, &mut rx).await;
+                if let Some(stream) = &input_stream {
+                    let _ = stream.play();
+                }
+                waited
+            }
+            _ => Ok(()),
+        };
+
+        if result.is_ok() {
+            let batch = match carry.take() {
+                Some(batch) => batch,
+                None => match next_batch(&mut rx, buffer_size, max_batch_delay).await {
+                    Some(batch) => batch,
+                    None => break,
+                },
+            };
+            let compared = compare.as_mut().map(|compare| compare.encode(&batch));
+            let audio_data = encoder.encode(batch);
+            let audio_data = match &mut splitter {
+                Some(splitter) => splitter.push(&audio_data),
+                None => audio_data,
+            };
+            if let Some(duplicate) = &duplicate {
+                let copy = compared.unwrap_or_else(|| audio_data.clone());
+                if !copy.is_empty() && duplicate.try_send(copy).is_err() {
+                    debug!("Duplicate receiver is behind, dropping a batch for it");
+                }
+            }
+            if audio_data.is_empty() {
+                continue;
+            }
+            resend_buffer.push(&audio_data);
+
+            let wire = framing.wrap(audio_data);
+            result = match &mut pacer {
+                Some(pacer) => pacer.write_all(&mut tcp_stream, &wire).await,
+                None => tcp_stream.write_all(&wire).await,
+            };
+
+            if result.is_ok() && on_backpressure != BackpressurePolicy::Buffer && !rx.is_empty() {
+                let mut queued = take_queued(&mut rx);
+                let queued_bytes: usize = queued.iter().map(Vec::len).sum();
+                if queued_bytes > backpressure_limit {
+                    let queued_ms = queued_bytes * 1000 / capture_bytes_per_second;
+                    if on_backpressure == BackpressurePolicy::Disconnect {
+                        queued.clear();
+                        result = Err(std::io::Error::new(
+                            ErrorKind::TimedOut,
+                            format!("connection too slow, {queued_ms} ms of audio queued"),
+                        ));
+                    } else {
+                        let dropped = drop_oldest(&mut queued, backpressure_limit);
+                        dropped_total += dropped;
+                        warn!(
+                            "Connection too slow ({queued_ms} ms queued), dropped {} ms of audio ({} ms in total)",
+                            dropped * 1000 / capture_bytes_per_second,
+                            dropped_total * 1000 / capture_bytes_per_second
+                        );
+                    }
+                }
+                if !queued.is_empty() {
+                    carry = Some(queued.into_iter().flatten().collect());
+                }
+            }
+
+            if result.is_ok()
+                && let Some(demand) = &mut demand
+            {
+                result = demand.read_available(&tcp_stream);
+            }
+        }
+
+        if let Err(e) = result {
+            error!("Failed to send audio data: {e}");
+
+            if reconnect_attempts_count < max_reconnect_attempts {
+                warn!(
+                    "Attempting to reconnect... ({}/{})",
+                    reconnect_attempts_count + 1,
+                    max_reconnect_attempts
+                );
+
+                match connect(&server_addr, header).await {
+                    Ok(new_stream) => {
+                        tcp_stream = new_stream;
+                        reconnect_attempts_count = 0;
+                        failback = None;
+                        info!("Reconnected successfully");
+                        replay_recent_audio(&mut tcp_stream, &resend_buffer, framing).await;
+                    }
+                    Err(e) => {
+                        error!("Reconnection failed: {e}");
+                        reconnect_attempts_count += 1;
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            } else if let Some(new_stream) = connect_standby(standby.as_deref(), header).await {
+                tcp_stream = new_stream;
+                reconnect_attempts_count = 0;
+                failback = Some(Failback::new(&server_addr, header));
+                replay_recent_audio(&mut tcp_stream, &resend_buffer, framing).await;
+            } else if wait_for_server {
+                warn!("Receiver unreachable, waiting for it to come back");
+                let Some((new_stream, buffered)) =
+                    wait_for_server_connection(&server_addr, header, &mut rx, wait_buffer_limit)
+                        .await
+                else {
+                    break;
+                };
+                tcp_stream = new_stream;
+                reconnect_attempts_count = 0;
+                failback = None;
+                info!("Reconnected successfully");
+                // Audio held back for the backpressure check is older than the buffered audio
+                let mut pending = carry.take().unwrap_or_default();
+                pending.extend(buffered);
+                carry = (!pending.is_empty()).then_some(pending);
+            } else {
+                return Err(anyhow::anyhow!("Max reconnection attempts reached"));
+            }
+        } else {
+            reconnect_attempts_count = 0;
+            if let Some(check) = &mut failback
+                && let Some(primary) = check.poll().await
+            {
+                info!("Receiver at {server_addr} is reachable again, leaving the standby");
+                tcp_stream = primary;
+                failback = None;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// State an embedding application asks of its [`Transmitter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Streaming,
+    Paused,
+    Stopped,
+}
+
+/// A transmitter embedded in another application
+///
+/// Streams on a background thread with its own Tokio runtime, so it can be
+/// used from synchronous code and from inside another runtime alike. While
+/// paused, capture stops and nothing is sent, but the connection to the
+/// receiver stays open. AES67 output cannot be paused.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rsonance::transmitter::Transmitter;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let mut transmitter = Transmitter::builder()
+///     .host("192.168.1.100")
+///     .port(8080)
+///     .buffer_size("20ms".parse()?)
+///     .build();
+/// transmitter.start()?;
+/// // ...
+/// transmitter.pause();
+/// // ...
+/// transmitter.start()?;
+/// transmitter.stop()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Transmitter {
+    config: TransmitterConfig,
+    control: watch::Sender<RunState>,
+    thread: Option<thread::JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Transmitter {
+    /// Builder starting from the defaults of [`TransmitterConfig`]
+    pub fn builder() -> TransmitterBuilder {
+        TransmitterBuilder::default()
+    }
+
+    /// Transmitter with the given configuration, not started yet
+    pub fn new(config: TransmitterConfig) -> Self {
+        Self {
+            config,
+            control: watch::Sender::new(RunState::Stopped),
+            thread: None,
+        }
+    }
+
+    /// Configuration the transmitter runs with
+    pub fn config(&self) -> &TransmitterConfig {
+        &self.config
+    }
+
+    /// Start streaming, or resume after [`Transmitter::pause`]
+    ///
+    /// Returns once the background thread is running; connecting to the
+    /// receiver happens there. If the transmitter has stopped on an error
+    /// since it was started, that error is returned instead and the next
+    /// call starts it again.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        if let Some(thread) = &self.thread
+            && !thread.is_finished()
+        {
+            self.control.send_replace(RunState::Streaming);
+            return Ok(());
+        }
+        if let Some(thread) = self.thread.take() {
+            join_transmitter(thread)?;
+        }
+
+        self.control.send_replace(RunState::Streaming);
+        let config = self.config.clone();
+        let control = self.control.subscribe();
+        let thread = thread::Builder::new()
+            .name("rsonance-transmitter".to_string())
+            .spawn(move || {
+                let runtime = tokio::runtime::Runtime::new()?;
+                runtime.block_on(async move {
+                    let mut stop = control.clone();
+                    tokio::select! {
+                        result = transmit(config, Some(control)) => result,
+                        _ = stop.wait_for(|state| *state == RunState::Stopped) => Ok(()),
+                    }
+                })
+            })?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    /// Stop capturing and sending until the next [`Transmitter::start`]
+    pub fn pause(&self) {
+        if self.thread.is_some() {
+            self.control.send_replace(RunState::Paused);
+        }
+    }
+
+    /// Stop streaming and close the connection
+    ///
+    /// Returns the error the transmitter stopped on, if it failed before
+    /// being stopped.
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        self.control.send_replace(RunState::Stopped);
+        match self.thread.take() {
+            Some(thread) => join_transmitter(thread),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Transmitter {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            debug!("Transmitter stopped with an error: {e}");
+        }
+    }
+}
+
+fn join_transmitter(thread: thread::JoinHandle<anyhow::Result<()>>) -> anyhow::Result<()> {
+    thread
+        .join()
+        .map_err(|_| anyhow::anyhow!("Transmitter thread panicked"))?
+}
+
+/// Wait until a paused transmitter is resumed or stopped, discarding captured
+/// audio meanwhile
+async fn wait_while_paused(control: &mut watch::Receiver<RunState>, rx: &mut CaptureReceiver) {
+    while *control.borrow_and_update() == RunState::Paused {
+        tokio::select! {
+            changed = control.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            packet = rx.recv() => {
+                if packet.is_none() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Builder for a [`Transmitter`]
+///
+/// Settings not covered by a method keep their [`TransmitterConfig`]
+/// default; use [`Transmitter::new`] to set any of them.
+#[derive(Debug, Clone, Default)]
+pub struct TransmitterBuilder {
+    config: TransmitterConfig,
+}
+
+impl TransmitterBuilder {
+    /// Receiver address to connect to
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = host.into();
+        self
+    }
+
+    /// Receiver port to connect to
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Size of the batches written to the connection
+    pub fn buffer_size(mut self, buffer_size: BufferSize) -> Self {
+        self.config.buffer_size = buffer_size;
+        self
+    }
+
+    /// Stream over TCP or as sequenced UDP datagrams
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// Wire encoding of the stream
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.config.codec = codec;
+        self
+    }
+
+    /// Maximum number of reconnection attempts on failure
+    pub fn reconnect_attempts(mut self, attempts: u32) -> Self {
+        self.config.reconnect_attempts = attempts;
+        self
+    }
+
+    /// Keep trying to connect until the receiver is reachable
+    pub fn wait_for_server(mut self, wait: bool) -> Self {
+        self.config.wait_for_server = wait;
+        self
+    }
+
+    /// Milliseconds of recently sent audio replayed after a reconnect
+    pub fn resend_ms(mut self, resend_ms: u64) -> Self {
+        self.config.resend_ms = resend_ms;
+        self
+    }
+
+    /// Capture from this input device instead of the host's default
+    pub fn device(mut self, device: DeviceSelector) -> Self {
+        self.config.device = Some(device);
+        self
+    }
+
+    /// Stream a generated test tone instead of capturing from the default
+    /// input device
+    pub fn mock_input(mut self, mock_input: bool) -> Self {
+        self.config.mock_input = mock_input;
+        self
+    }
+
+    /// Test signal generated with `mock_input`
+    pub fn mock_signal(mut self, signal: MockSignal) -> Self {
+        self.config.mock_signal = signal;
+        self
+    }
+
+    /// Create the transmitter without starting it
+    pub fn build(self) -> Transmitter {
+        Transmitter::new(self.config)
+    }
+}
+
+/// How encoded audio is put on a connection
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// As is, for `--raw-output-compat`
+    Raw,
+    /// Each batch as one frame
+    Batch,
+    /// In frames of this many bytes (`--frame-ms`)
+    Fixed(usize),
+}
+
+impl Framing {
+    fn wrap(self, audio: Vec<u8>) -> Vec<u8> {
+        match self {
+            Framing::Raw => audio,
+            Framing::Batch => frame(&audio),
+            Framing::Fixed(frame_len) => frame_chunks(&audio, frame_len),
+        }
+    }
+}
+
+/// Send the audio kept in `resend_buffer` on a new connection, so the
+/// receiver can fill the gap left by the old one
+///
+/// The audio is sent with `framing`, as one batch.
+async fn replay_recent_audio(
+    stream: &mut TcpStream,
+    resend_buffer: &ResendBuffer,
+    framing: Framing,
+) {
+    if resend_buffer.is_empty() {
+        return;
+    }
+    let replay = resend_buffer.contents();
+    debug!("Replaying {} bytes of recent audio", replay.len());
+    let replay = framing.wrap(replay);
+    if let Err(e) = stream.write_all(&replay).await {
+        error!("Failed to replay recent audio: {e}");
+    }
+}
+
+/// Batches queued for the duplicate receiver before new ones are dropped
+const DUPLICATE_QUEUE_BATCHES: usize = 64;
+
+/// Delay between connection attempts to an unreachable duplicate receiver
+const DUPLICATE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Send a copy of the stream to a second receiver at `addr`
+///
+/// The copy is written by its own task, so a slow or unreachable duplicate
+/// never holds up the main stream: batches arriving while it is
+/// disconnected, or while its queue is full, are dropped, and no audio is
+/// replayed after it reconnects. With a `header` each connection starts with
+/// it, and batches are sent with the main stream's `framing`.
+///
+/// # Returns
+///
+/// The queue to put encoded batches on; the task ends when it is dropped
+fn spawn_duplicate(
+    addr: String,
+    header: Option<StreamHeader>,
+    framing: Framing,
+) -> mpsc::Sender<Vec<u8>> {
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(DUPLICATE_QUEUE_BATCHES);
+    tokio::spawn(async move {
+        let mut stream = None;
+        let mut next_attempt = Instant::now();
+        while let Some(audio) = rx.recv().await {
+            if stream.is_none() && Instant::now() >= next_attempt {
+                match connect(&addr, header.as_ref()).await {
+                    Ok(connected) => {
+                        info!("Sending a copy of the stream to {addr}");
+                        stream = Some(connected);
+                    }
+                    Err(e) => {
+                        debug!("Duplicate receiver unreachable: {e}");
+                        next_attempt = Instant::now() + DUPLICATE_RETRY_INTERVAL;
+                    }
+                }
+            }
+            let audio = framing.wrap(audio);
+            if let Some(connected) = &mut stream
+                && let Err(e) = connected.write_all(&audio).await
+            {
+                warn!("Lost the duplicate receiver at {addr}: {e}");
+                stream = None;
+                next_attempt = Instant::now() + DUPLICATE_RETRY_INTERVAL;
+            }
+        }
+    });
+    tx
+}
+
+/// Encodes the copy for the duplicate receiver with `--compare-codec`
+///
+/// The copy is encoded from the captured audio, as the main stream is, so
+/// the two receivers get the same source in two codecs rather than one
+/// codec transcoded into another. Recording both (`--debug-dump`, or the
+/// null backend) gives an A/B comparison of what the codec costs.
+#[derive(Debug)]
+struct CompareEncoder {
+    encoder: Encoder,
+    /// Sample sizes of the captured audio and of the audio the codec takes
+    sample_sizes: (usize, usize),
+    splitter: Option<FrameSplitter>,
+    /// Header the duplicate connection starts with
+    header: StreamHeader,
+    framing: Framing,
+}
+
+impl CompareEncoder {
+    /// Encoder for `compare` of audio captured for `codec` at `sample_rate`
+    /// Hz with `channels` channels, in frames of `frame_ms` if not 0
+    fn new(
+        compare: Codec,
+        codec: Codec,
+        sample_rate: u32,
+        channels: u16,
+        frame_ms: u64,
+    ) -> anyhow::Result<Self> {
+        let splitter = match frame_ms {
+            0 => None,
+            ms => Some(FrameSplitter::for_duration(
+                compare,
+                sample_rate,
+                channels,
+                ms,
+            )?),
+        };
+        Ok(Self {
+            encoder: Encoder::new(compare, sample_rate, channels),
+            sample_sizes: (codec.capture_sample_size(), compare.capture_sample_size()),
+            framing: match &splitter {
+                Some(splitter) => Framing::Fixed(splitter.frame_len()),
+                None => Framing::Batch,
+            },
+            splitter,
+            header: StreamHeader::new(compare, sample_rate, channels),
+        })
+    }
+
+    /// Encode a captured batch, returning the copy to send
+    fn encode(&mut self, captured: &[u8]) -> Vec<u8> {
+        let captured = convert_pcm(captured, self.sample_sizes.0, self.sample_sizes.1);
+        let encoded = self.encoder.encode(captured);
+        match &mut self.splitter {
+            Some(splitter) => splitter.push(&encoded),
+            None => encoded,
+        }
+    }
+}
+
+/// Interval between attempts to reach the primary receiver again while
+/// streaming to the standby
+const FAILBACK_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Connect to the standby receiver, if one is configured
+async fn connect_standby(
+    standby: Option<&str>,
+    header: Option<&StreamHeader>,
+) -> Option<TcpStream> {
+    let standby = standby?;
+    warn!("Receiver unreachable, failing over to the standby at {standby}");
+    match connect(standby, header).await {
+        Ok(stream) => {
+            info!("Connected to the standby receiver");
+            Some(stream)
+        }
+        Err(e) => {
+            error!("Standby receiver unreachable: {e}");
+            None
+        }
+    }
+}
+
+/// Returns to the primary receiver while streaming to the standby
+///
+/// Every [`FAILBACK_INTERVAL`] a connection to the primary receiver is
+/// attempted in the background, so the stream to the standby is not held
+/// up. Once one succeeds the transmitter switches back to it; recent audio
+/// is not replayed there, as the standby already played it.
+#[derive(Debug)]
+struct Failback {
+    primary: String,
+    header: Option<StreamHeader>,
+    next_attempt: Instant,
+    attempt: Option<tokio::task::JoinHandle<anyhow::Result<TcpStream>>>,
+}
+
+impl Failback {
+    fn new(primary: &str, header: Option<&StreamHeader>) -> Self {
+        Self {
+            primary: primary.to_string(),
+            header: header.cloned(),
+            next_attempt: Instant::now() + FAILBACK_INTERVAL,
+            attempt: None,
+        }
+    }
+
+    /// Start an attempt when one is due, and return the connection to the
+    /// primary receiver once an attempt has succeeded
+    async fn poll(&mut self) -> Option<TcpStream> {
+        if let Some(attempt) = self.attempt.take_if(|attempt| attempt.is_finished()) {
+            // The attempt has finished, so this does not wait
+            match attempt.await {
+                Ok(Ok(stream)) => return Some(stream),
+                Ok(Err(e)) => debug!("Primary receiver still unreachable: {e}"),
+                Err(e) => debug!("Failback attempt failed: {e}"),
+            }
+            self.next_attempt = Instant::now() + FAILBACK_INTERVAL;
+        } else if self.attempt.is_none() && Instant::now() >= self.next_attempt {
+            let primary = self.primary.clone();
+            let header = self.header.clone();
+            self.attempt = Some(tokio::spawn(async move {
+                connect(&primary, header.as_ref()).await
+            }));
+        }
+        None
+    }
+}
+
+impl Drop for Failback {
+    fn drop(&mut self) {
+        if let Some(attempt) = &self.attempt {
+            attempt.abort();
+        }
+    }
+}
+
+/// Captured audio kept while waiting for the receiver with `--wait-for-server`
+///
+/// Below the receiver's default `--max-latency-ms`, so the backlog sent on
+/// connecting is played rather than skipped.
+const WAIT_BUFFER_MS: u64 = 500;
+
+/// Delay between connection attempts while waiting for the receiver
+const WAIT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep trying to connect to the receiver, buffering captured audio meanwhile
+///
+/// Packets arriving on `rx` are kept while waiting; once more than
+/// `buffer_limit` bytes have accumulated, the oldest are dropped, so the
+/// buffer always holds the most recent audio.
+///
+/// # Returns
+///
+/// The connected stream and the buffered audio, or `None` if capture stopped
+/// before the receiver became reachable
+async fn wait_for_server_connection(
+    server_addr: &str,
+    header: Option<&StreamHeader>,
+    rx: &mut CaptureReceiver,
+    buffer_limit: usize,
+) -> Option<(TcpStream, Vec<u8>)> {
+    let mut buffered = VecDeque::new();
+    let mut attempts = 0u64;
+
+    loop {
+        let retry_at = Instant::now() + WAIT_RETRY_INTERVAL;
+        let attempt = connect(server_addr, header);
+        tokio::pin!(attempt);
+        let result = loop {
+            tokio::select! {
+                result = &mut attempt => break result,
+                packet = rx.recv() => {
+                    buffered.push_back(packet?);
+                    drop_oldest(&mut buffered, buffer_limit);
+                }
+            }
+        };
+
+        match result {
+            Ok(stream) => return Some((stream, buffered.into_iter().flatten().collect())),
+            Err(e) => {
+                attempts += 1;
+                debug!("Receiver not available yet (attempt {attempts}): {e}");
+            }
+        }
+
+        let retry = tokio::time::sleep_until(retry_at);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                () = &mut retry => break,
+                packet = rx.recv() => {
+                    buffered.push_back(packet?);
+                    drop_oldest(&mut buffered, buffer_limit);
+                }
+            }
+        }
+    }
+}
+
+/// Take every packet currently queued in `rx` without waiting
+fn take_queued(rx: &mut CaptureReceiver) -> VecDeque<Vec<u8>> {
+    let mut queued = VecDeque::new();
+    while let Ok(packet) = rx.try_recv() {
+        queued.push_back(packet);
+    }
+    queued
+}
+
+/// Drop the oldest whole packets until at most `limit` bytes remain
+///
+/// Returns the number of bytes dropped.
+fn drop_oldest(packets: &mut VecDeque<Vec<u8>>, limit: usize) -> usize {
+    let mut remaining: usize = packets.iter().map(Vec::len).sum();
+    let mut dropped = 0;
+    while remaining > limit {
+        let Some(packet) = packets.pop_front() else {
+            break;
+        };
+        remaining -= packet.len();
+        dropped += packet.len();
+    }
+    dropped
+}
+
+/// Length of audio sent in one paced write, in milliseconds
+const PACING_CHUNK_MS: u32 = 2;
+
+/// Pacing rate relative to the nominal stream rate
+///
+/// Slightly faster than real time so a backlog (e.g. after a stall, or a
+/// capture clock running fast) drains instead of growing.
+const PACING_HEADROOM: f64 = 1.05;
+
+/// Token bucket that spreads writes over the real-time duration of the audio
+///
+/// Large capture buffers otherwise leave the host as one burst per callback,
+/// which on Wi-Fi ends up queued in the access point and adds jitter. Each
+/// batch is split into chunks of [`PACING_CHUNK_MS`] that are released at the
+/// stream's byte rate, with a burst allowance of one chunk.
+#[derive(Debug)]
+struct Pacer {
+    bytes_per_second: f64,
+    chunk_size: usize,
+    /// Bytes that may be sent now; negative while a write is being delayed
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Pacer {
+    /// Create a pacer for a stream with the given frame rate and frame size in bytes
+    fn new(frame_rate: u32, frame_size: usize) -> Self {
+        let chunk_frames = (frame_rate / 1000 * PACING_CHUNK_MS).max(1);
+        let chunk_size = chunk_frames as usize * frame_size;
+        Self {
+            bytes_per_second: f64::from(frame_rate) * frame_size as f64 * PACING_HEADROOM,
+            chunk_size,
+            tokens: chunk_size as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `len` bytes from the bucket, returning how long to wait before sending them
+    fn delay_for(&mut self, len: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.bytes_per_second)
+            .min(self.chunk_size as f64);
+
+        self.tokens -= len as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second)
+        }
+    }
+
+    /// Write `data` to `stream` in paced chunks
+    async fn write_all(&mut self, stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+        for chunk in data.chunks(self.chunk_size) {
+            let delay = self.delay_for(chunk.len(), Instant::now());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            stream.write_all(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Recently sent audio kept for replay after a reconnect
+///
+/// When the receiver restarts, audio written to the old connection just before
+/// it failed never reaches the new virtual microphone. Replaying the last few hundred
+/// milliseconds narrows the audible gap at the cost of briefly repeating audio
+/// the old receiver may already have played.
+///
+/// The buffer holds encoded audio and only ever drops whole frames, so replayed
+/// data stays channel-aligned.
+#[derive(Debug)]
+struct ResendBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    frame_size: usize,
 }
 
-impl ToS16 for f32 {
-    fn to_s16(self) -> i16 {
-        (self.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+impl ResendBuffer {
+    /// Create a buffer holding `duration_ms` of audio with the given frame rate
+    /// and frame size in bytes
+    ///
+    /// A duration of 0 creates a buffer that never stores anything.
+    fn new(duration_ms: u64, frame_rate: u32, frame_size: usize) -> Self {
+        let frames = frame_rate as u64 * duration_ms / 1000;
+        Self {
+            data: VecDeque::new(),
+            capacity: frames as usize * frame_size,
+            frame_size,
+        }
     }
-}
 
-impl ToS16 for i16 {
-    fn to_s16(self) -> i16 {
-        self
+    /// Append sent audio, discarding the oldest whole frames beyond capacity
+    fn push(&mut self, audio: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.data.extend(audio);
+        if self.data.len() > self.capacity {
+            let excess = self.data.len() - self.capacity;
+            let drop = excess.div_ceil(self.frame_size) * self.frame_size;
+            self.data.drain(..drop.min(self.data.len()));
+        }
     }
-}
 
-impl ToS16 for u16 {
-    fn to_s16(self) -> i16 {
-        (self as i32 - 32768) as i16
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Copy of the buffered audio, oldest first
+    fn contents(&self) -> Vec<u8> {
+        self.data.iter().copied().collect()
     }
 }
 
-/// Run the transmitter with the given configuration
+/// Print commands for receiving this transmitter's stream with external tools
 ///
-/// This function captures audio from the default microphone and streams it to
-/// a remote receiver over TCP. It supports automatic reconnection if the connection
-/// is lost.
+/// The default input device is probed for the rate and channel count the
+/// transmitter would capture with, so the commands match the actual stream.
 ///
 /// # Arguments
 ///
-/// * `host` - Server address to connect to (e.g., "127.0.0.1" or "192.168.1.100")
-/// * `port` - Server port to connect to
-/// * `buffer_size` - Audio buffer size in bytes (affects latency)
-/// * `reconnect_attempts` - Maximum number of reconnection attempts on failure
-/// * `verbose` - Enable verbose logging output
+/// * `config` - Transmitter configuration; `port` and `raw_output_compat` are used
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on successful completion, or an error if audio capture or connection fails
+/// Returns `Ok(())` once the commands are printed, or an error if the input
+/// device cannot be queried
+pub fn print_pipeline(config: &TransmitterConfig) -> anyhow::Result<()> {
+    let (sample_rate, channels) = if config.mock_input {
+        let mock = mock_config(false, config.raw_output_compat);
+        (mock.sample_rate.0, mock.channels)
+    } else {
+        let device = find_input_device(config.device.as_ref())?;
+        let capture = capture_config(&device, false, config.raw_output_compat)?;
+        (capture.sample_rate().0, capture.channels())
+    };
+    print!("{}", pipeline_commands(config.port, sample_rate, channels));
+    Ok(())
+}
+
+/// Choose the capture configuration: 48 kHz for AES67 and raw compat output,
+/// otherwise the device default
+fn capture_config(
+    device: &cpal::Device,
+    aes67: bool,
+    raw_output_compat: bool,
+) -> anyhow::Result<cpal::SupportedStreamConfig> {
+    if aes67 {
+        input_config_at_rate(device, AES67_SAMPLE_RATE)
+    } else if raw_output_compat {
+        input_config_at_rate(device, RAW_COMPAT_SAMPLE_RATE)
+    } else {
+        Ok(device.default_input_config()?)
+    }
+}
+
+/// Codec carrying samples of `format` without conversion, for
+/// `--passthrough`
 ///
-/// # Example
+/// 16-bit devices stream S16LE (unsigned samples are only re-centred) and
+/// float devices F32LE. 32-bit integer devices stream S24LE: their samples
+/// are nearly always 24-bit audio in 32-bit words, and the lowest 8 bits are
+/// dropped.
+pub fn passthrough_codec(format: cpal::SampleFormat) -> anyhow::Result<Codec> {
+    match format {
+        cpal::SampleFormat::I16 | cpal::SampleFormat::U16 => Ok(Codec::S16LE),
+        cpal::SampleFormat::I32 => Ok(Codec::S24LE),
+        cpal::SampleFormat::F32 => Ok(Codec::F32LE),
+        _ => Err(anyhow::anyhow!(
+            "Unsupported sample format for --passthrough: {format:?}"
+        )),
+    }
+}
+
+/// Stream configuration for `--mock-input`: 48 kHz for AES67 and raw compat
+/// output, otherwise the receiver's default format
+fn mock_config(aes67: bool, raw_output_compat: bool) -> cpal::StreamConfig {
+    let default = AudioConfig::default();
+    let sample_rate = if aes67 {
+        AES67_SAMPLE_RATE
+    } else if raw_output_compat {
+        RAW_COMPAT_SAMPLE_RATE
+    } else {
+        default.sample_rate
+    };
+    cpal::StreamConfig {
+        channels: default.channels,
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    }
+}
+
+/// Find an input configuration running at `sample_rate`
 ///
-/// ```no_run
-/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-/// rsonance::transmitter::run_transmitter(
-///     "127.0.0.1".to_string(),
-///     8080,
-///     4096,
-///     5,
-///     true
-/// ).await?;
-/// # Ok(())
-/// # }
-/// ```
-pub async fn run_transmitter(
-    host: String,
-    port: u16,
+/// The device's default configuration is used if it already runs at that
+/// rate; otherwise the first supported range covering it with the default
+/// channel count is chosen.
+fn input_config_at_rate(
+    device: &cpal::Device,
+    sample_rate: u32,
+) -> anyhow::Result<cpal::SupportedStreamConfig> {
+    let default = device.default_input_config()?;
+    if default.sample_rate().0 == sample_rate {
+        return Ok(default);
+    }
+
+    device
+        .supported_input_configs()?
+        .find(|range| {
+            range.channels() == default.channels()
+                && range.min_sample_rate().0 <= sample_rate
+                && sample_rate <= range.max_sample_rate().0
+        })
+        .map(|range| range.with_sample_rate(cpal::SampleRate(sample_rate)))
+        .ok_or_else(|| anyhow::anyhow!("Input device does not support {sample_rate} Hz capture"))
+}
+
+/// Send captured audio as AES67 RTP multicast until capture stops
+///
+/// The SDP describing the stream is logged so it can be imported into
+/// receivers that do not discover streams on their own.
+///
+/// # Arguments
+///
+/// * `aes67` - Multicast destination and payload encoding
+/// * `rx` - Channel receiving converted S16LE audio at 48 kHz
+/// * `channels` - Channel count of the captured audio
+/// * `buffer_size` - Batch size passed to [`next_batch`]
+/// * `max_batch_delay` - Maximum coalescing delay passed to [`next_batch`]
+async fn send_aes67(
+    aes67: &Aes67Config,
+    rx: &mut CaptureReceiver,
+    channels: u16,
     buffer_size: usize,
-    reconnect_attempts: u32,
-    verbose: bool,
+    max_batch_delay: Duration,
 ) -> anyhow::Result<()> {
-    let server_addr = format!("{host}:{port}");
+    let bind_addr = if aes67.destination.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    if aes67.destination.is_ipv4() {
+        socket.set_multicast_ttl_v4(MULTICAST_TTL)?;
+    }
+    socket.connect(aes67.destination).await?;
 
-    // Validate buffer size
-    validate_buffer_size(buffer_size)?;
+    let mut packetizer = RtpPacketizer::starting_now(aes67.encoding, channels);
+    info!(
+        "Sending AES67 RTP ({} {} Hz, {channels} channels) to {}",
+        aes67.encoding, AES67_SAMPLE_RATE, aes67.destination
+    );
+    info!(
+        "SDP:\n{}",
+        aes67.sdp(socket.local_addr()?.ip(), channels, packetizer.ssrc())
+    );
 
-    info!("Connecting to server at {server_addr}...");
+    while let Some(audio_data) = next_batch(rx, buffer_size, max_batch_delay).await {
+        for packet in packetizer.push(&audio_data) {
+            if let Err(e) = socket.send(&packet).await {
+                warn!("Failed to send RTP packet: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send captured audio to the receiver as sequenced UDP datagrams until
+/// capture stops
+///
+/// Nothing is connected, so there is nothing to reconnect: datagrams sent
+/// while the receiver is unreachable are lost, and streaming resumes as soon
+/// as it is back. See [`crate::udp`] for the format.
+///
+/// # Arguments
+///
+/// * `server_addr` - Receiver address in `host:port` form
+/// * `codec` - Wire encoding of the stream
+/// * `config` - Format of the captured audio
+/// * `rx` - Channel receiving captured audio
+/// * `buffer_size` - Batch size passed to [`next_batch`]
+/// * `max_batch_delay` - Maximum coalescing delay passed to [`next_batch`]
+/// * `frame_len` - Length of the `--frame-ms` frames, each sent in datagrams
+///   of its own
+/// * `control` - Pause requests of an embedded [`Transmitter`]
+#[allow(clippy::too_many_arguments)]
+async fn send_udp(
+    server_addr: &str,
+    codec: Codec,
+    config: &cpal::StreamConfig,
+    rx: &mut CaptureReceiver,
+    buffer_size: usize,
+    max_batch_delay: Duration,
+    frame_len: Option<usize>,
+    mut control: Option<watch::Receiver<RunState>>,
+) -> anyhow::Result<()> {
+    let addr = tokio::net::lookup_host(server_addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{server_addr} has no addresses"))?;
+    let bind_addr = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(addr).await?;
 
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .ok_or_else(|| anyhow::anyhow!("No input device available"))?;
+    let header = StreamHeader::new(codec, config.sample_rate.0, config.channels);
+    let (_, frame_size) = codec.wire_format(config.sample_rate.0, config.channels);
+    let mut encoder = Encoder::new(codec, config.sample_rate.0, config.channels);
+    let mut packetizer = Packetizer::new(&header, frame_size);
+    let mut splitter = frame_len.map(FrameSplitter::new);
+    info!("Sending UDP datagrams to the receiver at {addr}");
 
-    let config = device.default_input_config()?;
-    let sample_format = config.sample_format();
-    let config: cpal::StreamConfig = config.into();
+    // Set while sends fail, so an unreachable receiver is reported once
+    let mut failing = false;
+    loop {
+        if let Some(control) = &mut control
+            && *control.borrow() == RunState::Paused
+        {
+            wait_while_paused(control, rx).await;
+        }
+        let Some(batch) = next_batch(rx, buffer_size, max_batch_delay).await else {
+            break;
+        };
 
-    if verbose {
-        info!(
-            "Using audio format: {sample_format:?} at {} Hz with {} channels",
-            config.sample_rate.0, config.channels
-        );
-        debug!("Buffer size: {buffer_size} bytes");
-        debug!("Max reconnection attempts: {reconnect_attempts}");
+        let audio = encoder.encode(batch);
+        let (audio, frame_len) = match &mut splitter {
+            Some(splitter) => (splitter.push(&audio), splitter.frame_len()),
+            None => (audio, usize::MAX),
+        };
+        let mut result = Ok(());
+        for frame in audio.chunks(frame_len) {
+            for datagram in packetizer.push(frame) {
+                if let Err(e) = socket.send(&datagram).await {
+                    result = Err(e);
+                }
+            }
+        }
+        match result {
+            Err(e) if !failing => {
+                warn!("Failed to send to the receiver at {addr}: {e}");
+                failing = true;
+            }
+            Ok(()) if failing => {
+                info!("Sending to the receiver at {addr} again");
+                failing = false;
+            }
+            _ => {}
+        }
     }
 
-    let tcp_stream = TcpStream::connect(&server_addr).await?;
-    info!("Connected to server successfully");
+    Ok(())
+}
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+/// Longest `--frame-ms` accepted
+const MAX_FRAME_MS: u64 = 1000;
 
-    let err_fn = move |err| {
-        error!("Audio stream error: {err}");
-    };
+/// Sample rates accepted by `--sample-rate`
+const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8000..=192_000;
 
-    let stream = match sample_format {
-        cpal::SampleFormat::F32 => build_input_stream::<f32>(&device, &config, tx, err_fn)?,
-        cpal::SampleFormat::I16 => build_input_stream::<i16>(&device, &config, tx, err_fn)?,
-        cpal::SampleFormat::U16 => build_input_stream::<u16>(&device, &config, tx, err_fn)?,
-        _ => {
-            return Err(anyhow::anyhow!(
-                "Unsupported sample format: {:?}",
-                sample_format
-            ));
-        }
-    };
+/// Delay before racing the next address while earlier attempts are still
+/// pending, as recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
 
-    stream.play()?;
-    info!("Started streaming microphone audio... Press Ctrl+C to stop.");
+/// Connect to the receiver with Nagle's algorithm disabled
+///
+/// When the host resolves to several addresses (IPv4 and IPv6, or a
+/// multi-homed receiver), connections are raced Happy Eyeballs style, see
+/// [`race_connections`], so an unreachable address does not hold up the
+/// others.
+///
+/// Audio is already batched by [`next_batch`], so letting the kernel delay
+/// small writes would only add latency.
+///
+/// # Arguments
+///
+/// * `server_addr` - Receiver address in `host:port` form
+/// * `header` - Stream header to open the connection with, if any
+///
+/// Returns the connected stream, or an error if the connection fails
+async fn connect(server_addr: &str, header: Option<&StreamHeader>) -> anyhow::Result<TcpStream> {
+    let addrs = interleave_families(tokio::net::lookup_host(server_addr).await?.collect());
+    if addrs.is_empty() {
+        return Err(anyhow::anyhow!("{server_addr} has no addresses"));
+    }
+    let mut stream = race_connections(&addrs)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to {server_addr}: {e}"))?;
+    stream.set_nodelay(true)?;
+    if let Some(header) = header {
+        stream.write_all(&header.encode()).await?;
+    }
+    Ok(stream)
+}
 
-    let mut tcp_stream = tcp_stream;
-    let mut reconnect_attempts_count = 0;
-    let max_reconnect_attempts = reconnect_attempts;
+/// Order addresses alternating between IPv6 and IPv4, starting with the
+/// family of the first address
+///
+/// The resolver's order within each family is kept.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+/// Connect to the first of `addrs` that answers
+///
+/// Attempts start [`CONNECTION_ATTEMPT_DELAY`] apart, or as soon as the
+/// previous one fails, and run concurrently; the first to succeed wins and
+/// the others are cancelled.
+///
+/// # Returns
+///
+/// The winning stream, or an error listing why every address failed
+async fn race_connections(addrs: &[SocketAddr]) -> anyhow::Result<TcpStream> {
+    let mut pending = addrs.iter().copied().peekable();
+    let mut attempts = JoinSet::new();
+    let mut errors = Vec::new();
 
     loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => start_attempt(&mut attempts, addr),
+                None => return Err(anyhow::anyhow!(errors.join("; "))),
+            }
+        }
+
         tokio::select! {
-            data = rx.recv() => {
-                match data {
-                    Some(audio_data) => {
-                        if let Err(e) = tcp_stream.write_all(&audio_data).await {
-                            error!("Failed to send audio data: {e}");
-
-                            if reconnect_attempts_count < max_reconnect_attempts {
-                                warn!("Attempting to reconnect... ({}/{})",
-                                        reconnect_attempts_count + 1, max_reconnect_attempts);
-
-                                match TcpStream::connect(&server_addr).await {
-                                    Ok(new_stream) => {
-                                        tcp_stream = new_stream;
-                                        reconnect_attempts_count = 0;
-                                        info!("Reconnected successfully");
-                                    }
-                                    Err(e) => {
-                                        error!("Reconnection failed: {e}");
-                                        reconnect_attempts_count += 1;
-                                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                                    }
-                                }
-                            } else {
-                                return Err(anyhow::anyhow!("Max reconnection attempts reached"));
-                            }
-                        } else {
-                            reconnect_attempts_count = 0;
-                        }
+            Some(finished) = attempts.join_next() => match finished {
+                Ok((addr, Ok(stream))) => {
+                    debug!("Connected to {addr}");
+                    return Ok(stream);
+                }
+                Ok((addr, Err(e))) => {
+                    debug!("Connection to {addr} failed: {e}");
+                    errors.push(format!("{addr}: {e}"));
+                    if let Some(addr) = pending.next() {
+                        start_attempt(&mut attempts, addr);
                     }
-                    None => break,
+                }
+                Err(e) => errors.push(e.to_string()),
+            },
+            () = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if pending.peek().is_some() => {
+                if let Some(addr) = pending.next() {
+                    start_attempt(&mut attempts, addr);
                 }
             }
         }
     }
+}
 
-    Ok(())
+fn start_attempt(
+    attempts: &mut JoinSet<(SocketAddr, std::io::Result<TcpStream>)>,
+    addr: SocketAddr,
+) {
+    debug!("Trying {addr}");
+    attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+}
+
+/// Receive the next batch of captured audio, coalescing small packets
+///
+/// Waits for at least one packet, then keeps appending packets that arrive
+/// until `target_size` bytes are collected or `max_delay` has elapsed since
+/// the first packet. This turns many tiny capture callbacks into fewer TCP
+/// writes while bounding the extra latency to `max_delay`.
+///
+/// # Arguments
+///
+/// * `rx` - Channel receiving converted audio packets
+/// * `target_size` - Batch size in bytes after which the batch is sent immediately
+/// * `max_delay` - Maximum time to wait for further packets
+///
+/// # Returns
+///
+/// The coalesced batch, or `None` once the channel is closed and drained
+async fn next_batch(
+    rx: &mut CaptureReceiver,
+    target_size: usize,
+    max_delay: Duration,
+) -> Option<Vec<u8>> {
+    let mut batch = rx.recv().await?;
+    let deadline = Instant::now() + max_delay;
+
+    while batch.len() < target_size {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(packet)) => batch.extend_from_slice(&packet),
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    Some(batch)
 }
 
 /// Build an input stream for the specified audio sample type
 ///
 /// This function creates a CPAL input stream that captures audio data and sends it
-/// through the provided channel. It handles different sample formats (F32, I16, U16)
-/// and converts them all to S16LE format for compatibility.
+/// through the provided channel. It handles different sample formats (F32, I16, U16,
+/// I32) and converts them all to S16LE format for compatibility, or to S24LE
+/// or F32LE when `taps` are set up for 24-bit or float capture.
 ///
 /// # Arguments
 ///
@@ -178,6 +2188,10 @@ pub async fn run_transmitter(
 /// * `config` - Audio stream configuration (sample rate, channels, etc.)
 /// * `tx` - Channel sender for audio data
 /// * `err_fn` - Error callback function for stream errors
+/// * `realtime` - Request real-time scheduling for the capture callback thread
+///   on its first invocation
+/// * `taps` - Sidetone output, level meter and anomaly detector to copy the
+///   converted audio to, and the resampler to the stream's rate
 ///
 /// # Returns
 ///
@@ -185,18 +2199,29 @@ pub async fn run_transmitter(
 fn build_input_stream<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    tx: mpsc::UnboundedSender<Vec<u8>>,
+    tx: CaptureSender,
     err_fn: impl Fn(cpal::StreamError) + Send + 'static,
+    realtime: bool,
+    mut taps: CaptureTaps,
 ) -> anyhow::Result<cpal::Stream>
 where
     T: ToS16,
 {
+    let mut promote_pending = realtime;
     let stream = device.build_input_stream(
         config,
         move |data: &[T], _| {
-            let converted_data = convert_to_s16le(data);
+            if promote_pending {
+                promote_pending = false;
+                promote_current_thread("Audio capture");
+            }
+            let converted_data = match taps.sample_size {
+                4 => convert_to_f32le(data),
+                3 => convert_to_s24le(data),
+                _ => convert_to_s16le(data),
+            };
             debug!("Audio packet captured: {} bytes", converted_data.len());
-            if let Err(e) = tx.send(converted_data) {
+            if let Err(e) = tx.send(taps.process(converted_data)) {
                 error!("Failed to send audio data to channel: {e}");
             }
         },
@@ -207,6 +2232,78 @@ where
     Ok(stream)
 }
 
+/// Local consumers of the captured audio, fed from the capture callback
+struct CaptureTaps {
+    /// Notch filters for mains hum, applied before the taps
+    dehum: Option<Dehum>,
+    sidetone: Option<SidetoneTap>,
+    meter: Option<LevelMeter>,
+    anomaly: AnomalyDetector,
+    /// Set by the mute hotkey while silence is to be sent
+    muted: Option<Arc<AtomicBool>>,
+    /// Bytes per captured sample: 2 for S16LE, 3 for S24LE, 4 for F32LE
+    sample_size: usize,
+    /// Converter to the `--sample-rate` of the stream, applied after the
+    /// taps, which work at the capture rate
+    resampler: Option<Resampler>,
+}
+
+impl CaptureTaps {
+    /// Pass a block of captured audio to every tap, returning the audio to
+    /// stream
+    ///
+    /// Mains hum is removed first. While muted the block is silenced
+    /// instead, and the anomaly detector, which would take the silence for
+    /// a dead microphone, is skipped. The taps work on S16LE, so S24LE and
+    /// F32LE audio is reduced to 16 bits first.
+    fn process(&mut self, mut captured: Vec<u8>) -> Vec<u8> {
+        let muted = self
+            .muted
+            .as_ref()
+            .is_some_and(|muted| muted.load(Ordering::Relaxed));
+        if muted {
+            captured.fill(0);
+        } else if let Some(dehum) = &mut self.dehum {
+            dehum.process(&mut captured);
+        }
+        let reduced;
+        let s16le = if self.sample_size == 2 {
+            &captured[..]
+        } else {
+            reduced = convert_pcm(&captured, self.sample_size, 2);
+            &reduced[..]
+        };
+        if let Some(tap) = &self.sidetone {
+            tap.push(s16le);
+        }
+        if let Some(meter) = &self.meter {
+            meter.push(s16le);
+        }
+        if !muted {
+            for event in self.anomaly.push(s16le) {
+                log_anomaly(event);
+            }
+        }
+        match &mut self.resampler {
+            Some(resampler) => resampler.push(&captured),
+            None => captured,
+        }
+    }
+}
+
+/// Report a change in the capture anomaly state
+fn log_anomaly(event: AnomalyEvent) {
+    match event {
+        AnomalyEvent::Started(Anomaly::Clipping) => {
+            warn!("Microphone input is clipping, lower the input gain")
+        }
+        AnomalyEvent::Started(Anomaly::Silence) => {
+            warn!("Microphone input is pure silence, check that it is not muted or disconnected")
+        }
+        AnomalyEvent::Cleared(anomaly) => info!("Microphone input {anomaly} has stopped"),
+    }
+}
+
 /// Convert audio samples to S16LE format for PulseAudio compatibility
 ///
 /// This function takes audio samples of any supported format (F32, I16, U16) and
@@ -226,10 +2323,33 @@ where
 /// - F32 samples are clamped to [-1.0, 1.0] range before conversion
 /// - U16 samples are converted by subtracting 32768 to center around zero
 /// - I16 samples are passed through unchanged
+/// - On aarch64 with NEON, F32 samples are converted four at a time
 fn convert_to_s16le<T: ToS16>(data: &[T]) -> Vec<u8> {
     let mut result = Vec::with_capacity(data.len() * 2);
+    T::extend_s16le(data, &mut result);
+    result
+}
+
+/// Convert audio samples to packed S24LE for the S24LE codec
+///
+/// F32 samples are clamped like in [`convert_to_s16le`]; 16-bit samples
+/// leave the low 8 bits zero and I32 samples drop their low 8 bits.
+fn convert_to_s24le<T: ToS16>(data: &[T]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() * 3);
+    for sample in data.iter().copied() {
+        result.extend_from_slice(&sample.to_s24().to_le_bytes()[..3]);
+    }
+    result
+}
+
+/// Convert audio samples to F32LE for the F32LE codec
+///
+/// F32 samples are copied unchanged; integer samples are scaled to -1.0
+/// to 1.0.
+fn convert_to_f32le<T: ToS16>(data: &[T]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() * 4);
     for sample in data.iter().copied() {
-        result.extend_from_slice(&sample.to_s16().to_le_bytes());
+        result.extend_from_slice(&sample.to_f32().to_le_bytes());
     }
     result
 }
@@ -295,6 +2415,62 @@ mod tests {
         assert_eq!(samples[2], 32767);
     }
 
+    #[test]
+    fn test_convert_to_s24le() {
+        let samples = |data: Vec<u8>| -> Vec<i32> {
+            data.chunks_exact(3)
+                .map(|chunk| i32::from_le_bytes([0, chunk[0], chunk[1], chunk[2]]) >> 8)
+                .collect()
+        };
+
+        assert_eq!(
+            samples(convert_to_s24le(&[0.0f32, 1.0, -1.0, 2.0])),
+            vec![0, S24_MAX, -S24_MAX, S24_MAX]
+        );
+        assert_eq!(
+            samples(convert_to_s24le(&[1i16, i16::MIN])),
+            vec![256, -(1 << 23)]
+        );
+        assert_eq!(
+            samples(convert_to_s24le(&[0u16, 32768])),
+            vec![-(1 << 23), 0]
+        );
+        // 24-in-32 audio keeps all 24 bits
+        assert_eq!(
+            samples(convert_to_s24le(&[0x1234_5600i32, -256])),
+            vec![0x12_3456, -1]
+        );
+        assert_eq!((0x1234_5600i32).to_s16(), 0x1234);
+    }
+
+    #[test]
+    fn test_convert_to_f32le_and_passthrough_codecs() {
+        let samples = |data: Vec<u8>| -> Vec<f32> {
+            data.chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect()
+        };
+
+        // Float samples pass unchanged, out of range ones included
+        assert_eq!(
+            samples(convert_to_f32le(&[0.1f32, -1.5, f32::MIN_POSITIVE])),
+            vec![0.1, -1.5, f32::MIN_POSITIVE]
+        );
+        assert_eq!(
+            samples(convert_to_f32le(&[i16::MIN, 16384])),
+            vec![-1.0, 0.5]
+        );
+        assert_eq!(samples(convert_to_f32le(&[0u16])), vec![-1.0]);
+        assert_eq!(samples(convert_to_f32le(&[i32::MIN])), vec![-1.0]);
+
+        use cpal::SampleFormat;
+        assert_eq!(passthrough_codec(SampleFormat::I16).unwrap(), Codec::S16LE);
+        assert_eq!(passthrough_codec(SampleFormat::U16).unwrap(), Codec::S16LE);
+        assert_eq!(passthrough_codec(SampleFormat::I32).unwrap(), Codec::S24LE);
+        assert_eq!(passthrough_codec(SampleFormat::F32).unwrap(), Codec::F32LE);
+        assert!(passthrough_codec(SampleFormat::F64).is_err());
+    }
+
     #[test]
     fn test_convert_empty_data() {
         let empty_f32: &[f32] = &[];
@@ -302,6 +2478,161 @@ mod tests {
         assert_eq!(result.len(), 0);
     }
 
+    #[test]
+    fn test_convert_f32_odd_length_matches_per_sample() {
+        // Lengths that are not a multiple of four exercise the remainder path
+        let f32_data: Vec<f32> = (0..11).map(|i| i as f32 / 5.0 - 1.0).collect();
+        let expected: Vec<u8> = f32_data
+            .iter()
+            .flat_map(|s| s.to_s16().to_le_bytes())
+            .collect();
+        assert_eq!(convert_to_s16le(&f32_data), expected);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_neon_matches_scalar() {
+        let f32_data: Vec<f32> = (0..1027)
+            .map(|i| (i as f32 * 0.37).sin() * 1.5)
+            .chain([f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -0.0])
+            .collect();
+        let mut expected = Vec::new();
+        extend_s16le_scalar(&f32_data, &mut expected);
+
+        let mut result = Vec::new();
+        // SAFETY: NEON is mandatory on aarch64.
+        unsafe { neon::extend_f32_s16le(&f32_data, &mut result) };
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn test_next_batch_coalesces_until_target_size() {
+        let (tx, mut rx) = capture_queue(0, 1000);
+        for _ in 0..4 {
+            tx.send(vec![0u8; 256]).unwrap();
+        }
+
+        let batch = next_batch(&mut rx, 512, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 512);
+
+        let batch = next_batch(&mut rx, 512, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 512);
+    }
+
+    #[tokio::test]
+    async fn test_next_batch_respects_max_delay() {
+        let (tx, mut rx) = capture_queue(0, 1000);
+        tx.send(vec![0u8; 256]).unwrap();
+
+        // Only one packet is available; the batch is flushed once the delay elapses
+        let batch = next_batch(&mut rx, 4096, Duration::from_millis(5))
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 256);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_next_batch_closed_channel() {
+        let (tx, mut rx) = capture_queue(0, 1000);
+        tx.send(vec![1u8; 10]).unwrap();
+        drop(tx);
+
+        let batch = next_batch(&mut rx, 4096, Duration::from_millis(5))
+            .await
+            .unwrap();
+        assert_eq!(batch, vec![1u8; 10]);
+        assert!(
+            next_batch(&mut rx, 4096, Duration::from_millis(5))
+                .await
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_resend_buffer_keeps_latest_whole_frames() {
+        // 10 ms of stereo at 1 kHz: 10 frames of 4 bytes
+        let mut buffer = ResendBuffer::new(10, 1000, 4);
+        assert!(buffer.is_empty());
+
+        buffer.push(&[1u8; 24]);
+        assert_eq!(buffer.contents(), vec![1u8; 24]);
+
+        // Overflowing by 6 bytes drops two whole frames (8 bytes)
+        buffer.push(&[2u8; 22]);
+        let contents = buffer.contents();
+        assert_eq!(contents.len(), 38);
+        assert_eq!(&contents[..16], &[1u8; 16]);
+        assert_eq!(&contents[16..], &[2u8; 22]);
+    }
+
+    #[test]
+    fn test_pacer_spreads_bytes_at_stream_rate() {
+        // 1 kHz, 4-byte frames: 2 ms chunks of 8 bytes at 4200 bytes/s
+        let mut pacer = Pacer::new(1000, 4);
+        assert_eq!(pacer.chunk_size, 8);
+        let start = Instant::now();
+
+        // The first chunk fits the burst allowance
+        assert_eq!(pacer.delay_for(8, start), Duration::ZERO);
+        // The next one has to wait for its share of the rate
+        let delay = pacer.delay_for(8, start);
+        assert!((delay.as_secs_f64() - 8.0 / 4200.0).abs() < 1e-6);
+
+        // Idle time refills the bucket, but never beyond one chunk
+        assert_eq!(
+            pacer.delay_for(8, start + Duration::from_millis(100)),
+            Duration::ZERO
+        );
+        assert!(pacer.delay_for(8, start + Duration::from_millis(100)) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_newest_whole_packets() {
+        let mut packets: VecDeque<Vec<u8>> = (1..=4u8).map(|i| vec![i; 100]).collect();
+        assert_eq!(drop_oldest(&mut packets, 250), 200);
+        assert_eq!(packets, [vec![3u8; 100], vec![4u8; 100]]);
+
+        assert_eq!(drop_oldest(&mut packets, 1000), 0);
+        assert_eq!(packets.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_take_queued() {
+        let (tx, mut rx) = capture_queue(0, 1000);
+        tx.send(vec![1u8]).unwrap();
+        tx.send(vec![2u8]).unwrap();
+
+        assert_eq!(take_queued(&mut rx), [vec![1u8], vec![2u8]]);
+        assert!(take_queued(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn test_backpressure_policy_parse() {
+        for policy in [
+            BackpressurePolicy::Buffer,
+            BackpressurePolicy::Drop,
+            BackpressurePolicy::Disconnect,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<BackpressurePolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("block".parse::<BackpressurePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_resend_buffer_disabled() {
+        let mut buffer = ResendBuffer::new(0, 48000, 4);
+        buffer.push(&[0u8; 4096]);
+        assert!(buffer.is_empty());
+    }
+
     #[test]
     fn test_convert_f32_clamping() {
         // Test values outside [-1.0, 1.0] range
@@ -319,4 +2650,138 @@ mod tests {
         assert_eq!(samples[2], i16::MAX); // Infinity clamped to 1.0
         assert_eq!(samples[3], -32767); // -Infinity clamped to -1.0
     }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let order: Vec<String> = interleave_families(addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            order,
+            ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"]
+        );
+    }
+
+    #[test]
+    fn test_embedded_transmitter_pauses_and_stops() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut transmitter = Transmitter::builder()
+            .host("127.0.0.1")
+            .port(listener.local_addr().unwrap().port())
+            .mock_input(true)
+            .build();
+        transmitter.start().unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 4096];
+        assert!(receiver.read(&mut buf).unwrap() > 0);
+
+        // Audio already sent still arrives, then nothing until resumed
+        transmitter.pause();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        loop {
+            match receiver.read(&mut buf) {
+                Ok(n) => assert!(n > 0, "connection closed while paused"),
+                Err(e) => {
+                    assert!(matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ));
+                    break;
+                }
+            }
+        }
+
+        transmitter.start().unwrap();
+        receiver.set_read_timeout(None).unwrap();
+        assert!(receiver.read(&mut buf).unwrap() > 0);
+
+        transmitter.stop().unwrap();
+        while receiver.read(&mut buf).unwrap() > 0 {}
+    }
+
+    /// Local address with nothing listening on it
+    fn closed_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_race_connections_skips_unreachable_addresses() {
+        use std::os::fd::AsRawFd;
+
+        // A listener with a full accept queue drops new connection attempts,
+        // so connecting to it hangs like an unreachable address
+        let hanging = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let hanging_addr = hanging.local_addr().unwrap();
+        // SAFETY: listen() on a valid listening socket only changes its backlog
+        assert_eq!(unsafe { libc::listen(hanging.as_raw_fd(), 0) }, 0);
+        let _queued = std::net::TcpStream::connect(hanging_addr).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+
+        let started = Instant::now();
+        let stream = race_connections(&[closed_addr(), hanging_addr, live])
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live);
+        // The live address was tried one attempt delay after the hanging one
+        let elapsed = started.elapsed();
+        assert!(elapsed >= CONNECTION_ATTEMPT_DELAY, "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_race_connections_reports_every_failure() {
+        let (first, second) = (closed_addr(), closed_addr());
+        let error = race_connections(&[first, second])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(&first.to_string()), "{error}");
+        assert!(error.contains(&second.to_string()), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_server_connection_buffers_recent_audio() {
+        let port = closed_addr().port();
+        let (tx, mut rx) = capture_queue(0, 1000);
+        for packet in 0..10u8 {
+            tx.send(vec![packet; 4]).unwrap();
+        }
+
+        let accept = tokio::spawn(async move {
+            // Come up after the first attempt has failed
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+                .await
+                .unwrap();
+            listener.accept().await.unwrap()
+        });
+
+        let (_stream, buffered) =
+            wait_for_server_connection(&format!("127.0.0.1:{port}"), None, &mut rx, 12)
+                .await
+                .unwrap();
+        accept.await.unwrap();
+        // Only the three most recent packets fit in 12 bytes
+        assert_eq!(buffered, [[7u8; 4], [8; 4], [9; 4]].concat());
+
+        // Capture stopping ends the wait
+        drop(tx);
+        assert!(
+            wait_for_server_connection(&closed_addr().to_string(), None, &mut rx, 12)
+                .await
+                .is_none()
+        );
+    }
 }
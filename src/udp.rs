@@ -0,0 +1,276 @@
+//! UDP transport with sequence numbers (`--transport udp`)
+//!
+//! Over TCP a lost packet holds back everything sent after it until it has
+//! been retransmitted, which on lossy Wi-Fi is heard as a stall. Over UDP
+//! each datagram stands on its own: the receiver plays what arrives in
+//! order, skips over lost datagrams and discards ones that arrive after a
+//! later one was already played.
+//!
+//! # Format
+//!
+//! Every datagram is the [`StreamHeader`] of the stream, a sequence number
+//! (`u32`, little-endian, counting up from 0 and wrapping) and up to
+//! [`MAX_PAYLOAD`] bytes of encoded audio in whole frames. Repeating the
+//! header costs a few bytes but lets the receiver start playing from any
+//! datagram.
+//!
+//! The receiver turns the datagrams of a transmitter back into a framed
+//! stream, so decoding, pre-buffering and the virtual microphone work as
+//! they do for TCP. A transmitter is considered gone once nothing arrived
+//! from it for [`SESSION_TIMEOUT`]; until then, datagrams from other
+//! addresses are ignored. Lost audio is neither resent nor concealed.
+
+use crate::listen::ListenAddr;
+use crate::protocol::{HEADER_LEN, Metadata, StreamHeader};
+use std::fmt;
+use std::net::UdpSocket;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Largest audio payload of a datagram, so datagrams fit a 1280-byte IPv6
+/// minimum MTU and are never fragmented
+pub const MAX_PAYLOAD: usize = 1200;
+
+/// Size of the sequence number following the stream header
+const SEQUENCE_LEN: usize = 4;
+
+/// Time without datagrams after which the receiver ends a transmitter's
+/// session
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest the receiver waits for a datagram before checking for shutdown
+/// and transmitters that went quiet
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A datagram this many sequence numbers behind the newest one is taken as
+/// a restarted transmitter rather than a late datagram
+const RESTART_DISTANCE: u32 = 1024;
+
+/// Transport carrying the stream between transmitter and receiver
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::udp::Transport;
+///
+/// let transport: Transport = "udp".parse().unwrap();
+/// assert_eq!(transport, Transport::Udp);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// One TCP connection per transmitter, every byte delivered in order
+    #[default]
+    Tcp,
+    /// Sequenced UDP datagrams, lost and late ones skipped
+    Udp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+        })
+    }
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "udp" => Ok(Transport::Udp),
+            _ => Err(anyhow::anyhow!(
+                "Unknown transport '{s}' (expected tcp or udp)"
+            )),
+        }
+    }
+}
+
+/// Splits encoded audio into sequenced datagrams
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::codec::Codec;
+/// use rsonance::protocol::StreamHeader;
+/// use rsonance::udp::{Packetizer, parse_datagram};
+///
+/// let header = StreamHeader::new(Codec::S16LE, 48000, 2);
+/// let mut packetizer = Packetizer::new(&header, 4);
+/// let datagrams = packetizer.push(&[0; 2000]);
+/// assert_eq!(datagrams.len(), 2);
+/// let (parsed, sequence, audio) = parse_datagram(&datagrams[1]).unwrap();
+/// assert_eq!((parsed, sequence, audio.len()), (header, 1, 800));
+/// ```
+#[derive(Debug)]
+pub struct Packetizer {
+    header: [u8; HEADER_LEN],
+    /// Largest payload in whole frames
+    payload_len: usize,
+    sequence: u32,
+}
+
+impl Packetizer {
+    /// Packetizer for a stream described by `header` with `frame_size`
+    /// bytes per encoded frame
+    pub fn new(header: &StreamHeader, frame_size: usize) -> Self {
+        let frame_size = frame_size.clamp(1, MAX_PAYLOAD);
+        // Datagrams are kept small, so the metadata is not repeated in them
+        let header = StreamHeader {
+            metadata: Metadata::default(),
+            ..header.clone()
+        };
+        let mut fixed = [0u8; HEADER_LEN];
+        fixed.copy_from_slice(&header.encode());
+        Self {
+            header: fixed,
+            payload_len: MAX_PAYLOAD - MAX_PAYLOAD % frame_size,
+            sequence: 0,
+        }
+    }
+
+    /// Datagrams carrying `audio`, which holds whole frames
+    pub fn push(&mut self, audio: &[u8]) -> Vec<Vec<u8>> {
+        audio
+            .chunks(self.payload_len)
+            .map(|payload| {
+                let mut datagram = Vec::with_capacity(HEADER_LEN + SEQUENCE_LEN + payload.len());
+                datagram.extend_from_slice(&self.header);
+                datagram.extend_from_slice(&self.sequence.to_le_bytes());
+                datagram.extend_from_slice(payload);
+                self.sequence = self.sequence.wrapping_add(1);
+                datagram
+            })
+            .collect()
+    }
+}
+
+/// Split a datagram into its stream header, sequence number and audio
+///
+/// Returns an error for a datagram that is not from a rsonance transmitter
+/// or carries an invalid header.
+pub fn parse_datagram(datagram: &[u8]) -> anyhow::Result<(StreamHeader, u32, &[u8])> {
+    if datagram.len() < HEADER_LEN + SEQUENCE_LEN {
+        return Err(anyhow::anyhow!(
+            "Datagram of {} bytes is too short",
+            datagram.len()
+        ));
+    }
+    let header = StreamHeader::parse(&datagram[..HEADER_LEN])?;
+    let (sequence, audio) = datagram[HEADER_LEN..].split_at(SEQUENCE_LEN);
+    let sequence = u32::from_le_bytes([sequence[0], sequence[1], sequence[2], sequence[3]]);
+    Ok((header, sequence, audio))
+}
+
+/// Bind the receiver's UDP socket on `addr`
+///
+/// Only `HOST:PORT` addresses can receive datagrams; a Unix socket path is
+/// an error.
+pub(crate) fn bind(addr: &ListenAddr) -> anyhow::Result<UdpSocket> {
+    let ListenAddr::Tcp { host, port } = addr else {
+        return Err(anyhow::anyhow!(
+            "Cannot receive UDP on {addr}: --transport udp needs HOST:PORT listen addresses"
+        ));
+    };
+    let socket = UdpSocket::bind((host.as_str(), *port))
+        .map_err(|e| anyhow::anyhow!("Cannot receive UDP on {addr}: {e}"))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+/// Where a datagram falls in the sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    /// The datagram after the last one played, or the first one
+    InOrder,
+    /// Newer than expected: this many datagrams before it were lost (or
+    /// arrive later and are discarded then)
+    AfterLoss(u32),
+    /// Older than a datagram already played; to be discarded
+    Late,
+}
+
+/// Orders the datagrams of one transmitter by their sequence numbers
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    /// Sequence number expected next
+    next: Option<u32>,
+}
+
+impl SequenceTracker {
+    /// Classify the datagram numbered `sequence`, moving past it unless it
+    /// is late
+    ///
+    /// A datagram far behind the newest one starts the count over, as a
+    /// transmitter that restarted counts from 0 again.
+    pub fn arrive(&mut self, sequence: u32) -> Arrival {
+        let arrival = match self.next {
+            None => Arrival::InOrder,
+            Some(next) => {
+                let ahead = sequence.wrapping_sub(next);
+                let behind = next.wrapping_sub(sequence);
+                if ahead == 0 {
+                    Arrival::InOrder
+                } else if behind <= RESTART_DISTANCE {
+                    return Arrival::Late;
+                } else if ahead < u32::MAX / 2 {
+                    Arrival::AfterLoss(ahead)
+                } else {
+                    Arrival::InOrder
+                }
+            }
+        };
+        self.next = Some(sequence.wrapping_add(1));
+        arrival
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+
+    #[test]
+    fn test_packetizer_keeps_frames_whole() {
+        // 6-byte S24LE stereo frames
+        let header = StreamHeader::new(Codec::S24LE, 48000, 2);
+        let mut packetizer = Packetizer::new(&header, 6);
+        let datagrams = packetizer.push(&[0; 1500]);
+        let sizes: Vec<usize> = datagrams
+            .iter()
+            .map(|datagram| parse_datagram(datagram).unwrap().2.len())
+            .collect();
+        assert_eq!(sizes, vec![1200, 300]);
+        let (_, sequence, _) = parse_datagram(&packetizer.push(&[0; 6])[0]).unwrap();
+        assert_eq!(sequence, 2);
+
+        assert!(parse_datagram(&datagrams[0][..HEADER_LEN + 2]).is_err());
+        assert!(parse_datagram(b"not a datagram from a transmitter").is_err());
+    }
+
+    #[test]
+    fn test_sequence_tracker() {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.arrive(7), Arrival::InOrder);
+        assert_eq!(tracker.arrive(8), Arrival::InOrder);
+        assert_eq!(tracker.arrive(11), Arrival::AfterLoss(2));
+        // 9 and 10 turn up after 11 was played
+        assert_eq!(tracker.arrive(9), Arrival::Late);
+        assert_eq!(tracker.arrive(11), Arrival::Late);
+        assert_eq!(tracker.arrive(12), Arrival::InOrder);
+
+        // Wrapping around
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.arrive(u32::MAX), Arrival::InOrder);
+        assert_eq!(tracker.arrive(1), Arrival::AfterLoss(1));
+        assert_eq!(tracker.arrive(u32::MAX), Arrival::Late);
+
+        // A restarted transmitter counts from 0 again
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.arrive(50_000), Arrival::InOrder);
+        assert_eq!(tracker.arrive(0), Arrival::InOrder);
+        assert_eq!(tracker.arrive(1), Arrival::InOrder);
+    }
+}
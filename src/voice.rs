@@ -0,0 +1,288 @@
+//! Receiver `--record-when voice`: record only around speech
+//!
+//! Long monitoring sessions are mostly silence or room noise. With
+//! `--record-when voice` the audio goes through a [`VoiceGate`] before the
+//! recorder, so only the stretches where someone speaks reach the file,
+//! back to back. Playback and everything else are not affected.
+//!
+//! The gate is a level detector, not a speech recognizer. It measures the
+//! RMS level of every [`WINDOW_MS`] window and opens when the level is at
+//! least [`MIN_VOICE_DBFS`] and [`ABOVE_FLOOR_DB`] above the background
+//! noise floor. The floor follows the quietest windows at once and rises
+//! towards louder ones by [`FLOOR_RISE_DB_PER_SEC`], so steady hum or fan
+//! noise closes the gate again within seconds of starting.
+//! The gate keeps the last `--record-preroll-ms` of closed audio and
+//! records it when it opens, so the first syllable is not cut, and stays
+//! open for `--record-postroll-ms` after the last voiced window.
+
+use crate::{AudioConfig, AudioFormat};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Length of the windows levels are measured over
+pub const WINDOW_MS: u64 = 20;
+
+/// Quietest level counted as voice, in dBFS
+pub const MIN_VOICE_DBFS: f64 = -50.0;
+
+/// How far above the noise floor voice must be, in dB
+pub const ABOVE_FLOOR_DB: f64 = 10.0;
+
+/// How fast the noise floor rises towards louder audio, in dB per second
+pub const FLOOR_RISE_DB_PER_SEC: f64 = 5.0;
+
+/// Level given to digital silence, in dBFS
+const SILENCE_DBFS: f64 = -120.0;
+
+/// Which audio `--record` keeps
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::voice::RecordWhen;
+///
+/// let when: RecordWhen = "voice".parse().unwrap();
+/// assert_eq!(when, RecordWhen::Voice);
+/// assert_eq!(RecordWhen::default().to_string(), "always");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordWhen {
+    /// Everything received
+    #[default]
+    Always,
+    /// Only the audio around speech, see [`VoiceGate`]
+    Voice,
+}
+
+impl fmt::Display for RecordWhen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RecordWhen::Always => "always",
+            RecordWhen::Voice => "voice",
+        })
+    }
+}
+
+impl FromStr for RecordWhen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(RecordWhen::Always),
+            "voice" => Ok(RecordWhen::Voice),
+            _ => Err(anyhow::anyhow!(
+                "Unknown recording mode '{s}' (expected always or voice)"
+            )),
+        }
+    }
+}
+
+/// Passes on the audio around speech and holds back the rest, see the
+/// module documentation
+///
+/// # Examples
+///
+/// ```
+/// use rsonance::voice::VoiceGate;
+/// use rsonance::{AudioConfig, AudioFormat};
+/// use std::time::Duration;
+///
+/// let config = AudioConfig { sample_rate: 8000, channels: 1, format: AudioFormat::S16LE };
+/// let mut gate = VoiceGate::new(Duration::from_millis(100), Duration::from_millis(500));
+/// // A second of silence is held back
+/// assert!(gate.push(&config, &[0; 16000]).is_empty());
+/// // A loud tone opens the gate, with the pre-roll in front of it
+/// let tone: Vec<u8> = (0..8000i16)
+///     .flat_map(|i| (if i % 8 < 4 { 8000i16 } else { -8000 }).to_le_bytes())
+///     .collect();
+/// assert_eq!(gate.push(&config, &tone).len(), 1600 + 16000);
+/// assert!(gate.is_open());
+/// ```
+#[derive(Debug)]
+pub struct VoiceGate {
+    preroll: Duration,
+    postroll: Duration,
+    /// Format of the audio so far; a change starts over
+    config: Option<AudioConfig>,
+    /// Start of the next window
+    pending: Vec<u8>,
+    /// The latest closed audio, up to the pre-roll
+    held: VecDeque<u8>,
+    /// Windows the gate stays open for, 0 while closed
+    open_windows: u64,
+    /// Background level in dBFS, `None` until the first window
+    noise_floor: Option<f64>,
+}
+
+impl VoiceGate {
+    /// Gate keeping `preroll` before and `postroll` after speech
+    pub fn new(preroll: Duration, postroll: Duration) -> Self {
+        Self {
+            preroll,
+            postroll,
+            config: None,
+            pending: Vec::new(),
+            held: VecDeque::new(),
+            open_windows: 0,
+            noise_floor: None,
+        }
+    }
+
+    /// Whether the audio currently passes
+    pub fn is_open(&self) -> bool {
+        self.open_windows > 0
+    }
+
+    /// Take in `pcm` in `config` and return what of it, and of the audio
+    /// held back before it, is to be recorded
+    ///
+    /// Audio is passed on in whole windows, so up to one window is held
+    /// until the next call.
+    pub fn push(&mut self, config: &AudioConfig, pcm: &[u8]) -> Vec<u8> {
+        if self.config.as_ref() != Some(config) {
+            *self = Self::new(self.preroll, self.postroll);
+            self.config = Some(config.clone());
+        }
+        let frame_size = config.frame_size().max(1);
+        let frames_per_window = (u64::from(config.sample_rate) * WINDOW_MS / 1000).max(1);
+        let window_len = frames_per_window as usize * frame_size;
+        let preroll_len = duration_frames(config, self.preroll) as usize * frame_size;
+        let postroll_windows = self.postroll.as_millis() as u64 / WINDOW_MS;
+        let rise = FLOOR_RISE_DB_PER_SEC * WINDOW_MS as f64 / 1000.0;
+
+        self.pending.extend_from_slice(pcm);
+        let whole = self.pending.len() - self.pending.len() % window_len;
+        let mut passed = Vec::new();
+        for window in self.pending[..whole].chunks(window_len) {
+            let level = rms_dbfs(window, &config.format);
+            let floor = match self.noise_floor {
+                Some(floor) if level > floor => (floor + rise).min(level),
+                _ => level,
+            };
+            self.noise_floor = Some(floor);
+            let voiced = level >= MIN_VOICE_DBFS && level >= floor + ABOVE_FLOOR_DB;
+
+            if voiced {
+                passed.extend(self.held.drain(..));
+                self.open_windows = postroll_windows + 1;
+            }
+            if self.open_windows > 0 {
+                passed.extend_from_slice(window);
+                self.open_windows -= 1;
+            } else {
+                self.held.extend(window);
+                let excess = self.held.len().saturating_sub(preroll_len);
+                self.held.drain(..excess);
+            }
+        }
+        self.pending.drain(..whole);
+        passed
+    }
+}
+
+/// Whole frames of `config` in `duration`
+fn duration_frames(config: &AudioConfig, duration: Duration) -> u64 {
+    (u128::from(config.sample_rate) * duration.as_millis() / 1000) as u64
+}
+
+/// RMS level of the samples in `pcm`, in dBFS
+fn rms_dbfs(pcm: &[u8], format: &AudioFormat) -> f64 {
+    let size = format.sample_size();
+    let samples = pcm.chunks_exact(size).map(|sample| match format {
+        AudioFormat::S16LE => f64::from(i16::from_le_bytes([sample[0], sample[1]])) / 32768.0,
+        AudioFormat::S24LE => {
+            f64::from(i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) / 8_388_608.0
+        }
+        AudioFormat::F32LE => f64::from(f32::from_le_bytes([
+            sample[0], sample[1], sample[2], sample[3],
+        ])),
+    });
+    let (sum, count) = samples.fold((0.0, 0usize), |(sum, count), sample| {
+        (sum + sample * sample, count + 1)
+    });
+    if sum <= 0.0 || count == 0 {
+        return SILENCE_DBFS;
+    }
+    (10.0 * (sum / count as f64).log10()).max(SILENCE_DBFS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mono(format: AudioFormat) -> AudioConfig {
+        AudioConfig {
+            sample_rate: 8000,
+            channels: 1,
+            format,
+        }
+    }
+
+    /// `ms` of a square wave at `amplitude` (0 to 1) in S16LE
+    fn square(ms: u64, amplitude: f64) -> Vec<u8> {
+        (0..8 * ms)
+            .flat_map(|i| {
+                let sign = if i % 8 < 4 { 1.0 } else { -1.0 };
+                ((sign * amplitude * 32767.0) as i16).to_le_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(rms_dbfs(&[0; 320], &AudioFormat::S16LE), SILENCE_DBFS);
+        let full = rms_dbfs(&square(20, 1.0), &AudioFormat::S16LE);
+        assert!(full.abs() < 0.01, "{full}");
+        let tenth = rms_dbfs(&square(20, 0.1), &AudioFormat::S16LE);
+        assert!((tenth + 20.0).abs() < 0.01, "{tenth}");
+
+        let s24le: Vec<u8> = [-4_194_304i32, 4_194_304]
+            .iter()
+            .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+            .collect();
+        assert!((rms_dbfs(&s24le, &AudioFormat::S24LE) + 6.02).abs() < 0.01);
+        let f32le: Vec<u8> = [0.5f32, -0.5]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        assert!((rms_dbfs(&f32le, &AudioFormat::F32LE) + 6.02).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_gate_keeps_the_rolls_around_speech() {
+        let config = mono(AudioFormat::S16LE);
+        let mut gate = VoiceGate::new(Duration::from_millis(100), Duration::from_millis(200));
+        // Quiet room noise
+        assert!(gate.push(&config, &square(1000, 0.001)).is_empty());
+
+        // Speech brings 100 ms of noise along in front
+        let passed = gate.push(&config, &square(300, 0.1));
+        assert_eq!(passed.len(), 2 * 8 * (100 + 300));
+        assert_eq!(passed[..2 * 8 * 100], square(100, 0.001)[..]);
+
+        // 200 ms of post-roll, then closed again; a partial window waits
+        let passed = gate.push(&config, &square(510, 0.001));
+        assert_eq!(passed.len(), 2 * 8 * 200);
+        assert!(!gate.is_open());
+        assert_eq!(gate.pending.len(), 2 * 8 * 10);
+    }
+
+    #[test]
+    fn test_steady_noise_closes_the_gate() {
+        let config = mono(AudioFormat::S16LE);
+        let mut gate = VoiceGate::new(Duration::ZERO, Duration::ZERO);
+        assert!(gate.push(&config, &square(1000, 0.0001)).is_empty());
+        // A fan starting up is let through until the floor catches up
+        let passed = gate.push(&config, &square(30_000, 0.05));
+        assert!(!passed.is_empty());
+        assert!(passed.len() < 2 * 8 * 15_000, "{} bytes", passed.len());
+        assert!(!gate.is_open());
+
+        // Digital silence stays out, and a format change starts over
+        assert!(gate.push(&config, &[0; 1600]).is_empty());
+        gate.push(&mono(AudioFormat::F32LE), &[]);
+        assert_eq!(gate.noise_floor, None);
+    }
+}
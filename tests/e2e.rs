@@ -0,0 +1,1061 @@
+//! End-to-end streaming tests
+//!
+//! Each test runs a real receiver with the null backend and a transmitter
+//! with the mock input in this process, talking over loopback TCP, and
+//! checks the audio the receiver wrote. No sound server or microphone is
+//! needed.
+
+use rsonance::codec::{Codec, Encoder, convert_pcm, s16le_to_s24le};
+use rsonance::control::{add_marker, kick_client, list_clients};
+use rsonance::discovery::discover;
+use rsonance::events::ReceiverEvent;
+use rsonance::listen::ListenAddr;
+use rsonance::manifest::manifest_path;
+use rsonance::mock::{MockSignal, SignalSource};
+use rsonance::protocol::Metadata;
+use rsonance::receiver::{Backend, Receiver, ReceiverConfig, run_receiver};
+use rsonance::record::numbered_path;
+use rsonance::replay::{ReplayConfig, run_replay};
+use rsonance::transmitter::{TransmitterConfig, run_transmitter};
+use rsonance::udp::Transport;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Path for a test file, unique per process and test
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rsonance_e2e_{}_{name}", std::process::id()))
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Receiver writing to a file, with no control socket unless one is set
+fn receiver_config(port: u16, output: &Path, codec: Codec) -> ReceiverConfig {
+    ReceiverConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        fifo_path: output.to_string_lossy().into_owned(),
+        backend: Backend::Null,
+        codec,
+        control_socket: None,
+        state_file: None,
+        ..ReceiverConfig::default()
+    }
+}
+
+/// Start a receiver on a background thread and wait until it is listening
+fn start_receiver(config: ReceiverConfig) {
+    let output = PathBuf::from(&config.fifo_path);
+    let _ = std::fs::remove_file(&output);
+    thread::spawn(move || run_receiver(config).unwrap());
+    // The null backend creates its output file once the listener is bound
+    wait_for(|| output.exists());
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Run a mock-input transmitter for `duration`, then disconnect it
+fn transmit(config: TransmitterConfig, duration: Duration) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result =
+        runtime.block_on(async { tokio::time::timeout(duration, run_transmitter(config)).await });
+    if let Ok(result) = result {
+        panic!("transmitter stopped early: {result:?}");
+    }
+}
+
+fn mock_transmitter(port: u16, codec: Codec) -> TransmitterConfig {
+    TransmitterConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        codec,
+        mock_input: true,
+        ..TransmitterConfig::default()
+    }
+}
+
+/// Read `path` once the receiver has stopped writing to it
+fn read_settled(path: &Path) -> Vec<u8> {
+    let mut len = 0;
+    loop {
+        thread::sleep(Duration::from_millis(200));
+        let contents = std::fs::read(path).unwrap();
+        if contents.len() == len {
+            let _ = std::fs::remove_file(path);
+            return contents;
+        }
+        len = contents.len();
+    }
+}
+
+#[test]
+fn test_s16le_stream_is_bit_exact() {
+    let port = free_port();
+    let output = temp_path("s16le.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    transmit(
+        mock_transmitter(port, Codec::S16LE),
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+
+    // At least 200 ms of 44.1 kHz stereo arrived, identical to the source
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    assert_eq!(received.len() % 4, 0);
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_mock_signal_and_repeat_reach_the_receiver() {
+    let port = free_port();
+    let output = temp_path("pink.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    let config = TransmitterConfig {
+        mock_signal: MockSignal::Pink,
+        mock_repeat_ms: 100,
+        ..mock_transmitter(port, Codec::S16LE)
+    };
+    transmit(config, Duration::from_millis(500));
+    let received = read_settled(&output);
+
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(44100, 2)
+        .signal(MockSignal::Pink)
+        .repeat_ms(100)
+        .next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_fixed_duration_frames_keep_the_stream_bit_exact() {
+    let port = free_port();
+    let output = temp_path("frame-ms.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    // 7 ms at 44.1 kHz is 308.7 samples: frames of 308 samples, which
+    // never line up with the capture callbacks or batches
+    let config = TransmitterConfig {
+        frame_ms: 7,
+        ..mock_transmitter(port, Codec::S16LE)
+    };
+    transmit(config, Duration::from_millis(500));
+    let received = read_settled(&output);
+
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_sample_rate_resamples_the_captured_audio() {
+    let port = free_port();
+    let output = temp_path("sample-rate.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    // The mock input captures at 44.1 kHz
+    let config = TransmitterConfig {
+        sample_rate: Some(48000),
+        ..mock_transmitter(port, Codec::S16LE)
+    };
+    transmit(config, Duration::from_millis(500));
+    let received = read_settled(&output);
+
+    // The same tone, now in 48 kHz samples
+    assert!(received.len() >= 48000 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(48000, 2).next_block(received.len() / 4);
+    let samples = |s16le: &[u8]| -> Vec<i32> {
+        s16le
+            .chunks_exact(2)
+            .map(|b| i32::from(i16::from_le_bytes([b[0], b[1]])))
+            .collect()
+    };
+    let error = samples(&received)
+        .iter()
+        .zip(samples(&expected))
+        .skip(200)
+        .map(|(a, b)| (a - b).abs())
+        .max()
+        .unwrap();
+    assert!(error < 100, "differs from the source by up to {error}");
+}
+
+#[test]
+fn test_jitter_buffer_plays_the_stream_out_unchanged() {
+    let port = free_port();
+    let output = temp_path("jitter.raw");
+    start_receiver(ReceiverConfig {
+        jitter_buffer_ms: 200,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    transmit(
+        mock_transmitter(port, Codec::S16LE),
+        Duration::from_millis(600),
+    );
+    let received = read_settled(&output);
+
+    // Played out at the sample rate, and what is left once the stream ended
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_s24le_stream_reaches_the_output_in_24_bits() {
+    let port = free_port();
+    let output = temp_path("s24le.raw");
+    start_receiver(receiver_config(port, &output, Codec::S24LE));
+
+    transmit(
+        mock_transmitter(port, Codec::S24LE),
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+
+    // 44.1 kHz stereo in 3-byte samples, the 16-bit source widened losslessly
+    assert!(received.len() >= 44100 * 6 / 5, "{} bytes", received.len());
+    assert_eq!(received.len() % 6, 0);
+    let expected = s16le_to_s24le(&SignalSource::new(44100, 2).next_block(received.len() / 6));
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_f32le_stream_reaches_the_output_as_floats() {
+    let port = free_port();
+    let output = temp_path("f32le.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    transmit(
+        mock_transmitter(port, Codec::F32LE),
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+
+    // The header sets up the output for 4-byte floats, converted from the
+    // 16-bit source without loss
+    assert!(received.len() >= 44100 * 8 / 5, "{} bytes", received.len());
+    assert_eq!(received.len() % 8, 0);
+    let expected = convert_pcm(
+        &SignalSource::new(44100, 2).next_block(received.len() / 8),
+        2,
+        4,
+    );
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_g711_round_trip() {
+    for codec in [Codec::G711U, Codec::G711A] {
+        let port = free_port();
+        let output = temp_path(&format!("{codec}.raw"));
+        start_receiver(receiver_config(port, &output, codec));
+
+        transmit(mock_transmitter(port, codec), Duration::from_millis(500));
+        let received = read_settled(&output);
+
+        // 8 kHz mono, matching an offline encode and decode of the source
+        assert!(received.len() >= 8000 * 2 / 5, "{} bytes", received.len());
+        let frames = received.len() / 2 * 44100 / 8000 + 44100 / 100;
+        let encoded =
+            Encoder::new(codec, 44100, 2).encode(SignalSource::new(44100, 2).next_block(frames));
+        let mut expected = Vec::new();
+        codec.decode(&encoded, &mut expected);
+        assert!(
+            received[..] == expected[..received.len()],
+            "{codec} audio differs from the offline round trip"
+        );
+    }
+}
+
+#[test]
+fn test_receiver_follows_the_codec_in_the_stream_header() {
+    let port = free_port();
+    let output = temp_path("header.raw");
+    // The receiver's --codec only applies to raw streams
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+
+    transmit(
+        mock_transmitter(port, Codec::G711U),
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+
+    // Decoded from µ-law rather than written as it arrived
+    assert!(received.len() >= 8000 * 2 / 5, "{} bytes", received.len());
+    let frames = received.len() / 2 * 44100 / 8000 + 44100 / 100;
+    let encoded =
+        Encoder::new(Codec::G711U, 44100, 2).encode(SignalSource::new(44100, 2).next_block(frames));
+    let mut expected = Vec::new();
+    Codec::G711U.decode(&encoded, &mut expected);
+    assert!(received[..] == expected[..received.len()]);
+}
+
+#[test]
+fn test_sandboxed_receiver_restarts_the_output_command() {
+    let port = free_port();
+    let output = temp_path("sandbox.raw");
+    let _ = std::fs::remove_file(&output);
+    let config = ReceiverConfig {
+        sandbox: true,
+        pipe_to: Some(format!("head -c 4000 >> {}", output.display())),
+        ..receiver_config(port, &temp_path("sandbox.unused"), Codec::S16LE)
+    };
+    thread::spawn(move || run_receiver(config).unwrap());
+    wait_for(|| TcpStream::connect(("127.0.0.1", port)).is_ok());
+
+    transmit(mock_transmitter(port, Codec::S16LE), Duration::from_secs(2));
+    // Each run of the command takes 4000 bytes; the sandboxed writer cannot
+    // start it again itself
+    assert!(read_settled(&output).len() > 4000);
+}
+
+#[test]
+fn test_malformed_stream_bans_the_peer() {
+    let port = free_port();
+    let output = temp_path("ban.raw");
+    start_receiver(ReceiverConfig {
+        ban_after: 1,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    // Stream header of an unknown protocol version
+    let mut malformed = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    malformed
+        .write_all(b"RSNC\x09\0\0\0\x44\xac\0\0\x02\0")
+        .unwrap();
+    assert_eq!(malformed.read(&mut [0; 1]).unwrap_or(0), 0);
+    thread::sleep(Duration::from_millis(200));
+
+    // Closed right away, before its audio is written
+    let mut banned = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    banned
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let _ = banned.write_all(&[1; 4096]);
+    match banned.read(&mut [0; 1]) {
+        Ok(n) => assert_eq!(n, 0),
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+    assert!(read_settled(&output).is_empty());
+}
+
+#[test]
+fn test_connections_are_written_to_the_audit_log() {
+    let port = free_port();
+    let output = temp_path("audit.raw");
+    let audit = temp_path("audit.jsonl");
+    let _ = std::fs::remove_file(&audit);
+    start_receiver(ReceiverConfig {
+        audit_log: Some(audit.to_string_lossy().into_owned()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    transmit(
+        mock_transmitter(port, Codec::S16LE),
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+    let log = std::fs::read_to_string(&audit).unwrap();
+    let _ = std::fs::remove_file(&audit);
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{log}");
+    assert!(lines[0].contains(r#""event":"accepted","connection":1"#));
+    assert!(lines[1].contains(r#""event":"closed","connection":1"#));
+    // Counted on the wire, with the stream header and frame lengths
+    let bytes_received: usize = lines[1]
+        .split(r#""bytes_received":"#)
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(bytes_received > received.len(), "{log}");
+}
+
+#[test]
+fn test_udp_stream_is_bit_exact_and_ends_when_the_datagrams_stop() {
+    let port = free_port();
+    let output = temp_path("udp.raw");
+    let audit = temp_path("udp.jsonl");
+    let _ = std::fs::remove_file(&audit);
+    start_receiver(ReceiverConfig {
+        transport: Transport::Udp,
+        audit_log: Some(audit.to_string_lossy().into_owned()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    transmit(
+        TransmitterConfig {
+            transport: Transport::Udp,
+            ..mock_transmitter(port, Codec::S16LE)
+        },
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+
+    // Nothing is lost or reordered on loopback
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+
+    // The connection closes once the transmitter has gone quiet
+    wait_for(|| {
+        std::fs::read_to_string(&audit).is_ok_and(|log| log.contains(r#""event":"closed""#))
+    });
+    let log = std::fs::read_to_string(&audit).unwrap();
+    let _ = std::fs::remove_file(&audit);
+    assert!(
+        log.lines()
+            .next()
+            .unwrap()
+            .contains(r#""event":"accepted""#),
+        "{log}"
+    );
+}
+
+#[test]
+fn test_named_transmitter_is_listed_with_its_tags() {
+    let port = free_port();
+    let output = temp_path("named.raw");
+    let socket = temp_path("named.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(socket.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    let metadata = Metadata {
+        name: Some("Stage left".to_string()),
+        tags: vec![("room".to_string(), "hall".to_string())],
+    };
+    let config = TransmitterConfig {
+        metadata: metadata.clone(),
+        ..mock_transmitter(port, Codec::S16LE)
+    };
+    let transmitter = thread::spawn(move || transmit(config, Duration::from_secs(1)));
+    wait_for(|| {
+        list_clients(&socket)
+            .is_ok_and(|clients| clients.iter().any(|client| client.metadata == metadata))
+    });
+    transmitter.join().unwrap();
+
+    // The metadata block is not taken for audio
+    let received = read_settled(&output);
+    assert!(received.len() >= 44100 * 4 / 5, "{} bytes", received.len());
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+}
+
+#[test]
+fn test_transmitter_reconnects_after_kick() {
+    let port = free_port();
+    let output = temp_path("kick.raw");
+    let socket = temp_path("kick.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(socket.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    let transmitter = thread::spawn(move || {
+        transmit(mock_transmitter(port, Codec::S16LE), Duration::from_secs(3))
+    });
+
+    let connected = |id| {
+        list_clients(&socket)
+            .map(|clients| clients.iter().any(|client| client.id == id))
+            .unwrap_or(false)
+    };
+    wait_for(|| connected(1));
+    kick_client(&socket, 1).unwrap();
+    // The transmitter notices the closed connection and reconnects
+    wait_for(|| connected(2));
+
+    transmitter.join().unwrap();
+    let received = read_settled(&output);
+    assert!(!received.is_empty());
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_spectrum_shows_the_tone_band() {
+    let port = free_port();
+    let output = temp_path("spectrum.raw");
+    let socket = temp_path("spectrum.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(socket.clone()),
+        spectrum: true,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    let transmitter = thread::spawn(move || {
+        transmit(mock_transmitter(port, Codec::S16LE), Duration::from_secs(2))
+    });
+    // The first window takes a third of a second of audio
+    let mut spectrum = None;
+    wait_for(|| {
+        spectrum = rsonance::control::spectrum(&socket, 1).ok();
+        spectrum.is_some()
+    });
+    let spectrum = spectrum.unwrap();
+
+    // The 440 Hz tone at -6 dBFS falls into the 500 Hz octave
+    let loudest = spectrum
+        .bands
+        .iter()
+        .max_by(|a, b| a.dbfs.total_cmp(&b.dbfs))
+        .unwrap();
+    assert_eq!(loudest.center_hz, 500.0, "{spectrum}");
+    assert!((loudest.dbfs + 6.0).abs() < 1.0, "{spectrum}");
+    assert!(spectrum.hum_50hz_dbfs < -60.0, "{spectrum}");
+
+    transmitter.join().unwrap();
+    read_settled(&output);
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_sandboxed_recording_rotates_into_numbered_wav_files() {
+    let port = free_port();
+    let output = temp_path("record.raw");
+    let record = temp_path("record.wav").to_string_lossy().into_owned();
+    let files = [1, 2, 3].map(|n| numbered_path(&record, n));
+    for file in &files {
+        let _ = std::fs::remove_file(file);
+    }
+    start_receiver(ReceiverConfig {
+        sandbox: true,
+        record: Some(record.clone()),
+        record_max_secs: 1,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    transmit(
+        mock_transmitter(port, Codec::S16LE),
+        Duration::from_millis(2500),
+    );
+    let received = read_settled(&output);
+
+    // The sandboxed writer had the files created for it, each holding a
+    // second of the stream behind a 44-byte header
+    assert!(Path::new(&files[1]).exists());
+    let mut recorded = Vec::new();
+    for file in files.iter().filter(|file| Path::new(file).exists()) {
+        let wav = std::fs::read(file).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize,
+            wav.len() - 44
+        );
+        recorded.extend_from_slice(&wav[44..]);
+        let _ = std::fs::remove_file(file);
+    }
+    assert!(recorded.len() > 44100 * 4, "{} bytes", recorded.len());
+    assert!(recorded == received, "recording differs from the output");
+}
+
+/// Value of the unlabelled sample `name` in a scrape of the receiver's
+/// `--metrics-addr`
+fn scrape(port: u16, name: &str) -> Option<f64> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[test]
+fn test_metrics_count_connections_and_traffic() {
+    let port = free_port();
+    let metrics_port = free_port();
+    let output = temp_path("metrics.raw");
+    let socket = temp_path("metrics.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(socket.clone()),
+        metrics_addr: Some(format!("127.0.0.1:{metrics_port}")),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+    assert_eq!(
+        scrape(metrics_port, "rsonance_connections_total"),
+        Some(0.0)
+    );
+
+    let transmitter = thread::spawn(move || {
+        transmit(mock_transmitter(port, Codec::S16LE), Duration::from_secs(2))
+    });
+    wait_for(|| scrape(metrics_port, "rsonance_connected_clients") == Some(1.0));
+    thread::sleep(Duration::from_millis(300));
+    kick_client(&socket, 1).unwrap();
+    // The transmitter comes back from the same address
+    wait_for(|| scrape(metrics_port, "rsonance_reconnects_total") == Some(1.0));
+
+    transmitter.join().unwrap();
+    let received = read_settled(&output);
+    assert_eq!(
+        scrape(metrics_port, "rsonance_connections_total"),
+        Some(2.0)
+    );
+    let bytes = scrape(metrics_port, "rsonance_received_bytes_total").unwrap();
+    // The resent audio was received but not written twice
+    assert!(
+        bytes >= received.len() as f64,
+        "{bytes} < {}",
+        received.len()
+    );
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_replay_after_kick_is_not_written_twice() {
+    let port = free_port();
+    let output = temp_path("continuity.raw");
+    let socket = temp_path("continuity.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(socket.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    let transmitter = thread::spawn(move || {
+        transmit(mock_transmitter(port, Codec::S16LE), Duration::from_secs(2))
+    });
+    let connected = |id| {
+        list_clients(&socket)
+            .map(|clients| clients.iter().any(|client| client.id == id))
+            .unwrap_or(false)
+    };
+    wait_for(|| connected(1));
+    thread::sleep(Duration::from_millis(500));
+    kick_client(&socket, 1).unwrap();
+    wait_for(|| connected(2));
+
+    transmitter.join().unwrap();
+    let received = read_settled(&output);
+    // The replayed audio continues the stream instead of repeating it
+    assert_eq!(received.len() % 4, 0);
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(
+        received == expected,
+        "received audio differs from the source"
+    );
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_transmitter_waits_for_late_receiver() {
+    let port = free_port();
+    let output = temp_path("late.raw");
+    let late_receiver = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(700));
+        start_receiver(receiver_config(port, &output, Codec::S16LE));
+        output
+    });
+
+    transmit(
+        TransmitterConfig {
+            wait_for_server: true,
+            ..mock_transmitter(port, Codec::S16LE)
+        },
+        Duration::from_secs(2),
+    );
+    let received = read_settled(&late_receiver.join().unwrap());
+
+    // About a second streamed live after connecting, plus up to 500 ms
+    // buffered while the receiver was missing
+    assert!(
+        received.len() >= 44100 * 4 * 5 / 4,
+        "{} bytes",
+        received.len()
+    );
+}
+
+#[test]
+fn test_transmitter_fails_over_to_standby_and_back() {
+    let port = free_port();
+    let standby_port = free_port();
+    let output = temp_path("primary.raw");
+    let standby_output = temp_path("standby.raw");
+    start_receiver(receiver_config(standby_port, &standby_output, Codec::S16LE));
+    // The primary receiver only comes up once the stream is on the standby
+    let primary = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(500));
+        start_receiver(receiver_config(port, &output, Codec::S16LE));
+        output
+    });
+
+    transmit(
+        TransmitterConfig {
+            standby: Some(format!("127.0.0.1:{standby_port}")),
+            ..mock_transmitter(port, Codec::S16LE)
+        },
+        Duration::from_secs(5),
+    );
+
+    assert!(!read_settled(&standby_output).is_empty());
+    assert!(!read_settled(&primary.join().unwrap()).is_empty());
+}
+
+#[test]
+fn test_duplicate_receiver_gets_the_same_stream() {
+    let port = free_port();
+    let duplicate_port = free_port();
+    let output = temp_path("duplicated.raw");
+    let duplicate_output = temp_path("duplicate.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+    start_receiver(receiver_config(
+        duplicate_port,
+        &duplicate_output,
+        Codec::S16LE,
+    ));
+
+    transmit(
+        TransmitterConfig {
+            duplicate_to: Some(format!("127.0.0.1:{duplicate_port}")),
+            ..mock_transmitter(port, Codec::S16LE)
+        },
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+    let duplicated = read_settled(&duplicate_output);
+
+    // Both start at the beginning of the source
+    assert!(!duplicated.is_empty());
+    let common = received.len().min(duplicated.len());
+    assert!(
+        received[..common] == duplicated[..common],
+        "the copy differs from the stream"
+    );
+}
+
+#[test]
+fn test_compare_codec_encodes_the_copy_from_the_same_source() {
+    let port = free_port();
+    let duplicate_port = free_port();
+    let output = temp_path("compared_a.raw");
+    let duplicate_output = temp_path("compared_b.raw");
+    start_receiver(receiver_config(port, &output, Codec::S16LE));
+    start_receiver(receiver_config(
+        duplicate_port,
+        &duplicate_output,
+        Codec::S16LE,
+    ));
+
+    transmit(
+        TransmitterConfig {
+            duplicate_to: Some(format!("127.0.0.1:{duplicate_port}")),
+            compare_codec: Some(Codec::G711U),
+            ..mock_transmitter(port, Codec::S16LE)
+        },
+        Duration::from_millis(500),
+    );
+    let received = read_settled(&output);
+    let compared = read_settled(&duplicate_output);
+
+    // The stream as is, the copy as an offline µ-law round trip of the source
+    let expected = SignalSource::new(44100, 2).next_block(received.len() / 4);
+    assert!(received == expected, "the stream differs from the source");
+    assert!(compared.len() >= 8000 * 2 / 5, "{} bytes", compared.len());
+    let frames = compared.len() / 2 * 44100 / 8000 + 44100 / 100;
+    let encoded =
+        Encoder::new(Codec::G711U, 44100, 2).encode(SignalSource::new(44100, 2).next_block(frames));
+    let mut expected = Vec::new();
+    Codec::G711U.decode(&encoded, &mut expected);
+    assert!(
+        compared[..] == expected[..compared.len()],
+        "the copy differs from the µ-law round trip"
+    );
+}
+
+#[test]
+fn test_on_demand_transmitter_streams_once_the_receiver_reports_a_recorder() {
+    let port = free_port();
+    let output = temp_path("on_demand.raw");
+    let silent_port = free_port();
+    let silent_output = temp_path("on_demand_silent.raw");
+    // The null backend always reports a recorder when asked to
+    start_receiver(ReceiverConfig {
+        on_demand: true,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+    start_receiver(receiver_config(silent_port, &silent_output, Codec::S16LE));
+
+    let on_demand = |port| TransmitterConfig {
+        on_demand: true,
+        ..mock_transmitter(port, Codec::S16LE)
+    };
+    transmit(on_demand(port), Duration::from_millis(500));
+    transmit(on_demand(silent_port), Duration::from_millis(500));
+
+    assert!(!read_settled(&output).is_empty());
+    // A receiver that does not report its recorders gets nothing
+    assert!(read_settled(&silent_output).is_empty());
+}
+
+#[test]
+fn test_all_listeners_feed_the_same_output() {
+    let port = free_port();
+    let output = temp_path("listen.raw");
+    let socket = temp_path("listen.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        listen: vec![
+            format!("127.0.0.1:{port}").parse().unwrap(),
+            ListenAddr::Unix(socket.clone()),
+        ],
+        ..receiver_config(0, &output, Codec::S16LE)
+    });
+
+    let mut unix = UnixStream::connect(&socket).unwrap();
+    unix.write_all(&[1, 0, 2, 0]).unwrap();
+    drop(unix);
+    wait_for(|| std::fs::metadata(&output).unwrap().len() == 4);
+
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    tcp.write_all(&[3, 0, 4, 0]).unwrap();
+    drop(tcp);
+
+    assert_eq!(read_settled(&output), vec![1, 0, 2, 0, 3, 0, 4, 0]);
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_health_check_covers_listener_and_output() {
+    let port = free_port();
+    let output = temp_path("health.raw");
+    let control = temp_path("health.sock");
+    let control = control.to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        control_socket: Some(control.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+    wait_for(|| Path::new(&control).exists());
+
+    let checks = rsonance::control::check_health(&control).unwrap();
+    let names: Vec<&str> = checks.iter().map(|check| check.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [format!("listener 127.0.0.1:{port}").as_str(), "output file"]
+    );
+    assert!(rsonance::health::is_healthy(&checks), "{checks:?}");
+
+    std::fs::remove_file(&output).unwrap();
+    let checks = rsonance::control::check_health(&control).unwrap();
+    assert!(!rsonance::health::is_healthy(&checks));
+}
+
+#[test]
+fn test_receiver_shuts_down_cleanly() {
+    let port = free_port();
+    let output = temp_path("shutdown.raw");
+    let socket = temp_path("shutdown.sock").to_string_lossy().into_owned();
+    let control = temp_path("shutdown_control.sock")
+        .to_string_lossy()
+        .into_owned();
+    let mut receiver = Receiver::new(ReceiverConfig {
+        listen: vec![
+            format!("127.0.0.1:{port}").parse().unwrap(),
+            ListenAddr::Unix(socket.clone()),
+        ],
+        control_socket: Some(control.clone()),
+        ..receiver_config(0, &output, Codec::S16LE)
+    });
+    receiver.start().unwrap();
+    assert!(receiver.start().is_err());
+
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    tcp.write_all(&[1, 0, 2, 0]).unwrap();
+    wait_for(|| receiver.clients().len() == 1);
+
+    receiver.shutdown().unwrap();
+    // The connected transmitter was disconnected
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(tcp.read(&mut [0u8; 16]).unwrap(), 0);
+    assert!(receiver.clients().is_empty());
+    assert!(!Path::new(&socket).exists());
+    assert!(!Path::new(&control).exists());
+    // The port is free for the next receiver
+    TcpListener::bind(("127.0.0.1", port)).unwrap();
+    receiver.shutdown().unwrap();
+
+    assert_eq!(std::fs::read(&output).unwrap(), vec![1, 0, 2, 0]);
+    let _ = std::fs::remove_file(&output);
+}
+
+#[test]
+fn test_receiver_events_follow_a_kicked_connection() {
+    let port = free_port();
+    let output = temp_path("events.raw");
+    let control = temp_path("events_control.sock")
+        .to_string_lossy()
+        .into_owned();
+    let mut receiver = Receiver::new(ReceiverConfig {
+        control_socket: Some(control.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+    let events = receiver.events();
+    receiver.start().unwrap();
+    let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    tcp.write_all(&[1, 0, 2, 0]).unwrap();
+    let ReceiverEvent::Connected { id, peer } = next() else {
+        panic!("expected a connection");
+    };
+    assert_eq!(peer, Some(tcp.local_addr().unwrap()));
+
+    kick_client(&control, id).unwrap();
+    assert_eq!(next(), ReceiverEvent::Kicked { id });
+    match next() {
+        ReceiverEvent::Disconnected {
+            id: ended,
+            bytes_received,
+            error,
+            ..
+        } => assert_eq!((ended, bytes_received, error), (id, 4, None)),
+        event => panic!("expected a disconnect, got {event:?}"),
+    }
+
+    receiver.shutdown().unwrap();
+    assert!(events.try_recv().is_err());
+    let _ = std::fs::remove_file(&output);
+}
+
+#[test]
+fn test_advertised_receiver_is_discovered() {
+    let port = free_port();
+    let output = temp_path("advertise.raw");
+    let name = format!("rsonance e2e {}", std::process::id());
+    let mut receiver = Receiver::new(ReceiverConfig {
+        transport: Transport::Udp,
+        advertise: Some(name.clone()),
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+    receiver.start().unwrap();
+
+    let found = discover(Some(&name.to_uppercase()), Duration::from_secs(5)).unwrap();
+    assert_eq!(found.name, name);
+    assert_eq!(found.addr.port(), port);
+    assert_eq!(found.transport, Transport::Udp);
+    // A name nobody advertises lists the receivers that answered
+    let error = discover(Some("no such receiver"), Duration::from_secs(1)).unwrap_err();
+    assert!(error.to_string().contains(&name), "{error}");
+
+    receiver.shutdown().unwrap();
+    let _ = std::fs::remove_file(&output);
+}
+
+#[test]
+fn test_prebuffer_holds_back_the_start_of_a_stream() {
+    let port = free_port();
+    let output = temp_path("prebuffer.raw");
+    start_receiver(ReceiverConfig {
+        prebuffer_ms: 100,
+        ..receiver_config(port, &output, Codec::S16LE)
+    });
+
+    // 100 ms of 44.1 kHz stereo is 17640 bytes
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    tcp.write_all(&[1; 10000]).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), 0);
+
+    tcp.write_all(&[2; 10000]).unwrap();
+    wait_for(|| std::fs::metadata(&output).unwrap().len() == 20000);
+
+    // Once playing, audio is written as it arrives
+    tcp.write_all(&[3; 4]).unwrap();
+    drop(tcp);
+    assert_eq!(read_settled(&output).len(), 20004);
+}
+
+#[test]
+fn test_replayed_dump_reproduces_output() {
+    let port = free_port();
+    let output = temp_path("dump_source.raw");
+    let dump = temp_path("session.bin");
+    let control = temp_path("dump.sock").to_string_lossy().into_owned();
+    start_receiver(ReceiverConfig {
+        debug_dump: Some(dump.to_string_lossy().into_owned()),
+        control_socket: Some(control.clone()),
+        ..receiver_config(port, &output, Codec::G711U)
+    });
+    transmit(
+        mock_transmitter(port, Codec::G711U),
+        Duration::from_millis(500),
+    );
+    let original = read_settled(&output);
+    wait_for(|| Path::new(&control).exists());
+    let offset = add_marker(&control, "after the tone").unwrap();
+    assert!(offset >= 0.5, "marked at {offset} s");
+
+    let replay_port = free_port();
+    let replay_output = temp_path("dump_replay.raw");
+    start_receiver(receiver_config(replay_port, &replay_output, Codec::G711U));
+    run_replay(&ReplayConfig {
+        path: dump.to_string_lossy().into_owned(),
+        port: replay_port,
+        speed: 0.0,
+        ..ReplayConfig::default()
+    })
+    .unwrap();
+    let replayed = read_settled(&replay_output);
+    let manifest_path = manifest_path(&dump.to_string_lossy());
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let _ = std::fs::remove_file(&dump);
+    let _ = std::fs::remove_file(&manifest_path);
+
+    assert!(!original.is_empty());
+    assert!(
+        replayed == original,
+        "replayed audio differs from the original"
+    );
+    // The sidecar manifest marks the transmitter's connection and its end,
+    // with the levels of the mock tone
+    assert!(manifest.contains("\"codec\": \"g711u\""), "{manifest}");
+    assert!(manifest.contains("\"participant\":\"127.0.0.1\""));
+    assert!(manifest.contains("\"event\":\"connected\",\"connection\":1"));
+    assert!(manifest.contains("\"event\":\"disconnected\",\"connection\":1"));
+    assert!(!manifest.contains("\"peak_dbfs\":null"), "{manifest}");
+    assert!(manifest.contains("\"event\":\"mark\",\"label\":\"after the tone\""));
+}